@@ -0,0 +1,109 @@
+#![allow(non_snake_case)]
+
+use anyhow::Result;
+use std::env;
+use std::time::Duration;
+use VoxelTest::app::App;
+use VoxelTest::camera::{CameraController, OrbitCameraController};
+use VoxelTest::console::{ConsoleCommands, DebugConsole};
+use VoxelTest::dimension::{Dimension, DimensionSettings};
+use VoxelTest::engine::{Engine, Game};
+use VoxelTest::logging;
+use VoxelTest::net::Client;
+use VoxelTest::replication::PlayerController;
+use VoxelTest::seed::WorldSeed;
+use VoxelTest::ui::Crosshair;
+use VoxelTest::worldgen::WorldGenerator;
+
+// Free flying and orbiting cameras over generated terrain, with a second empty
+// dimension to switch to from the console. With `--connect <address>` the
+// terrain comes from a server instead, see the server example, and the camera
+// follows a player moved through it, with the other players drawn as boxes.
+struct Demo {
+    connect: Option<String>,
+    client: Option<Client>,
+}
+
+impl Game for Demo {
+    fn init(&mut self, app: &mut App) -> Result<()> {
+        app.add_actor(Box::new(
+            DebugConsole::new(ConsoleCommands::with_defaults()),
+        ));
+        app.register_model("cube.obj")?;
+        app.set_crosshair(Some(Crosshair::default()));
+        app.add_dimension(Dimension::new(
+            "overworld",
+            WorldGenerator::with_default_stages(WorldSeed::new(0)),
+            DimensionSettings::default(),
+            16,
+        ))?;
+        // Empty world with a frozen noon sky, for testing things without terrain around.
+        app.add_dimension(Dimension::new(
+            "void",
+            WorldGenerator::new(WorldSeed::new(0)),
+            DimensionSettings {
+                time_of_day: 0.5,
+                cycle_length: 0.0,
+                ..Default::default()
+            },
+            4,
+        ))?;
+        if let Some(address) = &self.connect {
+            let client = Client::connect(address, "player")?;
+            app.add_dimension(
+                Dimension::new(
+                    "server",
+                    WorldGenerator::new(client.seed()),
+                    DimensionSettings {
+                        spawn: client.spawn().into(),
+                        ..Default::default()
+                    },
+                    client.view_radius(),
+                )
+                .remote(),
+            )?;
+            app.switch_dimension("server")?;
+            // Inputs go out at the server's tick rate.
+            app.set_fixed_timestep(true);
+            app.add_actor(Box::new(PlayerController::new(client.prediction(), 1.0)));
+            self.client = Some(client);
+        } else {
+            app.switch_dimension("overworld")?;
+            let camera_controller = Box::new(CameraController::new(4.0, 1.0, app.camera()));
+            app.add_actor(camera_controller);
+            let orbit_controller = Box::new(OrbitCameraController::new(
+                6.0,
+                4.0,
+                1.0,
+                12.0,
+                app.camera(),
+            ));
+            app.add_actor(orbit_controller);
+        }
+        app.weather_mut().set_coverage(0.45);
+
+        Ok(())
+    }
+
+    fn update(&mut self, app: &mut App, dt: Duration) {
+        if let Some(client) = &mut self.client {
+            if let Err(e) = client.update(app, dt) {
+                tracing::warn!(target: logging::NET, "Disconnected: {e}");
+                self.client = None;
+                return;
+            }
+            for (_, state) in client.remote_entities() {
+                app.debug_draw_aabb(&state.aabb(), [1.0, 0.8, 0.2, 1.0]);
+            }
+        }
+    }
+}
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip_while(|arg| arg != "--connect");
+    let connect = args.nth(1);
+    Engine::new().with_title("VoxelTest").run(Demo {
+        connect,
+        client: None,
+    })
+}
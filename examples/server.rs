@@ -0,0 +1,23 @@
+#![allow(non_snake_case)]
+
+use anyhow::Result;
+use std::env;
+use VoxelTest::net::{Server, DEFAULT_PORT};
+use VoxelTest::repro;
+use VoxelTest::seed::WorldSeed;
+use VoxelTest::world::World;
+
+// Headless server for the demo to connect to, hosting a generated world.
+// `cargo run --example server -- [address] [seed]`
+fn main() -> Result<()> {
+    repro::init_logging_with("net=info");
+    let mut args = env::args().skip(1);
+    let address = args
+        .next()
+        .unwrap_or_else(|| format!("0.0.0.0:{DEFAULT_PORT}"));
+    let seed = args
+        .next()
+        .map_or(WorldSeed::new(0), |seed| WorldSeed::from_text(&seed));
+
+    Server::bind(address, World::new(seed), 8)?.run()
+}
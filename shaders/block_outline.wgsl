@@ -0,0 +1,44 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient_strength: f32,
+}
+
+struct OutlineUniform {
+    min: vec4<f32>,
+    max: vec4<f32>,
+    color: vec4<f32>,
+}
+
+@group(0)@binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1)@binding(0)
+var<uniform> outline: OutlineUniform;
+
+// Two vertices for each of the 12 edges, 4 along every axis.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let edge = index / 2u;
+    let axis = edge / 4u;
+    let side = edge % 4u;
+
+    var corner = vec3<f32>(0.0);
+    corner[axis] = f32(index % 2u);
+    corner[(axis + 1u) % 3u] = f32(side & 1u);
+    corner[(axis + 2u) % 3u] = f32(side >> 1u);
+    let position = mix(outline.min.xyz, outline.max.xyz, corner);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return outline.color;
+}
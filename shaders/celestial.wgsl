@@ -0,0 +1,85 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) fade: f32,
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient_strength: f32,
+}
+
+struct SkyUniform {
+    inv_view_proj: mat4x4<f32>,
+    top_color: vec4<f32>,
+    horizon_color: vec4<f32>,
+    ground_color: vec4<f32>,
+    sun_direction: vec4<f32>,
+    moon_direction: vec4<f32>,
+    stars: vec4<f32>,
+    clouds: vec4<f32>,
+    // x is the depth the sky is drawn at, right in front of the far plane.
+    depth: vec4<f32>,
+}
+
+@group(0)@binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1)@binding(0)
+var<uniform> sky: SkyUniform;
+
+@group(2)@binding(0)
+var t_celestial: texture_2d<f32>;
+@group(2)@binding(1)
+var s_celestial: sampler;
+
+// Instance 0 is the sun and 1 the moon, w of their direction is the angular size.
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+    @builtin(instance_index) instance: u32,
+) -> VertexOutput {
+    var body = sky.sun_direction;
+    if (instance == 1u) {
+        body = sky.moon_direction;
+    }
+    let direction = normalize(body.xyz);
+    var helper = vec3<f32>(0.0, 0.0, 1.0);
+    if (abs(direction.z) > 0.9) {
+        helper = vec3<f32>(0.0, 1.0, 0.0);
+    }
+    // Counter clockwise as seen from the camera, the pipeline culls back faces.
+    let right = normalize(cross(direction, helper));
+    let up = cross(right, direction);
+
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[index];
+    let point = direction + (right * corner.x + up * corner.y) * body.w;
+
+    // Points at infinity ignore the camera position, then the depth goes just
+    // in front of the far plane like the rest of the sky.
+    var clip = camera.view_proj * vec4<f32>(point, 0.0);
+    clip.z = clip.w * sky.depth.x;
+
+    var out: VertexOutput;
+    out.clip_position = clip;
+    // The atlas has the sun on the left half and the moon on the right one.
+    out.tex_coords = vec2<f32>((corner.x * 0.5 + 0.5 + f32(instance)) * 0.5, 0.5 - corner.y * 0.5);
+    // Both sink into the horizon haze instead of cutting off at it.
+    out.fade = smoothstep(-0.05, 0.05, direction.y);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_celestial, s_celestial, in.tex_coords);
+    return vec4<f32>(color.rgb, color.a * in.fade);
+}
@@ -0,0 +1,178 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) ao: f32,
+    @location(3) normal: vec3<f32>,
+    @location(4) tangent: vec4<f32>,
+    @location(5) light: f32,
+    @location(6) sky: f32,
+    @location(7) tint: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) ao: f32,
+    @location(2) world_position: vec3<f32>,
+    @location(3) normal: vec3<f32>,
+    @location(4) tangent: vec4<f32>,
+    @location(5) light: f32,
+    @location(6) sky: f32,
+    @location(7) tint: vec3<f32>,
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient_strength: f32,
+}
+
+struct EnvironmentUniform {
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    ambient_color: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_range: vec4<f32>,
+    // Sun angle, moon angle, star visibility and sun elevation.
+    celestial: vec4<f32>,
+    // Cloud coverage, the wind offset of the clouds and their altitude.
+    clouds: vec4<f32>,
+}
+
+struct DebugUniform {
+    view: u32,
+    width: u32,
+}
+
+@group(1)@binding(0)
+var<uniform> camera: CameraUniform;
+@group(1)@binding(2)
+var<uniform> debug: DebugUniform;
+@group(1)@binding(3)
+var<storage, read_write> overdraw: array<atomic<u32>>;
+@group(1)@binding(4)
+var<uniform> environment: EnvironmentUniform;
+
+@group(0)@binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0)@binding(1)
+var s_diffuse: sampler;
+@group(0)@binding(2)
+var t_normal: texture_2d<f32>;
+@group(0)@binding(3)
+var s_normal: sampler;
+
+const DEBUG_LIGHT_LEVELS: u32 = 1u;
+const DEBUG_AMBIENT_OCCLUSION: u32 = 2u;
+const DEBUG_NORMALS: u32 = 3u;
+const DEBUG_OVERDRAW: u32 = 4u;
+
+fn heatmap(value: f32) -> vec3<f32> {
+    let t = clamp(value, 0.0, 1.0);
+    return vec3<f32>(smoothstep(0.5, 1.0, t), sin(t * 3.14159), smoothstep(0.5, 0.0, t));
+}
+
+const CLOUD_SCALE: f32 = 48.0;
+const CLOUD_PERIOD: f32 = 64.0;
+
+fn cloud_hash(cell: vec2<f32>) -> f32 {
+    // Wrapped so the clouds repeat where the wind offset wraps.
+    let wrapped = cell - floor(cell / CLOUD_PERIOD) * CLOUD_PERIOD;
+    return fract(sin(dot(wrapped, vec2<f32>(127.1, 311.7))) * 43758.5453);
+}
+
+fn cloud_noise(position: vec2<f32>) -> f32 {
+    let cell = floor(position);
+    let f = fract(position);
+    let u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(cloud_hash(cell), cloud_hash(cell + vec2<f32>(1.0, 0.0)), u.x),
+        mix(cloud_hash(cell + vec2<f32>(0.0, 1.0)), cloud_hash(cell + vec2<f32>(1.0, 1.0)), u.x),
+        u.y,
+    );
+}
+
+// How thick the clouds are above `xz`, from 0.0 to 1.0.
+fn cloud_density(xz: vec2<f32>, clouds: vec4<f32>) -> f32 {
+    if (clouds.x <= 0.0) {
+        return 0.0;
+    }
+    let p = (xz + clouds.yz) / CLOUD_SCALE;
+    let n = (cloud_noise(p) * 0.5 + cloud_noise(p * 2.0) * 0.25 + cloud_noise(p * 4.0) * 0.125) / 0.875;
+    return smoothstep(1.0 - clouds.x, 1.2 - clouds.x, n);
+}
+
+// Light let through by the clouds between the sun and this point.
+fn cloud_shadow(world_position: vec3<f32>) -> f32 {
+    let sun = environment.sun_direction.xyz;
+    if (sun.y <= 0.0 || world_position.y >= environment.clouds.w) {
+        return 1.0;
+    }
+    let t = (environment.clouds.w - world_position.y) / sun.y;
+    return 1.0 - cloud_density(world_position.xz + sun.xz * t, environment.clouds) * 0.6;
+}
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let distance = length(world_position - camera.view_pos.xyz);
+    let linear = smoothstep(environment.fog_range.x, environment.fog_range.y, distance);
+    let haze = 1.0 - exp(-pow(distance * environment.fog_color.w, 2.0));
+    return mix(color, environment.fog_color.rgb, clamp(max(linear, haze), 0.0, 1.0));
+}
+
+// The normal map sample moved from tangent space to world space.
+fn mapped_normal(in: VertexOutput) -> vec3<f32> {
+    let normal = normalize(in.normal);
+    let tangent = normalize(in.tangent.xyz - normal * dot(normal, in.tangent.xyz));
+    let bitangent = cross(normal, tangent) * in.tangent.w;
+    let sample = textureSample(t_normal, s_normal, in.tex_coords).xyz * 2.0 - 1.0;
+    return normalize(mat3x3<f32>(tangent, bitangent, normal) * sample);
+}
+
+@vertex
+fn vs_main(model: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    out.ao = model.ao;
+    out.world_position = model.position;
+    out.normal = model.normal;
+    out.tangent = model.tangent;
+    out.light = model.light;
+    out.sky = model.sky;
+    out.tint = model.tint;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords) * vec4<f32>(in.tint, 1.0);
+    let occlusion = mix(0.35, 1.0, in.ao);
+    let normal = mapped_normal(in);
+    let diffuse = max(dot(normal, environment.sun_direction.xyz), 0.0) * environment.sun_direction.w
+        * cloud_shadow(in.world_position);
+    // Warm block light from lamps, falling off faster than linear like in Minecraft.
+    let torch = pow(in.light, 2.0) * vec3<f32>(1.0, 0.85, 0.6);
+    // Sun and ambient only reach as far as the sky light does, with a little
+    // left so caves aren't pitch black.
+    let daylight = environment.ambient_color.rgb + environment.sun_color.rgb * diffuse;
+    let light = daylight * max(pow(in.sky, 2.0), 0.03) + torch;
+
+    switch debug.view {
+        case DEBUG_LIGHT_LEVELS: {
+            return vec4<f32>(heatmap(max(in.light, in.sky)), 1.0);
+        }
+        case DEBUG_AMBIENT_OCCLUSION: {
+            return vec4<f32>(vec3<f32>(occlusion), 1.0);
+        }
+        case DEBUG_NORMALS: {
+            return vec4<f32>(normal * 0.5 + 0.5, 1.0);
+        }
+        case DEBUG_OVERDRAW: {
+            let pixel = u32(in.clip_position.y) * debug.width + u32(in.clip_position.x);
+            atomicAdd(&overdraw[pixel], 1u);
+        }
+        default: {}
+    }
+
+    return vec4<f32>(apply_fog(object_color.xyz * occlusion * light, in.world_position), object_color.a);
+}
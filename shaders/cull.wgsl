@@ -0,0 +1,52 @@
+struct CullEntry {
+    aabb_min: vec4<f32>,
+    aabb_max: vec4<f32>,
+    position: vec4<f32>,
+    index_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+}
+
+struct CullUniform {
+    planes: array<vec4<f32>, 6>,
+    // xyz is the camera position, w the squared max draw distance.
+    camera: vec4<f32>,
+    count: u32,
+}
+
+@group(0)@binding(0)
+var<uniform> cull: CullUniform;
+@group(0)@binding(1)
+var<storage, read> entries: array<CullEntry>;
+// Laid out as DrawIndexedIndirect arguments, 5 words per entry.
+@group(0)@binding(2)
+var<storage, read_write> draws: array<u32>;
+
+fn is_visible(entry: CullEntry) -> bool {
+    for (var i = 0u; i < 6u; i++) {
+        let plane = cull.planes[i];
+        let p = select(entry.aabb_max.xyz, entry.aabb_min.xyz, plane.xyz < vec3<f32>(0.0));
+        if (dot(plane.xyz, p) < -plane.w) {
+            return false;
+        }
+    }
+
+    let offset = entry.position.xyz - cull.camera.xyz;
+    return dot(offset, offset) < cull.camera.w;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let idx = id.x;
+    if (idx >= cull.count) {
+        return;
+    }
+
+    let entry = entries[idx];
+    let base = idx * 5u;
+    draws[base] = entry.index_count;
+    draws[base + 1u] = select(0u, 1u, is_visible(entry));
+    draws[base + 2u] = entry.first_index;
+    draws[base + 3u] = bitcast<u32>(entry.base_vertex);
+    draws[base + 4u] = 0u;
+}
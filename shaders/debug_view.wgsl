@@ -0,0 +1,35 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+};
+
+struct DebugUniform {
+    view: u32,
+    width: u32,
+}
+
+@group(0)@binding(2)
+var<uniform> debug: DebugUniform;
+@group(0)@binding(3)
+var<storage, read_write> overdraw: array<atomic<u32>>;
+
+fn heatmap(value: f32) -> vec3<f32> {
+    let t = clamp(value, 0.0, 1.0);
+    return vec3<f32>(smoothstep(0.5, 1.0, t), sin(t * 3.14159), smoothstep(0.5, 0.0, t));
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let pixel = u32(in.clip_position.y) * debug.width + u32(in.clip_position.x);
+    let count = atomicLoad(&overdraw[pixel]);
+
+    return vec4<f32>(heatmap(f32(count) / 8.0), 1.0);
+}
@@ -0,0 +1,101 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(3) normal: vec3<f32>,
+    @location(4) tangent: vec4<f32>,
+}
+
+struct InstanceInput {
+    // The w is the falling speed.
+    @location(5) position: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+};
+
+struct TimeUniform {
+    elapsed: f32,
+    delta: f32,
+    tick_alpha: f32,
+    tick_length: f32,
+}
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient_strength: f32,
+}
+
+struct EnvironmentUniform {
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    ambient_color: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_range: vec4<f32>,
+    // Sun angle, moon angle, star visibility and sun elevation.
+    celestial: vec4<f32>,
+    // Cloud coverage, the wind offset of the clouds and their altitude.
+    clouds: vec4<f32>,
+}
+
+@group(1)@binding(0)
+var<uniform> camera: CameraUniform;
+@group(1)@binding(1)
+var<uniform> time: TimeUniform;
+@group(1)@binding(4)
+var<uniform> environment: EnvironmentUniform;
+
+@group(0)@binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0)@binding(1)
+var s_diffuse: sampler;
+@group(0)@binding(2)
+var t_normal: texture_2d<f32>;
+@group(0)@binding(3)
+var s_normal: sampler;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let distance = length(world_position - camera.view_pos.xyz);
+    let linear = smoothstep(environment.fog_range.x, environment.fog_range.y, distance);
+    let haze = 1.0 - exp(-pow(distance * environment.fog_color.w, 2.0));
+    return mix(color, environment.fog_color.rgb, clamp(max(linear, haze), 0.0, 1.0));
+}
+
+// The normal map sample moved from tangent space to world space.
+fn mapped_normal(in: VertexOutput) -> vec3<f32> {
+    let normal = normalize(in.normal);
+    let tangent = normalize(in.tangent.xyz - normal * dot(normal, in.tangent.xyz));
+    let bitangent = cross(normal, tangent) * in.tangent.w;
+    let sample = textureSample(t_normal, s_normal, in.tex_coords).xyz * 2.0 - 1.0;
+    return normalize(mat3x3<f32>(tangent, bitangent, normal) * sample);
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    // Drawn between the last two ticks, the block fell speed * tick length on the last one.
+    let fallen = instance.position.w * time.tick_length * (1.0 - time.tick_alpha);
+    let world_position = model.position * 0.5 + instance.position.xyz + vec3<f32>(0.0, fallen, 0.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position;
+    out.normal = model.normal;
+    out.tangent = model.tangent;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let normal = mapped_normal(in);
+    let diffuse = max(dot(normal, environment.sun_direction.xyz), 0.0) * environment.sun_direction.w;
+    let light = environment.ambient_color.rgb + environment.sun_color.rgb * diffuse;
+
+    return vec4<f32>(apply_fog(object_color.xyz * light, in.world_position), 1.0);
+}
@@ -0,0 +1,115 @@
+struct InstanceInput {
+    // The center of the cell the decoration takes, w is its kind.
+    @location(5) position: vec4<f32>,
+    @location(6) tint: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) @interpolate(flat) kind: u32,
+    @location(3) tint: vec3<f32>,
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient_strength: f32,
+}
+
+struct TimeUniform {
+    elapsed: f32,
+    delta: f32,
+    tick_alpha: f32,
+    tick_length: f32,
+}
+
+struct EnvironmentUniform {
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    ambient_color: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_range: vec4<f32>,
+    // Sun angle, moon angle, star visibility and sun elevation.
+    celestial: vec4<f32>,
+    // Cloud coverage, the wind offset of the clouds and their altitude.
+    clouds: vec4<f32>,
+}
+
+@group(0)@binding(0)
+var<uniform> camera: CameraUniform;
+@group(0)@binding(1)
+var<uniform> time: TimeUniform;
+@group(0)@binding(4)
+var<uniform> environment: EnvironmentUniform;
+
+const GRASS: u32 = 0u;
+const HEIGHT: f32 = 0.8;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let distance = length(world_position - camera.view_pos.xyz);
+    let linear = smoothstep(environment.fog_range.x, environment.fog_range.y, distance);
+    let haze = 1.0 - exp(-pow(distance * environment.fog_color.w, 2.0));
+    return mix(color, environment.fog_color.rgb, clamp(max(linear, haze), 0.0, 1.0));
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, instance: InstanceInput) -> VertexOutput {
+    // Six vertices per quad, the odd quads mirrored so they face the other way.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 0.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 1.0), vec2<f32>(0.0, 1.0),
+    );
+    let quad = index / 6u;
+    let uv = corners[index % 6u];
+    var u = uv.x;
+    if (quad % 2u == 1u) {
+        u = 1.0 - u;
+    }
+    var axis = vec3<f32>(0.7071, 0.0, 0.7071);
+    if (quad >= 2u) {
+        axis = vec3<f32>(0.7071, 0.0, -0.7071);
+    }
+
+    let base = instance.position.xyz - vec3<f32>(0.0, 0.5, 0.0);
+    var world_position = base + axis * (u - 0.5) + vec3<f32>(0.0, uv.y * HEIGHT, 0.0);
+    // The tops sway in the wind, out of phase between neighbours.
+    let phase = dot(instance.position.xz, vec2<f32>(0.7, 1.3));
+    world_position.x += sin(time.elapsed * 1.7 + phase) * 0.06 * uv.y;
+    world_position.z += cos(time.elapsed * 1.3 + phase) * 0.04 * uv.y;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.uv = vec2<f32>(u, uv.y);
+    out.world_position = world_position;
+    out.kind = u32(instance.position.w);
+    out.tint = instance.tint.rgb;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let green = in.tint * mix(0.6, 1.0, in.uv.y);
+    var color = green;
+    if (in.kind == GRASS) {
+        // Three blades narrowing to a point.
+        let blade = 1.0 - abs(fract(in.uv.x * 3.0) - 0.5) * 2.0;
+        if (in.uv.y > blade) {
+            discard;
+        }
+    } else {
+        // A stem with a round head on top.
+        let head = distance(in.uv, vec2<f32>(0.5, 0.75)) < 0.2;
+        let stem = abs(in.uv.x - 0.5) < 0.05 && in.uv.y < 0.75;
+        if (!head && !stem) {
+            discard;
+        }
+        if (head) {
+            color = vec3<f32>(0.9, 0.3, 0.35);
+        }
+    }
+
+    let light = environment.ambient_color.rgb + environment.sun_color.rgb * environment.sun_direction.w;
+    return vec4<f32>(apply_fog(color * light, in.world_position), 1.0);
+}
@@ -0,0 +1,90 @@
+struct InstanceInput {
+    // The w is the size.
+    @location(0) position: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+    @location(2) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) world_position: vec3<f32>,
+};
+
+struct TimeUniform {
+    elapsed: f32,
+    delta: f32,
+    tick_alpha: f32,
+    tick_length: f32,
+}
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient_strength: f32,
+}
+
+struct EnvironmentUniform {
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    ambient_color: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_range: vec4<f32>,
+    celestial: vec4<f32>,
+    clouds: vec4<f32>,
+}
+
+@group(0)@binding(0)
+var<uniform> camera: CameraUniform;
+@group(0)@binding(1)
+var<uniform> time: TimeUniform;
+@group(0)@binding(4)
+var<uniform> environment: EnvironmentUniform;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let distance = length(world_position - camera.view_pos.xyz);
+    let linear = smoothstep(environment.fog_range.x, environment.fog_range.y, distance);
+    let haze = 1.0 - exp(-pow(distance * environment.fog_color.w, 2.0));
+    return mix(color, environment.fog_color.rgb, clamp(max(linear, haze), 0.0, 1.0));
+}
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Particles move on ticks, in between they carry on with their velocity.
+    let center = instance.position.xyz + instance.velocity.xyz * time.tick_alpha * time.tick_length;
+    let forward = normalize(camera.view_pos.xyz - center);
+    var helper = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(forward.y) > 0.99) {
+        helper = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    // Counter clockwise as seen from the camera, the pipeline culls back faces.
+    let right = normalize(cross(helper, forward));
+    let up = cross(forward, right);
+
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[index];
+    let world_position = center + (right * corner.x + up * corner.y) * instance.position.w;
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.color = instance.color;
+    out.world_position = world_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let light = environment.ambient_color.rgb
+        + environment.sun_color.rgb * max(environment.sun_direction.y, 0.0) * environment.sun_direction.w;
+    return vec4<f32>(apply_fog(in.color.rgb * light, in.world_position), in.color.a);
+}
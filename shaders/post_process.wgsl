@@ -0,0 +1,107 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+struct PostProcessUniform {
+    exposure: f32,
+    tonemapping: u32,
+    fxaa: u32,
+    vignette: f32,
+    texel_size: vec2<f32>,
+}
+
+@group(0)@binding(0)
+var t_scene: texture_2d<f32>;
+@group(0)@binding(1)
+var s_scene: sampler;
+@group(0)@binding(2)
+var<uniform> settings: PostProcessUniform;
+
+const TONEMAPPING_REINHARD: u32 = 1u;
+const TONEMAPPING_ACES: u32 = 2u;
+
+const FXAA_SPAN_MAX: f32 = 8.0;
+const FXAA_REDUCE_MUL: f32 = 0.125;
+const FXAA_REDUCE_MIN: f32 = 0.0078125;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(uv.x, 1.0 - uv.y);
+    return out;
+}
+
+fn tonemap(color: vec3<f32>) -> vec3<f32> {
+    let exposed = color * settings.exposure;
+    switch settings.tonemapping {
+        case TONEMAPPING_REINHARD: {
+            return exposed / (1.0 + exposed);
+        }
+        case TONEMAPPING_ACES: {
+            // Narkowicz's fit of the ACES filmic curve.
+            let a = exposed * (2.51 * exposed + 0.03);
+            let b = exposed * (2.43 * exposed + 0.59) + 0.14;
+            return clamp(a / b, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+        default: {
+            return clamp(exposed, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+}
+
+fn sample_tonemapped(uv: vec2<f32>) -> vec3<f32> {
+    return tonemap(textureSampleLevel(t_scene, s_scene, uv, 0.0).rgb);
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+// FXAA runs on the tonemapped colors so edges are detected on what ends up on screen.
+fn fxaa(uv: vec2<f32>) -> vec3<f32> {
+    let texel = settings.texel_size;
+    let rgb_m = sample_tonemapped(uv);
+    let luma_nw = luma(sample_tonemapped(uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(sample_tonemapped(uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(sample_tonemapped(uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(sample_tonemapped(uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_m = luma(rgb_m);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL, FXAA_REDUCE_MIN);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-FXAA_SPAN_MAX), vec2<f32>(FXAA_SPAN_MAX)) * texel;
+
+    let rgb_a = 0.5 * (sample_tonemapped(uv + dir * (1.0 / 3.0 - 0.5)) + sample_tonemapped(uv + dir * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5 + 0.25 * (sample_tonemapped(uv - dir * 0.5) + sample_tonemapped(uv + dir * 0.5));
+    let luma_b = luma(rgb_b);
+
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return rgb_a;
+    }
+    return rgb_b;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var color: vec3<f32>;
+    if (settings.fxaa != 0u) {
+        color = fxaa(in.uv);
+    } else {
+        color = sample_tonemapped(in.uv);
+    }
+
+    let vignette = 1.0 - settings.vignette * smoothstep(0.3, 0.8, distance(in.uv, vec2<f32>(0.5)));
+
+    return vec4<f32>(color * vignette, 1.0);
+}
@@ -0,0 +1,141 @@
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient_strength: f32,
+}
+
+struct SkyUniform {
+    inv_view_proj: mat4x4<f32>,
+    top_color: vec4<f32>,
+    horizon_color: vec4<f32>,
+    ground_color: vec4<f32>,
+    sun_direction: vec4<f32>,
+    moon_direction: vec4<f32>,
+    // x is how visible the stars are, y how far the night sky turned.
+    stars: vec4<f32>,
+    // Coverage, wind offset and altitude of the cloud layer.
+    clouds: vec4<f32>,
+    // x is the depth the sky is drawn at, right in front of the far plane.
+    depth: vec4<f32>,
+}
+
+@group(0)@binding(0)
+var<uniform> camera: CameraUniform;
+
+@group(1)@binding(0)
+var<uniform> sky: SkyUniform;
+
+fn hash(cell: vec3<f32>) -> f32 {
+    return fract(sin(dot(cell, vec3<f32>(12.9898, 78.233, 37.719))) * 43758.5453);
+}
+
+fn star_field(direction: vec3<f32>) -> f32 {
+    // Turn with the sun and moon, around the same axis.
+    let angle = -sky.stars.y;
+    let rotated = vec3<f32>(
+        direction.x * cos(angle) - direction.y * sin(angle),
+        direction.x * sin(angle) + direction.y * cos(angle),
+        direction.z,
+    );
+    let scaled = rotated * 150.0;
+    let cell = floor(scaled);
+    let star = hash(cell);
+    if (star < 0.996) {
+        return 0.0;
+    }
+    let offset = fract(scaled) - 0.5;
+    let brightness = (star - 0.996) / 0.004;
+    return brightness * (1.0 - smoothstep(0.1, 0.4, length(offset)));
+}
+
+const CLOUD_SCALE: f32 = 48.0;
+const CLOUD_PERIOD: f32 = 64.0;
+
+fn cloud_hash(cell: vec2<f32>) -> f32 {
+    // Wrapped so the clouds repeat where the wind offset wraps.
+    let wrapped = cell - floor(cell / CLOUD_PERIOD) * CLOUD_PERIOD;
+    return fract(sin(dot(wrapped, vec2<f32>(127.1, 311.7))) * 43758.5453);
+}
+
+fn cloud_noise(position: vec2<f32>) -> f32 {
+    let cell = floor(position);
+    let f = fract(position);
+    let u = f * f * (3.0 - 2.0 * f);
+    return mix(
+        mix(cloud_hash(cell), cloud_hash(cell + vec2<f32>(1.0, 0.0)), u.x),
+        mix(cloud_hash(cell + vec2<f32>(0.0, 1.0)), cloud_hash(cell + vec2<f32>(1.0, 1.0)), u.x),
+        u.y,
+    );
+}
+
+// How thick the clouds are above `xz`, from 0.0 to 1.0.
+fn cloud_density(xz: vec2<f32>, clouds: vec4<f32>) -> f32 {
+    if (clouds.x <= 0.0) {
+        return 0.0;
+    }
+    let p = (xz + clouds.yz) / CLOUD_SCALE;
+    let n = (cloud_noise(p) * 0.5 + cloud_noise(p * 2.0) * 0.25 + cloud_noise(p * 4.0) * 0.125) / 0.875;
+    return smoothstep(1.0 - clouds.x, 1.2 - clouds.x, n);
+}
+
+// Clouds where the view ray crosses the cloud layer, shaded by a second sample
+// toward the sun so their undersides come out darker. rgb is the color, a how
+// much of the sky they hide.
+fn cloud_layer(direction: vec3<f32>) -> vec4<f32> {
+    let height = sky.clouds.w - camera.view_pos.y;
+    if (direction.y <= 0.0 || height <= 0.0) {
+        return vec4<f32>(0.0);
+    }
+    let t = height / direction.y;
+    let xz = camera.view_pos.xz + direction.xz * t;
+    let density = cloud_density(xz, sky.clouds);
+    if (density <= 0.0) {
+        return vec4<f32>(0.0);
+    }
+
+    let toward_sun = cloud_density(xz + sky.sun_direction.xz * 8.0, sky.clouds);
+    let daylight = smoothstep(-0.1, 0.3, sky.sun_direction.y);
+    let lit = mix(sky.horizon_color.rgb * 0.6, vec3<f32>(1.0), daylight);
+    let color = lit * (1.0 - toward_sun * 0.35);
+    // Thin out toward the horizon where the layer is far away.
+    let fade = smoothstep(0.0, 0.15, direction.y);
+    return vec4<f32>(color, density * fade);
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    // Keep the sky just in front of the far plane so any geometry drawn later covers it.
+    out.clip_position = vec4<f32>(ndc, sky.depth.x, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let world = sky.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = normalize(world.xyz / world.w - camera.view_pos.xyz);
+
+    let height = direction.y;
+    var color: vec3<f32>;
+    if (height >= 0.0) {
+        color = mix(sky.horizon_color.rgb, sky.top_color.rgb, pow(height, 0.6));
+        if (sky.stars.x > 0.0) {
+            color += vec3<f32>(star_field(direction) * sky.stars.x * smoothstep(0.0, 0.2, height));
+        }
+        let clouds = cloud_layer(direction);
+        color = mix(color, clouds.rgb, clouds.a);
+    } else {
+        color = mix(sky.horizon_color.rgb, sky.ground_color.rgb, pow(-height, 0.4));
+    }
+
+    return vec4<f32>(color, 1.0);
+}
@@ -0,0 +1,92 @@
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+}
+
+struct InstanceInput {
+    // w is the height of the water surface inside the block.
+    @location(5) position: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+};
+
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    ambient_strength: f32,
+}
+
+struct TimeUniform {
+    elapsed: f32,
+    delta: f32,
+    tick_alpha: f32,
+    tick_length: f32,
+}
+
+struct EnvironmentUniform {
+    sun_direction: vec4<f32>,
+    sun_color: vec4<f32>,
+    ambient_color: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_range: vec4<f32>,
+    // Sun angle, moon angle, star visibility and sun elevation.
+    celestial: vec4<f32>,
+    // Cloud coverage, the wind offset of the clouds and their altitude.
+    clouds: vec4<f32>,
+}
+
+@group(1)@binding(0)
+var<uniform> camera: CameraUniform;
+@group(1)@binding(1)
+var<uniform> time: TimeUniform;
+@group(1)@binding(4)
+var<uniform> environment: EnvironmentUniform;
+
+@group(0)@binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(0)@binding(1)
+var s_diffuse: sampler;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let distance = length(world_position - camera.view_pos.xyz);
+    let linear = smoothstep(environment.fog_range.x, environment.fog_range.y, distance);
+    let haze = 1.0 - exp(-pow(distance * environment.fog_color.w, 2.0));
+    return mix(color, environment.fog_color.rgb, clamp(max(linear, haze), 0.0, 1.0));
+}
+
+@vertex
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
+    let scale = 0.5;
+
+    var world_position = vec4<f32>(model.position * scale, 1.0) + vec4<f32>(instance.position.xyz, 0.0);
+
+    // Only the top face of the water block moves, the bottom stays attached to the block below.
+    if (model.position.y > 0.0) {
+        let wave = sin(world_position.x * 0.8 + time.elapsed * 2.0) * cos(world_position.z * 0.6 + time.elapsed * 1.5);
+        world_position.y += wave * 0.08 - 0.1 - (1.0 - instance.position.w);
+    }
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let object_color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let water_color = vec3<f32>(0.1, 0.35, 0.6);
+
+    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
+    let fresnel = pow(1.0 - clamp(dot(view_dir, vec3<f32>(0.0, 1.0, 0.0)), 0.0, 1.0), 3.0);
+
+    let light = environment.ambient_color.rgb + environment.sun_color.rgb * environment.sun_direction.w;
+    let color = mix(object_color.xyz * water_color, water_color, 0.7) * light;
+    let alpha = mix(0.45, 0.9, fresnel);
+    return vec4<f32>(apply_fog(color, in.world_position), alpha);
+}
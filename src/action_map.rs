@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use winit::event::MouseButton;
+use winit::keyboard::KeyCode;
+
+pub const MOVE_FORWARD: &str = "move_forward";
+pub const MOVE_BACKWARD: &str = "move_backward";
+pub const MOVE_LEFT: &str = "move_left";
+pub const MOVE_RIGHT: &str = "move_right";
+pub const MOVE_UP: &str = "move_up";
+pub const MOVE_DOWN: &str = "move_down";
+pub const TOGGLE_CAMERA: &str = "toggle_camera";
+pub const TOGGLE_CONSOLE: &str = "toggle_console";
+
+// Names used for the keys in binding files.
+const KEY_NAMES: [(&str, KeyCode); 66] = [
+    ("A", KeyCode::KeyA),
+    ("B", KeyCode::KeyB),
+    ("C", KeyCode::KeyC),
+    ("D", KeyCode::KeyD),
+    ("E", KeyCode::KeyE),
+    ("F", KeyCode::KeyF),
+    ("G", KeyCode::KeyG),
+    ("H", KeyCode::KeyH),
+    ("I", KeyCode::KeyI),
+    ("J", KeyCode::KeyJ),
+    ("K", KeyCode::KeyK),
+    ("L", KeyCode::KeyL),
+    ("M", KeyCode::KeyM),
+    ("N", KeyCode::KeyN),
+    ("O", KeyCode::KeyO),
+    ("P", KeyCode::KeyP),
+    ("Q", KeyCode::KeyQ),
+    ("R", KeyCode::KeyR),
+    ("S", KeyCode::KeyS),
+    ("T", KeyCode::KeyT),
+    ("U", KeyCode::KeyU),
+    ("V", KeyCode::KeyV),
+    ("W", KeyCode::KeyW),
+    ("X", KeyCode::KeyX),
+    ("Y", KeyCode::KeyY),
+    ("Z", KeyCode::KeyZ),
+    ("0", KeyCode::Digit0),
+    ("1", KeyCode::Digit1),
+    ("2", KeyCode::Digit2),
+    ("3", KeyCode::Digit3),
+    ("4", KeyCode::Digit4),
+    ("5", KeyCode::Digit5),
+    ("6", KeyCode::Digit6),
+    ("7", KeyCode::Digit7),
+    ("8", KeyCode::Digit8),
+    ("9", KeyCode::Digit9),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+    ("Up", KeyCode::ArrowUp),
+    ("Down", KeyCode::ArrowDown),
+    ("Left", KeyCode::ArrowLeft),
+    ("Right", KeyCode::ArrowRight),
+    ("Space", KeyCode::Space),
+    ("Enter", KeyCode::Enter),
+    ("Escape", KeyCode::Escape),
+    ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Backspace),
+    ("Backquote", KeyCode::Backquote),
+    ("LeftShift", KeyCode::ShiftLeft),
+    ("RightShift", KeyCode::ShiftRight),
+    ("LeftControl", KeyCode::ControlLeft),
+    ("RightControl", KeyCode::ControlRight),
+    ("LeftAlt", KeyCode::AltLeft),
+    ("RightAlt", KeyCode::AltRight),
+    ("Delete", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+];
+
+const BUTTON_NAMES: [(&str, MouseButton); 5] = [
+    ("MouseLeft", MouseButton::Left),
+    ("MouseRight", MouseButton::Right),
+    ("MouseMiddle", MouseButton::Middle),
+    ("MouseBack", MouseButton::Back),
+    ("MouseForward", MouseButton::Forward),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl From<KeyCode> for Binding {
+    fn from(key: KeyCode) -> Self {
+        Binding::Key(key)
+    }
+}
+
+impl From<MouseButton> for Binding {
+    fn from(button: MouseButton) -> Self {
+        Binding::Mouse(button)
+    }
+}
+
+// Other mouse buttons are written as `MouseN`.
+pub fn binding_name(binding: Binding) -> Option<String> {
+    match binding {
+        Binding::Key(key) => KEY_NAMES
+            .iter()
+            .find(|(_, code)| *code == key)
+            .map(|(name, _)| name.to_string()),
+        Binding::Mouse(MouseButton::Other(n)) => Some(format!("Mouse{n}")),
+        Binding::Mouse(button) => BUTTON_NAMES
+            .iter()
+            .find(|(_, b)| *b == button)
+            .map(|(name, _)| name.to_string()),
+    }
+}
+
+pub fn binding_from_name(name: &str) -> Option<Binding> {
+    let key = KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|(_, code)| Binding::Key(*code));
+    let button = || {
+        BUTTON_NAMES
+            .iter()
+            .find(|(button_name, _)| button_name.eq_ignore_ascii_case(name))
+            .map(|(_, button)| Binding::Mouse(*button))
+    };
+    let other = || {
+        name.strip_prefix("Mouse")
+            .and_then(|n| n.parse().ok())
+            .map(|n| Binding::Mouse(MouseButton::Other(n)))
+    };
+
+    key.or_else(button).or_else(other)
+}
+
+// Named actions bound to physical keys and mouse buttons, so they stay in the
+// same place on every keyboard layout and can be rebound without touching the
+// actors reading them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ActionMap {
+    bindings: HashMap<String, Vec<Binding>>,
+}
+
+impl ActionMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_defaults() -> Self {
+        let mut map = Self::new();
+        map.bind(MOVE_FORWARD, KeyCode::KeyW);
+        map.bind(MOVE_BACKWARD, KeyCode::KeyS);
+        map.bind(MOVE_LEFT, KeyCode::KeyA);
+        map.bind(MOVE_RIGHT, KeyCode::KeyD);
+        map.bind(MOVE_UP, KeyCode::Space);
+        map.bind(MOVE_DOWN, KeyCode::ShiftLeft);
+        map.bind(TOGGLE_CAMERA, KeyCode::F5);
+        map.bind(TOGGLE_CONSOLE, KeyCode::Backquote);
+        map
+    }
+
+    pub fn bind<B: Into<Binding>>(&mut self, action: &str, binding: B) {
+        let binding = binding.into();
+        let bindings = self.bindings.entry(action.to_string()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind<B: Into<Binding>>(&mut self, action: &str, binding: B) {
+        let binding = binding.into();
+        if let Some(bindings) = self.bindings.get_mut(action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    pub fn set_bindings(&mut self, action: &str, bindings: Vec<Binding>) {
+        self.bindings.insert(action.to_string(), bindings);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.bindings.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn actions(&self) -> impl Iterator<Item = &str> {
+        self.bindings.keys().map(String::as_str)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_config())?;
+        Ok(())
+    }
+
+    // One `action = Key, Key` line per action, sorted so saved files diff cleanly.
+    pub fn to_config(&self) -> String {
+        let mut actions = self.bindings.iter().collect::<Vec<_>>();
+        actions.sort_by_key(|(action, _)| action.as_str());
+
+        let mut data = String::new();
+        for (action, bindings) in actions {
+            let names = bindings
+                .iter()
+                .filter_map(|binding| binding_name(*binding))
+                .collect::<Vec<_>>();
+            data.push_str(&format!("{action} = {}\n", names.join(", ")));
+        }
+
+        data
+    }
+
+    // Actions missing from the file keep their current bindings.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.load_config(&fs::read_to_string(path)?)
+    }
+
+    pub fn load_config(&mut self, data: &str) -> Result<()> {
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (action, names) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected `action = bindings`", i + 1))?;
+            let bindings = names
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| {
+                    binding_from_name(name)
+                        .ok_or_else(|| anyhow!("line {}: unknown binding `{name}`", i + 1))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            self.set_bindings(action.trim(), bindings);
+        }
+
+        Ok(())
+    }
+}
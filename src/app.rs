@@ -1,47 +1,127 @@
+use crate::action_map::ActionMap;
+use crate::asset_cache::AssetCache;
+use crate::batching::{BatchKey, BatchSource, GeometryBatch};
+use crate::bind_group_cache::{BindGroupCache, BindGroupCacheStats};
+use crate::block_outline::BlockOutline;
+use crate::buffer_pool::{BufferAllocation, BufferPool};
 use crate::camera::{Camera, CameraUniform, Projection};
+use crate::capabilities::{AdapterRequest, Capabilities, Capability, GpuInfo};
+use crate::chunks::Chunk;
 use crate::command_buffer::{
-    CommandBuffer, NCommandRender, NCommandSetup, NCommandUpdate, NResource,
+    CommandBuffer, GlobalResource, Index, NCommandRender, NCommandSetup, NCommandUpdate, NResource,
+    RenderLayer, ID,
+};
+use crate::debug::{DebugFlag, DebugKeys, DebugUniform, DebugView};
+use crate::debug_draw::DebugDraw;
+use crate::dimension::{Dimension, DimensionSettings};
+#[cfg(feature = "egui")]
+use crate::egui_layer::{EguiLayer, UiActor};
+use crate::environment::{EnvironmentUniform, TimeOfDay};
+use crate::error::EngineError;
+use crate::fonts::FontSettings;
+use crate::frame_graph::{
+    FrameGraphBuilder, FrameTargets, GlobalBindGroups, PassStage, RenderPassProvider,
 };
-use crate::create_render_pipeline;
 use crate::frustum::{Aabb, FrustumCuller};
+use crate::gpu_culling::{CullEntry, GpuCuller};
+use crate::handle::{Handle, HandleMap, HandleRef};
 use crate::input::InputState;
-use crate::model::{DrawModel, ModelVertex, Vertex};
-use crate::resource::load_model;
+use crate::lighting::{self, for_each_copy, LightChannel, LightMap};
+use crate::logging;
+use crate::mesher::CHUNK_SIZE;
+use crate::model::{DrawModel, ModelVertex, ObjModel, Vertex};
+use crate::particles::{ParticleEmitter, Particles};
+use crate::physics::{raycast_grid, BlockQuery};
+use crate::pipeline_cache::{PipelineCache, PipelineCacheStats, PipelineKey};
+use crate::post_process::{PostProcess, PostProcessSettings, HDR_FORMAT};
+use crate::profiler::{FrameStage, GpuTimer, Profiler, RenderStats};
+use crate::registry::{block_info, BehaviorRegistry};
+use crate::repro::{log_tail, ReproBundle};
+use crate::resource::{load_model, load_texture};
+use crate::seed::WorldSeed;
+use crate::settings::GraphicsSettings;
+use crate::sky::Sky;
+use crate::structures::BlockAccess;
+use crate::text::LabelId;
 use crate::texture::Texture;
+use crate::texture_streaming::{StreamedTextureId, TextureStreamStats, TextureStreamer};
+use crate::time::TimeUniform;
+use crate::transform::TransformHierarchy;
+use crate::ui::{Anchor, Component, ComponentId, Crosshair, Ui, UiRootId, Widget};
+use crate::vfs::Vfs;
+use crate::viewport::{Viewport, ViewportHandle, ViewportRect};
+use crate::weather::Weather;
+use crate::workers::{WorkerConfig, WorkerCounts, WorkerPools};
+use crate::world::{RaycastHit, World};
+use crate::world_edit::split_position;
+use crate::world_view::WorldView;
+use crate::worldgen::WorldGenerator;
+use crate::{create_render_pipeline, depth_clear_value, PipelineDesc};
+use anyhow::{anyhow, Result};
 use bytemuck::cast_slice;
-use glam::{Mat4, Vec3A};
-use glyphon::{
-    Attrs, Family, FontSystem, Metrics, Resolution, Shaping, SwashCache, TextArea, TextAtlas,
-    TextBounds, TextRenderer,
-};
+use glam::{IVec3, Mat4, UVec3, Vec2, Vec3, Vec3A};
+use glyphon::{Metrics, TextBounds};
+use image::RgbaImage;
+#[cfg(not(target_arch = "wasm32"))]
 use rayon::prelude::*;
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::iter;
+use std::mem;
+use std::mem::size_of;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
-use std::slice::Iter;
-use std::sync::Arc;
+use std::slice;
+use std::slice::{Iter, IterMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
 use std::time::Duration;
 use uuid::Uuid;
-use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use web_time::{Instant, SystemTime, UNIX_EPOCH};
+use wgpu::util::{BufferInitDescriptor, DeviceExt, RenderEncoder};
 use wgpu::{
-    Backends, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
-    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, Buffer, BufferBindingType,
-    BufferUsages, Color, CommandEncoderDescriptor, CompareFunction, DepthStencilState, Device,
-    Features, InstanceDescriptor, Limits, LoadOp, MultisampleState, Operations,
-    PipelineLayoutDescriptor, PowerPreference, PresentMode, Queue, RenderPass,
-    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
-    RenderPipeline, RequestAdapterOptions, SamplerBindingType, ShaderModuleDescriptor,
-    ShaderSource, ShaderStages, StoreOp, Surface, SurfaceConfiguration, TextureFormat,
-    TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+    Adapter, AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
+    BufferAddress, BufferBindingType, BufferDescriptor, BufferSlice, BufferUsages, CommandEncoder,
+    CommandEncoderDescriptor, CompositeAlphaMode, Device, DeviceLostReason, Extent3d, FilterMode,
+    ImageCopyBuffer, ImageDataLayout, Instance, InstanceDescriptor, LoadOp, Maintain, MapMode,
+    Operations, PipelineLayoutDescriptor, PolygonMode, PresentMode, PushConstantRange, Queue,
+    RenderBundle, RenderBundleDepthStencil, RenderBundleDescriptor, RenderBundleEncoderDescriptor,
+    RenderPass, RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+    RenderPipeline, Sampler, SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages, StoreOp, Surface, SurfaceConfiguration, TextureDescriptor,
+    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+    TextureViewDescriptor, TextureViewDimension, VertexAttribute, VertexStepMode,
+    COPY_BYTES_PER_ROW_ALIGNMENT,
 };
 use winit::dpi::PhysicalSize;
 use winit::event::WindowEvent;
-use winit::window::Window;
+use winit::window::{CursorGrabMode, Fullscreen, Window};
+
+// Default length of a tick, App::set_tick_rate changes it.
+pub const FIXED_TIMESTEP: f32 = 1.0 / 20.0;
+// Chunks streamed in per frame while moving, the rest follow on the next frames.
+pub const CHUNK_LOADS_PER_FRAME: usize = 8;
+// Dirty models set up again per frame, the rest wait for the next ones.
+pub const REMESHES_PER_FRAME: usize = 4;
+// Ticks past this many in a single frame are dropped instead of piling up.
+const MAX_TICKS_PER_FRAME: u32 = 5;
+// How far an orbiting camera stays from the blocks between it and its target.
+const ORBIT_CAMERA_MARGIN: f32 = 0.2;
+// Seconds between refreshes of the profiler overlay, so it stays readable.
+const PROFILER_REFRESH: f32 = 0.1;
+// How far away blocks can be targeted from the camera.
+pub const BLOCK_REACH: f32 = 6.0;
 
 pub trait Actor {
     fn id(&self) -> &Uuid;
-    fn update(&mut self, dt: &Duration, input_state: &InputState) -> CommandBuffer<NCommandUpdate>;
+    fn update(
+        &mut self,
+        dt: &Duration,
+        input_state: &InputState,
+        world: &WorldView,
+    ) -> CommandBuffer<NCommandUpdate>;
 }
 
 pub trait Model {
@@ -50,34 +130,126 @@ pub trait Model {
     fn position(&self) -> &Vec3A;
     fn setup(&self) -> CommandBuffer<NCommandSetup>;
     fn render(&self) -> CommandBuffer<NCommandRender>;
+
+    // True when render keeps returning the same commands until the next setup,
+    // the app then keeps them around instead of asking every frame.
+    fn static_render(&self) -> bool {
+        false
+    }
+
+    // Runs on every fixed tick, `dt` being the tick length in seconds.
+    fn tick(&mut self, _dt: f32) -> CommandBuffer<NCommandUpdate> {
+        CommandBuffer::new()
+    }
+
+    // State to write to disk when the model gets unloaded, None if it isn't saved.
+    fn save(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    // Block id at a position inside the model, for models made of blocks.
+    fn block(&self, _position: UVec3) -> Option<u16> {
+        None
+    }
+
+    // Changes the block there, None clears it. False if the model has no blocks.
+    fn set_block(&mut self, _position: UVec3, _id: Option<u16>) -> bool {
+        false
+    }
+
+    // Light of a cell inside the model, for models made of blocks.
+    fn light_level(&self, _position: UVec3, _channel: LightChannel) -> u8 {
+        0
+    }
+
+    // Positions one cell outside of the model keep the light of the cells
+    // there, so the model's faces can be lit by what's in front of them.
+    fn set_light_level(&mut self, _position: IVec3, _channel: LightChannel, _level: u8) {}
+
+    // Height of the cell above the highest block in a column, 0 for empty ones.
+    fn height(&self, _x: u32, _z: u32) -> u32 {
+        0
+    }
+
+    // Whether the structure decorators have run on it, only chunks ever get
+    // decorated.
+    fn decorated(&self) -> bool {
+        true
+    }
+
+    fn set_decorated(&mut self) {}
+
+    // Cells whose block changed since the last call, for the block ticks
+    // around them.
+    fn take_changed(&mut self) -> Vec<UVec3> {
+        vec![]
+    }
+
+    // Everything stored for a block, its id along with states like fluid
+    // levels, in the layout of chunks::Block.
+    fn block_state(&self, _position: UVec3) -> Option<u32> {
+        None
+    }
+
+    // Puts a block back in a state taken from block_state, as it was. Nothing
+    // around it wakes up, it's for models mirroring blocks simulated elsewhere.
+    fn set_block_state(&mut self, _position: UVec3, _state: Option<u32>) -> bool {
+        false
+    }
 }
 
+// The data is kept around for the batches, which copy it into their own buffers.
 pub struct NBuffer {
-    buffer: Buffer,
-    uniform: Rc<RefCell<Vec<u8>>>,
+    allocation: BufferAllocation,
+    data: Vec<u8>,
 }
 
 impl NBuffer {
-    pub fn new(buffer: Buffer, uniform: Rc<RefCell<Vec<u8>>>) -> Self {
-        Self { buffer, uniform }
+    pub fn new(allocation: BufferAllocation, data: Vec<u8>) -> Self {
+        Self { allocation, data }
     }
 
     pub fn buffer(&self) -> &Buffer {
-        &self.buffer
+        self.allocation.buffer()
+    }
+
+    pub fn slice(&self) -> BufferSlice<'_> {
+        self.allocation.slice()
+    }
+
+    pub fn binding(&self) -> BindingResource<'_> {
+        self.allocation.binding()
+    }
+
+    pub fn update(&mut self, device: &Device, pool: &mut BufferPool, data: Vec<u8>) {
+        pool.write(device, &self.allocation, &data);
+        self.data = data;
+    }
+
+    fn fits(&self, len: usize) -> bool {
+        len as BufferAddress <= self.allocation.capacity()
+    }
+
+    // Like update, but grows the buffer when the data doesn't fit. True if it had to.
+    pub fn replace(&mut self, device: &Device, pool: &mut BufferPool, data: Vec<u8>) -> bool {
+        let resized = pool.resize(device, &mut self.allocation, data.len());
+        self.update(device, pool, data);
+        resized
     }
 
-    pub fn update(&self, queue: &Queue) {
-        queue.write_buffer(&self.buffer, 0, &self.uniform.borrow());
+    pub fn data(&self) -> &[u8] {
+        &self.data
     }
 }
 
+// Shared with every model that asked for the same layout and resources.
 pub struct NBindGroup {
-    bind_group: BindGroup,
-    layout: BindGroupLayout,
+    bind_group: Arc<BindGroup>,
+    layout: Arc<BindGroupLayout>,
 }
 
 impl NBindGroup {
-    pub fn new(bind_group: BindGroup, layout: BindGroupLayout) -> Self {
+    pub fn new(bind_group: Arc<BindGroup>, layout: Arc<BindGroupLayout>) -> Self {
         Self { bind_group, layout }
     }
 
@@ -90,32 +262,306 @@ impl NBindGroup {
     }
 }
 
+// Per model transform uniform, written again whenever its world matrix changes.
+struct ModelTransform {
+    buffer: NBuffer,
+    bind_group: BindGroup,
+    world: Mat4,
+}
+
+// What a single setup command asks for, leaving out the contents of buffers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum SetupEntry {
+    Buffer(BufferUsages),
+    BindGroup(Vec<BindGroupLayoutEntry>, Vec<NResource>),
+    Pipeline {
+        bind_groups: Vec<Index>,
+        shader: &'static str,
+        layouts: Vec<(BufferAddress, VertexStepMode, Vec<VertexAttribute>)>,
+        use_model: bool,
+        layer: RenderLayer,
+        push_constant_ranges: Vec<PushConstantRange>,
+    },
+    SharePipeline(ID, Index),
+    Transform,
+    Bundle,
+}
+
+// Models passing `visible`, sorted so the ones sharing a pipeline get drawn
+// together. wgpu's types aren't Send on wasm32, so there it runs on this thread.
+#[cfg(not(target_arch = "wasm32"))]
+fn cull_models<F: Fn(&NModel) -> bool + Sync>(models: &[NModel], visible: F) -> Vec<&NModel> {
+    let mut models = models
+        .par_iter()
+        .filter(|model| visible(model))
+        .collect::<Vec<_>>();
+    models.par_sort_by_key(|model| model.pipeline_key());
+    models
+}
+
+#[cfg(target_arch = "wasm32")]
+fn cull_models<F: Fn(&NModel) -> bool>(models: &[NModel], visible: F) -> Vec<&NModel> {
+    let mut models = models
+        .iter()
+        .filter(|model| visible(model))
+        .collect::<Vec<_>>();
+    models.sort_by_key(|model| model.pipeline_key());
+    models
+}
+
+// Fails when a command points at something the model or the app doesn't have.
+fn check_commands<'a, I: IntoIterator<Item = &'a NCommandRender>>(
+    obj_models: &[ObjModel],
+    model: &NModel,
+    commands: I,
+) -> Result<(), EngineError> {
+    for command in commands {
+        match *command {
+            NCommandRender::SetPipeline(idx) => {
+                EngineError::check_index("pipeline", idx, model.pipelines.len())?
+            }
+            NCommandRender::SetVertexBuffer(_, idx) | NCommandRender::SetIndexBuffer(idx, _) => {
+                EngineError::check_index("buffer", idx, model.buffers.len())?
+            }
+            NCommandRender::SetBindGroup(_, idx) => {
+                EngineError::check_index("bind group", idx, model.bind_groups.len())?
+            }
+            NCommandRender::SetModelMaterial(_, model_idx, material_idx) => {
+                EngineError::check_index("registered model", model_idx, obj_models.len())?;
+                let materials = obj_models[model_idx].materials.len();
+                EngineError::check_index("material", material_idx, materials)?;
+            }
+            NCommandRender::DrawModelIndexed(idx, _, ref bind_groups) => {
+                EngineError::check_index("registered model", idx, obj_models.len())?;
+                for &i in bind_groups.iter() {
+                    EngineError::check_index("bind group", i, model.bind_groups.len())?;
+                }
+            }
+            NCommandRender::ExecuteBundle(idx) => {
+                EngineError::check_index("bundle", idx, model.bundles.len())?
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// The model's render commands for a frame. The ones a model makes anew every
+// frame get checked first, like its commands were after its setup.
+fn frame_layers<'a>(
+    obj_models: &[ObjModel],
+    model: &'a NModel,
+) -> Result<(RenderCommands<'a>, RenderCommands<'a>), EngineError> {
+    let (opaque, transparent) = model.render_layers();
+    if matches!(opaque, RenderCommands::Fresh(_)) {
+        check_commands(obj_models, model, opaque.iter().chain(transparent.iter()))?;
+    }
+
+    Ok((opaque, transparent))
+}
+
+// Streamed textures of the materials the commands draw with. Commands that
+// point at no registered model have none.
+fn streamed_textures<'a>(
+    obj_models: &'a [ObjModel],
+    commands: &'a [NCommandRender],
+) -> impl Iterator<Item = StreamedTextureId> + 'a {
+    commands.iter().flat_map(move |command| {
+        let materials = match *command {
+            NCommandRender::SetModelMaterial(_, idx, material) => obj_models
+                .get(idx)
+                .and_then(|model| model.materials.get(material..=material)),
+            NCommandRender::DrawModelIndexed(idx, _, _) => {
+                obj_models.get(idx).map(|model| &model.materials[..])
+            }
+            _ => None,
+        };
+        materials
+            .into_iter()
+            .flatten()
+            .filter_map(|material| material.streamed)
+    })
+}
+
+// Setups with the same signature can reuse each other's resources.
+fn setup_signature(commands: &[NCommandSetup]) -> Vec<SetupEntry> {
+    commands
+        .iter()
+        .map(|command| match command {
+            NCommandSetup::CreateBuffer(_, usage) => SetupEntry::Buffer(*usage),
+            NCommandSetup::CreateBindGroup(entries, resources) => {
+                SetupEntry::BindGroup(entries.clone(), resources.clone())
+            }
+            NCommandSetup::CreatePipeline(
+                bind_groups,
+                shader,
+                layouts,
+                use_model,
+                layer,
+                push_constant_ranges,
+            ) => SetupEntry::Pipeline {
+                bind_groups: bind_groups.clone(),
+                shader,
+                layouts: layouts
+                    .iter()
+                    .map(|layout| {
+                        (
+                            layout.array_stride,
+                            layout.step_mode,
+                            layout.attributes.to_vec(),
+                        )
+                    })
+                    .collect(),
+                use_model: *use_model,
+                layer: *layer,
+                push_constant_ranges: push_constant_ranges.clone(),
+            },
+            NCommandSetup::SharePipeline(id, idx) => SetupEntry::SharePipeline(*id, *idx),
+            NCommandSetup::CreateTransform(_) => SetupEntry::Transform,
+            NCommandSetup::CreateBundle(_) => SetupEntry::Bundle,
+        })
+        .collect()
+}
+
+// Recorded again from its commands whenever what it captured goes away, like
+// the buffers it draws or the camera bind group on a resize.
+struct NRenderBundle {
+    commands: Vec<NCommandRender>,
+    bundle: Option<RenderBundle>,
+}
+
+// Render commands of one layer, fresh from the model or out of its cache.
+pub enum RenderCommands<'a> {
+    Fresh(CommandBuffer<NCommandRender>),
+    Cached(&'a [NCommandRender]),
+}
+
+impl Deref for RenderCommands<'_> {
+    type Target = [NCommandRender];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            RenderCommands::Fresh(commands) => commands,
+            RenderCommands::Cached(commands) => commands,
+        }
+    }
+}
+
+type LayerCache = (Vec<NCommandRender>, Vec<NCommandRender>);
+
 pub struct NModel {
     model: Box<dyn Model + Send + Sync>,
-    pipelines: Vec<Rc<RenderPipeline>>,
+    pipelines: Vec<Arc<RenderPipeline>>,
+    batch_keys: Vec<Option<BatchKey>>,
     buffers: Vec<NBuffer>,
     bind_groups: Vec<NBindGroup>,
+    transform: Option<ModelTransform>,
+    // World space bounds, kept up to date with the transform so culling
+    // doesn't transform every box each frame. The sphere is around the box.
+    world_aabb: Aabb,
+    bounding_sphere: (Vec3, f32),
+    // None until the first setup.
+    signature: Option<Vec<SetupEntry>>,
+    // Queued for App::remesh_dirty_models.
+    dirty: bool,
+    // Set up with commands pointing at nothing, it draws nothing until set up again.
+    skipped: bool,
+    // Opaque and transparent commands of static models, filled on first use.
+    render_cache: OnceLock<LayerCache>,
+    bundles: Vec<NRenderBundle>,
+    // The opaque layer of static models, when the app records those.
+    opaque_bundle: Option<RenderBundle>,
 }
 
 impl NModel {
     pub fn new(model: Box<dyn Model + Send + Sync>) -> Self {
+        let aabb = *model.aabb();
         Self {
             model,
             pipelines: vec![],
+            batch_keys: vec![],
             buffers: vec![],
             bind_groups: vec![],
+            transform: None,
+            world_aabb: aabb,
+            bounding_sphere: (aabb.center(), aabb.extents().length()),
+            signature: None,
+            dirty: false,
+            skipped: false,
+            render_cache: OnceLock::new(),
+            bundles: vec![],
+            opaque_bundle: None,
         }
     }
 
+    pub fn model(&self) -> &(dyn Model + Send + Sync) {
+        self.model.as_ref()
+    }
+
+    // Buffers are handed back so they can be returned to the pool.
+    fn clear_resources(&mut self) -> Vec<NBuffer> {
+        self.render_cache.take();
+        self.bundles.clear();
+        self.opaque_bundle = None;
+        self.pipelines.clear();
+        self.batch_keys.clear();
+        self.bind_groups.clear();
+        let mut buffers = mem::take(&mut self.buffers);
+        buffers.extend(self.transform.take().map(|transform| transform.buffer));
+        self.update_bounds();
+        buffers
+    }
+
+    pub fn has_transform(&self) -> bool {
+        self.transform.is_some()
+    }
+
+    // The model's AABB moved along with its transform.
+    pub fn world_aabb(&self) -> Aabb {
+        self.world_aabb
+    }
+
+    pub fn bounding_sphere(&self) -> (Vec3, f32) {
+        self.bounding_sphere
+    }
+
+    fn update_bounds(&mut self) {
+        self.world_aabb = match &self.transform {
+            Some(transform) => self.aabb().transform(transform.world),
+            None => *self.aabb(),
+        };
+        self.bounding_sphere = (self.world_aabb.center(), self.world_aabb.extents().length());
+    }
+
+    // The sphere settles most models without looking at the box.
+    fn in_frustum(&self, culling: &FrustumCuller) -> bool {
+        let (center, radius) = self.bounding_sphere;
+        culling.test_bounds(&self.world_aabb, center, radius)
+    }
+
+    // Models drawn one after the other with the same pipeline don't switch it.
+    fn pipeline_key(&self) -> usize {
+        self.pipelines
+            .first()
+            .map_or(0, |p| Arc::as_ptr(p) as usize)
+    }
+
     pub fn add_pipeline(&mut self, pipeline: RenderPipeline) {
-        self.pipelines.push(Rc::new(pipeline));
+        self.pipelines.push(Arc::new(pipeline));
+        self.batch_keys.push(None);
     }
 
-    pub fn add_pipeline_rc(&mut self, pipeline: Rc<RenderPipeline>) {
+    pub fn add_shared_pipeline(&mut self, pipeline: Arc<RenderPipeline>) {
         self.pipelines.push(pipeline);
+        self.batch_keys.push(None);
+    }
+
+    pub fn batch_key(&self, idx: usize) -> Option<&BatchKey> {
+        self.batch_keys.get(idx)?.as_ref()
     }
 
-    pub fn pipelines(&self) -> &[Rc<RenderPipeline>] {
+    pub fn pipelines(&self) -> &[Arc<RenderPipeline>] {
         &self.pipelines
     }
 
@@ -135,8 +581,96 @@ impl NModel {
         &self.bind_groups
     }
 
-    pub fn update_buffer(&self, queue: &Queue, idx: usize) {
-        self.buffers[idx].update(queue);
+    pub fn update_buffer(
+        &mut self,
+        device: &Device,
+        pool: &mut BufferPool,
+        idx: usize,
+        data: Vec<u8>,
+    ) -> Result<(), EngineError> {
+        EngineError::check_index("buffer", idx, self.buffers.len())?;
+        self.buffers[idx].update(device, pool, data);
+        Ok(())
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        self.skipped
+    }
+
+    // The model's render commands split into the opaque and transparent layers.
+    pub fn render_layers(&self) -> (RenderCommands<'_>, RenderCommands<'_>) {
+        if self.skipped {
+            return (RenderCommands::Cached(&[]), RenderCommands::Cached(&[]));
+        }
+        if !self.model.static_render() {
+            let (opaque, transparent) = self.model.render().split_layers();
+            return (
+                RenderCommands::Fresh(opaque),
+                RenderCommands::Fresh(transparent),
+            );
+        }
+
+        let (opaque, transparent) = self.render_cache.get_or_init(|| {
+            let (opaque, transparent) = self.model.render().split_layers();
+            (opaque.to_vec(), transparent.to_vec())
+        });
+        (
+            RenderCommands::Cached(opaque),
+            RenderCommands::Cached(transparent),
+        )
+    }
+
+    pub fn has_cached_render(&self) -> bool {
+        self.render_cache.get().is_some()
+    }
+
+    // Writes the buffers of a repeated setup over the ones from the last setup,
+    // false when it asks for different resources and needs a rebuild instead.
+    fn refill(
+        &mut self,
+        device: &Device,
+        pool: &mut BufferPool,
+        commands: &mut [NCommandSetup],
+    ) -> bool {
+        if self.signature.as_ref() != Some(&setup_signature(commands)) {
+            return false;
+        }
+        let mut data = commands
+            .iter_mut()
+            .filter_map(|command| match command {
+                NCommandSetup::CreateBuffer(data, _) => Some(data),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        // Bind groups keep pointing at the buffers they were created with.
+        let grows = self
+            .buffers
+            .iter()
+            .zip(data.iter())
+            .any(|(buffer, data)| !buffer.fits(data.len()));
+        if grows && !self.bind_groups.is_empty() {
+            return false;
+        }
+
+        for (buffer, data) in self.buffers.iter_mut().zip(data.iter_mut()) {
+            buffer.replace(device, pool, mem::take(*data));
+        }
+        let bundles = commands.iter_mut().filter_map(|command| match command {
+            NCommandSetup::CreateBundle(commands) => Some(commands),
+            _ => None,
+        });
+        for (bundle, commands) in self.bundles.iter_mut().zip(bundles) {
+            bundle.commands = mem::take(commands);
+        }
+        true
+    }
+
+    pub fn has_opaque_bundle(&self) -> bool {
+        self.opaque_bundle.is_some()
     }
 }
 
@@ -148,129 +682,385 @@ impl Deref for NModel {
     }
 }
 
-unsafe impl Sync for NModel {}
+pub type ModelHandle = Handle<NModel>;
+pub type ModelRef = HandleRef<NModel>;
+pub type ActorHandle = Handle<Box<dyn Actor + Send>>;
+pub type ActorRef = HandleRef<Box<dyn Actor + Send>>;
 
 pub struct ModelState {
-    models: Vec<NModel>,
+    models: HandleMap<NModel>,
 }
 
 impl ModelState {
     pub fn new() -> Self {
-        Self { models: vec![] }
+        Self {
+            models: HandleMap::new(),
+        }
     }
 
     pub fn get_model(&self, id: &Uuid) -> Option<&NModel> {
-        self.models.iter().find(|&model| model.id() == id)
+        self.models.get(HandleRef::Id(*id))
+    }
+
+    pub fn get_model_mut(&mut self, id: &Uuid) -> Option<&mut NModel> {
+        self.models.get_mut(HandleRef::Id(*id))
+    }
+
+    pub fn get<R: Into<ModelRef>>(&self, model: R) -> Option<&NModel> {
+        self.models.get(model.into())
+    }
+
+    pub fn get_mut<R: Into<ModelRef>>(&mut self, model: R) -> Option<&mut NModel> {
+        self.models.get_mut(model.into())
+    }
+
+    pub fn handle(&self, id: &Uuid) -> Option<ModelHandle> {
+        self.models.handle(id)
+    }
+
+    pub fn id<R: Into<ModelRef>>(&self, model: R) -> Option<Uuid> {
+        self.models.id(model.into())
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.models.contains(id)
     }
 
-    pub fn push(&mut self, model: NModel) {
-        self.models.push(model);
+    // Replaces the model with the same id if there's one.
+    pub fn push(&mut self, model: NModel) -> ModelHandle {
+        self.models.insert(*model.id(), model)
     }
 
     pub fn iter_models(&self) -> Iter<'_, NModel> {
         self.models.iter()
     }
 
+    pub fn iter_models_mut(&mut self) -> IterMut<'_, NModel> {
+        self.models.iter_mut()
+    }
+
     pub fn models(&self) -> &[NModel] {
-        &self.models
+        self.models.as_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.models.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+}
+
+// Blocks and light of a dimension's chunks, the ones in the scene and the ones
+// that just loaded and aren't in it yet.
+struct LoadedChunks<'a> {
+    models: &'a mut ModelState,
+    dimension: &'a Dimension,
+    pending: &'a mut [Chunk],
+}
+
+impl LoadedChunks<'_> {
+    fn chunk(&self, chunk_position: IVec3) -> Option<&(dyn Model + Send + Sync)> {
+        let id = self.dimension.chunk_id(chunk_position)?;
+        match self.pending.iter().find(|chunk| chunk.id() == id) {
+            Some(chunk) => Some(chunk),
+            None => self.models.get_model(id).map(NModel::model),
+        }
+    }
+
+    fn chunk_mut(
+        &mut self,
+        chunk_position: IVec3,
+    ) -> Option<&mut (dyn Model + Send + Sync + 'static)> {
+        let id = *self.dimension.chunk_id(chunk_position)?;
+        match self.pending.iter_mut().find(|chunk| *chunk.id() == id) {
+            Some(chunk) => Some(chunk),
+            None => self
+                .models
+                .get_model_mut(&id)
+                .map(|model| model.model.as_mut()),
+        }
+    }
+}
+
+impl BlockAccess for LoadedChunks<'_> {
+    fn block(&self, position: IVec3) -> Option<u16> {
+        let (chunk_position, local) = split_position(position);
+        self.chunk(chunk_position)?.block(local)
+    }
+
+    fn set_block(&mut self, position: IVec3, id: Option<u16>) {
+        let (chunk_position, local) = split_position(position);
+        if let Some(chunk) = self.chunk_mut(chunk_position) {
+            chunk.set_block(local, id);
+        }
+    }
+
+    fn block_state(&self, position: IVec3) -> Option<u32> {
+        let (chunk_position, local) = split_position(position);
+        self.chunk(chunk_position)?.block_state(local)
+    }
+
+    fn set_block_state(&mut self, position: IVec3, state: Option<u32>) {
+        let (chunk_position, local) = split_position(position);
+        if let Some(chunk) = self.chunk_mut(chunk_position) {
+            chunk.set_block_state(local, state);
+        }
+    }
+
+    fn chunk_loaded(&self, chunk_position: IVec3) -> bool {
+        self.dimension.chunk_id(chunk_position).is_some()
+    }
+
+    fn decorated(&self, chunk_position: IVec3) -> bool {
+        self.chunk(chunk_position)
+            .is_some_and(|chunk| chunk.decorated())
+    }
+
+    fn set_decorated(&mut self, chunk_position: IVec3) {
+        if let Some(chunk) = self.chunk_mut(chunk_position) {
+            chunk.set_decorated();
+        }
+    }
+}
+
+impl LightMap for LoadedChunks<'_> {
+    fn is_loaded(&self, position: IVec3) -> bool {
+        self.chunk_loaded(split_position(position).0)
+    }
+
+    fn height(&self, position: IVec3) -> i32 {
+        let (chunk_position, local) = split_position(position);
+        let height = self
+            .chunk(chunk_position)
+            .map_or(0, |chunk| chunk.height(local.x, local.z));
+        chunk_position.y * CHUNK_SIZE + height as i32
+    }
+
+    fn light(&self, position: IVec3, channel: LightChannel) -> u8 {
+        let (chunk_position, local) = split_position(position);
+        self.chunk(chunk_position)
+            .map_or(0, |chunk| chunk.light_level(local, channel))
     }
 
-    fn remove(&mut self, idx: usize) {
-        self.models.swap_remove(idx);
+    fn set_light(&mut self, position: IVec3, channel: LightChannel, level: u8) {
+        for_each_copy(position, |chunk_position, local| {
+            if let Some(chunk) = self.chunk_mut(chunk_position) {
+                chunk.set_light_level(local, channel, level);
+            }
+        });
     }
 }
 
 pub struct ActorState {
-    actors: Vec<Box<dyn Actor + Send>>,
+    actors: HandleMap<Box<dyn Actor + Send>>,
 }
 
 impl ActorState {
     pub fn new() -> Self {
-        Self { actors: vec![] }
+        Self {
+            actors: HandleMap::new(),
+        }
+    }
+
+    // Replaces the actor with the same id if there's one.
+    pub fn push(&mut self, actor: Box<dyn Actor + Send>) -> ActorHandle {
+        self.actors.insert(*actor.id(), actor)
+    }
+
+    pub fn get_actor<R: Into<ActorRef>>(&self, actor: R) -> Option<&(dyn Actor + Send)> {
+        self.actors.get(actor.into()).map(|actor| actor.as_ref())
     }
 
-    pub fn push(&mut self, actor: Box<dyn Actor + Send>) {
-        self.actors.push(actor);
+    pub fn handle(&self, id: &Uuid) -> Option<ActorHandle> {
+        self.actors.handle(id)
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.actors.contains(id)
     }
 
     pub fn iter_actors(&self) -> Iter<'_, Box<dyn Actor + Send>> {
         self.actors.iter()
     }
 
-    pub fn remove(&mut self, idx: usize) {
-        self.actors.swap_remove(idx);
+    pub fn len(&self) -> usize {
+        self.actors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actors.is_empty()
     }
 
-    pub fn mut_actors(&mut self) -> &mut Vec<Box<dyn Actor + Send>> {
-        &mut self.actors
+    pub fn remove<R: Into<ActorRef>>(&mut self, actor: R) -> Option<Box<dyn Actor + Send>> {
+        self.actors.remove(actor.into())
     }
+
+    pub fn mut_actors(&mut self) -> &mut [Box<dyn Actor + Send>] {
+        self.actors.as_mut_slice()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FullscreenMode {
+    Windowed,
+    Borderless,
+    Exclusive,
+}
+
+pub enum RenderTarget<'a> {
+    Window {
+        surface: Surface<'a>,
+        window: Arc<Window>,
+        present_modes: Vec<PresentMode>,
+    },
+    Offscreen {
+        texture: wgpu::Texture,
+    },
+}
+
+// What the app holds on to right now, for spotting leaks over long runs.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ResourceCounts {
+    pub models: usize,
+    pub buffers: usize,
+    pub bind_groups: usize,
+    pub pooled_bytes: u64,
+    pub uniform_slabs: usize,
+    pub loaded_chunks: usize,
 }
 
 pub struct App<'a> {
     actors: ActorState,
     models: Rc<RefCell<ModelState>>,
+    dirty_models: VecDeque<ModelHandle>,
     input_state: InputState,
+    debug_keys: DebugKeys,
 
-    surface: Surface<'a>,
+    target: RenderTarget<'a>,
+    instance: Rc<Instance>,
     device: Rc<Device>,
     queue: Queue,
     config: SurfaceConfiguration,
     size: PhysicalSize<u32>,
-    window: Arc<Window>,
     depth_texture: Rc<Texture>,
+    msaa_view: Option<TextureView>,
+    sample_count: u32,
+    reverse_z: bool,
+    capabilities: Capabilities,
+    adapter_request: AdapterRequest,
+    // Set from wgpu's callback, checked by the engine before every frame.
+    device_lost: Arc<AtomicBool>,
+    post_process: PostProcess,
+    buffer_pool: RefCell<BufferPool>,
+    gpu_culler: Option<GpuCuller>,
+    gpu_culling: bool,
+    batch: Option<GeometryBatch>,
+    draw_batching: bool,
+    render_bundles: bool,
 
-    camera: Rc<RefCell<Camera>>,
+    camera: Arc<RwLock<Camera>>,
     projection: Projection,
     camera_uniform: CameraUniform,
     camera_buffer: Buffer,
     camera_bind_group_layout: BindGroupLayout,
     camera_bind_group: Rc<BindGroup>,
+    transform_layout: BindGroupLayout,
+    transforms: RefCell<TransformHierarchy>,
+    bind_group_cache: RefCell<BindGroupCache>,
+    pipeline_cache: RefCell<PipelineCache>,
+
+    time_uniform: TimeUniform,
+    time_buffer: Buffer,
+
+    debug_view: DebugView,
+    debug_buffer: Buffer,
+    overdraw_buffer: Buffer,
+    debug_view_pipeline: RenderPipeline,
+
+    sky: Sky,
+    graphics_settings: GraphicsSettings,
+    main_viewport: ViewportRect,
+    viewports: HandleMap<Viewport>,
+    particles: Particles,
+    debug_draw: DebugDraw,
+    wireframe: bool,
+    block_outline: BlockOutline,
+    targeted_block: Option<RaycastHit>,
+    time_of_day: TimeOfDay,
+    weather: Weather,
+    environment_uniform: EnvironmentUniform,
+    environment_buffer: Buffer,
 
     model_layout: BindGroupLayout,
+    vfs: Vfs,
     obj_models: Vec<crate::model::ObjModel>,
+    // What register_model got, to load them again on a new device.
+    obj_model_names: Vec<String>,
+    asset_cache: Option<AssetCache>,
+    texture_streamer: Option<TextureStreamer>,
+    // Textures models bind by name, with the sampler they get through NResource::Sampler.
+    named_textures: RefCell<HashMap<String, Texture>>,
+    resource_sampler: Sampler,
+    pass_providers: Vec<Box<dyn RenderPassProvider>>,
+
+    fps_label: LabelId,
+    toast_label: LabelId,
+    stats_label: LabelId,
+    profiler_label: LabelId,
+    console_label: LabelId,
+    ui: Ui,
+    #[cfg(feature = "egui")]
+    egui_layer: Option<EguiLayer>,
 
-    font_system: FontSystem,
-    cache: SwashCache,
-    text_atlas: TextAtlas,
-    text_renderer: TextRenderer,
-    text_buffer: glyphon::Buffer,
+    profiler: Profiler,
+    render_stats: RenderStats,
+    gpu_timer: Option<GpuTimer>,
+    profiler_refresh: f32,
+
+    tick_accumulator: f32,
+    // Model ticks go on simulated time, which can be paused and scaled, while
+    // actors keep running so cameras and menus still work.
+    sim_accumulator: f32,
+    timestep: f32,
+    // How far the frame is between the last model tick and the next one.
+    tick_alpha: f32,
+    paused: bool,
+    time_scale: f32,
+    pending_steps: u32,
+    // Actors run on the fixed ticks too and the camera is interpolated between them.
+    fixed_timestep: bool,
+    dimensions: Vec<Dimension>,
+    current_dimension: Option<usize>,
+    // Runs the block behaviors and edits of the current dimension's chunks,
+    // which stay in the scene, so its own chunks and generator go unused;
+    // dimensions generate theirs. Its ticks go with the chunks when switching
+    // away from the dimension.
+    world: World,
+    workers: WorkerPools,
 
     calc_fps: u32,
     last_time: f32,
 }
 
-impl App<'_> {
-    pub async fn new(window: Arc<Window>) -> Self {
+impl<'a> App<'a> {
+    pub async fn new(
+        window: Arc<Window>,
+        settings: GraphicsSettings,
+        adapter_request: &AdapterRequest,
+    ) -> Result<Self, EngineError> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(InstanceDescriptor {
-            backends: Backends::all(),
+            backends: adapter_request.backends(),
             ..Default::default()
         });
 
-        let surface = instance.create_surface(window.clone()).unwrap();
-
-        let adapter = instance
-            .request_adapter(&RequestAdapterOptions {
-                power_preference: PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: Features::empty(),
-                    required_limits: Limits::default(),
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let surface = instance.create_surface(window.clone())?;
 
-        let device = Rc::new(device);
+        let (adapter, capabilities, device, queue) =
+            Self::open_device(&instance, adapter_request, Some(&surface)).await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -284,80 +1074,541 @@ impl App<'_> {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: PresentMode::AutoNoVsync,
+            present_mode: if settings.vsync {
+                PresentMode::AutoVsync
+            } else {
+                PresentMode::AutoNoVsync
+            },
             desired_maximum_frame_latency: 2,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
         surface.configure(&device, &config);
 
-        let depth_texture = Rc::new(Texture::create_depth_texture(
-            &device,
-            &config,
-            "depth_texture",
-        ));
-
-        let camera = Rc::new(RefCell::new(Camera::new((0.0, 5.0, 10.0), -1.57, -0.35)));
-        let projection = Projection::new(config.width, config.height, 0.78, 0.1, 4096.0);
+        let mut app = Self::from_parts(
+            Rc::new(instance),
+            device,
+            queue,
+            config,
+            RenderTarget::Window {
+                surface,
+                window,
+                present_modes: surface_caps.present_modes,
+            },
+            capabilities,
+            settings,
+        );
+        app.adapter_request = adapter_request.clone();
+        Ok(app)
+    }
 
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera.borrow(), &projection);
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        sample_count: u32,
+    ) -> Result<Self, EngineError> {
+        Self::new_headless_on(width, height, sample_count, &AdapterRequest::default()).await
+    }
 
-        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("Camera Buffer"),
-            contents: cast_slice(&[camera_uniform]),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    pub async fn new_headless_on(
+        width: u32,
+        height: u32,
+        sample_count: u32,
+        adapter_request: &AdapterRequest,
+    ) -> Result<Self, EngineError> {
+        let instance = wgpu::Instance::new(InstanceDescriptor {
+            backends: adapter_request.backends(),
+            ..Default::default()
         });
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
+        let (_, capabilities, device, queue) =
+            Self::open_device(&instance, adapter_request, None).await?;
 
-        let camera_bind_group = Rc::new(device.create_bind_group(&BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-            label: Some("camera_bind_group"),
-        }));
+        let config = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: PresentMode::AutoNoVsync,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+        let texture = Self::create_offscreen_texture(&device, &config);
 
-        let mut font_system = FontSystem::new();
-        let cache = SwashCache::new();
-        let mut atlas = TextAtlas::new(&device, &queue, TextureFormat::Bgra8UnormSrgb);
-        let text_renderer = TextRenderer::new(
-            &mut atlas,
-            &device,
-            MultisampleState::default(),
-            Some(DepthStencilState {
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: false,
-                depth_compare: CompareFunction::Never,
-                stencil: Default::default(),
-                bias: Default::default(),
+        let mut app = Self::from_parts(
+            Rc::new(instance),
+            device,
+            queue,
+            config,
+            RenderTarget::Offscreen { texture },
+            capabilities,
+            GraphicsSettings {
+                msaa: sample_count,
+                ..Default::default()
+            },
+        );
+        app.adapter_request = adapter_request.clone();
+        Ok(app)
+    }
+
+    async fn open_device(
+        instance: &Instance,
+        adapter_request: &AdapterRequest,
+        surface: Option<&Surface<'_>>,
+    ) -> Result<(Adapter, Capabilities, Device, Queue), EngineError> {
+        let adapter = adapter_request.select(instance, surface).await?;
+        let capabilities = Capabilities::detect(&adapter, adapter_request.capabilities());
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    required_features: capabilities.required_features(),
+                    required_limits: capabilities.required_limits(),
+                },
+                None,
+            )
+            .await?;
+
+        Ok((adapter, capabilities, device, queue))
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Relaxed)
+    }
+
+    // Starts over on a new device from the same instance and surface, keeping
+    // the worlds, actors, cameras and settings. Models, registered meshes and
+    // their textures are set up again from what they keep on the CPU. HUD
+    // labels, UI roots, the egui layer and pass providers hold on to the old
+    // device and get dropped, games add them again from `Game::device_recovered`.
+    pub async fn recover_device(&mut self) -> Result<(), EngineError> {
+        let instance = self.instance.clone();
+        let surface = match &self.target {
+            RenderTarget::Window { surface, .. } => Some(surface),
+            RenderTarget::Offscreen { .. } => None,
+        };
+        let (adapter, capabilities, device, queue) =
+            Self::open_device(&instance, &self.adapter_request, surface).await?;
+
+        // The surface moves to the new app, the old one is left drawing offscreen
+        // until it gets dropped.
+        let config = self.config.clone();
+        let offscreen = RenderTarget::Offscreen {
+            texture: Self::create_offscreen_texture(&device, &config),
+        };
+        let target = match mem::replace(&mut self.target, offscreen) {
+            RenderTarget::Window {
+                surface, window, ..
+            } => {
+                surface.configure(&device, &config);
+                RenderTarget::Window {
+                    present_modes: surface.get_capabilities(&adapter).present_modes,
+                    surface,
+                    window,
+                }
+            }
+            RenderTarget::Offscreen { .. } => RenderTarget::Offscreen {
+                texture: Self::create_offscreen_texture(&device, &config),
+            },
+        };
+        let mut fresh = Self::from_parts(
+            instance,
+            device,
+            queue,
+            config,
+            target,
+            capabilities,
+            self.graphics_settings,
+        );
+        fresh.adapter_request = self.adapter_request.clone();
+
+        mem::swap(&mut fresh.actors, &mut self.actors);
+        mem::swap(&mut fresh.models, &mut self.models);
+        mem::swap(&mut fresh.dirty_models, &mut self.dirty_models);
+        mem::swap(&mut fresh.input_state, &mut self.input_state);
+        mem::swap(&mut fresh.debug_keys, &mut self.debug_keys);
+        mem::swap(&mut fresh.camera, &mut self.camera);
+        mem::swap(&mut fresh.projection, &mut self.projection);
+        mem::swap(&mut fresh.transforms, &mut self.transforms);
+        mem::swap(&mut fresh.time_of_day, &mut self.time_of_day);
+        mem::swap(&mut fresh.weather, &mut self.weather);
+        mem::swap(&mut fresh.vfs, &mut self.vfs);
+        mem::swap(&mut fresh.asset_cache, &mut self.asset_cache);
+        mem::swap(&mut fresh.dimensions, &mut self.dimensions);
+        mem::swap(&mut fresh.world, &mut self.world);
+        mem::swap(&mut fresh.workers, &mut self.workers);
+        mem::swap(&mut fresh.profiler, &mut self.profiler);
+        fresh.current_dimension = self.current_dimension;
+        fresh.main_viewport = self.main_viewport;
+        fresh.debug_view = self.debug_view;
+        fresh.reverse_z = self.reverse_z;
+        fresh.wireframe =
+            self.wireframe && fresh.capabilities.supports(Capability::PolygonLineMode);
+        fresh.gpu_culling = self.gpu_culling && fresh.gpu_culler.is_some();
+        fresh.draw_batching = self.draw_batching && fresh.gpu_culler.is_some();
+        fresh.render_bundles = self.render_bundles;
+        fresh.tick_accumulator = self.tick_accumulator;
+        fresh.sim_accumulator = self.sim_accumulator;
+        fresh.timestep = self.timestep;
+        fresh.tick_alpha = self.tick_alpha;
+        fresh.paused = self.paused;
+        fresh.time_scale = self.time_scale;
+        fresh.pending_steps = self.pending_steps;
+        fresh.fixed_timestep = self.fixed_timestep;
+        fresh.set_crosshair(self.crosshair());
+        fresh.set_font_settings(self.font_settings().clone());
+        fresh.set_post_process_settings(*self.post_process_settings());
+        fresh.write_debug_uniform();
+        fresh.update_fog();
+
+        fresh.texture_streamer = self
+            .texture_streamer
+            .as_ref()
+            .map(|streamer| TextureStreamer::new(streamer.budget()));
+        for name in &self.obj_model_names {
+            fresh.register_model(name)?;
+        }
+        for (handle, id, viewport) in self.viewports.detach_all() {
+            let viewport = fresh.create_viewport(
+                viewport.camera(),
+                viewport.projection().clone(),
+                viewport.rect(),
+            );
+            fresh.viewports.attach(handle, id, viewport);
+        }
+        // The old buffers can't go back to the new pool.
+        for model in fresh.models.borrow_mut().iter_models_mut() {
+            drop(model.clear_resources());
+        }
+        fresh.rebuild_pipelines();
+
+        *self = fresh;
+        tracing::info!(
+            target: logging::RENDER,
+            "Recovered on {}",
+            self.capabilities.adapter_name()
+        );
+        Ok(())
+    }
+
+    fn create_msaa_view(
+        device: &Device,
+        config: &SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Option<TextureView> {
+        if sample_count <= 1 {
+            return None;
+        }
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("msaa_target"),
+            size: Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        Some(texture.create_view(&TextureViewDescriptor::default()))
+    }
+
+    fn create_debug_view_pipeline(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        format: TextureFormat,
+    ) -> RenderPipeline {
+        create_render_pipeline(
+            device,
+            PipelineDesc {
+                layout: &device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                    label: Some("debug_view_pipeline_layout"),
+                    bind_group_layouts: &[camera_layout],
+                    push_constant_ranges: &[],
+                }),
+                color_format: format,
+                depth_format: None,
+                vertex_layouts: &[],
+                shader: ShaderModuleDescriptor {
+                    label: Some("debug_view_shader"),
+                    source: ShaderSource::Wgsl(include_str!("../shaders/debug_view.wgsl").into()),
+                },
+                layer: RenderLayer::Transparent,
+                polygon_mode: PolygonMode::Fill,
+                sample_count: 1,
+                reverse_z: false,
+            },
+        )
+    }
+
+    fn create_offscreen_texture(device: &Device, config: &SurfaceConfiguration) -> wgpu::Texture {
+        device.create_texture(&TextureDescriptor {
+            label: Some("offscreen_target"),
+            size: Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: config.format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    }
+
+    fn from_parts(
+        instance: Rc<Instance>,
+        device: Device,
+        queue: Queue,
+        config: SurfaceConfiguration,
+        target: RenderTarget<'a>,
+        capabilities: Capabilities,
+        mut settings: GraphicsSettings,
+    ) -> Self {
+        let device_lost = Arc::new(AtomicBool::new(false));
+        let lost = device_lost.clone();
+        device.set_device_lost_callback(move |reason, message| {
+            // Dropping the device on purpose reports it lost too.
+            if matches!(
+                reason,
+                DeviceLostReason::Unknown | DeviceLostReason::Destroyed
+            ) {
+                tracing::error!(target: logging::RENDER, "Device lost: {message}");
+                lost.store(true, Ordering::Relaxed);
+            }
+        });
+        let device = Rc::new(device);
+        let size = PhysicalSize::new(config.width, config.height);
+
+        if !capabilities.sample_counts().contains(&settings.msaa) {
+            tracing::warn!(
+                target: logging::RENDER,
+                "{}x MSAA is not supported, falling back to no MSAA",
+                settings.msaa
+            );
+            settings.msaa = 1;
+        }
+        let sample_count = settings.msaa;
+        let msaa_view = Self::create_msaa_view(&device, &config, sample_count);
+        let depth_texture = Rc::new(Texture::create_depth_texture(
+            &device,
+            &config,
+            sample_count,
+            "depth_texture",
+        ));
+
+        let camera = Arc::new(RwLock::new(Camera::new((0.0, 5.0, 10.0), -1.57, -0.35)));
+        let projection = Projection::new(
+            config.width,
+            config.height,
+            settings.fov.to_radians(),
+            0.1,
+            settings.view_distance(),
+        );
+
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera.read().unwrap(), &projection);
+
+        let camera_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: cast_slice(&[camera_uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let time_uniform = TimeUniform::new();
+
+        let time_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Time Buffer"),
+            contents: cast_slice(&[time_uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("camera_bind_group_layout"),
+            });
+
+        let debug_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Debug Buffer"),
+            contents: cast_slice(&[DebugUniform::new(DebugView::None, config.width)]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let overdraw_buffer = Self::create_overdraw_buffer(&device, &config);
+
+        let time_of_day = TimeOfDay::new(0.4, 600.0);
+        let mut environment_uniform = EnvironmentUniform::new();
+        environment_uniform.update(&time_of_day);
+        Self::set_fog(
+            &mut environment_uniform,
+            settings.fog,
+            projection.z_far(),
+            DimensionSettings::default().fog_density,
+        );
+        let environment_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Environment Buffer"),
+            contents: cast_slice(&[environment_uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let resource_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Resource Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let camera_bind_group = Rc::new(Self::create_camera_bind_group(
+            &device,
+            &camera_bind_group_layout,
+            &[
+                &camera_buffer,
+                &time_buffer,
+                &debug_buffer,
+                &overdraw_buffer,
+                &environment_buffer,
+            ],
+        ));
+
+        let debug_view_pipeline =
+            Self::create_debug_view_pipeline(&device, &camera_bind_group_layout, config.format);
+
+        let sky = Sky::new(
+            &device,
+            &camera_bind_group_layout,
+            &queue,
+            HDR_FORMAT,
+            sample_count,
+            false,
+        );
+        let particles = Particles::new(&device, &camera_bind_group_layout, sample_count, false);
+        let debug_draw = DebugDraw::new(&device, &camera_bind_group_layout, sample_count, false);
+        let block_outline =
+            BlockOutline::new(&device, &camera_bind_group_layout, sample_count, false);
+        let post_process = PostProcess::new(&device, &config);
+        let gpu_culler = capabilities
+            .supports(Capability::IndirectDraws)
+            .then(|| GpuCuller::new(&device));
+        let gpu_culling = gpu_culler.is_some();
+
+        let mut ui = Ui::new(&device, &queue, config.format, config.width, config.height);
+        let text_layer = ui.text_mut();
+        let fps_label = text_layer.add_label(
+            Metrics::new(30.0, 42.0),
+            (10.0, 10.0),
+            Some(TextBounds {
+                left: 0,
+                top: 0,
+                right: 600,
+                bottom: 160,
             }),
+            glyphon::Color::rgb(255, 255, 255),
         );
-        let mut buffer = glyphon::Buffer::new(&mut font_system, Metrics::new(30.0, 42.0));
-
-        buffer.set_size(&mut font_system, config.width as f32, config.height as f32);
-        buffer.set_text(
-            &mut font_system,
-            "0 fps",
-            Attrs::new().family(Family::SansSerif),
-            Shaping::Basic,
+        text_layer.set_text(fps_label, "0 fps");
+        let toast_label = text_layer.add_label(
+            Metrics::new(20.0, 28.0),
+            (10.0, 60.0),
+            None,
+            glyphon::Color::rgb(255, 255, 0),
         );
-        buffer.shape_until_scroll(&mut font_system);
+        let stats_label = text_layer.add_label(
+            Metrics::new(20.0, 28.0),
+            (160.0, 16.0),
+            None,
+            glyphon::Color::rgb(255, 255, 255),
+        );
+        let profiler_label = text_layer.add_label(
+            Metrics::new(16.0, 20.0),
+            (10.0, 100.0),
+            None,
+            glyphon::Color::rgb(255, 255, 255),
+        );
+        let console_label = text_layer.add_label(
+            Metrics::new(18.0, 24.0),
+            (10.0, 10.0),
+            None,
+            glyphon::Color::rgb(200, 255, 200),
+        );
+        let gpu_timer = capabilities
+            .supports(Capability::TimestampQueries)
+            .then(|| GpuTimer::new(&device, &queue));
+
+        let transform_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("transform_bind_group_layout"),
+        });
 
         let model_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             entries: &[
@@ -377,6 +1628,23 @@ impl App<'_> {
                     ty: BindingType::Sampler(SamplerBindingType::Filtering),
                     count: None,
                 },
+                // Normal map, a flat one for materials without.
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
             ],
             label: Some("texture_bind_group_layout"),
         });
@@ -384,15 +1652,30 @@ impl App<'_> {
         Self {
             actors: ActorState::new(),
             models: Rc::new(RefCell::new(ModelState::new())),
+            dirty_models: VecDeque::new(),
             input_state: InputState::new(),
+            debug_keys: DebugKeys::with_defaults(),
 
-            surface,
+            target,
+            instance,
             device,
             queue,
             config,
             size,
-            window,
             depth_texture,
+            msaa_view,
+            sample_count,
+            reverse_z: false,
+            capabilities,
+            adapter_request: AdapterRequest::default(),
+            device_lost,
+            post_process,
+            buffer_pool: RefCell::new(BufferPool::new()),
+            gpu_culler,
+            gpu_culling,
+            batch: None,
+            draw_batching: gpu_culling,
+            render_bundles: false,
 
             camera,
             projection,
@@ -400,289 +1683,2415 @@ impl App<'_> {
             camera_bind_group_layout,
             camera_bind_group,
             camera_uniform,
+            transform_layout,
+            transforms: RefCell::new(TransformHierarchy::new()),
+            bind_group_cache: RefCell::new(BindGroupCache::new()),
+            pipeline_cache: RefCell::new(PipelineCache::new()),
+
+            time_uniform,
+            time_buffer,
+
+            debug_view: DebugView::None,
+            debug_buffer,
+            overdraw_buffer,
+            debug_view_pipeline,
+
+            sky,
+            graphics_settings: settings,
+            main_viewport: ViewportRect::FULL,
+            viewports: HandleMap::new(),
+            particles,
+            debug_draw,
+            wireframe: false,
+            block_outline,
+            targeted_block: None,
+            time_of_day,
+            weather: Weather::default(),
+            environment_uniform,
+            environment_buffer,
 
             model_layout,
+            vfs: Vfs::with_embedded(),
             obj_models: vec![],
+            obj_model_names: vec![],
+            asset_cache: None,
+            texture_streamer: None,
+            named_textures: RefCell::new(HashMap::new()),
+            resource_sampler,
+            pass_providers: vec![],
+
+            fps_label,
+            toast_label,
+            stats_label,
+            profiler_label,
+            console_label,
+            ui,
+            #[cfg(feature = "egui")]
+            egui_layer: None,
 
-            font_system,
-            cache,
-            text_atlas: atlas,
-            text_renderer,
-            text_buffer: buffer,
+            profiler: Profiler::new(),
+            render_stats: RenderStats::default(),
+            gpu_timer,
+            profiler_refresh: 0.0,
+
+            tick_accumulator: 0.0,
+            sim_accumulator: 0.0,
+            timestep: FIXED_TIMESTEP,
+            tick_alpha: 1.0,
+            paused: false,
+            time_scale: 1.0,
+            pending_steps: 0,
+            fixed_timestep: false,
+            dimensions: vec![],
+            current_dimension: None,
+            world: World::with_generator(WorldGenerator::new(WorldSeed::new(0))),
+            workers: WorkerPools::new(WorkerCounts::default()).unwrap(),
 
             calc_fps: 0,
             last_time: 0.0,
         }
     }
 
-    pub fn add_model(&mut self, mut model: NModel) {
-        let buffer = model.setup();
+    fn create_overdraw_buffer(device: &Device, config: &SurfaceConfiguration) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Overdraw Buffer"),
+            size: (config.width * config.height) as BufferAddress
+                * size_of::<u32>() as BufferAddress,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_camera_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        buffers: &[&Buffer],
+    ) -> BindGroup {
+        let entries = buffers
+            .iter()
+            .enumerate()
+            .map(|(idx, buffer)| BindGroupEntry {
+                binding: idx as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<BindGroupEntry>>();
+
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &entries,
+            label: Some("camera_bind_group"),
+        })
+    }
+
+    fn setup_model(&self, model: &mut NModel) {
+        let commands = model.setup().iter_command().collect::<Vec<_>>();
+        self.apply_setup(commands, model);
+    }
 
-        for command in buffer.iter_command() {
-            self.parse_setup_command(command, &mut model);
+    // A model whose commands don't add up is kept but skipped, drawing nothing
+    // until it gets set up again.
+    fn apply_setup(&self, commands: Vec<NCommandSetup>, model: &mut NModel) {
+        model.signature = Some(setup_signature(&commands));
+        model.dirty = false;
+        model.render_cache.take();
+        let result = commands
+            .into_iter()
+            .try_for_each(|command| self.parse_setup_command(command, model))
+            .and_then(|_| self.check_render_commands(model));
+        model.skipped = result.is_err();
+        if let Err(e) = result {
+            tracing::warn!(target: logging::RENDER, "Skipping model {}: {e}", model.id());
+            self.release_buffers(model.clear_resources());
+            return;
         }
-        self.models.borrow_mut().push(model);
+        self.record_bundles(model);
     }
 
-    pub fn add_actor(&mut self, actor: Box<dyn Actor + Send>) {
-        self.actors.push(actor);
+    // Like a model whose setup failed, the models stay skipped until they get
+    // set up again.
+    fn skip_models(&self, ids: &[Uuid]) {
+        let mut models = self.models.borrow_mut();
+        for id in ids {
+            if let Some(model) = models.get_model_mut(id) {
+                model.skipped = true;
+                self.release_buffers(model.clear_resources());
+            }
+        }
     }
 
-    pub fn register_model(&mut self, name: &str) {
-        self.obj_models
-            .push(load_model(name, &self.device, &self.queue, &self.model_layout).unwrap());
+    // Checks the commands the model gives right after its setup and those of its
+    // bundles. Models that don't keep their commands get theirs checked every
+    // frame too, see frame_layers.
+    fn check_render_commands(&self, model: &NModel) -> Result<(), EngineError> {
+        let commands = model.model.render();
+        let bundles = model.bundles.iter().flat_map(|bundle| &bundle.commands);
+        check_commands(&self.obj_models, model, commands.iter().chain(bundles))
     }
 
-    pub fn camera(&self) -> Rc<RefCell<Camera>> {
-        self.camera.clone()
+    // Records every bundle of the model from scratch, along with the one of its
+    // opaque layer when it's static and the app records those.
+    fn record_bundles(&self, model: &mut NModel) {
+        let bundles = model
+            .bundles
+            .iter()
+            .map(|bundle| self.record_bundle(model, &bundle.commands))
+            .collect::<Vec<_>>();
+        for (bundle, recorded) in model.bundles.iter_mut().zip(bundles) {
+            bundle.bundle = Some(recorded);
+        }
+
+        model.opaque_bundle = None;
+        if self.render_bundles && model.static_render() {
+            let (opaque, _) = model.render_layers();
+            let recorded = (!opaque.is_empty()).then(|| self.record_bundle(model, &opaque));
+            drop(opaque);
+            model.opaque_bundle = recorded;
+        }
     }
 
-    pub fn resize(&mut self, new_size: &PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            self.size = *new_size;
-            self.config.width = new_size.width;
-            self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+    fn record_bundle(&self, model: &NModel, commands: &[NCommandRender]) -> RenderBundle {
+        let mut encoder =
+            self.device
+                .create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
+                    label: Some("Model Render Bundle"),
+                    color_formats: &[Some(HDR_FORMAT)],
+                    depth_stencil: Some(RenderBundleDepthStencil {
+                        format: Texture::DEPTH_FORMAT,
+                        depth_read_only: false,
+                        stencil_read_only: true,
+                    }),
+                    sample_count: self.sample_count,
+                    multiview: None,
+                });
+        for command in commands {
+            self.encode_render_command(command, model, &self.camera_bind_group, &mut encoder);
+        }
+        encoder.finish(&RenderBundleDescriptor {
+            label: Some("Model Render Bundle"),
+        })
+    }
 
-            self.projection.resize(new_size.width, new_size.height);
+    fn record_all_bundles(&mut self) {
+        let models = self.models.clone();
+        for model in models.borrow_mut().iter_models_mut() {
+            self.record_bundles(model);
+        }
+    }
 
-            self.depth_texture = Rc::new(Texture::create_depth_texture(
-                &self.device,
-                &self.config,
-                "depth_texture",
-            ));
+    pub fn render_bundles(&self) -> bool {
+        self.render_bundles
+    }
+
+    // Static models get their opaque layer recorded into a bundle at setup and
+    // remesh, replayed each frame instead of going through their commands. Only
+    // used while GPU culling is off, culled draws need the indirect buffer.
+    pub fn set_render_bundles(&mut self, enabled: bool) {
+        if self.render_bundles != enabled {
+            self.render_bundles = enabled;
+            self.record_all_bundles();
         }
     }
 
-    pub fn input(&mut self, event: &WindowEvent) -> bool {
-        self.input_state.input(event)
+    // The handle stays valid until the model is removed, rebuilds keep it.
+    pub fn add_model(&mut self, mut model: NModel) -> ModelHandle {
+        self.setup_model(&mut model);
+        let handle = self.models.borrow_mut().push(model);
+        self.invalidate_culling();
+        handle
     }
 
-    pub fn parse_update_command(&mut self, command: NCommandUpdate) {
-        match command {
-            NCommandUpdate::CreateModel(model) => {
-                let mut n_model = NModel::new(model);
-                let buffer = n_model.setup();
+    pub fn remove_model<R: Into<ModelRef>>(&mut self, model: R) {
+        let removed = self.models.borrow_mut().models.remove(model.into());
+        if let Some(mut model) = removed {
+            self.release_buffers(model.clear_resources());
+            self.transforms.borrow_mut().remove(model.id());
+            drop(model);
+            self.prune_caches();
+            self.invalidate_culling();
+        }
+    }
 
-                for command in buffer.iter_command() {
-                    self.parse_setup_command(command, &mut n_model);
-                }
+    // Set up again from scratch, under the same handle.
+    fn rebuild_model(&mut self, model: ModelRef) {
+        let detached = self.models.borrow_mut().models.detach(model);
+        if let Some((handle, id, mut model)) = detached {
+            self.release_buffers(model.clear_resources());
+            self.setup_model(&mut model);
+            self.models.borrow_mut().models.attach(handle, id, model);
+            self.invalidate_culling();
+            self.prune_caches();
+        }
+    }
 
-                self.models.borrow_mut().push(n_model);
-            }
-            NCommandUpdate::CreateActor(actor) => {
-                self.actors.push(actor);
-            }
-            NCommandUpdate::RemoveModel(id) => {
-                let mut idx = None;
-                for (i, model) in self.models.borrow().iter_models().enumerate() {
-                    if model.id() == &id {
-                        idx = Some(i);
-                        break;
-                    }
-                }
-                if let Some(i) = idx {
-                    self.models.borrow_mut().remove(i);
-                }
-            }
-            NCommandUpdate::RemoveActor(id) => {
-                let mut idx = None;
-                for (i, actor) in self.actors.iter_actors().enumerate() {
-                    if actor.id() == &id {
-                        idx = Some(i);
-                        break;
-                    }
-                }
-                if let Some(i) = idx {
-                    self.actors.remove(i);
-                }
-            }
-            NCommandUpdate::MoveCamera(offset) => {
-                self.camera.borrow_mut().move_position(offset);
-            }
-            NCommandUpdate::RotateCamera(yaw, pitch) => {
-                self.camera.borrow_mut().add_yaw(yaw);
-                self.camera.borrow_mut().add_pitch(pitch);
-            }
-            NCommandUpdate::FovCamera(_fov) => {}
-            NCommandUpdate::UpdateBuffer(id, idx) => {
-                self.models
-                    .borrow_mut()
-                    .get_model(&id)
-                    .unwrap()
-                    .update_buffer(&self.queue, idx);
-            }
+    // Queues the model for a remesh, marking it again before that is a no-op.
+    pub fn mark_dirty<R: Into<ModelRef>>(&mut self, model: R) {
+        let mut models = self.models.borrow_mut();
+        let Some(handle) = models.models.resolve(model.into()) else {
+            return;
+        };
+        let model = models.get_mut(handle).unwrap();
+        model.render_cache.take();
+        if !model.dirty {
+            model.dirty = true;
+            self.dirty_models.push_back(handle);
         }
     }
 
-    pub fn parse_setup_command(&self, command: NCommandSetup, n_model: &mut NModel) {
-        match command {
-            NCommandSetup::CreateBuffer(uniform, buffer_usages) => {
-                let buffer = self.device.create_buffer_init(&BufferInitDescriptor {
-                    label: None,
-                    contents: &uniform.borrow(),
-                    usage: buffer_usages,
-                });
-                let n_buffer = NBuffer::new(buffer, uniform);
-                n_model.add_buffer(n_buffer);
+    pub fn dirty_models(&self) -> usize {
+        self.dirty_models.len()
+    }
+
+    // Remeshes up to `budget` dirty models, in the order they got marked.
+    fn remesh_dirty_models(&mut self, budget: usize) {
+        let mut remeshed = 0;
+        while remeshed < budget {
+            let Some(handle) = self.dirty_models.pop_front() else {
+                break;
+            };
+            // Removed or rebuilt since it got marked.
+            if self
+                .models
+                .borrow()
+                .get(handle)
+                .is_some_and(|model| model.dirty)
+            {
+                self.remesh_model(handle);
+                remeshed += 1;
             }
-            NCommandSetup::CreateBindGroup(layout_entries, resources) => {
-                let layout = self
-                    .device
-                    .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                        label: None,
-                        entries: &layout_entries,
-                    });
+        }
+    }
 
-                let entries: Vec<BindGroupEntry> = resources
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, resource)| {
-                        let r = match resource {
-                            NResource::Buffer(i) => {
-                                n_model.buffers()[*i].buffer().as_entire_binding()
-                            }
-                        };
-                        BindGroupEntry {
-                            binding: idx as u32,
-                            resource: r,
-                        }
-                    })
-                    .collect();
+    fn remesh_model(&mut self, handle: ModelHandle) {
+        let detached = self.models.borrow_mut().models.detach(handle.into());
+        let Some((handle, id, mut model)) = detached else {
+            return;
+        };
 
-                let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
-                    label: None,
-                    layout: &layout,
-                    entries: &entries,
-                });
+        model.dirty = false;
+        model.render_cache.take();
+        let mut commands = model.setup().iter_command().collect::<Vec<_>>();
+        let refilled = model.refill(
+            &self.device,
+            &mut self.buffer_pool.borrow_mut(),
+            &mut commands,
+        );
+        if refilled {
+            self.record_bundles(&mut model);
+        } else {
+            self.release_buffers(model.clear_resources());
+            self.apply_setup(commands, &mut model);
+            self.prune_caches();
+        }
+        self.models.borrow_mut().models.attach(handle, id, model);
+        self.invalidate_culling();
+    }
 
-                n_model.add_bind_group(NBindGroup::new(bind_group, layout));
-            }
-            NCommandSetup::CreatePipeline(bind_groups, shader, mut vertex_layouts, use_model) => {
-                let mut bind_group_layouts = vec![];
-                if use_model {
-                    bind_group_layouts.push(&self.model_layout);
-                    vertex_layouts.insert(0, ModelVertex::desc());
-                }
-                bind_group_layouts.push(&self.camera_bind_group_layout);
-                bind_group_layouts.append(
-                    &mut bind_groups
-                        .iter()
-                        .map(|idx| n_model.bind_groups[*idx].layout())
-                        .collect::<Vec<_>>(),
-                );
+    pub fn model_handle(&self, id: &Uuid) -> Option<ModelHandle> {
+        self.models.borrow().handle(id)
+    }
 
-                let pipeline_layout =
-                    self.device
-                        .create_pipeline_layout(&PipelineLayoutDescriptor {
-                            label: None,
-                            bind_group_layouts: &bind_group_layouts,
-                            push_constant_ranges: &[],
-                        });
-                let shader = ShaderModuleDescriptor {
-                    label: None,
-                    source: ShaderSource::Wgsl(shader.into()),
-                };
+    // Ids pass through as they are, handles only while their model is around.
+    fn model_id(&self, model: ModelRef) -> Option<Uuid> {
+        match model {
+            HandleRef::Id(id) => Some(id),
+            HandleRef::Handle(_) => self.models.borrow().id(model),
+        }
+    }
 
-                let render_pipeline = create_render_pipeline(
-                    &self.device,
-                    &pipeline_layout,
-                    self.config.format,
-                    Some(Texture::DEPTH_FORMAT),
-                    &vertex_layouts,
-                    shader,
-                );
+    pub fn resource_counts(&self) -> ResourceCounts {
+        let models = self.models.borrow();
+        let buffer_pool = self.buffer_pool.borrow();
+        ResourceCounts {
+            models: models.len(),
+            buffers: models
+                .iter_models()
+                .map(|model| model.buffers().len())
+                .sum(),
+            bind_groups: models
+                .iter_models()
+                .map(|model| model.bind_groups().len())
+                .sum(),
+            pooled_bytes: buffer_pool.pooled_bytes(),
+            uniform_slabs: buffer_pool.slab_count(),
+            loaded_chunks: self
+                .current_dimension()
+                .map_or(0, |dimension| dimension.loaded_chunks()),
+        }
+    }
 
-                n_model.add_pipeline(render_pipeline);
-            }
-            NCommandSetup::SharePipeline(id, idx) => {
-                if let Some(model) = self.models.borrow().get_model(id) {
-                    let pipeline = model.pipelines()[idx].clone();
-                    n_model.add_pipeline_rc(pipeline);
-                }
-            }
+    pub fn add_dimension(&mut self, dimension: Dimension) -> Result<()> {
+        if self.dimension_index(dimension.name()).is_some() {
+            return Err(anyhow!(
+                "dimension `{}` is already registered",
+                dimension.name()
+            ));
         }
+
+        self.dimensions.push(dimension);
+        Ok(())
     }
 
-    pub fn parse_render_command<'b, 'a: 'b>(
-        &'a self,
-        command: NCommandRender,
-        model: &'a NModel,
-        render_pass: &'b mut RenderPass<'a>,
-    ) {
+    fn dimension_index(&self, name: &str) -> Option<usize> {
+        self.dimensions
+            .iter()
+            .position(|dimension| dimension.name() == name)
+    }
+
+    pub fn current_dimension(&self) -> Option<&Dimension> {
+        self.current_dimension.map(|idx| &self.dimensions[idx])
+    }
+
+    pub fn current_dimension_mut(&mut self) -> Option<&mut Dimension> {
+        self.current_dimension.map(|idx| &mut self.dimensions[idx])
+    }
+
+    pub fn dimension_names(&self) -> Vec<&str> {
+        self.dimensions
+            .iter()
+            .map(|dimension| dimension.name())
+            .collect()
+    }
+
+    // Unloads the current dimension, remembering where the player and its clock
+    // were, then moves the player into the other one and loads everything in its
+    // load radius right away.
+    pub fn switch_dimension(&mut self, name: &str) -> Result<()> {
+        let idx = self
+            .dimension_index(name)
+            .ok_or_else(|| anyhow!("dimension `{name}` is not registered"))?;
+
+        if let Some(current) = self.current_dimension {
+            let position = self.camera.read().unwrap().position();
+            let dimension = &mut self.dimensions[current];
+            dimension.set_player_position(position);
+            dimension.settings_mut().time_of_day = self.time_of_day.time();
+            for (chunk_position, id) in dimension.unload_all() {
+                self.unload_chunk(current, chunk_position, &id);
+            }
+            self.world.clear_block_ticks();
+        }
+
+        self.current_dimension = Some(idx);
+        let dimension = &self.dimensions[idx];
+        let settings = *dimension.settings();
+        self.camera
+            .write()
+            .unwrap()
+            .set_position(dimension.entry_position());
+        self.camera.write().unwrap().snap();
+        self.time_of_day = TimeOfDay::new(settings.time_of_day, settings.cycle_length);
+        self.update_fog();
+        self.stream_chunks(usize::MAX);
+        Ok(())
+    }
+
+    fn orbit_camera(&self, target: Vec3A, yaw: f32, pitch: f32, distance: f32) {
+        let mut camera = self.camera.write().unwrap();
+        camera.set_rotation(yaw, pitch);
+        let back = -camera.forward();
+        let distance = self
+            .raycast_solid(target.into(), back.into(), distance)
+            .map_or(distance, |hit| (hit - ORBIT_CAMERA_MARGIN).max(0.0));
+        camera.set_position(target + back * distance);
+    }
+
+    fn stream_chunks(&mut self, budget: usize) {
+        let Some(idx) = self.current_dimension else {
+            return;
+        };
+
+        let camera = self.camera.read().unwrap();
+        let center = (camera.position() / CHUNK_SIZE as f32).floor().as_ivec3();
+        let forward = camera.forward();
+        drop(camera);
+        let (load, unload) =
+            self.dimensions[idx].plan_streaming(center, Vec2::new(forward.x, forward.z));
+        for (chunk_position, id) in unload {
+            self.unload_chunk(idx, chunk_position, &id);
+        }
+        let load = load.into_iter().take(budget).collect::<Vec<_>>();
+        let mut chunks = self.dimensions[idx].load_chunks(&load, &self.workers);
+        self.prepare_loaded_chunks(idx, &load, &mut chunks);
+        self.workers.mesh_chunks(&chunks);
+        for chunk in chunks {
+            self.add_model(NModel::new(Box::new(chunk)));
+        }
+    }
+
+    // Rebuilds the worker pools with the sizes from `config`, see
+    // WorkerConfig::resolve for where the rest come from.
+    pub fn configure_workers<P: AsRef<Path>>(
+        &mut self,
+        config: &WorkerConfig,
+        calibration_path: P,
+    ) -> Result<()> {
+        self.workers = WorkerPools::new(config.resolve(calibration_path))?;
+        Ok(())
+    }
+
+    pub fn workers(&self) -> &WorkerPools {
+        &self.workers
+    }
+
+    pub fn block_behaviors(&self) -> &BehaviorRegistry {
+        self.world.behaviors()
+    }
+
+    // Custom block behaviors go in here.
+    pub fn block_behaviors_mut(&mut self) -> &mut BehaviorRegistry {
+        self.world.behaviors_mut()
+    }
+
+    // Block at a world position in the current dimension, None for air or
+    // chunks that aren't loaded.
+    pub fn block(&self, position: IVec3) -> Option<u16> {
+        let (chunk_position, local) = split_position(position);
+        let id = self.current_dimension()?.chunk_id(chunk_position)?;
+        self.models.borrow().get_model(id)?.model().block(local)
+    }
+
+    // Changes a block of the current dimension at a world position, loading its
+    // chunk first if it isn't. Chunks out of the load radius go again on the
+    // next streaming pass, with the edit if the dimension saves.
+    pub fn set_block(&mut self, position: IVec3, id: Option<u16>) -> Result<()> {
+        let idx = self
+            .current_dimension
+            .ok_or_else(|| anyhow!("no dimension to place blocks in"))?;
+        // Broken blocks fall apart, placed ones puff out a little dust.
+        if let Some(block) = self.place_block(idx, position, id) {
+            self.particles
+                .spawn(ParticleEmitter::debris(position, block_info(block).color));
+        }
+
+        Ok(())
+    }
+
+    // Stores a block, loading its chunk first, and updates the light and
+    // meshes around it. Returns the block that was placed or broken, if the
+    // cell changed.
+    fn place_block(&mut self, idx: usize, position: IVec3, id: Option<u16>) -> Option<u16> {
+        let (chunk_position, _) = split_position(position);
+        if self.dimensions[idx].chunk_id(chunk_position).is_none() {
+            self.load_chunk(idx, chunk_position);
+        }
+
+        let mut models = self.models.borrow_mut();
+        let mut loaded = LoadedChunks {
+            models: &mut models,
+            dimension: &self.dimensions[idx],
+            pending: &mut [],
+        };
+        let previous = loaded.block(position);
+        let lit = World::place_block(&mut loaded, position, id);
+        drop(models);
+        self.block_changed(idx, position, lit?);
+        id.or(previous)
+    }
+
+    // Puts a block of the current dimension in a state from Model::block_state,
+    // without waking anything up around it. False when its chunk isn't loaded
+    // or it already was in that state.
+    pub fn set_block_state(&mut self, position: IVec3, state: Option<u32>) -> bool {
+        let (chunk_position, local) = split_position(position);
+        let Some(idx) = self.current_dimension else {
+            return false;
+        };
+        let Some(&chunk_id) = self.dimensions[idx].chunk_id(chunk_position) else {
+            return false;
+        };
+
+        let mut models = self.models.borrow_mut();
+        let changed = models
+            .get_model_mut(&chunk_id)
+            .is_some_and(|model| model.model.set_block_state(local, state));
+        if !changed {
+            return false;
+        }
+        let lit = lighting::update_block(
+            &mut LoadedChunks {
+                models: &mut models,
+                dimension: &self.dimensions[idx],
+                pending: &mut [],
+            },
+            position,
+        );
+        drop(models);
+        self.block_changed(idx, position, lit);
+        true
+    }
+
+    // Remeshes the chunk of a block that changed, the ones facing it across
+    // its chunk's border and the ones the light changed in.
+    fn block_changed(&mut self, idx: usize, position: IVec3, lit: HashSet<IVec3>) {
+        let (chunk_position, local) = split_position(position);
+        if let Some(&id) = self.dimensions[idx].chunk_id(chunk_position) {
+            self.mark_dirty(id);
+        }
+        self.mark_border_dirty(idx, chunk_position, local);
+        self.mark_chunks_dirty(idx, lit);
+    }
+
+    // Puts a chunk made elsewhere, like one received from a server, into the
+    // current dimension in place of the one at its position.
+    pub fn insert_chunk(&mut self, mut chunk: Chunk) -> Result<()> {
+        let idx = self
+            .current_dimension
+            .ok_or_else(|| anyhow!("no dimension to insert chunks in"))?;
+        let chunk_position = chunk.chunk_position().as_ivec3();
+        self.remove_chunk(chunk_position);
+
+        self.dimensions[idx].generator().tint_chunk(&mut chunk);
+        self.dimensions[idx].insert_loaded(chunk_position, *chunk.id());
+        self.prepare_loaded_chunks(idx, &[chunk_position], slice::from_mut(&mut chunk));
+        self.workers.mesh_chunks(slice::from_ref(&chunk));
+        self.add_model(NModel::new(Box::new(chunk)));
+        Ok(())
+    }
+
+    // Unloads a chunk of the current dimension, saving it if the dimension
+    // saves.
+    pub fn remove_chunk(&mut self, chunk_position: IVec3) {
+        let Some(idx) = self.current_dimension else {
+            return;
+        };
+        if let Some(id) = self.dimensions[idx].remove_loaded(chunk_position) {
+            self.unload_chunk(idx, chunk_position, &id);
+        }
+    }
+
+    fn load_chunk(&mut self, dimension: usize, chunk_position: IVec3) -> Uuid {
+        let mut chunk = self.dimensions[dimension].load_chunk(chunk_position);
+        self.prepare_loaded_chunks(dimension, &[chunk_position], slice::from_mut(&mut chunk));
+        self.workers.mesh_chunks(slice::from_ref(&chunk));
+        let id = *chunk.id();
+        self.add_model(NModel::new(Box::new(chunk)));
+        id
+    }
+
+    // Decorates and lights chunks that just loaded, before they get meshed.
+    // Loaded chunks around them that structures or light got into get remeshed.
+    fn prepare_loaded_chunks(
+        &mut self,
+        dimension: usize,
+        chunk_positions: &[IVec3],
+        chunks: &mut [Chunk],
+    ) {
+        let mut models = self.models.borrow_mut();
+        let mut loaded = LoadedChunks {
+            models: &mut models,
+            dimension: &self.dimensions[dimension],
+            pending: chunks,
+        };
+        let built = self.dimensions[dimension]
+            .generator()
+            .decorate(&mut loaded, chunk_positions);
+        let mut touched = chunk_positions
+            .iter()
+            .flat_map(|&chunk_position| lighting::light_chunk(&mut loaded, chunk_position))
+            .collect::<HashSet<_>>();
+        for position in built {
+            if !chunk_positions.contains(&split_position(position).0) {
+                touched.extend(lighting::update_block(&mut loaded, position));
+            }
+        }
+        touched.retain(|chunk_position| !chunk_positions.contains(chunk_position));
+        drop(models);
+        self.mark_chunks_dirty(dimension, touched);
+    }
+
+    fn mark_chunks_dirty(&mut self, dimension: usize, chunk_positions: HashSet<IVec3>) {
+        for chunk_position in chunk_positions {
+            if let Some(&id) = self.dimensions[dimension].chunk_id(chunk_position) {
+                self.mark_dirty(id);
+            }
+        }
+    }
+
+    // Blocks on a chunk's border face the loaded chunks across it, those get
+    // remeshed along with it.
+    fn mark_border_dirty(&mut self, dimension: usize, chunk_position: IVec3, local: UVec3) {
+        for axis in 0..3 {
+            let mut offset = IVec3::ZERO;
+            match local[axis] {
+                0 => offset[axis] = -1,
+                l if l == CHUNK_SIZE as u32 - 1 => offset[axis] = 1,
+                _ => continue,
+            }
+            if let Some(&id) = self.dimensions[dimension].chunk_id(chunk_position + offset) {
+                self.mark_dirty(id);
+            }
+        }
+    }
+
+    fn unload_chunk(&mut self, dimension: usize, chunk_position: IVec3, id: &Uuid) {
+        if self.dimensions[dimension].saves() {
+            let data = self
+                .models
+                .borrow()
+                .get_model(id)
+                .and_then(|model| model.model.save());
+            if let Some(data) = data {
+                self.dimensions[dimension].store_chunk(chunk_position, &data);
+            }
+        }
+
+        self.remove_model(*id);
+    }
+
+    // Writes every loaded chunk of the current dimension, to call before exiting.
+    pub fn save_dimension(&self) {
+        let Some(idx) = self.current_dimension else {
+            return;
+        };
+        let dimension = &self.dimensions[idx];
+        if !dimension.saves() {
+            return;
+        }
+
+        let models = self.models.borrow();
+        for (chunk_position, id) in dimension.loaded() {
+            let data = models.get_model(id).and_then(|model| model.model.save());
+            if let Some(data) = data {
+                dimension.store_chunk(*chunk_position, &data);
+            }
+        }
+    }
+
+    pub fn add_actor(&mut self, actor: Box<dyn Actor + Send>) -> ActorHandle {
+        self.actors.push(actor)
+    }
+
+    pub fn remove_actor<R: Into<ActorRef>>(&mut self, actor: R) {
+        self.actors.remove(actor);
+    }
+
+    pub fn actor_handle(&self, id: &Uuid) -> Option<ActorHandle> {
+        self.actors.handle(id)
+    }
+
+    pub fn add_render_pass(&mut self, provider: Box<dyn RenderPassProvider>) {
+        self.pass_providers.push(provider);
+    }
+
+    // Processed assets get reused from the cache by models registered after this.
+    pub fn set_asset_cache(&mut self, asset_cache: Option<AssetCache>) {
+        self.asset_cache = asset_cache;
+    }
+
+    // Render commands refer to registered models by the order they got registered.
+    pub fn register_model(&mut self, name: &str) -> Result<(), EngineError> {
+        let model = load_model(
+            &self.vfs,
+            name,
+            &self.device,
+            &self.queue,
+            &self.model_layout,
+            self.asset_cache.as_ref(),
+            self.texture_streamer.as_mut(),
+        )
+        .map_err(|e| EngineError::Asset(name.to_string(), e))?;
+        self.obj_model_names.push(name.to_string());
+        self.obj_models.push(model);
+        Ok(())
+    }
+
+    // Textures that fail to load are left white, so the bind group still gets made.
+    fn load_named_texture(&self, name: &str) {
+        if self.named_textures.borrow().contains_key(name) {
+            return;
+        }
+
+        let texture = load_texture(
+            &self.vfs,
+            name,
+            &self.device,
+            &self.queue,
+            false,
+            self.asset_cache.as_ref(),
+        )
+        .or_else(|e| {
+            tracing::warn!(target: logging::ASSETS, "{e}");
+            Texture::from_rgba(
+                &self.device,
+                &self.queue,
+                (1, 1),
+                &[255; 4],
+                Some(name),
+                false,
+            )
+        })
+        .unwrap();
+        self.named_textures
+            .borrow_mut()
+            .insert(name.to_string(), texture);
+    }
+
+    // Where models and textures get read from, mount resource packs here before
+    // registering the models they override.
+    pub fn vfs(&self) -> &Vfs {
+        &self.vfs
+    }
+
+    pub fn vfs_mut(&mut self) -> &mut Vfs {
+        &mut self.vfs
+    }
+
+    // Textures of the models registered afterwards start with their small mips
+    // and stream the rest within `budget` bytes. None loads them whole.
+    pub fn set_texture_streaming(&mut self, budget: Option<u64>) -> Result<()> {
+        if !self.obj_models.is_empty() {
+            return Err(anyhow!(
+                "texture streaming has to be set before registering models"
+            ));
+        }
+
+        self.texture_streamer = budget.map(TextureStreamer::new);
+        Ok(())
+    }
+
+    pub fn texture_stream_stats(&self) -> Option<TextureStreamStats> {
+        self.texture_streamer.as_ref().map(TextureStreamer::stats)
+    }
+
+    // Swaps in whatever the streamer uploaded for the textures the last frame
+    // drew with, see render.
+    fn stream_textures(&mut self) {
+        let Some(streamer) = self.texture_streamer.as_mut() else {
+            return;
+        };
+
+        for (id, texture) in streamer.update(&self.device, &self.queue) {
+            let material = self
+                .obj_models
+                .iter_mut()
+                .flat_map(|model| model.materials.iter_mut())
+                .find(|material| material.streamed == Some(id));
+            if let Some(material) = material {
+                material.set_texture(&self.device, texture, &self.model_layout);
+            }
+        }
+    }
+
+    pub fn debug_keys(&self) -> &DebugKeys {
+        &self.debug_keys
+    }
+
+    pub fn debug_keys_mut(&mut self) -> &mut DebugKeys {
+        &mut self.debug_keys
+    }
+
+    pub fn action_map(&self) -> &ActionMap {
+        self.input_state.action_map()
+    }
+
+    pub fn action_map_mut(&mut self) -> &mut ActionMap {
+        self.input_state.action_map_mut()
+    }
+
+    pub fn time_buffer(&self) -> &Buffer {
+        &self.time_buffer
+    }
+
+    pub fn elapsed_time(&self) -> f32 {
+        self.time_uniform.elapsed()
+    }
+
+    pub fn time_of_day(&self) -> &TimeOfDay {
+        &self.time_of_day
+    }
+
+    pub fn time_of_day_mut(&mut self) -> &mut TimeOfDay {
+        &mut self.time_of_day
+    }
+
+    pub fn weather(&self) -> &Weather {
+        &self.weather
+    }
+
+    pub fn weather_mut(&mut self) -> &mut Weather {
+        &mut self.weather
+    }
+
+    pub fn particles(&self) -> &Particles {
+        &self.particles
+    }
+
+    pub fn particles_mut(&mut self) -> &mut Particles {
+        &mut self.particles
+    }
+
+    pub fn spawn_particles(&mut self, emitter: ParticleEmitter) {
+        self.particles.spawn(emitter);
+    }
+
+    pub fn sky(&self) -> &Sky {
+        &self.sky
+    }
+
+    pub fn sky_mut(&mut self) -> &mut Sky {
+        &mut self.sky
+    }
+
+    pub fn camera(&self) -> Arc<RwLock<Camera>> {
+        self.camera.clone()
+    }
+
+    pub fn bind_group_cache_stats(&self) -> BindGroupCacheStats {
+        self.bind_group_cache.borrow().stats()
+    }
+
+    pub fn pipeline_cache_stats(&self) -> PipelineCacheStats {
+        self.pipeline_cache.borrow().stats()
+    }
+
+    // Drops what no model uses anymore, once they let go of their resources.
+    fn prune_caches(&mut self) {
+        self.pipeline_cache.get_mut().prune();
+        self.bind_group_cache.get_mut().prune();
+    }
+
+    pub fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
+    pub fn projection_mut(&mut self) -> &mut Projection {
+        &mut self.projection
+    }
+
+    pub fn graphics_settings(&self) -> &GraphicsSettings {
+        &self.graphics_settings
+    }
+
+    pub fn target_fps(&self) -> Option<u32> {
+        self.graphics_settings.max_fps
+    }
+
+    // Caps how often the engine renders, whether or not vsync is on. None, or 0,
+    // renders as fast as the present mode allows.
+    pub fn set_target_fps(&mut self, fps: Option<u32>) {
+        self.graphics_settings.max_fps = fps.filter(|&fps| fps > 0);
+    }
+
+    // Shortest time between two frames, when there's a target fps.
+    pub fn frame_interval(&self) -> Option<Duration> {
+        self.target_fps()
+            .map(|fps| Duration::from_secs_f64(1.0 / fps as f64))
+    }
+
+    // Checks everything first, so unsupported settings change nothing.
+    pub fn apply_settings(&mut self, settings: GraphicsSettings) -> Result<()> {
+        let vsync = settings.vsync != self.vsync() && self.window().is_some();
+        if !self.capabilities.sample_counts().contains(&settings.msaa) {
+            return Err(anyhow!("{}x MSAA is not supported", settings.msaa));
+        }
+
+        if vsync {
+            self.set_vsync(settings.vsync)?;
+        }
+        self.set_sample_count(settings.msaa)?;
+        self.projection.set_fov_y(settings.fov.to_radians());
+        self.projection.set_z_far(settings.view_distance());
+        for viewport in self.viewports.iter_mut() {
+            viewport
+                .projection_mut()
+                .set_z_far(settings.view_distance());
+        }
+        self.graphics_settings = settings;
+        self.update_fog();
+
+        Ok(())
+    }
+
+    // Chunks are culled by their center, so the fog has to be opaque half a chunk
+    // diagonal before the far plane to hide them popping out. Without fog it
+    // starts past everything that gets drawn.
+    fn set_fog(uniform: &mut EnvironmentUniform, enabled: bool, z_far: f32, density: f32) {
+        if enabled {
+            let fog_end = z_far - CHUNK_SIZE as f32;
+            uniform.set_fog_range(fog_end * 0.6, fog_end);
+            uniform.set_fog_density(density);
+        } else {
+            uniform.set_fog_range(z_far * 2.0, z_far * 3.0);
+            uniform.set_fog_density(0.0);
+        }
+    }
+
+    fn update_fog(&mut self) {
+        let density = self
+            .current_dimension()
+            .map_or(DimensionSettings::default(), |dimension| {
+                *dimension.settings()
+            })
+            .fog_density;
+        Self::set_fog(
+            &mut self.environment_uniform,
+            self.graphics_settings.fog,
+            self.projection.z_far(),
+            density,
+        );
+    }
+
+    pub fn main_viewport(&self) -> ViewportRect {
+        self.main_viewport
+    }
+
+    // Where the main camera draws, the other cameras draw over it in their own.
+    pub fn set_main_viewport(&mut self, rect: ViewportRect) {
+        self.main_viewport = rect;
+        let (_, _, width, height) = rect.to_pixels(self.config.width, self.config.height);
+        self.projection.resize(width, height);
+    }
+
+    // Another camera drawing into `rect`, for split-screen or a minimap. It starts
+    // with the main camera's projection.
+    pub fn add_camera(&mut self, camera: Camera, rect: ViewportRect) -> ViewportHandle {
+        let viewport =
+            self.create_viewport(Arc::new(RwLock::new(camera)), self.projection.clone(), rect);
+        self.viewports.insert(Uuid::new_v4(), viewport)
+    }
+
+    fn create_viewport(
+        &self,
+        camera: Arc<RwLock<Camera>>,
+        projection: Projection,
+        rect: ViewportRect,
+    ) -> Viewport {
+        let mut uniform = CameraUniform::new();
+        uniform.update_view_proj(&camera.read().unwrap(), &projection);
+        let buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Viewport Camera Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let bind_group = Self::create_camera_bind_group(
+            &self.device,
+            &self.camera_bind_group_layout,
+            &[
+                &buffer,
+                &self.time_buffer,
+                &self.debug_buffer,
+                &self.overdraw_buffer,
+                &self.environment_buffer,
+            ],
+        );
+
+        let mut viewport = Viewport::new(
+            camera,
+            projection,
+            rect,
+            buffer,
+            bind_group,
+            self.sky.create_view(&self.device),
+        );
+        viewport.resize(self.config.width, self.config.height);
+        viewport
+    }
+
+    pub fn remove_camera(&mut self, viewport: ViewportHandle) {
+        self.viewports.remove(viewport.into());
+    }
+
+    pub fn viewport(&self, viewport: ViewportHandle) -> Option<&Viewport> {
+        self.viewports.get(viewport.into())
+    }
+
+    pub fn viewport_mut(&mut self, viewport: ViewportHandle) -> Option<&mut Viewport> {
+        self.viewports.get_mut(viewport.into())
+    }
+
+    pub fn set_viewport_rect(&mut self, viewport: ViewportHandle, rect: ViewportRect) {
+        let (width, height) = (self.config.width, self.config.height);
+        if let Some(viewport) = self.viewports.get_mut(viewport.into()) {
+            viewport.set_rect(rect, width, height);
+        }
+    }
+
+    pub fn font_settings(&self) -> &FontSettings {
+        self.ui.text().font_settings()
+    }
+
+    pub fn set_font_settings(&mut self, settings: FontSettings) {
+        self.ui
+            .text_mut()
+            .set_font_settings(&self.device, &self.queue, settings);
+    }
+
+    pub fn post_process_settings(&self) -> &PostProcessSettings {
+        self.post_process.settings()
+    }
+
+    pub fn set_post_process_settings(&mut self, settings: PostProcessSettings) {
+        self.post_process.set_settings(&self.queue, settings);
+    }
+
+    pub fn set_overlay_visible(&mut self, visible: bool) {
+        self.ui.text_mut().set_visible(visible);
+    }
+
+    pub fn fixed_timestep(&self) -> bool {
+        self.fixed_timestep
+    }
+
+    pub fn set_fixed_timestep(&mut self, fixed_timestep: bool) {
+        self.fixed_timestep = fixed_timestep;
+        self.camera.write().unwrap().snap();
+    }
+
+    // Ticks per second, of the models and, with a fixed timestep, of the actors.
+    pub fn tick_rate(&self) -> f32 {
+        1.0 / self.timestep
+    }
+
+    pub fn set_tick_rate(&mut self, rate: f32) {
+        self.timestep = 1.0 / rate.max(1.0);
+        self.tick_accumulator = self.tick_accumulator.min(self.timestep);
+        self.sim_accumulator = self.sim_accumulator.min(self.timestep);
+    }
+
+    // Between 0.0 right on the last model tick and 1.0 on the next one, for
+    // rendering things that move on ticks in between.
+    pub fn tick_alpha(&self) -> f32 {
+        self.tick_alpha
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.time_scale = scale.max(0.0);
+    }
+
+    // Runs one model tick on the next update, mostly for stepping through a
+    // paused simulation.
+    pub fn step(&mut self) {
+        self.pending_steps += 1;
+    }
+
+    pub fn resize(&mut self, new_size: &PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.size = *new_size;
+            self.config.width = new_size.width;
+            self.config.height = new_size.height;
+            match &mut self.target {
+                RenderTarget::Window { surface, .. } => {
+                    surface.configure(&self.device, &self.config)
+                }
+                RenderTarget::Offscreen { texture } => {
+                    *texture = Self::create_offscreen_texture(&self.device, &self.config);
+                }
+            }
+
+            let (_, _, width, height) = self
+                .main_viewport
+                .to_pixels(new_size.width, new_size.height);
+            self.projection.resize(width, height);
+            self.post_process
+                .resize(&self.device, &self.queue, &self.config);
+            self.ui.resize(new_size.width, new_size.height);
+
+            self.msaa_view = Self::create_msaa_view(&self.device, &self.config, self.sample_count);
+            self.depth_texture = Rc::new(Texture::create_depth_texture(
+                &self.device,
+                &self.config,
+                self.sample_count,
+                "depth_texture",
+            ));
+
+            self.overdraw_buffer = Self::create_overdraw_buffer(&self.device, &self.config);
+            self.camera_bind_group = Rc::new(Self::create_camera_bind_group(
+                &self.device,
+                &self.camera_bind_group_layout,
+                &[
+                    &self.camera_buffer,
+                    &self.time_buffer,
+                    &self.debug_buffer,
+                    &self.overdraw_buffer,
+                    &self.environment_buffer,
+                ],
+            ));
+            for viewport in self.viewports.iter_mut() {
+                viewport.resize(new_size.width, new_size.height);
+                viewport.set_bind_group(Self::create_camera_bind_group(
+                    &self.device,
+                    &self.camera_bind_group_layout,
+                    &[
+                        viewport.buffer(),
+                        &self.time_buffer,
+                        &self.debug_buffer,
+                        &self.overdraw_buffer,
+                        &self.environment_buffer,
+                    ],
+                ));
+            }
+            self.record_all_bundles();
+            self.write_debug_uniform();
+        }
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn gpu_info(&self) -> GpuInfo {
+        self.capabilities.gpu_info()
+    }
+
+    pub fn present_mode(&self) -> PresentMode {
+        self.config.present_mode
+    }
+
+    // The auto modes are always there since they fall back to Fifo. Offscreen
+    // targets never present, so they only report the mode they were created with.
+    pub fn supported_present_modes(&self) -> Vec<PresentMode> {
+        match &self.target {
+            RenderTarget::Window { present_modes, .. } => {
+                let mut modes = vec![PresentMode::AutoVsync, PresentMode::AutoNoVsync];
+                modes.extend(present_modes.iter().copied());
+                modes
+            }
+            RenderTarget::Offscreen { .. } => vec![self.config.present_mode],
+        }
+    }
+
+    pub fn set_present_mode(&mut self, present_mode: PresentMode) -> Result<()> {
+        if !self.supported_present_modes().contains(&present_mode) {
+            return Err(anyhow!(
+                "{present_mode:?} present mode is not supported by {}",
+                self.capabilities.adapter_name()
+            ));
+        }
+
+        self.config.present_mode = present_mode;
+        if let RenderTarget::Window { surface, .. } = &self.target {
+            surface.configure(&self.device, &self.config);
+        }
+        Ok(())
+    }
+
+    pub fn vsync(&self) -> bool {
+        matches!(
+            self.config.present_mode,
+            PresentMode::AutoVsync | PresentMode::Fifo | PresentMode::FifoRelaxed
+        )
+    }
+
+    pub fn set_vsync(&mut self, enabled: bool) -> Result<()> {
+        self.set_present_mode(if enabled {
+            PresentMode::AutoVsync
+        } else {
+            PresentMode::AutoNoVsync
+        })
+    }
+
+    pub fn gpu_culling(&self) -> bool {
+        self.gpu_culling
+    }
+
+    pub fn set_gpu_culling(&mut self, enabled: bool) -> Result<()> {
+        if enabled && self.gpu_culler.is_none() {
+            return Err(anyhow!(
+                "GPU culling needs indirect draws, not supported by {}",
+                self.capabilities.adapter_name()
+            ));
+        }
+
+        self.gpu_culling = enabled;
+        Ok(())
+    }
+
+    pub fn draw_batching(&self) -> bool {
+        self.draw_batching
+    }
+
+    // Batched chunks are drawn from the indirect buffer of the culling pass, so
+    // batching only applies while GPU culling is on.
+    pub fn set_draw_batching(&mut self, enabled: bool) -> Result<()> {
+        if enabled && self.gpu_culler.is_none() {
+            return Err(anyhow!(
+                "draw batching needs multi draw indirect, not supported by {}",
+                self.capabilities.adapter_name()
+            ));
+        }
+
+        if self.draw_batching != enabled {
+            self.draw_batching = enabled;
+            self.invalidate_culling();
+        }
+        Ok(())
+    }
+
+    fn release_buffers(&self, buffers: Vec<NBuffer>) {
+        let mut pool = self.buffer_pool.borrow_mut();
+        for buffer in buffers {
+            pool.release(buffer.allocation);
+        }
+    }
+
+    // Writes the world matrix of every model whose transform or one of its
+    // parents changed since the last frame.
+    fn update_transforms(&mut self) {
+        if !self.transforms.get_mut().take_dirty() {
+            return;
+        }
+
+        let mut moved = false;
+        {
+            let mut pool = self.buffer_pool.borrow_mut();
+            let transforms = self.transforms.borrow();
+            let mut models = self.models.borrow_mut();
+            for model in models.iter_models_mut() {
+                let world = transforms.world(model.id());
+                let Some(transform) = model.transform.as_mut() else {
+                    continue;
+                };
+                if transform.world != world {
+                    transform.world = world;
+                    transform.buffer.update(
+                        &self.device,
+                        &mut pool,
+                        cast_slice(&[world.to_cols_array()]).to_vec(),
+                    );
+                    model.update_bounds();
+                    moved = true;
+                }
+            }
+        }
+
+        if moved {
+            self.invalidate_culling();
+        }
+    }
+
+    fn invalidate_culling(&mut self) {
+        if let Some(culler) = self.gpu_culler.as_mut() {
+            culler.invalidate();
+        }
+    }
+
+    // Gives every model with a culled draw a slot in the indirect buffer, batched
+    // models first so the multi draw can go over them in one go.
+    fn upload_cull_entries(&mut self) {
+        let Some(culler) = self.gpu_culler.as_mut() else {
+            return;
+        };
+
+        let cull_entry = |model: &NModel, index_count, first_index, base_vertex| {
+            let aabb = model.world_aabb();
+            (
+                *model.id(),
+                CullEntry::new(
+                    aabb.min().into(),
+                    aabb.max().into(),
+                    (*model.position()).into(),
+                    index_count,
+                )
+                .with_offsets(first_index, base_vertex),
+            )
+        };
+
+        let models = self.models.clone();
+        let models = models.borrow();
+        let mut entries = vec![];
+        self.batch = None;
+        if self.draw_batching {
+            let mut sources = models
+                .iter_models()
+                .filter_map(BatchSource::from_model)
+                .collect::<Vec<_>>();
+            if let Some((batch, batched)) = GeometryBatch::build(&self.device, &mut sources) {
+                entries.extend(
+                    batched
+                        .into_iter()
+                        .map(|(source, first_index, base_vertex)| {
+                            cull_entry(
+                                source.model(),
+                                source.index_count(),
+                                first_index,
+                                base_vertex,
+                            )
+                        }),
+                );
+                self.batch = Some(batch);
+            }
+        }
+
+        let batch = self.batch.as_ref();
+        // wgpu's types aren't Send on wasm32, models get walked on this thread there.
+        #[cfg(not(target_arch = "wasm32"))]
+        let iter = models.models().par_iter();
+        #[cfg(target_arch = "wasm32")]
+        let iter = models.models().iter();
+        entries.extend(
+            iter.filter(|model| !batch.is_some_and(|batch| batch.contains(model.id())))
+                .filter_map(|model| {
+                    let (opaque, transparent) = model.render_layers();
+                    let index_count = opaque.iter().chain(transparent.iter()).find_map(
+                        |command| match *command {
+                            NCommandRender::DrawIndexedCulled(index_count) => Some(index_count),
+                            _ => None,
+                        },
+                    )?;
+                    Some(cull_entry(model, index_count, 0, 0))
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        culler.upload(&self.device, &self.queue, entries);
+    }
+
+    // Rebuilds every render target and pipeline for the new sample count, models are
+    // set up again from scratch so their pipelines pick it up too.
+    pub fn set_sample_count(&mut self, sample_count: u32) -> Result<()> {
+        if !self.capabilities.sample_counts().contains(&sample_count) {
+            return Err(anyhow!("{sample_count}x MSAA is not supported"));
+        }
+        if sample_count == self.sample_count {
+            return Ok(());
+        }
+
+        self.sample_count = sample_count;
+        self.resize(&self.size());
+        self.rebuild_pipelines();
+
+        Ok(())
+    }
+
+    pub fn wireframe(&self) -> bool {
+        self.wireframe
+    }
+
+    // Model pipelines drawn as lines, only where the adapter can.
+    pub fn set_wireframe(&mut self, wireframe: bool) {
+        if wireframe == self.wireframe {
+            return;
+        }
+        if wireframe && !self.capabilities.supports(Capability::PolygonLineMode) {
+            tracing::warn!(
+                target: logging::RENDER,
+                "wireframe rendering isn't supported by this adapter"
+            );
+            self.debug_keys.set_enabled(DebugFlag::Wireframe, false);
+            return;
+        }
+
+        self.wireframe = wireframe;
+        self.debug_keys.set_enabled(DebugFlag::Wireframe, wireframe);
+        self.rebuild_pipelines();
+    }
+
+    fn polygon_mode(&self) -> PolygonMode {
+        if self.wireframe {
+            PolygonMode::Line
+        } else {
+            PolygonMode::Fill
+        }
+    }
+
+    // The solid block the camera looks at within reach, what clicks act on.
+    pub fn targeted_block(&self) -> Option<RaycastHit> {
+        self.targeted_block
+    }
+
+    pub fn block_outline(&self) -> &BlockOutline {
+        &self.block_outline
+    }
+
+    pub fn block_outline_mut(&mut self) -> &mut BlockOutline {
+        &mut self.block_outline
+    }
+
+    fn update_targeted_block(&mut self) {
+        let pose = self.camera.read().unwrap().view();
+        let models = self.models.borrow();
+        let dimension = self.current_dimension.map(|idx| &self.dimensions[idx]);
+        let world = WorldView::new(pose, &models, dimension);
+        // Fluids don't stop the ray, the block under the water gets picked.
+        self.targeted_block = raycast_grid(
+            pose.position.into(),
+            pose.forward().into(),
+            BLOCK_REACH,
+            |cell| world.block(cell).filter(|&id| block_info(id).is_solid()),
+        )
+        .map(|(id, position, normal, distance)| RaycastHit {
+            position,
+            normal,
+            distance,
+            id,
+        });
+        drop(models);
+
+        let target = self.targeted_block.map(|hit| hit.position);
+        if target != self.block_outline.target() {
+            self.block_outline.set_target(&self.queue, target);
+        }
+    }
+
+    pub fn debug_draw_mut(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    pub fn debug_draw_line(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) {
+        self.debug_draw.line(start, end, color);
+    }
+
+    pub fn debug_draw_aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        self.debug_draw.aabb(aabb, color);
+    }
+
+    pub fn debug_draw_sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4]) {
+        self.debug_draw.sphere(center, radius, color);
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    // Reversed depth with an infinite far plane, so distant chunks don't z-fight.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        if reverse_z == self.reverse_z {
+            return;
+        }
+
+        self.reverse_z = reverse_z;
+        self.projection.set_reverse_z(reverse_z);
+        self.rebuild_pipelines();
+    }
+
+    // Models set up again, for settings their pipelines depend on.
+    fn rebuild_pipelines(&mut self) {
+        self.sky = Sky::new(
+            &self.device,
+            &self.camera_bind_group_layout,
+            &self.queue,
+            HDR_FORMAT,
+            self.sample_count,
+            self.reverse_z,
+        );
+        for viewport in self.viewports.iter_mut() {
+            viewport.set_sky(self.sky.create_view(&self.device));
+        }
+        self.particles.rebuild_pipeline(
+            &self.device,
+            &self.camera_bind_group_layout,
+            self.sample_count,
+            self.reverse_z,
+        );
+        self.debug_draw.rebuild_pipeline(
+            &self.device,
+            &self.camera_bind_group_layout,
+            self.sample_count,
+            self.reverse_z,
+        );
+        self.block_outline.rebuild_pipeline(
+            &self.device,
+            &self.camera_bind_group_layout,
+            self.sample_count,
+            self.reverse_z,
+        );
+
+        let models = self.models.borrow_mut().models.detach_all();
+        for (handle, id, mut model) in models {
+            self.release_buffers(model.clear_resources());
+            self.setup_model(&mut model);
+            self.models.borrow_mut().models.attach(handle, id, model);
+        }
+        self.invalidate_culling();
+        self.prune_caches();
+    }
+
+    fn write_debug_uniform(&self) {
+        self.queue.write_buffer(
+            &self.debug_buffer,
+            0,
+            cast_slice(&[DebugUniform::new(self.debug_view, self.config.width)]),
+        );
+    }
+
+    pub fn input(&mut self, event: &WindowEvent) -> bool {
+        #[cfg(feature = "egui")]
+        if let (Some(layer), RenderTarget::Window { window, .. }) =
+            (self.egui_layer.as_mut(), &self.target)
+        {
+            if layer.on_window_event(window, event) {
+                return true;
+            }
+        }
+        self.input_state.input(event)
+    }
+
+    // Adds an egui pass on top of the UI, made on the first call.
+    #[cfg(feature = "egui")]
+    pub fn add_ui_actor(&mut self, actor: Box<dyn UiActor>) {
+        let window = match &self.target {
+            RenderTarget::Window { window, .. } => Some(window.as_ref()),
+            RenderTarget::Offscreen { .. } => None,
+        };
+        self.egui_layer
+            .get_or_insert_with(|| EguiLayer::new(&self.device, self.config.format, window))
+            .add_actor(actor);
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn ui<F: FnMut(&egui::Context) + 'static>(&mut self, f: F) {
+        self.add_ui_actor(Box::new(f));
+    }
+
+    #[cfg(feature = "egui")]
+    fn update_ui(&mut self) {
+        let Some(layer) = self.egui_layer.as_mut() else {
+            return;
+        };
+        let window = match &self.target {
+            RenderTarget::Window { window, .. } => Some(window.as_ref()),
+            RenderTarget::Offscreen { .. } => None,
+        };
+        for buffer in layer.run(window, self.size) {
+            for command in buffer.iter_command() {
+                self.parse_update_command(command);
+            }
+        }
+    }
+
+    pub fn parse_update_command(&mut self, command: NCommandUpdate) {
+        match command {
+            NCommandUpdate::CreateModel(model) => {
+                self.add_model(NModel::new(model));
+            }
+            NCommandUpdate::CreateActor(actor) => {
+                self.actors.push(actor);
+            }
+            NCommandUpdate::RemoveModel(model) => self.remove_model(model),
+            NCommandUpdate::RemoveActor(actor) => self.remove_actor(actor),
+            NCommandUpdate::MoveCamera(offset) => {
+                self.camera.write().unwrap().move_position(offset);
+            }
+            NCommandUpdate::TeleportCamera(position) => {
+                let mut camera = self.camera.write().unwrap();
+                camera.set_position(position);
+                camera.snap();
+            }
+            NCommandUpdate::RotateCamera(yaw, pitch) => {
+                self.camera.write().unwrap().add_yaw(yaw);
+                self.camera.write().unwrap().add_pitch(pitch);
+            }
+            NCommandUpdate::OrbitCamera(target, yaw, pitch, distance) => {
+                self.orbit_camera(target, yaw, pitch, distance);
+            }
+            NCommandUpdate::FovCamera(amount) => self.projection.zoom(amount),
+            NCommandUpdate::SetFov(fov_y) => self.projection.set_fov_y(fov_y),
+            NCommandUpdate::SetTimeOfDay(time) => {
+                self.time_of_day.set_time(time);
+            }
+            NCommandUpdate::SetPaused(paused) => self.set_paused(paused),
+            NCommandUpdate::SetTimeScale(scale) => self.set_time_scale(scale),
+            NCommandUpdate::StepSimulation => self.step(),
+            NCommandUpdate::SetWeather(coverage, wind) => {
+                self.weather.change_to(coverage, wind);
+            }
+            NCommandUpdate::SetTargetFps(fps) => self.set_target_fps(fps),
+            NCommandUpdate::SetPresentMode(present_mode) => {
+                if let Err(e) = self.set_present_mode(present_mode) {
+                    tracing::warn!(target: logging::RENDER, "{e}");
+                }
+            }
+            NCommandUpdate::SetFullscreen(mode) => {
+                if let Err(e) = self.set_fullscreen(mode) {
+                    tracing::warn!(target: logging::RENDER, "{e}");
+                }
+            }
+            NCommandUpdate::GrabCursor(grab) => {
+                if let Err(e) = self.grab_cursor(grab) {
+                    tracing::warn!(target: logging::INPUT, "{e}");
+                }
+            }
+            NCommandUpdate::SetResizable(resizable) => {
+                if let Err(e) = self.set_resizable(resizable) {
+                    tracing::warn!(target: logging::RENDER, "{e}");
+                }
+            }
+            NCommandUpdate::SetWindowSizeLimits(min, max) => {
+                if let Err(e) = self.set_window_size_limits(min, max) {
+                    tracing::warn!(target: logging::RENDER, "{e}");
+                }
+            }
+            NCommandUpdate::SwitchDimension(name) => {
+                if let Err(e) = self.switch_dimension(&name) {
+                    tracing::warn!(target: logging::CHUNKS, "{e}");
+                }
+            }
+            NCommandUpdate::SetBlock(position, id) => {
+                if let Err(e) = self.set_block(position, id) {
+                    tracing::warn!(target: logging::CHUNKS, "{e}");
+                }
+            }
+            NCommandUpdate::SpawnParticles(emitter) => self.particles.spawn(emitter),
+            NCommandUpdate::DebugDraw(shape, color) => self.debug_draw.shape(shape, color),
+            NCommandUpdate::CaptureText(capture) => {
+                self.input_state.set_text_capture(capture);
+                // Composed input like CJK only arrives while the IME is allowed.
+                if let Some(window) = self.window() {
+                    window.set_ime_allowed(capture);
+                }
+            }
+            NCommandUpdate::SetConsole(text) => self.set_console_text(text),
+            NCommandUpdate::ExportReproBundle => match self.export_repro_bundle() {
+                Ok(path) => self.debug_keys.toast(format!("Saved {}", path.display())),
+                Err(e) => tracing::warn!("{e}"),
+            },
+            // Transforms are kept by id, also for models that aren't added yet.
+            NCommandUpdate::SetTransform(model, matrix) => {
+                if let Some(id) = self.model_id(model) {
+                    self.transforms.get_mut().set_local(id, matrix)
+                }
+            }
+            NCommandUpdate::SetParent(model, parent) => {
+                let Some(id) = self.model_id(model) else {
+                    return;
+                };
+                let parent = match parent.map(|parent| self.model_id(parent)) {
+                    Some(None) => return,
+                    parent => parent.flatten(),
+                };
+                if let Err(e) = self.transforms.get_mut().set_parent(id, parent) {
+                    tracing::warn!(target: logging::RENDER, "{e}");
+                }
+            }
+            NCommandUpdate::RebuildModel(model) => self.rebuild_model(model),
+            NCommandUpdate::MarkDirty(model) => self.mark_dirty(model),
+            NCommandUpdate::UpdateBuffer(model, idx, data) => {
+                let mut models = self.models.borrow_mut();
+                if let Some(model) = models.get_mut(model) {
+                    let updated = model.update_buffer(
+                        &self.device,
+                        &mut self.buffer_pool.borrow_mut(),
+                        idx,
+                        data,
+                    );
+                    if let Err(e) = updated {
+                        tracing::warn!(target: logging::RENDER, "{e}");
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn parse_setup_command(
+        &self,
+        command: NCommandSetup,
+        n_model: &mut NModel,
+    ) -> Result<(), EngineError> {
         match command {
+            NCommandSetup::CreateBuffer(data, buffer_usages) => {
+                let mut pool = self.buffer_pool.borrow_mut();
+                let allocation = pool.allocate(&self.device, data.len(), buffer_usages);
+                pool.write(&self.device, &allocation, &data);
+                let n_buffer = NBuffer::new(allocation, data);
+                n_model.add_buffer(n_buffer);
+            }
+            NCommandSetup::CreateBindGroup(layout_entries, resources) => {
+                for resource in resources.iter() {
+                    match resource {
+                        NResource::Texture(name) => self.load_named_texture(name),
+                        NResource::Buffer(i) | NResource::StorageBuffer(i) => {
+                            EngineError::check_index("buffer", *i, n_model.buffers.len())?
+                        }
+                        _ => {}
+                    }
+                }
+
+                let textures = self.named_textures.borrow();
+                let mut cache = self.bind_group_cache.borrow_mut();
+                let layout = cache.layout(&self.device, &layout_entries);
+                let resources = resources
+                    .iter()
+                    .map(|resource| match resource {
+                        NResource::Buffer(i) | NResource::StorageBuffer(i) => {
+                            n_model.buffers()[*i].binding()
+                        }
+                        NResource::Texture(name) => {
+                            BindingResource::TextureView(&textures[name].view)
+                        }
+                        NResource::Sampler => BindingResource::Sampler(&self.resource_sampler),
+                        NResource::Global(GlobalResource::Camera) => {
+                            self.camera_buffer.as_entire_binding()
+                        }
+                        NResource::Global(GlobalResource::Lights) => {
+                            self.environment_buffer.as_entire_binding()
+                        }
+                        NResource::Global(GlobalResource::Time) => {
+                            self.time_buffer.as_entire_binding()
+                        }
+                    })
+                    .collect();
+                let bind_group = cache.bind_group(&self.device, &layout, resources);
+                drop(cache);
+                drop(textures);
+
+                n_model.add_bind_group(NBindGroup::new(bind_group, layout));
+            }
+            NCommandSetup::CreateBundle(commands) => {
+                n_model.bundles.push(NRenderBundle {
+                    commands,
+                    bundle: None,
+                });
+            }
+            NCommandSetup::CreateTransform(matrix) => {
+                let id = *n_model.id();
+                self.transforms.borrow_mut().insert(id, matrix);
+                let world = self.transforms.borrow().world(&id);
+                let data = cast_slice(&[world.to_cols_array()]).to_vec();
+                let mut pool = self.buffer_pool.borrow_mut();
+                let allocation =
+                    pool.allocate(&self.device, size_of::<Mat4>(), BufferUsages::UNIFORM);
+                pool.write(&self.device, &allocation, &data);
+                let buffer = NBuffer::new(allocation, data);
+                let bind_group = self.device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Transform Bind Group"),
+                    layout: &self.transform_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.binding(),
+                    }],
+                });
+                n_model.transform = Some(ModelTransform {
+                    buffer,
+                    bind_group,
+                    world,
+                });
+                n_model.update_bounds();
+            }
+            NCommandSetup::CreatePipeline(
+                bind_groups,
+                shader,
+                mut vertex_layouts,
+                use_model,
+                layer,
+                mut push_constant_ranges,
+            ) => {
+                for &idx in bind_groups.iter() {
+                    EngineError::check_index("bind group", idx, n_model.bind_groups.len())?;
+                }
+                if !push_constant_ranges.is_empty()
+                    && !self.capabilities.supports(Capability::PushConstants)
+                {
+                    tracing::warn!(
+                        target: logging::RENDER,
+                        "push constants aren't supported by this adapter, ignoring them"
+                    );
+                    push_constant_ranges.clear();
+                }
+                let mut bind_group_layouts = vec![];
+                if use_model {
+                    bind_group_layouts.push(&self.model_layout);
+                    vertex_layouts.insert(0, ModelVertex::desc());
+                }
+                // Per model bind groups and push constants can't be shared, so those
+                // pipelines never get batched.
+                let batch_key = (bind_groups.is_empty()
+                    && !n_model.has_transform()
+                    && push_constant_ranges.is_empty())
+                .then(|| BatchKey {
+                    shader,
+                    strides: vertex_layouts
+                        .iter()
+                        .map(|layout| layout.array_stride)
+                        .collect(),
+                    use_model,
+                    layer,
+                });
+                bind_group_layouts.push(&self.camera_bind_group_layout);
+                if n_model.has_transform() {
+                    bind_group_layouts.push(&self.transform_layout);
+                }
+                bind_group_layouts.append(
+                    &mut bind_groups
+                        .iter()
+                        .map(|idx| n_model.bind_groups[*idx].layout())
+                        .collect::<Vec<_>>(),
+                );
+
+                // Models asking for the same pipeline, like every chunk, share one.
+                let key = PipelineKey {
+                    shader,
+                    bind_group_layouts: bind_group_layouts
+                        .iter()
+                        .map(|layout| layout.global_id())
+                        .collect(),
+                    vertex_layouts: vertex_layouts.clone(),
+                    push_constant_ranges,
+                    layer,
+                    polygon_mode: self.polygon_mode(),
+                    sample_count: self.sample_count,
+                    reverse_z: self.reverse_z,
+                };
+                let render_pipeline = self.pipeline_cache.borrow_mut().pipeline(
+                    &self.device,
+                    key,
+                    &bind_group_layouts,
+                    |pipeline_layout| {
+                        create_render_pipeline(
+                            &self.device,
+                            PipelineDesc {
+                                layout: pipeline_layout,
+                                color_format: HDR_FORMAT,
+                                depth_format: Some(Texture::DEPTH_FORMAT),
+                                vertex_layouts: &vertex_layouts,
+                                shader: ShaderModuleDescriptor {
+                                    label: None,
+                                    source: ShaderSource::Wgsl(shader.into()),
+                                },
+                                layer,
+                                polygon_mode: self.polygon_mode(),
+                                sample_count: self.sample_count,
+                                reverse_z: self.reverse_z,
+                            },
+                        )
+                    },
+                );
+
+                n_model.add_shared_pipeline(render_pipeline);
+                *n_model.batch_keys.last_mut().unwrap() = batch_key;
+            }
+            NCommandSetup::SharePipeline(id, idx) => {
+                if let Some(model) = self.models.borrow().get_model(&id) {
+                    EngineError::check_index("pipeline", idx, model.pipelines.len())?;
+                    let pipeline = model.pipelines()[idx].clone();
+                    n_model.add_shared_pipeline(pipeline);
+                    *n_model.batch_keys.last_mut().unwrap() = model.batch_keys[idx].clone();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn parse_render_command<'b, 'c: 'b>(
+        &'c self,
+        command: &NCommandRender,
+        model: &'c NModel,
+        render_pass: &'b mut RenderPass<'c>,
+    ) {
+        match *command {
+            NCommandRender::DrawIndexedCulled(indices) => {
+                let indirect = self
+                    .gpu_culler
+                    .as_ref()
+                    .filter(|_| self.gpu_culling)
+                    .and_then(|culler| Some((culler, culler.draw_offset(model.id())?)));
+                match indirect {
+                    Some((culler, offset)) => {
+                        render_pass.draw_indexed_indirect(culler.indirect_buffer(), offset)
+                    }
+                    None => render_pass.draw_indexed(0..indices, 0, 0..1),
+                }
+            }
+            NCommandRender::ExecuteBundle(idx) => {
+                if let Some(bundle) = &model.bundles[idx].bundle {
+                    render_pass.execute_bundles(iter::once(bundle));
+                }
+            }
+            _ => self.encode_render_command(command, model, &self.camera_bind_group, render_pass),
+        }
+    }
+
+    // What a render pass and a render bundle encoder can both record.
+    fn encode_render_command<'c, R: RenderEncoder<'c>>(
+        &'c self,
+        command: &NCommandRender,
+        model: &'c NModel,
+        camera: &'c BindGroup,
+        encoder: &mut R,
+    ) {
+        match *command {
+            NCommandRender::SetLayer(_) => {}
             NCommandRender::SetPipeline(idx) => {
-                render_pass.set_pipeline(&model.pipelines()[idx]);
+                encoder.set_pipeline(&model.pipelines()[idx]);
             }
             NCommandRender::SetVertexBuffer(slot, idx) => {
-                render_pass.set_vertex_buffer(slot, model.buffers[idx].buffer().slice(..));
+                encoder.set_vertex_buffer(slot, model.buffers[idx].slice());
             }
             NCommandRender::SetIndexBuffer(idx, index_format) => {
-                render_pass.set_index_buffer(model.buffers[idx].buffer().slice(..), index_format);
+                encoder.set_index_buffer(model.buffers[idx].slice(), index_format);
             }
             NCommandRender::SetBindGroup(i, idx) => {
-                render_pass.set_bind_group(i, model.bind_groups()[idx].bind_group(), &[]);
+                encoder.set_bind_group(i, model.bind_groups()[idx].bind_group(), &[]);
+            }
+            NCommandRender::SetModelMaterial(i, model_idx, material_idx) => {
+                encoder.set_bind_group(
+                    i,
+                    &self.obj_models[model_idx].materials[material_idx].bind_group,
+                    &[],
+                );
+            }
+            NCommandRender::SetCameraBindGroup(i) => {
+                encoder.set_bind_group(i, camera, &[]);
+            }
+            NCommandRender::SetTransformBindGroup(i) => {
+                if let Some(transform) = &model.transform {
+                    encoder.set_bind_group(i, &transform.bind_group, &[]);
+                }
+            }
+            NCommandRender::Draw(vertices, instances) => {
+                encoder.draw(0..vertices, 0..instances);
             }
             NCommandRender::DrawIndexed(indices, instances) => {
-                render_pass.draw_indexed(0..indices, 0, 0..instances);
+                encoder.draw_indexed(0..indices, 0, 0..instances);
+            }
+            // Recorded ahead of time, so there's no culling result to draw from.
+            NCommandRender::DrawIndexedCulled(indices) => {
+                encoder.draw_indexed(0..indices, 0, 0..1);
+            }
+            // Bundles can't execute other bundles.
+            NCommandRender::ExecuteBundle(_) => {}
+            NCommandRender::SetPushConstants(stages, offset, data) => {
+                if self.capabilities.supports(Capability::PushConstants) {
+                    encoder.set_push_constants(stages, offset, cast_slice(&data));
+                }
             }
-            NCommandRender::DrawModelIndexed(idx, instances, bind_groups_idx) => {
+            NCommandRender::DrawModelIndexed(idx, instances, ref bind_groups_idx) => {
                 let bind_groups: Vec<&BindGroup> = bind_groups_idx
                     .iter()
                     .map(|i| model.bind_groups()[*i].bind_group())
                     .collect();
-                render_pass.draw_model_instanced(
+                // The transform takes the group right after the camera, like in the pipeline layout.
+                encoder.draw_model_instanced(
                     &self.obj_models[idx],
                     0..instances,
-                    &self.camera_bind_group,
-                    None,
+                    camera,
+                    model
+                        .transform
+                        .as_ref()
+                        .map(|transform| &transform.bind_group),
                     &bind_groups,
                 );
             }
         }
     }
 
-    pub fn update(&mut self, dt: Duration) {
-        self.actors
-            .mut_actors()
-            .par_iter_mut()
-            .map(|actor| actor.update(&dt, &self.input_state))
-            .collect::<Vec<CommandBuffer<NCommandUpdate>>>()
-            .into_iter()
-            .for_each(|buffer| {
-                for command in buffer.iter_command() {
-                    self.parse_update_command(command);
-                }
-            });
+    // Runs the block ticks due in the current dimension through the world,
+    // which places their edits like any other so light and meshes follow.
+    fn tick_blocks(&mut self) {
+        let Some(idx) = self.current_dimension else {
+            return;
+        };
+
+        let mut models = self.models.borrow_mut();
+        let dimension = &self.dimensions[idx];
+        let mut changed = vec![];
+        for (&chunk_position, id) in dimension.loaded() {
+            if let Some(model) = models.get_model_mut(id) {
+                let origin = chunk_position * CHUNK_SIZE;
+                changed.extend(
+                    model
+                        .model
+                        .take_changed()
+                        .into_iter()
+                        .map(|local| origin + local.as_ivec3()),
+                );
+            }
+        }
+
+        let edits = self.world.tick_blocks(
+            &mut LoadedChunks {
+                models: &mut models,
+                dimension,
+                pending: &mut [],
+            },
+            &changed,
+        );
+        drop(models);
+        for (position, lit) in edits {
+            self.block_changed(idx, position, lit);
+        }
+    }
+
+    fn fixed_update(&mut self) {
+        let timestep = self.timestep;
+        let buffers = self
+            .models
+            .borrow_mut()
+            .iter_models_mut()
+            .map(|model| model.model.tick(timestep))
+            .collect::<Vec<CommandBuffer<NCommandUpdate>>>();
+
+        {
+            let camera = self.camera.read().unwrap().pose();
+            let models = self.models.borrow();
+            let dimension = self.current_dimension.map(|idx| &self.dimensions[idx]);
+            let world = WorldView::new(camera, &models, dimension);
+            self.particles.tick(timestep, |cell| {
+                world
+                    .block(cell)
+                    .is_some_and(|id| block_info(id).is_solid())
+            });
+        }
+
+        for buffer in buffers {
+            for command in buffer.iter_command() {
+                self.parse_update_command(command);
+            }
+        }
+        self.tick_blocks();
+    }
+
+    fn update_actors(&mut self, dt: Duration) {
+        let camera = self.camera.read().unwrap().pose();
+        let models = self.models.borrow();
+        let dimension = self.current_dimension.map(|idx| &self.dimensions[idx]);
+        let world = WorldView::new(camera, &models, dimension);
+        #[cfg(not(target_arch = "wasm32"))]
+        let actors = self.actors.mut_actors().par_iter_mut();
+        #[cfg(target_arch = "wasm32")]
+        let actors = self.actors.mut_actors().iter_mut();
+        let buffers = actors
+            .map(|actor| actor.update(&dt, &self.input_state, &world))
+            .collect::<Vec<CommandBuffer<NCommandUpdate>>>();
+        drop(models);
+
+        for buffer in buffers {
+            for command in buffer.iter_command() {
+                self.parse_update_command(command);
+            }
+        }
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        let _span = tracing::info_span!(target: logging::RENDER, "update").entered();
+        let update_start = Instant::now();
+        self.tick_accumulator += dt.as_secs_f32();
+        let ticks = (self.tick_accumulator / self.timestep) as u32;
+        self.tick_accumulator -= ticks as f32 * self.timestep;
+
+        let sim_dt = if self.paused {
+            0.0
+        } else {
+            dt.as_secs_f32() * self.time_scale
+        };
+        self.sim_accumulator += sim_dt;
+        let sim_ticks = (self.sim_accumulator / self.timestep) as u32;
+        self.sim_accumulator -= sim_ticks as f32 * self.timestep;
+        // Steps take what's left of the frame's ticks, the rest wait for the
+        // next frames instead of getting dropped.
+        let steps = self
+            .pending_steps
+            .min(MAX_TICKS_PER_FRAME.saturating_sub(sim_ticks));
+        self.pending_steps -= steps;
+        let sim_ticks = sim_ticks + steps;
+        let sim_dt = sim_dt + steps as f32 * self.timestep;
+
+        // With a fixed timestep input is read on ticks, frames without one keep
+        // it for the next.
+        if !self.fixed_timestep {
+            self.debug_keys.update(&dt, &self.input_state);
+            self.update_actors(dt);
+        } else if ticks > 0 {
+            let elapsed = Duration::from_secs_f32(ticks as f32 * self.timestep);
+            self.debug_keys.update(&elapsed, &self.input_state);
+        }
+        if !self.fixed_timestep || ticks > 0 {
+            for command in self.ui.handle_input(&self.input_state) {
+                self.parse_update_command(command);
+            }
+        }
+
+        // Unpaused at normal speed both kinds of tick line up, actors first.
+        for tick in 0..ticks.max(sim_ticks).min(MAX_TICKS_PER_FRAME) {
+            if self.fixed_timestep && tick < ticks {
+                self.camera.write().unwrap().store_previous();
+                self.update_actors(Duration::from_secs_f32(self.timestep));
+                // Presses and mouse movement only count for the first tick.
+                if tick == 0 {
+                    self.input_state.update();
+                }
+            }
+            if tick < sim_ticks {
+                self.fixed_update();
+            }
+        }
+        self.stream_chunks(CHUNK_LOADS_PER_FRAME);
+        self.remesh_dirty_models(REMESHES_PER_FRAME);
+        self.update_transforms();
+
+        let camera_alpha = if self.fixed_timestep {
+            (self.tick_accumulator / self.timestep).min(1.0)
+        } else {
+            1.0
+        };
+        self.tick_alpha = (self.sim_accumulator / self.timestep).min(1.0);
+        self.camera
+            .write()
+            .unwrap()
+            .update_view(dt.as_secs_f32(), camera_alpha);
+        self.projection.update(dt.as_secs_f32());
+        self.camera_uniform
+            .update_view_proj(&self.camera.read().unwrap(), &self.projection);
+        self.queue
+            .write_buffer(&self.camera_buffer, 0, cast_slice(&[self.camera_uniform]));
+        self.update_targeted_block();
+        self.stream_textures();
+        self.time_of_day.update(sim_dt);
+        self.environment_uniform.update(&self.time_of_day);
+        self.weather.update(sim_dt);
+        self.environment_uniform
+            .set_clouds(self.weather.cloud_params());
+        self.queue.write_buffer(
+            &self.environment_buffer,
+            0,
+            cast_slice(&[self.environment_uniform]),
+        );
+        let (top, horizon, ground) = self.time_of_day.sky_colors();
+        self.sky.set_colors(top, horizon, ground);
+        self.sky.set_clouds(self.weather.cloud_params());
+        self.sky.set_celestial(
+            self.time_of_day.sun_direction(),
+            self.time_of_day.moon_direction(),
+            self.time_of_day.star_visibility(),
+            self.time_of_day.sun_angle(),
+        );
+        self.sky.update(
+            &self.queue,
+            Mat4::from_cols_array_2d(&self.camera_uniform.view_proj),
+        );
+        for viewport in self.viewports.iter_mut() {
+            viewport.update(&self.queue, dt.as_secs_f32());
+            self.sky
+                .update_view(&self.queue, viewport.sky(), viewport.view_proj());
+        }
+
+        self.time_uniform.update(sim_dt);
+        self.time_uniform.set_tick(self.tick_alpha, self.timestep);
+        self.queue
+            .write_buffer(&self.time_buffer, 0, cast_slice(&[self.time_uniform]));
+        self.particles.upload(&self.device, &self.queue);
+
+        self.last_time += dt.as_secs_f32();
+        self.calc_fps += 1;
+
+        if self.last_time >= 1.0 {
+            tracing::debug!(target: logging::RENDER, fps = self.calc_fps);
+            self.ui
+                .text_mut()
+                .set_text(self.fps_label, &format!("{} fps", self.calc_fps));
+            if let Some(stats) = self.texture_stream_stats() {
+                self.ui.text_mut().set_text(
+                    self.stats_label,
+                    &format!(
+                        "textures {:.1}/{:.0} MiB, {} pending",
+                        stats.resident_bytes as f32 / (1 << 20) as f32,
+                        stats.budget as f32 / (1 << 20) as f32,
+                        stats.pending
+                    ),
+                );
+            }
+            self.calc_fps = 0;
+            self.last_time = 0.0;
+        }
+
+        if self.debug_keys.is_enabled(DebugFlag::Wireframe) != self.wireframe {
+            self.set_wireframe(!self.wireframe);
+        }
+        if self.debug_keys.view() != self.debug_view {
+            self.debug_view = self.debug_keys.view();
+            self.write_debug_uniform();
+            self.post_process
+                .set_bypass(&self.queue, self.debug_view != DebugView::None);
+        }
+        if self.debug_keys.take_dirty() {
+            self.ui
+                .text_mut()
+                .set_text(self.toast_label, &self.debug_keys.toasts_text());
+        }
+        self.update_profiler_label(dt);
+        self.ui.update();
+        #[cfg(feature = "egui")]
+        self.update_ui();
+
+        if !self.fixed_timestep {
+            self.input_state.update();
+        }
+        self.profiler.set_frame_time(dt);
+        self.profiler
+            .record(FrameStage::Update, update_start.elapsed());
+    }
+
+    // The profiler's times and the render stats share a label, under each other.
+    fn update_profiler_label(&mut self, dt: Duration) {
+        let profiler = self.debug_keys.is_enabled(DebugFlag::Profiler);
+        let stats = self.debug_keys.is_enabled(DebugFlag::PipelineStats);
+        if !profiler && !stats {
+            self.profiler_refresh = 0.0;
+            self.ui.text_mut().set_text(self.profiler_label, "");
+            return;
+        }
+
+        self.profiler_refresh -= dt.as_secs_f32();
+        if self.profiler_refresh <= 0.0 {
+            self.profiler_refresh = PROFILER_REFRESH;
+            let text = [
+                profiler.then(|| self.profiler.overlay_text()),
+                stats.then(|| self.render_stats.overlay_text()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("\n");
+            self.ui.text_mut().set_text(self.profiler_label, &text);
+        }
+    }
+
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    // Filled in by `render`, all zeros before the first frame.
+    pub fn last_frame_stats(&self) -> &RenderStats {
+        &self.render_stats
+    }
+
+    pub fn crosshair(&self) -> Option<Crosshair> {
+        self.ui.crosshair()
+    }
+
+    pub fn set_crosshair(&mut self, crosshair: Option<Crosshair>) {
+        self.ui.set_crosshair(crosshair);
+    }
 
-        self.camera_uniform
-            .update_view_proj(&self.camera.borrow(), &self.projection);
-        self.queue
-            .write_buffer(&self.camera_buffer, 0, cast_slice(&[self.camera_uniform]));
+    // Text on the HUD that keeps its place relative to `anchor` when the window
+    // gets resized.
+    pub fn add_hud_label(
+        &mut self,
+        metrics: Metrics,
+        anchor: Anchor,
+        offset: (f32, f32),
+        color: glyphon::Color,
+    ) -> LabelId {
+        self.ui
+            .text_mut()
+            .add_anchored_label(metrics, anchor, offset, None, color)
+    }
 
-        self.last_time += dt.as_secs_f32();
-        self.calc_fps += 1;
+    pub fn set_hud_text(&mut self, label: LabelId, text: &str) {
+        self.ui.text_mut().set_text(label, text);
+    }
 
-        if self.last_time >= 1.0 {
-            println!("{} fps", self.calc_fps);
-            self.text_buffer.set_text(
-                &mut self.font_system,
-                &format!("{} fps", self.calc_fps),
-                Attrs::new().family(Family::SansSerif),
-                Shaping::Basic,
-            );
-            self.calc_fps = 0;
-            self.last_time = 0.0;
+    pub fn remove_hud_label(&mut self, label: LabelId) {
+        self.ui.text_mut().remove_label(label);
+    }
+
+    pub fn add_component(&mut self, component: Box<dyn Component>) -> ComponentId {
+        self.ui.add_component(component)
+    }
+
+    pub fn remove_component(&mut self, id: ComponentId) {
+        self.ui.remove_component(id);
+    }
+
+    pub fn add_widget(&mut self, anchor: Anchor, offset: Vec2, widget: Widget) -> UiRootId {
+        self.ui.add(anchor, offset, widget)
+    }
+
+    pub fn remove_widget(&mut self, id: UiRootId) {
+        self.ui.remove(id);
+    }
+
+    // False when no button or label in the tree has that name.
+    pub fn set_widget_text(&mut self, id: UiRootId, name: &str, text: &str) -> bool {
+        self.ui.set_text(id, name, text)
+    }
+
+    // The console drops down over the other labels, they come back once it closes.
+    fn set_console_text(&mut self, text: Option<String>) {
+        let open = text.is_some();
+        self.ui
+            .text_mut()
+            .set_text(self.console_label, text.as_deref().unwrap_or(""));
+        for label in [
+            self.fps_label,
+            self.toast_label,
+            self.stats_label,
+            self.profiler_label,
+        ] {
+            self.ui.text_mut().set_label_visible(label, !open);
+        }
+    }
+
+    // Model bounds and the borders of the loaded chunks, green where they pass
+    // frustum culling, red where they don't and white around the camera's chunk.
+    fn draw_debug_overlays(&mut self) {
+        if self.debug_keys.is_enabled(DebugFlag::Aabbs) {
+            for model in self.models.borrow().iter_models() {
+                self.debug_draw
+                    .aabb(&model.world_aabb(), [1.0, 1.0, 0.0, 1.0]);
+            }
         }
 
-        self.input_state.update();
+        if !self.debug_keys.is_enabled(DebugFlag::ChunkBorders) {
+            return;
+        }
+        let Some(dimension) = self.current_dimension.map(|idx| &self.dimensions[idx]) else {
+            return;
+        };
+        let culling =
+            FrustumCuller::from_matrix(Mat4::from_cols_array_2d(&self.camera_uniform.view_proj));
+        let position = self.camera.read().unwrap().view().position;
+        let (camera_chunk, _) = split_position((position + 0.5).floor().as_ivec3());
+        for (chunk_position, _) in dimension.loaded() {
+            // Blocks are centered on their position, so chunks start half a block early.
+            let min = (*chunk_position * CHUNK_SIZE).as_vec3() - 0.5;
+            let aabb = Aabb::from_params(min, min + CHUNK_SIZE as f32);
+            let color = if *chunk_position == camera_chunk {
+                [1.0, 1.0, 1.0, 1.0]
+            } else if culling.test_bounding_box(&aabb) {
+                [0.2, 1.0, 0.2, 0.6]
+            } else {
+                [1.0, 0.3, 0.2, 0.4]
+            };
+            self.debug_draw.aabb(&aabb, color);
+        }
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output
-            .texture
-            .create_view(&TextureViewDescriptor::default());
+        let render_start = Instant::now();
+        if let Some(gpu_time) = self
+            .gpu_timer
+            .as_mut()
+            .and_then(|timer| timer.poll(&self.device))
+        {
+            self.profiler.set_gpu_time(gpu_time);
+        }
+        let (buffer_uploads, upload_bytes) = self.buffer_pool.borrow_mut().submit(&self.queue);
+        let mut stats = RenderStats {
+            buffer_uploads,
+            upload_bytes,
+            ..Default::default()
+        };
+        self.draw_debug_overlays();
+        self.debug_draw.upload(&self.device, &self.queue);
+        let (output, view) = match &self.target {
+            RenderTarget::Window { surface, .. } => {
+                let output = surface.get_current_texture()?;
+                let view = output
+                    .texture
+                    .create_view(&TextureViewDescriptor::default());
+                (Some(output), view)
+            }
+            RenderTarget::Offscreen { texture } => {
+                (None, texture.create_view(&TextureViewDescriptor::default()))
+            }
+        };
 
         let mut encoder = self
             .device
@@ -690,105 +4099,790 @@ impl App<'_> {
                 label: Some("Render Encoder"),
             });
 
+        let mut providers = mem::take(&mut self.pass_providers);
+        // Models whose commands this frame point at nothing.
+        let mut broken = vec![];
+        // Streamed textures of the models in view with their distance.
+        let mut texture_requests = vec![];
+
+        if self.debug_view == DebugView::Overdraw {
+            encoder.clear_buffer(&self.overdraw_buffer, 0, None);
+        }
+
+        let culling =
+            FrustumCuller::from_matrix(Mat4::from_cols_array_2d(&self.camera_uniform.view_proj));
+        let cam_position = self.camera.read().unwrap().view().position;
+        let cull_span = tracing::info_span!(target: logging::RENDER, "cull").entered();
+        let cull_start = Instant::now();
+        let gpu_culling = self.gpu_culling && self.gpu_culler.is_some();
+        if gpu_culling {
+            if self.gpu_culler.as_ref().is_some_and(GpuCuller::is_dirty) {
+                self.upload_cull_entries();
+            }
+            if let Some(culler) = self.gpu_culler.as_ref() {
+                culler.dispatch(
+                    &self.queue,
+                    &mut encoder,
+                    &culling,
+                    cam_position,
+                    self.projection.z_far(),
+                );
+            }
+        }
+        let mut cull_time = cull_start.elapsed();
+        drop(cull_span);
+        let record_span = tracing::info_span!(target: logging::RENDER, "record").entered();
+
         {
-            let culling = FrustumCuller::from_matrix(Mat4::from_cols_array_2d(
-                &self.camera_uniform.view_proj,
-            ));
             let depth = self.depth_texture.clone();
             let cam_bind_group = self.camera_bind_group.clone();
             let models = self.models.clone();
             let models = models.borrow();
-            self.text_renderer
-                .prepare(
-                    &self.device,
-                    &self.queue,
-                    &mut self.font_system,
-                    &mut self.text_atlas,
-                    Resolution {
-                        width: self.config.width,
-                        height: self.config.height,
-                    },
-                    [TextArea {
-                        buffer: &self.text_buffer,
-                        left: 10.0,
-                        top: 10.0,
-                        scale: 1.0,
-                        bounds: TextBounds {
-                            left: 0,
-                            top: 0,
-                            right: 600,
-                            bottom: 160,
-                        },
-                        default_color: glyphon::Color::rgb(255, 255, 255),
-                    }],
-                    &mut self.cache,
-                )
-                .unwrap();
+            let hdr_view = self.post_process.hdr_view();
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: self.msaa_view.as_ref().unwrap_or(hdr_view),
+                    resolve_target: self.msaa_view.as_ref().map(|_| hdr_view),
                     ops: Operations {
-                        load: LoadOp::Clear(Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: LoadOp::Clear(self.sky.clear_color()),
                         store: StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
                     view: &depth.view,
                     depth_ops: Some(Operations {
-                        load: LoadOp::Clear(1.0),
+                        load: LoadOp::Clear(depth_clear_value(self.reverse_z)),
                         store: StoreOp::Store,
                     }),
                     stencil_ops: None,
                 }),
-                timestamp_writes: None,
+                timestamp_writes: self.gpu_timer.as_ref().and_then(GpuTimer::begin_writes),
                 occlusion_query_set: None,
             });
 
-            render_pass.set_bind_group(0, &cam_bind_group, &[]);
+            if !self.main_viewport.is_full() {
+                self.apply_viewport(&mut render_pass, self.main_viewport);
+            }
+            self.sky.render(&mut render_pass, &cam_bind_group);
+
+            // With GPU culling the chunk draws get culled by the compute pass and the
+            // rest is left to the rasterizer instead of testing every model here.
+            let cull_span = tracing::info_span!(target: logging::RENDER, "cull").entered();
+            let cull_start = Instant::now();
+            let z_far = self.projection.z_far();
+            let visible = cull_models(models.models(), |model| {
+                gpu_culling
+                    || model.position().distance_squared(cam_position) < z_far.powi(2)
+                        && model.in_frustum(&culling)
+            });
+            cull_time += cull_start.elapsed();
+            drop(cull_span);
+            stats.models = models.models().len();
+            stats.models_drawn = visible.len();
+            stats.models_culled = stats.models - visible.len();
+
+            #[cfg(not(target_arch = "wasm32"))]
+            let iter = visible.par_iter();
+            #[cfg(target_arch = "wasm32")]
+            let iter = visible.iter();
+            let obj_models = &self.obj_models;
+            let layers = iter
+                .map(|&model| (model, frame_layers(obj_models, model)))
+                .collect::<Vec<_>>();
+            let mut opaque = Vec::with_capacity(layers.len());
+            let mut transparent = Vec::with_capacity(layers.len());
+            for (model, layers) in layers {
+                let (opaque_layer, transparent_layer) = match layers {
+                    Ok(layers) => layers,
+                    Err(e) => {
+                        tracing::warn!(target: logging::RENDER, "Skipping model {}: {e}", model.id());
+                        broken.push(*model.id());
+                        continue;
+                    }
+                };
+                let distance = model
+                    .world_aabb()
+                    .center()
+                    .distance_squared(cam_position.into());
+                // With GPU culling every model gets here, only the ones in view ask.
+                if self.texture_streamer.is_some() && model.in_frustum(&culling) {
+                    texture_requests.extend(
+                        streamed_textures(&self.obj_models, &opaque_layer)
+                            .chain(streamed_textures(&self.obj_models, &transparent_layer))
+                            .map(|id| (id, distance.sqrt())),
+                    );
+                }
+                opaque.push((model, opaque_layer));
+                transparent.push((model, distance, transparent_layer));
+            }
 
-            let cam_position = self.camera.borrow().position();
+            let batch = self.batch.as_ref().filter(|_| gpu_culling);
+            if let (Some(batch), Some(culler)) = (batch, self.gpu_culler.as_ref()) {
+                if let Some(owner) = models.get_model(batch.owner()) {
+                    for command in batch.state() {
+                        self.parse_render_command(command, owner, &mut render_pass);
+                    }
+                    batch.draw(&mut render_pass, culler.indirect_buffer());
+                    stats.draw_calls += 1;
+                }
+            }
 
-            models
-                .models()
-                .par_iter()
-                .filter(|model| culling.test_bounding_box(model.aabb()))
-                .filter(|model| {
-                    model.position().distance_squared(cam_position)
-                        < self.projection.z_far().powi(2)
-                })
-                .map(|model| (model, model.render()))
-                .collect::<Vec<(&NModel, CommandBuffer<NCommandRender>)>>()
+            opaque
                 .into_iter()
-                .for_each(|(model, command_buffer)| {
-                    for command in command_buffer.iter_command() {
-                        self.parse_render_command(command, model, &mut render_pass);
+                .for_each(|(model, commands)| match &model.opaque_bundle {
+                    Some(bundle) if !gpu_culling => {
+                        render_pass.execute_bundles(iter::once(bundle));
+                        self.count_draws(&commands, model, &mut stats);
+                    }
+                    _ => {
+                        // The batch's single draw already counted as the call,
+                        // the triangles are still the model's.
+                        let start = batch.and_then(|batch| batch.tail(model.id()));
+                        let (batched, rest) = commands.split_at(start.unwrap_or(0));
+                        let draw_calls = stats.draw_calls;
+                        self.count_draws(batched, model, &mut stats);
+                        stats.draw_calls = draw_calls;
+                        self.count_draws(rest, model, &mut stats);
+                        for command in rest.iter() {
+                            self.parse_render_command(command, model, &mut render_pass);
+                        }
                     }
                 });
-            self.text_renderer
-                .render(&self.text_atlas, &mut render_pass)
-                .unwrap();
+            drop(render_pass);
+
+            transparent.retain(|(_, _, commands)| !commands.is_empty());
+            transparent.sort_by(|(_, a, _), (_, b, _)| b.total_cmp(a));
+
+            let globals = GlobalBindGroups {
+                camera: &cam_bind_group,
+                camera_layout: &self.camera_bind_group_layout,
+            };
+            // The builder borrows the encoder until the providers are done.
+            {
+                let mut builder = FrameGraphBuilder::new(
+                    &self.device,
+                    &self.queue,
+                    &mut encoder,
+                    FrameTargets {
+                        color_view: self.msaa_view.as_ref().unwrap_or(hdr_view),
+                        resolve_target: self.msaa_view.as_ref().map(|_| hdr_view),
+                        depth_view: &depth.view,
+                        color_format: HDR_FORMAT,
+                        sample_count: self.sample_count,
+                        reverse_z: self.reverse_z,
+                    },
+                );
+
+                for provider in providers
+                    .iter_mut()
+                    .filter(|provider| provider.stage() == PassStage::AfterOpaque)
+                {
+                    provider.render(&mut builder, &globals, &visible);
+                }
+
+                {
+                    let mut render_pass = builder.begin_pass("Transparent Render Pass");
+                    if !self.main_viewport.is_full() {
+                        self.apply_viewport(&mut render_pass, self.main_viewport);
+                    }
+                    transparent.into_iter().for_each(|(model, _, commands)| {
+                        self.count_draws(&commands, model, &mut stats);
+                        for command in commands.iter() {
+                            self.parse_render_command(command, model, &mut render_pass);
+                        }
+                    });
+                    self.particles.render(&mut render_pass, &cam_bind_group);
+                    self.debug_draw.render(&mut render_pass, &cam_bind_group);
+                    self.block_outline.render(&mut render_pass, &cam_bind_group);
+                }
+
+                for provider in providers
+                    .iter_mut()
+                    .filter(|provider| provider.stage() == PassStage::AfterTransparent)
+                {
+                    provider.render(&mut builder, &globals, &visible);
+                }
+            }
+
+            for viewport in self.viewports.iter() {
+                self.render_viewport(
+                    &mut encoder,
+                    viewport,
+                    models.models(),
+                    &mut broken,
+                    &mut stats,
+                );
+            }
+
+            self.post_process.render(&mut encoder, &view);
+            self.ui.prepare(&self.device, &self.queue);
+            #[cfg(feature = "egui")]
+            if let Some(layer) = self.egui_layer.as_mut() {
+                layer.prepare(&self.device, &self.queue, &mut encoder);
+            }
+
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("UI Render Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: self.gpu_timer.as_ref().and_then(GpuTimer::end_writes),
+                occlusion_query_set: None,
+            });
+            if self.debug_view == DebugView::Overdraw {
+                render_pass.set_pipeline(&self.debug_view_pipeline);
+                render_pass.set_bind_group(0, &cam_bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+            self.ui.render(&mut render_pass);
+            #[cfg(feature = "egui")]
+            if let Some(layer) = self.egui_layer.as_ref() {
+                layer.render(&mut render_pass);
+            }
+        }
+
+        self.pass_providers = providers;
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            timer.resolve(&mut encoder);
         }
+        drop(record_span);
+        self.profiler.record(FrameStage::Cull, cull_time);
+        self.profiler
+            .record(FrameStage::Record, render_start.elapsed() - cull_time);
 
+        let _span = tracing::info_span!(target: logging::RENDER, "submit").entered();
+        let submit_start = Instant::now();
         self.queue.submit(iter::once(encoder.finish()));
-        output.present();
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            timer.after_submit();
+        }
+        if let Some(output) = output {
+            output.present();
+        }
+        self.profiler
+            .record(FrameStage::Submit, submit_start.elapsed());
+        self.profiler.end_frame();
+        stats.timings = self.profiler.latest().copied().unwrap_or_default();
+        self.render_stats = stats;
+        self.skip_models(&broken);
+        if let Some(streamer) = self.texture_streamer.as_mut() {
+            for (id, distance) in texture_requests {
+                streamer.request(id, distance);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Draws of the commands as they'd go to the GPU, bundles included.
+    fn count_draws(&self, commands: &[NCommandRender], model: &NModel, stats: &mut RenderStats) {
+        for command in commands {
+            match *command {
+                NCommandRender::Draw(vertices, instances)
+                | NCommandRender::DrawIndexed(vertices, instances) => {
+                    stats.add_draw(vertices, instances)
+                }
+                NCommandRender::DrawIndexedCulled(indices) => stats.add_draw(indices, 1),
+                NCommandRender::DrawModelIndexed(idx, instances, _) => {
+                    for mesh in self.obj_models[idx].meshes.iter() {
+                        stats.add_draw(mesh.num_elements, instances);
+                    }
+                }
+                NCommandRender::ExecuteBundle(idx) => {
+                    self.count_draws(&model.bundles[idx].commands, model, stats)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn apply_viewport(&self, render_pass: &mut RenderPass<'_>, rect: ViewportRect) {
+        let (x, y, width, height) = rect.to_pixels(self.config.width, self.config.height);
+        render_pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(x, y, width, height);
+    }
+
+    // Draws the scene again for another camera, over its rectangle of what the
+    // main one drew. Its depth isn't needed anymore, so each of them clears it.
+    // Nothing is culled on the GPU for these and bundles are replayed command by
+    // command, both were made for the main camera.
+    fn render_viewport(
+        &self,
+        encoder: &mut CommandEncoder,
+        viewport: &Viewport,
+        models: &[NModel],
+        broken: &mut Vec<Uuid>,
+        stats: &mut RenderStats,
+    ) {
+        let camera = viewport.bind_group();
+        let culling = FrustumCuller::from_matrix(viewport.view_proj());
+        let cam_position = viewport.camera().read().unwrap().view().position;
+        let z_far = viewport.projection().z_far();
+        let visible = cull_models(models, |model| {
+            model.position().distance_squared(cam_position) < z_far.powi(2)
+                && model.in_frustum(&culling)
+        });
+        stats.models_drawn += visible.len();
+
+        let hdr_view = self.post_process.hdr_view();
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Viewport Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: self.msaa_view.as_ref().unwrap_or(hdr_view),
+                resolve_target: self.msaa_view.as_ref().map(|_| hdr_view),
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(depth_clear_value(self.reverse_z)),
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        self.apply_viewport(&mut render_pass, viewport.rect());
+        self.sky
+            .render_view(&mut render_pass, camera, viewport.sky());
+
+        let mut transparent = vec![];
+        for model in visible {
+            if broken.contains(model.id()) {
+                continue;
+            }
+            let (opaque, commands) = match frame_layers(&self.obj_models, model) {
+                Ok(layers) => layers,
+                Err(e) => {
+                    tracing::warn!(target: logging::RENDER, "Skipping model {}: {e}", model.id());
+                    broken.push(*model.id());
+                    continue;
+                }
+            };
+            self.count_draws(&opaque, model, stats);
+            for command in opaque.iter() {
+                self.encode_viewport_command(command, model, camera, &mut render_pass);
+            }
+            if !commands.is_empty() {
+                let distance = model
+                    .world_aabb()
+                    .center()
+                    .distance_squared(cam_position.into());
+                transparent.push((model, distance, commands));
+            }
+        }
+
+        transparent.sort_by(|(_, a, _), (_, b, _)| b.total_cmp(a));
+        for (model, _, commands) in transparent {
+            self.count_draws(&commands, model, stats);
+            for command in commands.iter() {
+                self.encode_viewport_command(command, model, camera, &mut render_pass);
+            }
+        }
+        self.particles.render(&mut render_pass, camera);
+    }
+
+    fn encode_viewport_command<'c>(
+        &'c self,
+        command: &NCommandRender,
+        model: &'c NModel,
+        camera: &'c BindGroup,
+        render_pass: &mut RenderPass<'c>,
+    ) {
+        match *command {
+            NCommandRender::ExecuteBundle(idx) => {
+                for command in model.bundles[idx].commands.iter() {
+                    self.encode_render_command(command, model, camera, render_pass);
+                }
+            }
+            _ => self.encode_render_command(command, model, camera, render_pass),
+        }
+    }
+
+    pub fn window(&self) -> Option<&Window> {
+        match &self.target {
+            RenderTarget::Window { window, .. } => Some(window),
+            RenderTarget::Offscreen { .. } => None,
+        }
+    }
+
+    fn require_window(&self) -> Result<&Window> {
+        self.window()
+            .ok_or_else(|| anyhow!("window settings need a window, the app is headless"))
+    }
+
+    pub fn fullscreen(&self) -> FullscreenMode {
+        match self.window().and_then(Window::fullscreen) {
+            Some(Fullscreen::Borderless(_)) => FullscreenMode::Borderless,
+            Some(Fullscreen::Exclusive(_)) => FullscreenMode::Exclusive,
+            None => FullscreenMode::Windowed,
+        }
+    }
+
+    // Exclusive fullscreen takes the biggest and then fastest video mode of the
+    // monitor the window is on.
+    pub fn set_fullscreen(&mut self, mode: FullscreenMode) -> Result<()> {
+        let window = self.require_window()?;
+        let fullscreen = match mode {
+            FullscreenMode::Windowed => None,
+            FullscreenMode::Borderless => Some(Fullscreen::Borderless(window.current_monitor())),
+            FullscreenMode::Exclusive => {
+                let video_mode = window
+                    .current_monitor()
+                    .and_then(|monitor| {
+                        monitor.video_modes().max_by_key(|mode| {
+                            (
+                                mode.size().width * mode.size().height,
+                                mode.refresh_rate_millihertz(),
+                            )
+                        })
+                    })
+                    .ok_or_else(|| anyhow!("no video mode available for exclusive fullscreen"))?;
+                Some(Fullscreen::Exclusive(video_mode))
+            }
+        };
+
+        window.set_fullscreen(fullscreen);
+        Ok(())
+    }
 
-        self.text_atlas.trim();
+    // Locks the cursor in place for mouse look, falling back to confining it to
+    // the window on platforms that can't lock it.
+    pub fn grab_cursor(&mut self, grab: bool) -> Result<()> {
+        let window = self.require_window()?;
+        if grab {
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))?;
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)?;
+        }
+
+        window.set_cursor_visible(!grab);
+        Ok(())
+    }
 
+    pub fn set_resizable(&mut self, resizable: bool) -> Result<()> {
+        self.require_window()?.set_resizable(resizable);
         Ok(())
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    pub fn set_window_size_limits(
+        &mut self,
+        min: Option<PhysicalSize<u32>>,
+        max: Option<PhysicalSize<u32>>,
+    ) -> Result<()> {
+        let window = self.require_window()?;
+        window.set_min_inner_size(min);
+        window.set_max_inner_size(max);
+        Ok(())
+    }
+
+    pub fn capture_frame(&self) -> Result<RgbaImage> {
+        let texture = match &self.target {
+            RenderTarget::Offscreen { texture } => texture,
+            RenderTarget::Window { .. } => {
+                return Err(anyhow!(
+                    "frames can only be captured from offscreen targets"
+                ))
+            }
+        };
+
+        let width = self.config.width;
+        let height = self.config.height;
+        let padded_row =
+            (width * 4).div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT) * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("capture_buffer"),
+            size: (padded_row * height) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        self.queue.submit(iter::once(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = flume::bounded(1);
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(Maintain::Wait);
+        receiver.recv()??;
+
+        let data = slice.get_mapped_range();
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for row in data.chunks(padded_row as usize) {
+            pixels.extend_from_slice(&row[..(width * 4) as usize]);
+        }
+        drop(data);
+        buffer.unmap();
+
+        RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow!("captured frame has an unexpected size"))
     }
 
     pub fn size(&self) -> PhysicalSize<u32> {
         self.size
     }
+
+    // Settings, world, the latest input and log lines and what the GPU supports,
+    // everything needed to go after a bug report.
+    pub fn repro_bundle(&self) -> ReproBundle {
+        let mut bundle = ReproBundle::new();
+
+        let config = [
+            format!("size = {}x{}", self.size.width, self.size.height),
+            format!("sample_count = {}", self.sample_count),
+            format!("present_mode = {:?}", self.config.present_mode),
+            format!("reverse_z = {}", self.reverse_z),
+            format!("fixed_timestep = {}", self.fixed_timestep),
+            format!("tick_rate = {:.1}", self.tick_rate()),
+            format!("time_scale = {:.2}", self.time_scale),
+            format!("gpu_culling = {}", self.gpu_culling),
+            format!("draw_batching = {}", self.draw_batching),
+            format!("fov = {:.1}", self.projection.fov_y().to_degrees()),
+            format!(
+                "render_distance = {}",
+                self.graphics_settings.render_distance
+            ),
+            format!("fog = {}", self.graphics_settings.fog),
+        ];
+        bundle.add("config.txt", config.join("\n") + "\n");
+        bundle.add("bindings.txt", self.action_map().to_config());
+        bundle.add("workers.txt", self.workers.counts().to_config());
+
+        let mut world = String::new();
+        for (idx, dimension) in self.dimensions.iter().enumerate() {
+            world.push_str(&format!(
+                "dimension {}{}: seed {}, load radius {}, {} chunks loaded\n",
+                dimension.name(),
+                if self.current_dimension == Some(idx) {
+                    " (current)"
+                } else {
+                    ""
+                },
+                dimension.generator().seed(),
+                dimension.load_radius(),
+                dimension.loaded_chunks()
+            ));
+        }
+        let pose = self.camera.read().unwrap().pose();
+        world.push_str(&format!(
+            "camera {} yaw {:.3} pitch {:.3}\n",
+            pose.position, pose.yaw, pose.pitch
+        ));
+        world.push_str(&format!("time of day {:.4}\n", self.time_of_day.time()));
+        world.push_str(&format!(
+            "weather coverage {:.2} wind {}\n",
+            self.weather.coverage(),
+            self.weather.wind()
+        ));
+        bundle.add("world.txt", world);
+
+        let input = self
+            .input_state
+            .recording()
+            .map(|event| format!("{event}\n"))
+            .collect::<String>();
+        bundle.add("input.txt", input);
+        bundle.add("log.txt", log_tail().join("\n") + "\n");
+        bundle.add("gpu.txt", self.capabilities.report());
+
+        bundle
+    }
+
+    // Writes the bundle next to the executable's working directory, named after
+    // when it got written.
+    pub fn export_repro_bundle(&self) -> Result<PathBuf> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let path = PathBuf::from(format!("repro-{timestamp}.zip"));
+        self.repro_bundle().write(&path)?;
+        tracing::info!("Saved reproduction bundle to {}", path.display());
+        Ok(path)
+    }
+}
+
+// Looks blocks up in the chunks loaded in the current dimension.
+impl BlockQuery for App<'_> {
+    fn is_solid(&self, position: IVec3) -> bool {
+        let Some(dimension) = self.current_dimension() else {
+            return false;
+        };
+        let (chunk_position, local) = split_position(position);
+        let Some(id) = dimension.chunk_id(chunk_position) else {
+            return false;
+        };
+
+        self.models
+            .borrow()
+            .get_model(id)
+            .and_then(|model| model.model.block(local))
+            .is_some_and(|id| block_info(id).is_solid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    struct TickCounter {
+        id: Uuid,
+        aabb: Aabb,
+        position: Vec3A,
+        ticks: Arc<AtomicU32>,
+    }
+
+    impl Model for TickCounter {
+        fn id(&self) -> &Uuid {
+            &self.id
+        }
+
+        fn aabb(&self) -> &Aabb {
+            &self.aabb
+        }
+
+        fn position(&self) -> &Vec3A {
+            &self.position
+        }
+
+        fn setup(&self) -> CommandBuffer<NCommandSetup> {
+            CommandBuffer::new()
+        }
+
+        fn render(&self) -> CommandBuffer<NCommandRender> {
+            CommandBuffer::new()
+        }
+
+        fn tick(&mut self, _dt: f32) -> CommandBuffer<NCommandUpdate> {
+            self.ticks.fetch_add(1, Ordering::Relaxed);
+            CommandBuffer::new()
+        }
+    }
+
+    // Points at a pipeline it doesn't have once `broken` gets set.
+    struct BreaksLater {
+        id: Uuid,
+        aabb: Aabb,
+        position: Vec3A,
+        broken: Arc<AtomicBool>,
+    }
+
+    impl Model for BreaksLater {
+        fn id(&self) -> &Uuid {
+            &self.id
+        }
+
+        fn aabb(&self) -> &Aabb {
+            &self.aabb
+        }
+
+        fn position(&self) -> &Vec3A {
+            &self.position
+        }
+
+        fn setup(&self) -> CommandBuffer<NCommandSetup> {
+            CommandBuffer::new()
+        }
+
+        fn render(&self) -> CommandBuffer<NCommandRender> {
+            let mut buffer = CommandBuffer::new();
+            if self.broken.load(Ordering::Relaxed) {
+                buffer.push(NCommandRender::SetPipeline(3));
+            }
+            buffer
+        }
+    }
+
+    #[test]
+    fn models_breaking_after_setup_get_skipped() {
+        let mut app = match pollster::block_on(App::new_headless(64, 64, 1)) {
+            Ok(app) => app,
+            Err(err) => {
+                eprintln!("skipping app test: {err}");
+                return;
+            }
+        };
+        let id = Uuid::new_v4();
+        let broken = Arc::new(AtomicBool::new(false));
+        app.add_model(NModel::new(Box::new(BreaksLater {
+            id,
+            aabb: Aabb::from_params(Vec3::splat(-1000.0), Vec3::splat(1000.0)),
+            position: Vec3A::ZERO,
+            broken: broken.clone(),
+        })));
+        app.update(Duration::from_secs_f32(FIXED_TIMESTEP));
+        app.render().unwrap();
+        assert!(!app.models.borrow().get_model(&id).unwrap().is_skipped());
+
+        broken.store(true, Ordering::Relaxed);
+        app.render().unwrap();
+        assert!(app.models.borrow().get_model(&id).unwrap().is_skipped());
+    }
+
+    #[test]
+    fn queued_steps_all_run() {
+        let mut app = match pollster::block_on(App::new_headless(64, 64, 1)) {
+            Ok(app) => app,
+            Err(err) => {
+                eprintln!("skipping app test: {err}");
+                return;
+            }
+        };
+        let ticks = Arc::new(AtomicU32::new(0));
+        app.add_model(NModel::new(Box::new(TickCounter {
+            id: Uuid::new_v4(),
+            aabb: Aabb::from_params(Vec3::ZERO, Vec3::ONE),
+            position: Vec3A::ZERO,
+            ticks: ticks.clone(),
+        })));
+
+        // Four ticks a frame from the time scale leave room for a single step.
+        app.set_time_scale(4.0);
+        for _ in 0..12 {
+            app.step();
+        }
+        let frames = 20;
+        for _ in 0..frames {
+            app.update(Duration::from_secs_f32(FIXED_TIMESTEP));
+        }
+
+        assert_eq!(app.pending_steps, 0);
+        let scaled = frames * 4;
+        let ticks = ticks.load(Ordering::Relaxed);
+        assert!(
+            (scaled + 12 - 1..=scaled + 12).contains(&ticks),
+            "{ticks} ticks"
+        );
+    }
 }
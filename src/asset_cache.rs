@@ -0,0 +1,132 @@
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::logging;
+
+pub const DEFAULT_CACHE_SIZE: u64 = 256 << 20;
+
+// FNV-1a, std's hasher isn't guaranteed to stay the same between releases and
+// the keys have to survive restarts.
+fn content_hash(kind: &str, version: u32, source: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let version = version.to_le_bytes();
+    let bytes = kind.as_bytes().iter().chain(version.iter()).chain(source);
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+// Processed assets stored on disk by the hash of what they were made from. A
+// changed source or a bumped processor version just misses, the stale entries
+// get evicted, least recently used first, once the cache grows past its limit.
+pub struct AssetCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl AssetCache {
+    pub fn new<P: Into<PathBuf>>(dir: P, max_bytes: u64) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, max_bytes })
+    }
+
+    fn path(&self, kind: &str, version: u32, source: &[u8]) -> PathBuf {
+        self.dir.join(format!(
+            "{kind}-{:016x}-{}",
+            content_hash(kind, version, source),
+            source.len()
+        ))
+    }
+
+    pub fn get(&self, kind: &str, version: u32, source: &[u8]) -> Option<Vec<u8>> {
+        let path = self.path(kind, version, source);
+        let data = fs::read(&path).ok()?;
+        // Touch the entry so eviction sees it as recently used.
+        if let Ok(file) = fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+
+        Some(data)
+    }
+
+    pub fn put(&self, kind: &str, version: u32, source: &[u8], data: &[u8]) {
+        let path = self.path(kind, version, source);
+        if let Err(e) = fs::write(&path, data) {
+            tracing::warn!(
+                target: logging::ASSETS,
+                "Couldn't write {} to the asset cache: {e}",
+                path.display()
+            );
+            return;
+        }
+
+        if let Err(e) = self.evict() {
+            tracing::warn!(target: logging::ASSETS, "Couldn't evict the asset cache: {e}");
+        }
+    }
+
+    // Returns the cached result or runs `process` and stores what it made.
+    pub fn get_or_insert_with<F: FnOnce() -> Result<Vec<u8>>>(
+        &self,
+        kind: &str,
+        version: u32,
+        source: &[u8],
+        process: F,
+    ) -> Result<Vec<u8>> {
+        if let Some(data) = self.get(kind, version, source) {
+            return Ok(data);
+        }
+
+        let data = process()?;
+        self.put(kind, version, source, &data);
+        Ok(data)
+    }
+
+    pub fn size(&self) -> Result<u64> {
+        let mut size = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            size += entry?.metadata()?.len();
+        }
+
+        Ok(size)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            fs::remove_file(entry?.path())?;
+        }
+
+        Ok(())
+    }
+
+    fn evict(&self) -> Result<()> {
+        let mut entries = vec![];
+        let mut size = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            size += metadata.len();
+            entries.push((metadata.modified()?, metadata.len(), entry.path()));
+        }
+        if size <= self.max_bytes {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        for (_, len, path) in entries {
+            if size <= self.max_bytes {
+                break;
+            }
+            fs::remove_file(path)?;
+            size -= len;
+        }
+
+        Ok(())
+    }
+}
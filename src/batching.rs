@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{Buffer, BufferAddress, BufferUsages, Device, IndexFormat, RenderPass};
+
+use crate::app::NModel;
+use crate::command_buffer::{NCommandRender, RenderLayer};
+
+// What a pipeline was created from, two models can only share a batch when their
+// pipelines are interchangeable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchKey {
+    pub shader: &'static str,
+    pub strides: Vec<BufferAddress>,
+    pub use_model: bool,
+    pub layer: RenderLayer,
+}
+
+// A model whose opaque pass starts with a single culled draw with nothing but
+// global state around it, so its geometry can be moved into a shared buffer.
+// What comes after that draw still gets drawn by the model, starting with a
+// pipeline of its own.
+pub struct BatchSource<'a> {
+    model: &'a NModel,
+    key: &'a BatchKey,
+    state: Vec<NCommandRender>,
+    streams: Vec<(u32, usize)>,
+    indices: usize,
+    index_count: u32,
+    tail: usize,
+}
+
+impl<'a> BatchSource<'a> {
+    pub fn from_model(model: &'a NModel) -> Option<Self> {
+        let (opaque, _) = model.render_layers();
+        let draw = opaque
+            .iter()
+            .position(|command| matches!(command, NCommandRender::DrawIndexedCulled(_)))?;
+        let (rest, tail) = opaque.split_at(draw);
+        let NCommandRender::DrawIndexedCulled(index_count) = tail[0] else {
+            return None;
+        };
+        if !matches!(tail.get(1), None | Some(NCommandRender::SetPipeline(_))) {
+            return None;
+        }
+
+        let mut key = None;
+        let mut state = vec![];
+        let mut streams = vec![];
+        let mut indices = None;
+        for command in rest {
+            match *command {
+                NCommandRender::SetPipeline(idx) => {
+                    key = model.batch_key(idx);
+                    state.push(command.clone());
+                }
+                NCommandRender::SetVertexBuffer(slot, idx) => streams.push((slot, idx)),
+                NCommandRender::SetIndexBuffer(idx, IndexFormat::Uint32) => indices = Some(idx),
+                NCommandRender::SetModelMaterial(..) | NCommandRender::SetCameraBindGroup(_) => {
+                    state.push(command.clone())
+                }
+                NCommandRender::SetLayer(_) => {}
+                _ => return None,
+            }
+        }
+
+        let key = key?;
+        if streams.len() != key.strides.len() {
+            return None;
+        }
+
+        Some(Self {
+            model,
+            key,
+            state,
+            streams,
+            indices: indices?,
+            index_count,
+            tail: draw + 1,
+        })
+    }
+
+    pub fn model(&self) -> &'a NModel {
+        self.model
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    // Pipeline indices are per model, so they are left out when comparing state.
+    fn compatible(&self, other: &BatchSource) -> bool {
+        let global = |state: &[NCommandRender]| {
+            state
+                .iter()
+                .filter(|command| !matches!(command, NCommandRender::SetPipeline(_)))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let slots =
+            |streams: &[(u32, usize)]| streams.iter().map(|(slot, _)| *slot).collect::<Vec<_>>();
+
+        self.key == other.key
+            && slots(&self.streams) == slots(&other.streams)
+            && global(&self.state) == global(&other.state)
+    }
+}
+
+// Each batched source with the first index and base vertex of its geometry.
+pub type BatchOffsets<'a> = Vec<(BatchSource<'a>, u32, i32)>;
+
+// All the batched geometry in one set of vertex and index buffers, drawn with a
+// single multi draw over the indirect arguments the culling pass wrote.
+pub struct GeometryBatch {
+    owner: Uuid,
+    state: Vec<NCommandRender>,
+    // Where the commands of each batched model go on after its batched draw.
+    tails: HashMap<Uuid, usize>,
+    vertex_buffers: Vec<(u32, Buffer)>,
+    index_buffer: Buffer,
+    count: u32,
+}
+
+impl GeometryBatch {
+    // Takes every source compatible with the first one out of `sources`.
+    pub fn build<'a>(
+        device: &Device,
+        sources: &mut Vec<BatchSource<'a>>,
+    ) -> Option<(Self, BatchOffsets<'a>)> {
+        if sources.is_empty() {
+            return None;
+        }
+        let first = sources.remove(0);
+        let (mut batched, rest): (Vec<_>, Vec<_>) = sources
+            .drain(..)
+            .partition(|source| source.compatible(&first));
+        *sources = rest;
+        batched.insert(0, first);
+
+        let first = &batched[0];
+        let owner = *first.model.id();
+        let state = first.state.clone();
+        // Every stream has the same vertex count, the first one is enough to find it.
+        let (first_slot, _) = first.streams[0];
+        let stride = first.key.strides[first_slot as usize];
+        let slots = first
+            .streams
+            .iter()
+            .map(|(slot, _)| *slot)
+            .collect::<Vec<u32>>();
+
+        let mut vertex_data = vec![vec![]; slots.len()];
+        let mut index_data = vec![];
+        let mut offsets = vec![];
+        let mut first_index = 0;
+        let mut base_vertex = 0;
+        for source in batched {
+            for (i, (_, idx)) in source.streams.iter().enumerate() {
+                vertex_data[i].extend_from_slice(source.model.buffers()[*idx].data());
+            }
+            let vertex_count =
+                source.model.buffers()[source.streams[0].1].data().len() as u64 / stride;
+            index_data.extend_from_slice(source.model.buffers()[source.indices].data());
+
+            offsets.push((source, first_index, base_vertex));
+            first_index += offsets.last().unwrap().0.index_count;
+            base_vertex += vertex_count as i32;
+        }
+
+        let vertex_buffers = slots
+            .into_iter()
+            .zip(vertex_data)
+            .map(|(slot, data)| {
+                let buffer = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some("Batch Vertex Buffer"),
+                    contents: &data,
+                    usage: BufferUsages::VERTEX,
+                });
+                (slot, buffer)
+            })
+            .collect();
+        let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Batch Index Buffer"),
+            contents: &index_data,
+            usage: BufferUsages::INDEX,
+        });
+
+        let batch = Self {
+            owner,
+            state,
+            tails: offsets
+                .iter()
+                .map(|(source, _, _)| (*source.model.id(), source.tail))
+                .collect(),
+            vertex_buffers,
+            index_buffer,
+            count: offsets.len() as u32,
+        };
+
+        Some((batch, offsets))
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.tails.contains_key(id)
+    }
+
+    // Opaque commands of a batched model left to replay after the batch.
+    pub fn tail(&self, id: &Uuid) -> Option<usize> {
+        self.tails.get(id).copied()
+    }
+
+    pub fn owner(&self) -> &Uuid {
+        &self.owner
+    }
+
+    // Global state of the owner to replay before drawing.
+    pub fn state(&self) -> &[NCommandRender] {
+        &self.state
+    }
+
+    // Batched draws take the first `count` slots of the indirect buffer.
+    pub fn draw<'a>(&'a self, render_pass: &mut RenderPass<'a>, indirect: &'a Buffer) {
+        for (slot, buffer) in self.vertex_buffers.iter() {
+            render_pass.set_vertex_buffer(*slot, buffer.slice(..));
+        }
+        render_pass.set_index_buffer(self.index_buffer.slice(..), IndexFormat::Uint32);
+        render_pass.multi_draw_indexed_indirect(indirect, 0, self.count);
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, Buffer, Device, Id, Sampler, TextureView,
+};
+
+// What a bind group entry points at, by identity, with the bound range for
+// buffers since pooled allocations share their buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceKey {
+    Buffer(Id<Buffer>, u64, Option<u64>),
+    TextureView(Id<TextureView>),
+    Sampler(Id<Sampler>),
+}
+
+impl ResourceKey {
+    // Arrays of resources aren't cached.
+    pub fn of(resource: &BindingResource) -> Option<Self> {
+        match resource {
+            BindingResource::Buffer(binding) => Some(ResourceKey::Buffer(
+                binding.buffer.global_id(),
+                binding.offset,
+                binding.size.map(|size| size.get()),
+            )),
+            BindingResource::TextureView(view) => Some(ResourceKey::TextureView(view.global_id())),
+            BindingResource::Sampler(sampler) => Some(ResourceKey::Sampler(sampler.global_id())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BindGroupCacheStats {
+    pub layouts: usize,
+    pub bind_groups: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+// Layouts keyed by their entries and bind groups keyed by their layout and the
+// resources they bind, so models asking for the same combination share one.
+// Entries nothing else holds anymore go away on `prune`.
+#[derive(Default)]
+pub struct BindGroupCache {
+    layouts: HashMap<Vec<BindGroupLayoutEntry>, Arc<BindGroupLayout>>,
+    bind_groups: HashMap<(Id<BindGroupLayout>, Vec<ResourceKey>), Arc<BindGroup>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl BindGroupCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn layout(
+        &mut self,
+        device: &Device,
+        entries: &[BindGroupLayoutEntry],
+    ) -> Arc<BindGroupLayout> {
+        self.layouts
+            .entry(entries.to_vec())
+            .or_insert_with(|| {
+                Arc::new(device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                    label: None,
+                    entries,
+                }))
+            })
+            .clone()
+    }
+
+    // Resources are bound in order, starting from binding 0.
+    pub fn bind_group(
+        &mut self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        resources: Vec<BindingResource>,
+    ) -> Arc<BindGroup> {
+        let keys = resources
+            .iter()
+            .map(ResourceKey::of)
+            .collect::<Option<Vec<_>>>();
+        let key = keys.map(|keys| (layout.global_id(), keys));
+        if let Some(bind_group) = key.as_ref().and_then(|key| self.bind_groups.get(key)) {
+            self.hits += 1;
+            return bind_group.clone();
+        }
+
+        self.misses += 1;
+        let entries = resources
+            .into_iter()
+            .enumerate()
+            .map(|(idx, resource)| BindGroupEntry {
+                binding: idx as u32,
+                resource,
+            })
+            .collect::<Vec<_>>();
+        let bind_group = Arc::new(device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &entries,
+        }));
+        if let Some(key) = key {
+            self.bind_groups.insert(key, bind_group.clone());
+        }
+
+        bind_group
+    }
+
+    pub fn prune(&mut self) {
+        self.bind_groups
+            .retain(|_, bind_group| Arc::strong_count(bind_group) > 1);
+        self.layouts
+            .retain(|_, layout| Arc::strong_count(layout) > 1);
+    }
+
+    pub fn stats(&self) -> BindGroupCacheStats {
+        BindGroupCacheStats {
+            layouts: self.layouts.len(),
+            bind_groups: self.bind_groups.len(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
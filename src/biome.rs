@@ -0,0 +1,188 @@
+use anyhow::{anyhow, Result};
+use glam::Vec2;
+
+use crate::chunks::{GRAVEL_ID, SAND_ID, STONE_ID};
+use crate::seed::WorldSeed;
+
+// Width in blocks of the noise cells biomes get picked from.
+const BIOME_SCALE: f32 = 96.0;
+// Terrain parameters get averaged over this far around a column, so biome
+// borders slope instead of ending in a cliff.
+const BLEND_RADIUS: i32 = 4;
+const NOISE_SPREAD: f32 = 1.6;
+
+// Feature the biome map takes its seed from.
+const BIOME_MAP: &str = "biome_map";
+
+pub const PLAINS: &str = "plains";
+pub const DESERT: &str = "desert";
+pub const HILLS: &str = "hills";
+
+// What the grass on top of the terrain and the decorations growing out of it
+// get multiplied by.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BiomeTint {
+    pub grass: [f32; 3],
+    pub foliage: [f32; 3],
+}
+
+impl Default for BiomeTint {
+    fn default() -> Self {
+        Self {
+            grass: [1.0, 1.0, 1.0],
+            foliage: [0.22, 0.5, 0.16],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Biome {
+    pub name: &'static str,
+    // The surface waves around the base height, this far up and down.
+    pub base_height: f32,
+    pub height_variation: f32,
+    // Block the top layers of the terrain are made of, stone below them.
+    pub surface: u16,
+    pub surface_depth: u32,
+    // Whether grass and flowers grow on the surface.
+    pub vegetation: bool,
+    pub tint: BiomeTint,
+}
+
+impl Biome {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            base_height: 2.0,
+            height_variation: 1.5,
+            surface: STONE_ID,
+            surface_depth: 1,
+            vegetation: true,
+            tint: BiomeTint::default(),
+        }
+    }
+}
+
+// Height and variation of the terrain in a column, blended between biomes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TerrainParams {
+    pub base_height: f32,
+    pub height_variation: f32,
+}
+
+// Biomes the generator picks from, in registration order. Starts out with the
+// built-in ones, custom biomes get registered next to them.
+pub struct BiomeRegistry {
+    biomes: Vec<Biome>,
+}
+
+impl BiomeRegistry {
+    pub fn new() -> Self {
+        Self {
+            biomes: vec![
+                Biome {
+                    tint: BiomeTint {
+                        grass: [0.9, 1.0, 0.85],
+                        ..BiomeTint::default()
+                    },
+                    ..Biome::new(PLAINS)
+                },
+                Biome {
+                    base_height: 3.0,
+                    height_variation: 0.8,
+                    surface: SAND_ID,
+                    surface_depth: 3,
+                    vegetation: false,
+                    tint: BiomeTint {
+                        grass: [1.0, 0.95, 0.85],
+                        foliage: [0.5, 0.5, 0.25],
+                    },
+                    ..Biome::new(DESERT)
+                },
+                Biome {
+                    base_height: 6.0,
+                    height_variation: 4.0,
+                    surface: GRAVEL_ID,
+                    surface_depth: 1,
+                    tint: BiomeTint {
+                        grass: [0.85, 0.95, 0.9],
+                        foliage: [0.16, 0.42, 0.2],
+                    },
+                    ..Biome::new(HILLS)
+                },
+            ],
+        }
+    }
+
+    pub fn register(&mut self, biome: Biome) -> Result<()> {
+        if self.get(biome.name).is_some() {
+            return Err(anyhow!("biome `{}` is already registered", biome.name));
+        }
+
+        self.biomes.push(biome);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Biome> {
+        self.biomes.iter().find(|biome| biome.name == name)
+    }
+
+    pub fn biomes(&self) -> &[Biome] {
+        &self.biomes
+    }
+
+    // Biomes are laid out by smooth noise, so the ones next to each other in
+    // the registry tend to border each other too.
+    pub fn biome_at(&self, seed: WorldSeed, x: i32, z: i32) -> &Biome {
+        let noise = value_noise(
+            seed.feature(BIOME_MAP),
+            Vec2::new(x as f32, z as f32) / BIOME_SCALE,
+        );
+        // Interpolated noise bunches up in the middle, spread it back out so
+        // the first and last biomes come up about as often as the others.
+        let noise = ((noise - 0.5) * NOISE_SPREAD + 0.5).clamp(0.0, 1.0);
+        let idx = (noise * self.biomes.len() as f32) as usize;
+        &self.biomes[idx.min(self.biomes.len() - 1)]
+    }
+
+    pub fn terrain_at(&self, seed: WorldSeed, x: i32, z: i32) -> TerrainParams {
+        let mut base_height = 0.0;
+        let mut height_variation = 0.0;
+        let mut samples = 0.0;
+        for dx in [-BLEND_RADIUS, 0, BLEND_RADIUS] {
+            for dz in [-BLEND_RADIUS, 0, BLEND_RADIUS] {
+                let biome = self.biome_at(seed, x + dx, z + dz);
+                base_height += biome.base_height;
+                height_variation += biome.height_variation;
+                samples += 1.0;
+            }
+        }
+
+        TerrainParams {
+            base_height: base_height / samples,
+            height_variation: height_variation / samples,
+        }
+    }
+}
+
+impl Default for BiomeRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn lattice(seed: WorldSeed, x: i32, y: i32) -> f32 {
+    (seed.hash2(x, y) >> 40) as f32 / (1u64 << 24) as f32
+}
+
+// Random values on a grid, smoothly interpolated in between. From 0 to 1.
+fn value_noise(seed: WorldSeed, position: Vec2) -> f32 {
+    let cell = position.floor();
+    let t = position - cell;
+    let t = t * t * (3.0 - 2.0 * t);
+    let (x, y) = (cell.x as i32, cell.y as i32);
+    let top = lattice(seed, x, y) + (lattice(seed, x + 1, y) - lattice(seed, x, y)) * t.x;
+    let bottom =
+        lattice(seed, x, y + 1) + (lattice(seed, x + 1, y + 1) - lattice(seed, x, y + 1)) * t.x;
+    top + (bottom - top) * t.y
+}
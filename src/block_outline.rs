@@ -0,0 +1,205 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::{IVec3, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, BlendState, Buffer, BufferBindingType, BufferUsages,
+    ColorTargetState, ColorWrites, DepthBiasState, DepthStencilState, Device, FragmentState,
+    FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StencilState, VertexState,
+};
+
+use crate::depth_compare;
+use crate::post_process::HDR_FORMAT;
+use crate::texture::Texture;
+
+// How far the outline sits outside the block, so it doesn't z-fight its faces.
+const INFLATE: f32 = 0.005;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct OutlineUniform {
+    min: [f32; 4],
+    max: [f32; 4],
+    color: [f32; 4],
+}
+
+fn create_pipeline(
+    device: &Device,
+    camera_layout: &BindGroupLayout,
+    layout: &BindGroupLayout,
+    sample_count: u32,
+    reverse_z: bool,
+) -> RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("block_outline_pipeline_layout"),
+        bind_group_layouts: &[camera_layout, layout],
+        push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("block_outline_shader"),
+        source: ShaderSource::Wgsl(include_str!("../shaders/block_outline.wgsl").into()),
+    });
+
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("block_outline_pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: HDR_FORMAT,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::LineList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: depth_compare(reverse_z),
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// The edges of the block the camera is looking at. The 24 line vertices come
+// out of the shader, all it needs is the box.
+pub struct BlockOutline {
+    uniform: OutlineUniform,
+    buffer: Buffer,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+    target: Option<IVec3>,
+    visible: bool,
+}
+
+impl BlockOutline {
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        sample_count: u32,
+        reverse_z: bool,
+    ) -> Self {
+        let uniform = OutlineUniform {
+            min: [0.0; 4],
+            max: [0.0; 4],
+            color: [0.0, 0.0, 0.0, 0.8],
+        };
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Block Outline Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("block_outline_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("block_outline_bind_group"),
+        });
+        let pipeline = create_pipeline(device, camera_layout, &layout, sample_count, reverse_z);
+
+        Self {
+            uniform,
+            buffer,
+            layout,
+            bind_group,
+            pipeline,
+            target: None,
+            visible: true,
+        }
+    }
+
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        sample_count: u32,
+        reverse_z: bool,
+    ) {
+        self.pipeline =
+            create_pipeline(device, camera_layout, &self.layout, sample_count, reverse_z);
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        self.visible = visible;
+    }
+
+    pub fn set_color(&mut self, queue: &Queue, color: [f32; 4]) {
+        self.uniform.color = color;
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    pub fn target(&self) -> Option<IVec3> {
+        self.target
+    }
+
+    // Blocks are centered on their position.
+    pub fn set_target(&mut self, queue: &Queue, target: Option<IVec3>) {
+        self.target = target;
+        if let Some(block) = target {
+            let center = block.as_vec3();
+            let half = Vec3::splat(0.5 + INFLATE);
+            self.uniform.min = (center - half).extend(0.0).to_array();
+            self.uniform.max = (center + half).extend(0.0).to_array();
+        }
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        if !self.visible || self.target.is_none() {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..24, 0..1);
+    }
+}
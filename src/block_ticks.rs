@@ -0,0 +1,257 @@
+use glam::IVec3;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::chunks::Block;
+use crate::registry::{block_info, BehaviorRegistry, BlockBehavior};
+use crate::structures::BlockAccess;
+use crate::world_edit::split_position;
+
+// Scheduled ticks run at most in a single fixed tick, the rest waits for the next one.
+pub const TICK_BUDGET: usize = 256;
+
+const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+// What a behavior sees of the world on its tick. Reads show the blocks as they
+// were before the tick and edits get applied once every due tick has run, so
+// the order ticks run in doesn't change what they do.
+pub struct TickContext<'a> {
+    access: &'a dyn BlockAccess,
+    edits: &'a mut Vec<(IVec3, Option<u32>)>,
+    scheduled: &'a mut Vec<(IVec3, u32)>,
+}
+
+impl TickContext<'_> {
+    pub fn block(&self, position: IVec3) -> Option<u16> {
+        self.access.block(position)
+    }
+
+    // Everything stored for the block, like fluid levels, see Model::block_state.
+    pub fn block_state(&self, position: IVec3) -> Option<u32> {
+        self.access.block_state(position)
+    }
+
+    pub fn chunk_loaded(&self, chunk_position: IVec3) -> bool {
+        self.access.chunk_loaded(chunk_position)
+    }
+
+    // Goes through the world's usual set_block after the tick. Does nothing
+    // where no chunk is loaded.
+    pub fn set_block(&mut self, position: IVec3, id: Option<u16>) {
+        self.set_block_state(position, id.map(|id| Block::default().with_id(id).data()));
+    }
+
+    // Like set_block, with a state from block_state.
+    pub fn set_block_state(&mut self, position: IVec3, state: Option<u32>) {
+        if self.chunk_loaded(split_position(position).0) {
+            self.edits.push((position, state));
+        }
+    }
+
+    // Ticks the cell again in `delay` fixed ticks, on top of the ticks edits
+    // schedule around them.
+    pub fn schedule(&mut self, position: IVec3, delay: u32) {
+        self.scheduled.push((position, delay));
+    }
+}
+
+// Cells waiting for the behavior of their block to run, by the tick they're
+// due on. Chunks register the cells that changed in them, which schedules them
+// and their neighbours.
+#[derive(Default)]
+pub struct BlockTicks {
+    now: u64,
+    due: BTreeMap<u64, Vec<IVec3>>,
+    queued: HashSet<IVec3>,
+}
+
+impl BlockTicks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A cell is queued once, scheduling it again before its tick does nothing.
+    pub fn schedule(&mut self, position: IVec3, delay: u32) {
+        if self.queued.insert(position) {
+            self.due
+                .entry(self.now + delay.max(1) as u64)
+                .or_default()
+                .push(position);
+        }
+    }
+
+    // Schedules the changed cells and the ones around them that have a block
+    // with a behavior. Sorted first, so the order chunks hand them over in
+    // doesn't matter.
+    pub fn register(
+        &mut self,
+        changed: &[IVec3],
+        access: &dyn BlockAccess,
+        behaviors: &BehaviorRegistry,
+    ) {
+        let mut positions = changed
+            .iter()
+            .flat_map(|&position| {
+                NEIGHBOURS
+                    .iter()
+                    .map(move |&offset| position + offset)
+                    .chain([position])
+            })
+            .collect::<Vec<_>>();
+        positions.sort_unstable_by_key(|position| (position.x, position.y, position.z));
+        positions.dedup();
+
+        for position in positions {
+            if let Some(behavior) = behavior_at(access, behaviors, position) {
+                self.schedule(position, behavior.delay());
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.due.clear();
+        self.queued.clear();
+    }
+
+    // Advances by one fixed tick and runs the behaviors of the cells due by
+    // then, returns their edits as block states in order for the caller to
+    // apply.
+    pub fn tick(
+        &mut self,
+        access: &dyn BlockAccess,
+        behaviors: &BehaviorRegistry,
+    ) -> Vec<(IVec3, Option<u32>)> {
+        self.now += 1;
+        let mut batch = vec![];
+        while batch.len() < TICK_BUDGET {
+            let Some(mut entry) = self.due.first_entry() else {
+                break;
+            };
+            if *entry.key() > self.now {
+                break;
+            }
+
+            let count = entry.get().len().min(TICK_BUDGET - batch.len());
+            batch.extend(entry.get_mut().drain(..count));
+            if entry.get().is_empty() {
+                entry.remove();
+            }
+        }
+
+        let mut edits = vec![];
+        let mut scheduled = vec![];
+        for position in batch {
+            self.queued.remove(&position);
+            // Cells whose chunk unloaded or whose block changed since go away.
+            let Some(behavior) = behavior_at(access, behaviors, position) else {
+                continue;
+            };
+            let mut ctx = TickContext {
+                access,
+                edits: &mut edits,
+                scheduled: &mut scheduled,
+            };
+            behavior.tick(&mut ctx, position);
+        }
+        for (position, delay) in scheduled {
+            self.schedule(position, delay);
+        }
+
+        edits
+    }
+}
+
+fn behavior_at<'a>(
+    access: &dyn BlockAccess,
+    behaviors: &'a BehaviorRegistry,
+    position: IVec3,
+) -> Option<&'a dyn BlockBehavior> {
+    access.block(position).and_then(|id| behaviors.get(id))
+}
+
+// Gravity blocks with nothing solid below them fall a cell at a time, across
+// chunk borders too. This is the only thing that makes blocks fall.
+pub struct GravityBehavior;
+
+impl BlockBehavior for GravityBehavior {
+    fn delay(&self) -> u32 {
+        2
+    }
+
+    fn tick(&self, ctx: &mut TickContext<'_>, position: IVec3) {
+        let below = position - IVec3::Y;
+        let supported = ctx.block(below).is_some_and(|id| block_info(id).is_solid());
+        if !ctx.chunk_loaded(split_position(below).0) || supported {
+            return;
+        }
+
+        let state = ctx.block_state(position);
+        ctx.set_block(position, None);
+        ctx.set_block_state(below, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::{SAND_ID, STONE_ID};
+    use crate::seed::WorldSeed;
+    use crate::world::World;
+    use crate::worldgen::WorldGenerator;
+
+    #[test]
+    fn sand_column_settles_across_a_chunk_border() {
+        let mut world = World::with_generator(WorldGenerator::new(WorldSeed::new(1)));
+        world.generate_region(IVec3::ZERO, IVec3::Y);
+        world
+            .set_block(IVec3::new(8, 3, 8), Some(STONE_ID))
+            .unwrap();
+        for y in 12..=20 {
+            world.set_block(IVec3::new(8, y, 8), Some(SAND_ID)).unwrap();
+        }
+        for _ in 0..200 {
+            world.tick();
+        }
+
+        let column = (0..32)
+            .filter(|&y| world.block(IVec3::new(8, y, 8)) == Some(SAND_ID))
+            .collect::<Vec<_>>();
+        assert_eq!(column, (4..=12).collect::<Vec<_>>());
+        assert!(world
+            .chunks()
+            .values()
+            .all(|chunk| chunk.falling_blocks().is_empty()));
+    }
+
+    #[test]
+    fn sand_at_the_bottom_of_a_chunk_falls_into_the_one_below() {
+        let mut world = World::with_generator(WorldGenerator::new(WorldSeed::new(1)));
+        world.generate_region(IVec3::ZERO, IVec3::Y);
+        world
+            .set_block(IVec3::new(8, 3, 8), Some(STONE_ID))
+            .unwrap();
+        world
+            .set_block(IVec3::new(8, 16, 8), Some(SAND_ID))
+            .unwrap();
+        for _ in 0..100 {
+            world.tick();
+        }
+
+        assert_eq!(world.block(IVec3::new(8, 16, 8)), None);
+        assert_eq!(world.block(IVec3::new(8, 4, 8)), Some(SAND_ID));
+    }
+}
@@ -0,0 +1,78 @@
+use glam::IVec3;
+use std::collections::{HashSet, VecDeque};
+
+use crate::logging;
+use crate::mesher::CHUNK_SIZE;
+
+// Notifications handled at most in a single tick, the rest waits for the next one.
+pub const UPDATE_BUDGET: usize = 512;
+// How many times an update may trigger another one before the chain gets cut.
+pub const MAX_CASCADE_DEPTH: u8 = 16;
+
+const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockUpdate {
+    pub position: IVec3,
+    // Number of updates that led to this one, 0 for a direct edit.
+    pub depth: u8,
+}
+
+// Queue of the cells whose update handlers have to run because something next to
+// them changed. Every cell is queued once no matter how many neighbours changed,
+// so loops between handlers can't flood the queue within a tick.
+#[derive(Default)]
+pub struct BlockUpdates {
+    pending: VecDeque<BlockUpdate>,
+    queued: HashSet<IVec3>,
+}
+
+impl BlockUpdates {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Notifies the changed cell and its 6 neighbours.
+    pub fn notify(&mut self, position: IVec3, depth: u8) {
+        if depth > MAX_CASCADE_DEPTH {
+            tracing::debug!(target: logging::CHUNKS, "Dropping block update cascade at {position}");
+            return;
+        }
+
+        self.push(position, depth);
+        for offset in NEIGHBOURS {
+            self.push(position + offset, depth);
+        }
+    }
+
+    fn push(&mut self, position: IVec3, depth: u8) {
+        let inside =
+            position.cmpge(IVec3::ZERO).all() && position.cmplt(IVec3::splat(CHUNK_SIZE)).all();
+        if inside && self.queued.insert(position) {
+            self.pending.push_back(BlockUpdate { position, depth });
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.queued.clear();
+    }
+
+    // Takes this tick's batch. Updates notified while handling it go to the next tick.
+    pub fn drain(&mut self) -> Vec<BlockUpdate> {
+        let count = self.pending.len().min(UPDATE_BUDGET);
+        let batch = self.pending.drain(..count).collect::<Vec<_>>();
+        for update in batch.iter() {
+            self.queued.remove(&update.position);
+        }
+
+        batch
+    }
+}
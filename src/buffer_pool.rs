@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::mem;
+use std::num::NonZeroU64;
+use std::sync::Arc;
+use wgpu::util::StagingBelt;
+use wgpu::{
+    BindingResource, Buffer, BufferAddress, BufferBinding, BufferDescriptor, BufferSize,
+    BufferSlice, BufferUsages, CommandEncoder, CommandEncoderDescriptor, Device, Queue,
+    COPY_BUFFER_ALIGNMENT,
+};
+
+use crate::logging;
+
+// Smallest dedicated buffer, sizes get rounded up to a power of two from here so
+// released buffers fit the next request of the same class.
+const MIN_BUFFER_SIZE: BufferAddress = 256;
+// Uniforms up to this size share a slab, one slot each. Matches the default
+// min_uniform_buffer_offset_alignment so every slot can be bound on its own.
+const UNIFORM_SLOT_SIZE: BufferAddress = 256;
+const SLAB_SLOTS: usize = 64;
+const STAGING_CHUNK_SIZE: BufferAddress = 1 << 20;
+// Released buffers past this many bytes are dropped instead of kept around.
+const MAX_POOLED_BYTES: BufferAddress = 64 << 20;
+
+enum Allocation {
+    Dedicated,
+    Slot { slab: usize, slot: usize },
+}
+
+pub struct BufferAllocation {
+    buffer: Arc<Buffer>,
+    offset: BufferAddress,
+    size: BufferAddress,
+    usage: BufferUsages,
+    allocation: Allocation,
+}
+
+impl BufferAllocation {
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn slice(&self) -> BufferSlice<'_> {
+        self.buffer.slice(self.offset..self.offset + self.size)
+    }
+
+    // What the buffer has room for, the rest of its size class past the requested size.
+    pub fn capacity(&self) -> BufferAddress {
+        match self.allocation {
+            Allocation::Dedicated => self.buffer.size(),
+            Allocation::Slot { .. } => UNIFORM_SLOT_SIZE,
+        }
+    }
+
+    pub fn binding(&self) -> BindingResource<'_> {
+        BindingResource::Buffer(BufferBinding {
+            buffer: &self.buffer,
+            offset: self.offset,
+            size: BufferSize::new(self.size),
+        })
+    }
+}
+
+struct UniformSlab {
+    buffer: Arc<Buffer>,
+    usage: BufferUsages,
+    free: Vec<usize>,
+}
+
+// Hands out GPU buffers for model resources. Buffers released on a rebuild go
+// back to a free list by size class, small uniforms are suballocated from shared
+// slabs and every upload goes through a staging belt recorded in its own encoder,
+// submitted right before the frame that needs it.
+pub struct BufferPool {
+    free: HashMap<(BufferUsages, BufferAddress), Vec<Arc<Buffer>>>,
+    pooled_bytes: BufferAddress,
+    slabs: Vec<UniformSlab>,
+    belt: StagingBelt,
+    encoder: Option<CommandEncoder>,
+    // Writes and their bytes waiting for the next submit.
+    uploads: usize,
+    upload_bytes: BufferAddress,
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self {
+            free: HashMap::new(),
+            pooled_bytes: 0,
+            slabs: vec![],
+            belt: StagingBelt::new(STAGING_CHUNK_SIZE),
+            encoder: None,
+            uploads: 0,
+            upload_bytes: 0,
+        }
+    }
+
+    pub fn pooled_bytes(&self) -> BufferAddress {
+        self.pooled_bytes
+    }
+
+    pub fn slab_count(&self) -> usize {
+        self.slabs.len()
+    }
+
+    fn aligned_size(size: usize) -> BufferAddress {
+        (size as BufferAddress)
+            .max(COPY_BUFFER_ALIGNMENT)
+            .next_multiple_of(COPY_BUFFER_ALIGNMENT)
+    }
+
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        size: usize,
+        usage: BufferUsages,
+    ) -> BufferAllocation {
+        let usage = usage | BufferUsages::COPY_DST;
+        let size = Self::aligned_size(size);
+
+        if usage.contains(BufferUsages::UNIFORM) && size <= UNIFORM_SLOT_SIZE {
+            return self.allocate_slot(device, size, usage);
+        }
+
+        let class = size.max(MIN_BUFFER_SIZE).next_power_of_two();
+        let buffer = match self.free.get_mut(&(usage, class)).and_then(Vec::pop) {
+            Some(buffer) => {
+                self.pooled_bytes -= class;
+                buffer
+            }
+            None => Arc::new(device.create_buffer(&BufferDescriptor {
+                label: Some("Pooled Buffer"),
+                size: class,
+                usage,
+                mapped_at_creation: false,
+            })),
+        };
+
+        BufferAllocation {
+            buffer,
+            offset: 0,
+            size,
+            usage,
+            allocation: Allocation::Dedicated,
+        }
+    }
+
+    fn allocate_slot(
+        &mut self,
+        device: &Device,
+        size: BufferAddress,
+        usage: BufferUsages,
+    ) -> BufferAllocation {
+        let slab = match self
+            .slabs
+            .iter()
+            .position(|slab| slab.usage == usage && !slab.free.is_empty())
+        {
+            Some(slab) => slab,
+            None => {
+                self.slabs.push(UniformSlab {
+                    buffer: Arc::new(device.create_buffer(&BufferDescriptor {
+                        label: Some("Uniform Slab"),
+                        size: UNIFORM_SLOT_SIZE * SLAB_SLOTS as BufferAddress,
+                        usage,
+                        mapped_at_creation: false,
+                    })),
+                    usage,
+                    free: (0..SLAB_SLOTS).rev().collect(),
+                });
+                self.slabs.len() - 1
+            }
+        };
+
+        let slot = self.slabs[slab].free.pop().unwrap();
+        BufferAllocation {
+            buffer: self.slabs[slab].buffer.clone(),
+            offset: slot as BufferAddress * UNIFORM_SLOT_SIZE,
+            size,
+            usage,
+            allocation: Allocation::Slot { slab, slot },
+        }
+    }
+
+    // Makes the allocation `size` bytes long, in place while its buffer has the
+    // room and otherwise swapping it for a new one. True when the buffer changed.
+    pub fn resize(
+        &mut self,
+        device: &Device,
+        allocation: &mut BufferAllocation,
+        size: usize,
+    ) -> bool {
+        let aligned = Self::aligned_size(size);
+        if aligned <= allocation.capacity() {
+            allocation.size = aligned;
+            return false;
+        }
+
+        let resized = self.allocate(device, size, allocation.usage);
+        self.release(mem::replace(allocation, resized));
+        true
+    }
+
+    pub fn release(&mut self, allocation: BufferAllocation) {
+        match allocation.allocation {
+            Allocation::Slot { slab, slot } => self.slabs[slab].free.push(slot),
+            Allocation::Dedicated => {
+                let class = allocation.buffer.size();
+                if self.pooled_bytes + class <= MAX_POOLED_BYTES {
+                    self.pooled_bytes += class;
+                    self.free
+                        .entry((allocation.usage, class))
+                        .or_default()
+                        .push(allocation.buffer);
+                }
+            }
+        }
+    }
+
+    pub fn write(&mut self, device: &Device, allocation: &BufferAllocation, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if data.len() as BufferAddress > allocation.size {
+            tracing::warn!(
+                target: logging::RENDER,
+                "Upload of {} bytes doesn't fit a {} bytes buffer, truncating it",
+                data.len(),
+                allocation.size
+            );
+        }
+
+        let len = data.len().min(allocation.size as usize);
+        let size = Self::aligned_size(len);
+        let encoder = self.encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Upload Encoder"),
+            })
+        });
+        let mut view = self.belt.write_buffer(
+            encoder,
+            &allocation.buffer,
+            allocation.offset,
+            NonZeroU64::new(size).unwrap(),
+            device,
+        );
+        view[..len].copy_from_slice(&data[..len]);
+        view[len..].fill(0);
+        self.uploads += 1;
+        self.upload_bytes += size;
+    }
+
+    // Sends the pending uploads, has to run before any submission using the
+    // buffers. Returns how many there were and their bytes.
+    pub fn submit(&mut self, queue: &Queue) -> (usize, BufferAddress) {
+        if let Some(encoder) = self.encoder.take() {
+            self.belt.finish();
+            queue.submit(std::iter::once(encoder.finish()));
+            self.belt.recall();
+        }
+
+        (
+            mem::take(&mut self.uploads),
+            mem::take(&mut self.upload_bytes),
+        )
+    }
+}
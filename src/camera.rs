@@ -1,47 +1,170 @@
+use crate::action_map::{
+    MOVE_BACKWARD, MOVE_DOWN, MOVE_FORWARD, MOVE_LEFT, MOVE_RIGHT, MOVE_UP, TOGGLE_CAMERA,
+};
+use crate::app::Actor;
+use crate::command_buffer::{CommandBuffer, NCommandUpdate};
+use crate::input::InputState;
+use crate::world_view::WorldView;
 use bytemuck::{Pod, Zeroable};
 use glam::{Mat4, Vec3, Vec3A};
-use std::cell::RefCell;
 use std::f32::consts::FRAC_PI_2;
-use std::rc::Rc;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use uuid::Uuid;
-use winit::keyboard::{Key, NamedKey, SmolStr};
-
-use crate::app::Actor;
-use crate::command_buffer::{CommandBuffer, NCommandUpdate};
-use crate::input::InputState;
 
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+const MIN_FOV: f32 = 0.1;
+const MAX_FOV: f32 = 2.0;
+// Radians of field of view per scroll step.
+const ZOOM_STEP: f32 = 0.05;
+const ZOOM_SMOOTHING: f32 = 12.0;
+const ORBIT_MIN_DISTANCE: f32 = 2.0;
+const ORBIT_MAX_DISTANCE: f32 = 32.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CameraPose {
+    pub position: Vec3A,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl CameraPose {
+    pub fn lerp(&self, other: &CameraPose, t: f32) -> Self {
+        Self {
+            position: self.position.lerp(other.position, t),
+            yaw: self.yaw + (other.yaw - self.yaw) * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+        }
+    }
+
+    pub fn forward(&self) -> Vec3A {
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        Vec3A::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw)
+    }
+}
 
+// Commands move the camera's pose, what gets rendered is its view, which follows
+// the pose. With smoothing the view catches up with it exponentially instead of
+// jumping, and in fixed timestep mode it's interpolated between the last two
+// ticks first.
 pub struct Camera {
     position: Vec3A,
     yaw: f32,
     pitch: f32,
+    previous: CameraPose,
+    view: CameraPose,
+    // Rates per second, 0.0 turns smoothing off.
+    position_smoothing: f32,
+    rotation_smoothing: f32,
 }
 
 impl Camera {
     pub fn new<V: Into<Vec3A>>(position: V, yaw: f32, pitch: f32) -> Self {
-        Self {
+        let pose = CameraPose {
             position: position.into(),
             yaw,
             pitch,
+        };
+        Self {
+            position: pose.position,
+            yaw,
+            pitch,
+            previous: pose,
+            view: pose,
+            position_smoothing: 0.0,
+            rotation_smoothing: 0.0,
         }
     }
 
     pub fn calc_matrix(&self) -> Mat4 {
-        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
-        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.view.pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = self.view.yaw.sin_cos();
         Mat4::look_to_rh(
-            self.position.into(),
+            self.view.position.into(),
             Vec3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
             Vec3::Y,
         )
     }
 
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            position: self.position,
+            yaw: self.yaw,
+            pitch: self.pitch,
+        }
+    }
+
+    pub fn view(&self) -> CameraPose {
+        self.view
+    }
+
+    pub fn set_smoothing(&mut self, position_smoothing: f32, rotation_smoothing: f32) {
+        self.position_smoothing = position_smoothing;
+        self.rotation_smoothing = rotation_smoothing;
+    }
+
+    pub fn smoothing(&self) -> (f32, f32) {
+        (self.position_smoothing, self.rotation_smoothing)
+    }
+
+    // Pose the view interpolates from, taken at the start of every fixed tick.
+    pub fn store_previous(&mut self) {
+        self.previous = self.pose();
+    }
+
+    // Moves the view toward the pose, `alpha` of the way from the previous one.
+    pub fn update_view(&mut self, dt: f32, alpha: f32) {
+        let target = self.previous.lerp(&self.pose(), alpha);
+        let factor = |smoothing: f32| {
+            if smoothing > 0.0 {
+                1.0 - (-smoothing * dt).exp()
+            } else {
+                1.0
+            }
+        };
+        let position = factor(self.position_smoothing);
+        let rotation = factor(self.rotation_smoothing);
+        self.view = CameraPose {
+            position: self.view.position.lerp(target.position, position),
+            yaw: self.view.yaw + (target.yaw - self.view.yaw) * rotation,
+            pitch: self.view.pitch + (target.pitch - self.view.pitch) * rotation,
+        };
+    }
+
+    // Jumps the view to the pose, for teleports that shouldn't be smoothed.
+    pub fn snap(&mut self) {
+        self.previous = self.pose();
+        self.view = self.previous;
+    }
+
     pub fn position(&self) -> Vec3A {
         self.position
     }
 
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    pub fn forward(&self) -> Vec3A {
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        Vec3A::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw)
+    }
+
+    pub fn set_position<V: Into<Vec3A>>(&mut self, position: V) {
+        self.position = position.into();
+    }
+
+    pub fn set_rotation(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+    }
+
     pub fn move_position(&mut self, offset: Vec3A) {
         self.position += offset;
     }
@@ -60,34 +183,82 @@ impl Camera {
     }
 }
 
+// Zooming changes `target_fov_y`, the field of view follows it smoothly.
+#[derive(Clone)]
 pub struct Projection {
     aspect: f32,
     fov_y: f32,
+    target_fov_y: f32,
     z_near: f32,
     z_far: f32,
+    reverse_z: bool,
 }
 
 impl Projection {
     pub fn new(width: u32, height: u32, fov_y: f32, z_near: f32, z_far: f32) -> Self {
+        let fov_y = fov_y.clamp(MIN_FOV, MAX_FOV);
         Self {
             aspect: width as f32 / height as f32,
             fov_y,
+            target_fov_y: fov_y,
             z_near,
             z_far,
+            reverse_z: false,
         }
     }
 
+    pub fn fov_y(&self) -> f32 {
+        self.fov_y
+    }
+
+    // Changes the field of view right away.
+    pub fn set_fov_y(&mut self, fov_y: f32) {
+        self.fov_y = fov_y.clamp(MIN_FOV, MAX_FOV);
+        self.target_fov_y = self.fov_y;
+    }
+
+    pub fn target_fov_y(&self) -> f32 {
+        self.target_fov_y
+    }
+
+    // Narrower with a negative `amount`, wider with a positive one.
+    pub fn zoom(&mut self, amount: f32) {
+        self.target_fov_y = (self.target_fov_y + amount).clamp(MIN_FOV, MAX_FOV);
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let t = 1.0 - (-ZOOM_SMOOTHING * dt).exp();
+        self.fov_y += (self.target_fov_y - self.fov_y) * t;
+    }
+
     pub fn resize(&mut self, width: u32, height: u32) {
         self.aspect = width as f32 / height as f32;
     }
 
+    // z_far still limits the view distance with reverse-Z, just not the depth.
     pub fn calc_matrix(&self) -> Mat4 {
-        Mat4::perspective_rh(self.fov_y, self.aspect, self.z_near, self.z_far)
+        if self.reverse_z {
+            Mat4::perspective_infinite_reverse_rh(self.fov_y, self.aspect, self.z_near)
+        } else {
+            Mat4::perspective_rh(self.fov_y, self.aspect, self.z_near, self.z_far)
+        }
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
     }
 
     pub fn z_far(&self) -> f32 {
         self.z_far
     }
+
+    pub fn set_z_far(&mut self, z_far: f32) {
+        self.z_far = z_far;
+    }
 }
 
 #[repr(C)]
@@ -110,7 +281,7 @@ impl CameraUniform {
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
-        let eye = camera.position.to_array();
+        let eye = camera.view.position.to_array();
         self.view_position = [eye[0], eye[1], eye[2], 0.0];
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).to_cols_array_2d();
     }
@@ -128,12 +299,13 @@ pub struct CameraController {
     scroll: f32,
     speed: f32,
     sensitivity: f32,
+    active: bool,
     id: Uuid,
-    camera: Rc<RefCell<Camera>>,
+    camera: Arc<RwLock<Camera>>,
 }
 
 impl CameraController {
-    pub fn new(speed: f32, sensitivity: f32, camera: Rc<RefCell<Camera>>) -> Self {
+    pub fn new(speed: f32, sensitivity: f32, camera: Arc<RwLock<Camera>>) -> Self {
         Self {
             id: Uuid::new_v4(),
             amount_left: 0.0,
@@ -147,46 +319,19 @@ impl CameraController {
             scroll: 0.0,
             speed,
             sensitivity,
+            active: true,
             camera,
         }
     }
 
     pub fn process_keyboard(&mut self, inputs: &InputState) {
-        if inputs.is_key_just_pressed(&Key::Character(SmolStr::new("W"))) {
-            self.amount_forward = 1.0;
-        } else if inputs.is_key_just_released(&Key::Character(SmolStr::new("W"))) {
-            self.amount_forward = 0.0;
-        }
-
-        if inputs.is_key_just_pressed(&Key::Character(SmolStr::new("S"))) {
-            self.amount_backward = 1.0;
-        } else if inputs.is_key_just_released(&Key::Character(SmolStr::new("S"))) {
-            self.amount_backward = 0.0;
-        }
-
-        if inputs.is_key_just_pressed(&Key::Character(SmolStr::new("A"))) {
-            self.amount_left = 1.0;
-        } else if inputs.is_key_just_released(&Key::Character(SmolStr::new("A"))) {
-            self.amount_left = 0.0;
-        }
-
-        if inputs.is_key_just_pressed(&Key::Character(SmolStr::new("D"))) {
-            self.amount_right = 1.0;
-        } else if inputs.is_key_just_released(&Key::Character(SmolStr::new("D"))) {
-            self.amount_right = 0.0;
-        }
-
-        if inputs.is_key_just_pressed(&Key::from(NamedKey::Space)) {
-            self.amount_up = 1.0;
-        } else if inputs.is_key_just_released(&Key::from(NamedKey::Space)) {
-            self.amount_up = 0.0;
-        }
-
-        if inputs.is_key_just_pressed(&Key::from(NamedKey::Shift)) {
-            self.amount_down = 1.0;
-        } else if inputs.is_key_just_released(&Key::from(NamedKey::Shift)) {
-            self.amount_down = 0.0;
-        }
+        let amount = |action| inputs.is_action_pressed(action) as u8 as f32;
+        self.amount_forward = amount(MOVE_FORWARD);
+        self.amount_backward = amount(MOVE_BACKWARD);
+        self.amount_left = amount(MOVE_LEFT);
+        self.amount_right = amount(MOVE_RIGHT);
+        self.amount_up = amount(MOVE_UP);
+        self.amount_down = amount(MOVE_DOWN);
     }
 
     pub fn process_mouse(&mut self, inputs: &InputState) {
@@ -251,28 +396,35 @@ impl Actor for CameraController {
         &self.id
     }
 
-    fn update(&mut self, dt: &Duration, inputs: &InputState) -> CommandBuffer<NCommandUpdate> {
+    fn update(
+        &mut self,
+        dt: &Duration,
+        inputs: &InputState,
+        _: &WorldView,
+    ) -> CommandBuffer<NCommandUpdate> {
         let mut buffer = CommandBuffer::new();
         let dt = dt.as_secs_f32();
 
+        // The orbit camera takes over until the action is pressed again.
+        if inputs.is_action_just_pressed(TOGGLE_CAMERA) {
+            self.active = !self.active;
+        }
+        if !self.active {
+            return buffer;
+        }
+
         self.process_keyboard(inputs);
         self.process_mouse(inputs);
         self.process_scroll(inputs);
 
         // Move forward/backward and left/right
-        let (yaw_sin, yaw_cos) = self.camera.borrow().yaw.sin_cos();
+        let (yaw_sin, yaw_cos) = self.camera.read().unwrap().yaw.sin_cos();
         let forward = Vec3A::new(yaw_cos, 0.0, yaw_sin).normalize();
         let right = Vec3A::new(-yaw_sin, 0.0, yaw_cos).normalize();
         let mut offset = Vec3A::ZERO;
         offset += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
         offset += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
-        // Move in/out (aka. "zoom")
-        let (pitch_sin, pitch_cos) = self.camera.borrow().pitch.sin_cos();
-        let scrollward =
-            Vec3A::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        offset += scrollward * self.scroll * self.speed * self.sensitivity * dt;
-
         // Move up/down.
         offset.y += (self.amount_up - self.amount_down) * self.speed * dt;
 
@@ -281,9 +433,164 @@ impl Actor for CameraController {
             self.rotate_horizontal * self.sensitivity * dt,
             -self.rotate_vertical * self.sensitivity * dt,
         ));
+        // Scrolling up zooms in.
+        if self.scroll != 0.0 {
+            buffer.push(NCommandUpdate::FovCamera(-self.scroll * ZOOM_STEP));
+        }
 
         buffer
     }
 }
 
-unsafe impl Send for CameraController {}
+// Third person camera looking at a target point from `distance` away, moved in
+// closer when terrain gets between them. Starts inactive, the toggle action
+// swaps it with the first person CameraController, with the target where the
+// first person camera was.
+pub struct OrbitCameraController {
+    id: Uuid,
+    camera: Arc<RwLock<Camera>>,
+    active: bool,
+    speed: f32,
+    sensitivity: f32,
+    // How quickly the camera catches up with the input, higher is snappier.
+    smoothing: f32,
+    min_distance: f32,
+    max_distance: f32,
+    target: Vec3A,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    // Where the camera currently is on its way to the values above.
+    current_target: Vec3A,
+    current_yaw: f32,
+    current_pitch: f32,
+    current_distance: f32,
+}
+
+impl OrbitCameraController {
+    pub fn new(
+        distance: f32,
+        speed: f32,
+        sensitivity: f32,
+        smoothing: f32,
+        camera: Arc<RwLock<Camera>>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            camera,
+            active: false,
+            speed,
+            sensitivity,
+            smoothing,
+            min_distance: ORBIT_MIN_DISTANCE,
+            max_distance: ORBIT_MAX_DISTANCE,
+            target: Vec3A::ZERO,
+            yaw: 0.0,
+            pitch: 0.0,
+            distance: distance.clamp(ORBIT_MIN_DISTANCE, ORBIT_MAX_DISTANCE),
+            current_target: Vec3A::ZERO,
+            current_yaw: 0.0,
+            current_pitch: 0.0,
+            current_distance: 0.0,
+        }
+    }
+
+    pub fn set_distance_limits(&mut self, min_distance: f32, max_distance: f32) {
+        self.min_distance = min_distance;
+        self.max_distance = max_distance.max(min_distance);
+        self.distance = self.distance.clamp(self.min_distance, self.max_distance);
+    }
+
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn target(&self) -> Vec3A {
+        self.target
+    }
+
+    pub fn set_target<V: Into<Vec3A>>(&mut self, target: V) {
+        self.target = target.into();
+    }
+
+    // Starts from inside the target so the camera pulls back out of it.
+    fn activate(&mut self) {
+        let camera = self.camera.read().unwrap();
+        self.target = camera.position();
+        self.yaw = camera.yaw();
+        self.pitch = camera.pitch();
+        self.current_target = self.target;
+        self.current_yaw = self.yaw;
+        self.current_pitch = self.pitch;
+        self.current_distance = 0.0;
+    }
+}
+
+impl Actor for OrbitCameraController {
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    fn update(
+        &mut self,
+        dt: &Duration,
+        inputs: &InputState,
+        _: &WorldView,
+    ) -> CommandBuffer<NCommandUpdate> {
+        let mut buffer = CommandBuffer::new();
+        let dt = dt.as_secs_f32();
+
+        if inputs.is_action_just_pressed(TOGGLE_CAMERA) {
+            self.active = !self.active;
+            if self.active {
+                self.activate();
+            } else {
+                // Back to first person where the target is.
+                buffer.push(NCommandUpdate::OrbitCamera(
+                    self.current_target,
+                    self.current_yaw,
+                    self.current_pitch,
+                    0.0,
+                ));
+            }
+        }
+        if !self.active {
+            return buffer;
+        }
+
+        let (mouse_x, mouse_y) = inputs.mouse_delta();
+        self.yaw += mouse_x * self.sensitivity * dt;
+        self.pitch =
+            (self.pitch - mouse_y * self.sensitivity * dt).clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+        self.distance = (self.distance - inputs.mouse_scroll() * self.sensitivity)
+            .clamp(self.min_distance, self.max_distance);
+
+        let amount = |action| inputs.is_action_pressed(action) as u8 as f32;
+        let (yaw_sin, yaw_cos) = self.yaw.sin_cos();
+        let forward = Vec3A::new(yaw_cos, 0.0, yaw_sin);
+        let right = Vec3A::new(-yaw_sin, 0.0, yaw_cos);
+        self.target += forward * (amount(MOVE_FORWARD) - amount(MOVE_BACKWARD)) * self.speed * dt;
+        self.target += right * (amount(MOVE_RIGHT) - amount(MOVE_LEFT)) * self.speed * dt;
+        self.target.y += (amount(MOVE_UP) - amount(MOVE_DOWN)) * self.speed * dt;
+
+        // Same amount of catching up per second whatever the frame rate.
+        let t = 1.0 - (-self.smoothing * dt).exp();
+        self.current_target = self.current_target.lerp(self.target, t);
+        self.current_yaw += (self.yaw - self.current_yaw) * t;
+        self.current_pitch += (self.pitch - self.current_pitch) * t;
+        self.current_distance += (self.distance - self.current_distance) * t;
+
+        buffer.push(NCommandUpdate::OrbitCamera(
+            self.current_target,
+            self.current_yaw,
+            self.current_pitch,
+            self.current_distance,
+        ));
+
+        buffer
+    }
+}
@@ -0,0 +1,376 @@
+use wgpu::{
+    Adapter, AdapterInfo, Backend, Backends, DeviceType, DownlevelFlags, Features, Instance,
+    Limits, PowerPreference, RequestAdapterOptions, Surface,
+};
+
+use crate::error::EngineError;
+use crate::logging;
+use crate::post_process::HDR_FORMAT;
+use crate::texture::Texture;
+
+const PUSH_CONSTANT_SIZE: u32 = 128;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    IndirectDraws,
+    PushConstants,
+    TimestampQueries,
+    PolygonLineMode,
+    AdapterSpecificFormats,
+    BcTextures,
+    Etc2Textures,
+    AstcTextures,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 8] = [
+        Capability::IndirectDraws,
+        Capability::PushConstants,
+        Capability::TimestampQueries,
+        Capability::PolygonLineMode,
+        Capability::AdapterSpecificFormats,
+        Capability::BcTextures,
+        Capability::Etc2Textures,
+        Capability::AstcTextures,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::IndirectDraws => "Indirect draws",
+            Capability::PushConstants => "Push constants",
+            Capability::TimestampQueries => "Timestamp queries",
+            Capability::PolygonLineMode => "Polygon line mode",
+            Capability::AdapterSpecificFormats => "Adapter specific formats",
+            Capability::BcTextures => "BC texture compression",
+            Capability::Etc2Textures => "ETC2 texture compression",
+            Capability::AstcTextures => "ASTC texture compression",
+        }
+    }
+
+    // What gets turned off when the capability is missing, used for the startup log.
+    pub fn dependents(&self) -> &'static str {
+        match self {
+            Capability::IndirectDraws => "GPU culling",
+            Capability::PushConstants => "per draw push constants",
+            Capability::TimestampQueries => "GPU pass timings",
+            Capability::PolygonLineMode => "wireframe rendering",
+            Capability::AdapterSpecificFormats => "MSAA sample counts other than 4",
+            Capability::BcTextures => "BC textures without decoding them on the CPU",
+            Capability::Etc2Textures => "ETC2 textures",
+            Capability::AstcTextures => "ASTC textures",
+        }
+    }
+
+    fn features(&self) -> Features {
+        match self {
+            Capability::IndirectDraws => {
+                Features::INDIRECT_FIRST_INSTANCE | Features::MULTI_DRAW_INDIRECT
+            }
+            Capability::PushConstants => Features::PUSH_CONSTANTS,
+            Capability::TimestampQueries => Features::TIMESTAMP_QUERY,
+            Capability::PolygonLineMode => Features::POLYGON_MODE_LINE,
+            Capability::AdapterSpecificFormats => {
+                Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+            }
+            Capability::BcTextures => Features::TEXTURE_COMPRESSION_BC,
+            Capability::Etc2Textures => Features::TEXTURE_COMPRESSION_ETC2,
+            Capability::AstcTextures => Features::TEXTURE_COMPRESSION_ASTC,
+        }
+    }
+
+    fn is_supported(&self, adapter: &Adapter) -> bool {
+        let downlevel = adapter.get_downlevel_capabilities().flags;
+        let supported = adapter.features().contains(self.features());
+
+        match self {
+            Capability::IndirectDraws => {
+                supported && downlevel.contains(DownlevelFlags::INDIRECT_EXECUTION)
+            }
+            Capability::PushConstants => {
+                supported && adapter.limits().max_push_constant_size >= PUSH_CONSTANT_SIZE
+            }
+            _ => supported,
+        }
+    }
+}
+
+// Every adapter the backends can see, to pick one from by name.
+pub fn enumerate_adapters(backends: Backends) -> Vec<AdapterInfo> {
+    let instance = Instance::new(wgpu::InstanceDescriptor {
+        backends,
+        ..Default::default()
+    });
+    instance
+        .enumerate_adapters(backends)
+        .iter()
+        .map(Adapter::get_info)
+        .collect()
+}
+
+// Which adapter the app runs on and what it tries to turn on there. Capabilities
+// that weren't asked for stay off even if the adapter has them, missing ones
+// just turn off what depends on them.
+#[derive(Clone, Debug)]
+pub struct AdapterRequest {
+    name: Option<String>,
+    backends: Backends,
+    power_preference: PowerPreference,
+    capabilities: Vec<Capability>,
+}
+
+impl Default for AdapterRequest {
+    fn default() -> Self {
+        Self {
+            name: None,
+            backends: Backends::all(),
+            power_preference: PowerPreference::HighPerformance,
+            capabilities: Capability::ALL.to_vec(),
+        }
+    }
+}
+
+impl AdapterRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Any adapter with this in its name, ignoring case. When none has it the
+    // one the power preference picks is used instead.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_lowercase());
+        self
+    }
+
+    pub fn with_backends(mut self, backends: Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+
+    pub fn with_power_preference(mut self, power_preference: PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: &[Capability]) -> Self {
+        self.capabilities = capabilities.to_vec();
+        self
+    }
+
+    pub fn backends(&self) -> Backends {
+        self.backends
+    }
+
+    pub fn capabilities(&self) -> &[Capability] {
+        &self.capabilities
+    }
+
+    pub async fn select(
+        &self,
+        instance: &Instance,
+        surface: Option<&Surface<'_>>,
+    ) -> Result<Adapter, EngineError> {
+        if let Some(name) = &self.name {
+            let adapter = instance
+                .enumerate_adapters(self.backends)
+                .into_iter()
+                .filter(|adapter| {
+                    surface.is_none_or(|surface| adapter.is_surface_supported(surface))
+                })
+                .find(|adapter| adapter.get_info().name.to_lowercase().contains(name));
+            match adapter {
+                Some(adapter) => return Ok(adapter),
+                None => {
+                    tracing::warn!(
+                        target: logging::RENDER,
+                        "No adapter named like `{name}`, using the default one"
+                    )
+                }
+            }
+        }
+
+        instance
+            .request_adapter(&RequestAdapterOptions {
+                power_preference: self.power_preference,
+                compatible_surface: surface,
+                force_fallback_adapter: false,
+            })
+            .await
+            .ok_or(EngineError::NoAdapter)
+    }
+}
+
+// What the app ended up running on, for games to scale their settings by.
+#[derive(Clone, Debug)]
+pub struct GpuInfo {
+    pub name: String,
+    pub vendor: u32,
+    pub device: u32,
+    pub device_type: DeviceType,
+    pub backend: Backend,
+    pub driver: String,
+    pub driver_info: String,
+    pub capabilities: Vec<Capability>,
+    pub sample_counts: Vec<u32>,
+    pub max_texture_size: u32,
+    pub max_buffer_size: u64,
+}
+
+impl GpuInfo {
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    pub fn is_integrated(&self) -> bool {
+        matches!(
+            self.device_type,
+            DeviceType::IntegratedGpu | DeviceType::Cpu
+        )
+    }
+}
+
+pub struct Capabilities {
+    adapter_name: String,
+    driver: String,
+    info: AdapterInfo,
+    adapter_limits: Limits,
+    requested: Vec<Capability>,
+    enabled: Vec<Capability>,
+    features: Features,
+    limits: Limits,
+    sample_counts: Vec<u32>,
+}
+
+impl Capabilities {
+    pub fn detect(adapter: &Adapter, requested: &[Capability]) -> Self {
+        let info = adapter.get_info();
+        let adapter_name = format!("{} ({:?})", info.name, info.backend);
+        let driver = format!(
+            "{} {} ({:?})",
+            info.driver, info.driver_info, info.device_type
+        );
+
+        let mut features = Features::empty();
+        let mut enabled = vec![];
+        for capability in Capability::ALL {
+            if !requested.contains(&capability) {
+                tracing::info!(
+                    target: logging::RENDER,
+                    "{} not requested, disabling {}",
+                    capability.name(),
+                    capability.dependents()
+                );
+            } else if capability.is_supported(adapter) {
+                tracing::info!(
+                    target: logging::RENDER,
+                    "{} enabled on {adapter_name}",
+                    capability.name()
+                );
+                features |= capability.features();
+                enabled.push(capability);
+            } else {
+                tracing::warn!(
+                    target: logging::RENDER,
+                    "{} not supported by {adapter_name}, disabling {}",
+                    capability.name(),
+                    capability.dependents()
+                );
+            }
+        }
+
+        let mut limits = Limits::default();
+        if enabled.contains(&Capability::PushConstants) {
+            limits.max_push_constant_size = PUSH_CONSTANT_SIZE;
+        }
+
+        let color = adapter.get_texture_format_features(HDR_FORMAT).flags;
+        let depth = adapter
+            .get_texture_format_features(Texture::DEPTH_FORMAT)
+            .flags;
+        // Without adapter specific format features only 1 and 4 samples are allowed.
+        let adapter_specific = enabled.contains(&Capability::AdapterSpecificFormats);
+        let sample_counts = [1, 2, 4, 8, 16]
+            .into_iter()
+            .filter(|&count| adapter_specific || count == 1 || count == 4)
+            .filter(|&count| {
+                color.sample_count_supported(count) && depth.sample_count_supported(count)
+            })
+            .collect();
+
+        Self {
+            adapter_name,
+            driver,
+            info,
+            adapter_limits: adapter.limits(),
+            requested: requested.to_vec(),
+            enabled,
+            features,
+            limits,
+            sample_counts,
+        }
+    }
+
+    pub fn adapter_name(&self) -> &str {
+        &self.adapter_name
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.enabled.contains(&capability)
+    }
+
+    pub fn enabled(&self) -> &[Capability] {
+        &self.enabled
+    }
+
+    pub fn required_features(&self) -> Features {
+        self.features
+    }
+
+    pub fn required_limits(&self) -> Limits {
+        self.limits.clone()
+    }
+
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.sample_counts
+    }
+
+    pub fn gpu_info(&self) -> GpuInfo {
+        GpuInfo {
+            name: self.info.name.clone(),
+            vendor: self.info.vendor,
+            device: self.info.device,
+            device_type: self.info.device_type,
+            backend: self.info.backend,
+            driver: self.info.driver.clone(),
+            driver_info: self.info.driver_info.clone(),
+            capabilities: self.enabled.clone(),
+            sample_counts: self.sample_counts.clone(),
+            max_texture_size: self.adapter_limits.max_texture_dimension_2d,
+            max_buffer_size: self.adapter_limits.max_buffer_size,
+        }
+    }
+
+    // What got detected, for bug reports.
+    pub fn report(&self) -> String {
+        let mut report = format!("adapter: {}\ndriver: {}\n", self.adapter_name, self.driver);
+        for capability in Capability::ALL {
+            if self.supports(capability) {
+                report.push_str(&format!("{}: enabled\n", capability.name()));
+            } else if !self.requested.contains(&capability) {
+                report.push_str(&format!("{}: not requested\n", capability.name()));
+            } else {
+                report.push_str(&format!(
+                    "{}: unsupported, no {}\n",
+                    capability.name(),
+                    capability.dependents()
+                ));
+            }
+        }
+        let sample_counts = self
+            .sample_counts
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>();
+        report.push_str(&format!("sample counts: {}\n", sample_counts.join(", ")));
+        report
+    }
+}
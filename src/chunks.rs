@@ -1,20 +1,61 @@
-use std::{cell::RefCell, mem::size_of, rc::Rc};
+use std::{
+    collections::HashSet,
+    mem::{self, size_of},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+};
 
 use bytemuck::{Pod, Zeroable};
-use glam::{UVec3, Vec3, Vec3A};
+use glam::{IVec3, UVec3, Vec3, Vec3A};
 use uuid::Uuid;
 use wgpu::{
-    BufferAddress, BufferUsages, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode,
+    BufferAddress, BufferUsages, IndexFormat, VertexAttribute, VertexBufferLayout, VertexFormat,
+    VertexStepMode,
 };
 
 use crate::{
     app::Model,
-    command_buffer::{CommandBuffer, NCommandRender, NCommandSetup},
+    biome::BiomeTint,
+    command_buffer::{
+        BindGroupList, CommandBuffer, NCommandRender, NCommandSetup, NCommandUpdate, RenderLayer,
+    },
+    fluid::MAX_FLUID_LEVEL,
+    foliage::{Decoration, FoliageInstance, FOLIAGE_VERTICES},
     frustum::Aabb,
     instance::{Instance, InstanceRaw},
+    lighting::LightChannel,
+    mesher::{mesh_chunk, AoVertex, ChunkMesh, LightSamples, Occupancy, CHUNK_SIZE},
     model::Vertex,
+    palette::{PalettedContainer, CELLS},
+    registry::block_info,
+    save::encode_chunk,
 };
 
+pub const STONE_ID: u16 = 0;
+pub const WATER_ID: u16 = 1;
+pub const ORE_ID: u16 = 2;
+pub const SAND_ID: u16 = 3;
+pub const GRAVEL_ID: u16 = 4;
+pub const LAMP_ID: u16 = 5;
+pub const LOG_ID: u16 = 6;
+pub const LEAVES_ID: u16 = 7;
+
+// Cells without a block. The position bits get masked out of stored blocks,
+// so no block ever looks like this.
+const AIR: u32 = POSITION_MASK;
+const POSITION_MASK: u32 = 0b111111111111;
+
+pub const MAX_LIGHT: u8 = 15;
+// Cell data is the metadata byte, then the block light and sky light nibbles.
+const METADATA_SHIFT: u32 = 0;
+const BLOCK_LIGHT_SHIFT: u32 = 8;
+const SKY_LIGHT_SHIFT: u32 = 12;
+
+const GRAVITY: f32 = 20.0;
+const TERMINAL_VELOCITY: f32 = 40.0;
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Pod, Zeroable)]
 pub struct Block {
@@ -56,10 +97,10 @@ impl Block {
         self
     }
 
+    // Another block takes the cell, the fluid state goes with the old one.
     pub fn with_id(mut self, id: u16) -> Self {
         let position = self.data & 0b111111111111;
-        let data = self.data >> 28;
-        self.data = data << 28 | (id as u32) << 12 | position;
+        self.data = (id as u32) << 12 | position;
 
         self
     }
@@ -85,9 +126,37 @@ impl Block {
         }
     }
 
+    pub fn data(&self) -> u32 {
+        self.data
+    }
+
     pub fn id(&self) -> u16 {
         ((self.data >> 12) & 0xffff) as u16
     }
+
+    // The top 4 bits hold the fluid state, the level and then the falling flag.
+    pub fn with_fluid(mut self, level: u8, falling: bool) -> Self {
+        let data = self.data & 0x0fffffff;
+        self.data = (falling as u32) << 31 | (level.min(MAX_FLUID_LEVEL) as u32) << 28 | data;
+
+        self
+    }
+
+    pub fn fluid_level(&self) -> u8 {
+        ((self.data >> 28) & 0b111) as u8
+    }
+
+    pub fn is_falling(&self) -> bool {
+        self.data >> 31 == 1
+    }
+
+    pub fn fluid_height(&self) -> f32 {
+        if self.is_falling() {
+            return 1.0;
+        }
+
+        1.0 - self.fluid_level() as f32 / (MAX_FLUID_LEVEL + 1) as f32
+    }
 }
 
 impl Default for Block {
@@ -96,13 +165,76 @@ impl Default for Block {
     }
 }
 
+// A gravity block on its way down, positions are local to the chunk.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FallingBlock {
+    pub id: u16,
+    pub position: Vec3A,
+    pub velocity: f32,
+}
+
 pub struct Chunk {
     id: Uuid,
     position: Vec3A,
     aabb: Aabb,
-    blocks: Vec<Block>,
-    instances: Vec<Instance>,
-    block_data: Rc<RefCell<Vec<u8>>>,
+    blocks: PalettedContainer,
+    // Metadata and light of every cell, the block bits have no room left for them.
+    cell_data: PalettedContainer,
+    // Block and sky light of the cells just outside each side, for meshing.
+    border_light: Vec<u8>,
+    // Height of the cell above the highest block in every column, kept up to
+    // date as blocks get stored.
+    heightmap: Vec<u8>,
+    // Biome colors of every column, see WorldGenerator::tint_chunk.
+    tints: Vec<BiomeTint>,
+    // Whether the decorators have run on it, see structures::decorate_ready.
+    decorated: bool,
+    // Written by setup, which only gets a shared reference like render. Render
+    // draws what the last setup uploaded, blocks may have changed since.
+    index_count: AtomicU32,
+    water_instances: AtomicU32,
+    falling_instances: AtomicU32,
+    // Built ahead of setup, off the main thread, when the chunk gets streamed in.
+    prebuilt_mesh: Mutex<Option<ChunkMesh>>,
+    // Cells that changed since the world last took them for its block ticks.
+    changed: Vec<UVec3>,
+    // Falls stored in saves from before GravityBehavior did all the falling.
+    // They land where they are and the block ticks move them on from there.
+    falling: Vec<FallingBlock>,
+    falling_buffer: Mutex<Option<usize>>,
+    decorations: Vec<Decoration>,
+    decoration_instances: AtomicU32,
+    foliage_buffer: Mutex<Option<usize>>,
+}
+
+// Storage index of a position inside the chunk, laid out like Block's position bits.
+fn cell(position: UVec3) -> Option<usize> {
+    position
+        .cmplt(UVec3::splat(16))
+        .all()
+        .then_some((position.x << 8 | position.y << 4 | position.z) as usize)
+}
+
+// Index of a position just outside one of the chunk's sides, not past an edge.
+fn border_cell(position: IVec3) -> Option<usize> {
+    let outside = (0..3)
+        .filter(|&axis| !(0..CHUNK_SIZE).contains(&position[axis]))
+        .collect::<Vec<_>>();
+    let &[axis] = outside.as_slice() else {
+        return None;
+    };
+    let side = match position[axis] {
+        -1 => axis * 2,
+        CHUNK_SIZE => axis * 2 + 1,
+        _ => return None,
+    };
+    let (u, v) = (position[(axis + 1) % 3], position[(axis + 2) % 3]);
+    Some(side * 256 + (u * 16 + v) as usize)
+}
+
+fn block_in(blocks: &PalettedContainer, position: UVec3) -> Option<Block> {
+    let state = blocks.get(cell(position)?);
+    (state != AIR).then(|| Block::new(state).with_position(position))
 }
 
 impl Chunk {
@@ -112,30 +244,182 @@ impl Chunk {
             id,
             position,
             aabb: Aabb::from_params(aabb_pos.into(), Into::<Vec3>::into(aabb_pos) + 16.0),
-            blocks: vec![],
-            instances: vec![],
-            block_data: Rc::new(RefCell::new(vec![])),
+            blocks: PalettedContainer::new(AIR),
+            cell_data: PalettedContainer::new(0),
+            border_light: vec![0; 6 * 16 * 16],
+            heightmap: vec![0; 16 * 16],
+            tints: vec![BiomeTint::default(); 16 * 16],
+            decorated: false,
+            index_count: AtomicU32::new(0),
+            water_instances: AtomicU32::new(0),
+            falling_instances: AtomicU32::new(0),
+            prebuilt_mesh: Mutex::new(None),
+            changed: vec![],
+            falling: vec![],
+            falling_buffer: Mutex::new(None),
+            decorations: vec![],
+            decoration_instances: AtomicU32::new(0),
+            foliage_buffer: Mutex::new(None),
         }
     }
 
     pub fn exists_block<V: Into<UVec3>>(&self, position: V) -> bool {
+        self.get_block(position).is_some()
+    }
+
+    pub fn get_block<V: Into<UVec3>>(&self, position: V) -> Option<Block> {
+        block_in(&self.blocks, position.into())
+    }
+
+    // Positions outside of the chunk are ignored.
+    fn store(&mut self, position: UVec3, block: Option<Block>) {
+        if let Some(idx) = cell(position) {
+            let state = block.map_or(AIR, |block| block.data() & !POSITION_MASK);
+            let previous = self.blocks.set(idx, state);
+            // Metadata belongs to the block, it goes when the block does or
+            // gets replaced by another one.
+            let id = |state: u32| (state != AIR).then(|| Block::new(state).id());
+            if id(previous) != id(state) {
+                self.set_cell_bits(idx, METADATA_SHIFT, 0xff, 0);
+            }
+            self.update_height(position, state != AIR);
+        }
+    }
+
+    fn update_height(&mut self, position: UVec3, filled: bool) {
+        let column = (position.x * 16 + position.z) as usize;
+        let height = &mut self.heightmap[column];
+        if filled {
+            *height = (*height).max(position.y as u8 + 1);
+        } else if position.y as u8 + 1 == *height {
+            *height = (0..position.y)
+                .rev()
+                .find(|&y| {
+                    let below = UVec3::new(position.x, y, position.z);
+                    self.blocks.get(cell(below).unwrap()) != AIR
+                })
+                .map_or(0, |y| y as u8 + 1);
+        }
+    }
+
+    fn cell_bits(&self, position: UVec3, shift: u32, mask: u32) -> u8 {
+        cell(position).map_or(0, |idx| ((self.cell_data.get(idx) >> shift) & mask) as u8)
+    }
+
+    fn set_cell_bits(&mut self, idx: usize, shift: u32, mask: u32, value: u8) {
+        let data = self.cell_data.get(idx) & !(mask << shift);
+        self.cell_data
+            .set(idx, data | (value as u32 & mask) << shift);
+    }
+
+    // Orientation, growth stage or whatever else the block needs, 0 for air.
+    pub fn metadata<V: Into<UVec3>>(&self, position: V) -> u8 {
+        self.cell_bits(position.into(), METADATA_SHIFT, 0xff)
+    }
+
+    // Air keeps no metadata, setting it there does nothing.
+    pub fn set_metadata<V: Into<UVec3>>(&mut self, position: V, metadata: u8) {
         let position: UVec3 = position.into();
-        for block in &self.blocks {
-            if block.position() == position {
-                return true;
+        if self.exists_block(position) {
+            let idx = cell(position).unwrap();
+            self.set_cell_bits(idx, METADATA_SHIFT, 0xff, metadata);
+        }
+    }
+
+    pub fn block_light<V: Into<UVec3>>(&self, position: V) -> u8 {
+        self.cell_bits(position.into(), BLOCK_LIGHT_SHIFT, 0xf)
+    }
+
+    // Levels go up to MAX_LIGHT, anything above gets clamped.
+    pub fn set_block_light<V: Into<UVec3>>(&mut self, position: V, level: u8) {
+        if let Some(idx) = cell(position.into()) {
+            self.set_cell_bits(idx, BLOCK_LIGHT_SHIFT, 0xf, level.min(MAX_LIGHT));
+        }
+    }
+
+    pub fn sky_light<V: Into<UVec3>>(&self, position: V) -> u8 {
+        self.cell_bits(position.into(), SKY_LIGHT_SHIFT, 0xf)
+    }
+
+    pub fn set_sky_light<V: Into<UVec3>>(&mut self, position: V, level: u8) {
+        if let Some(idx) = cell(position.into()) {
+            self.set_cell_bits(idx, SKY_LIGHT_SHIFT, 0xf, level.min(MAX_LIGHT));
+        }
+    }
+
+    // The brighter of the two.
+    pub fn light<V: Into<UVec3>>(&self, position: V) -> u8 {
+        let position = position.into();
+        self.block_light(position).max(self.sky_light(position))
+    }
+
+    // Light of every cell and of the ones just outside, as far as it's known.
+    pub fn light_samples(&self) -> LightSamples {
+        let mut samples = LightSamples::new();
+        for (idx, data) in self.cell_data.iter().enumerate() {
+            let position = IVec3::new((idx >> 8) as i32, (idx >> 4) as i32 & 15, idx as i32 & 15);
+            let block = (data >> BLOCK_LIGHT_SHIFT) & 0xf;
+            let sky = (data >> SKY_LIGHT_SHIFT) & 0xf;
+            samples.set(position, LightChannel::Block, block as u8);
+            samples.set(position, LightChannel::Sky, sky as u8);
+        }
+        for side in 0..6 {
+            let (axis, positive) = (side / 2, side % 2 == 1);
+            for u in 0..CHUNK_SIZE {
+                for v in 0..CHUNK_SIZE {
+                    let mut position = IVec3::ZERO;
+                    position[axis] = if positive { CHUNK_SIZE } else { -1 };
+                    position[(axis + 1) % 3] = u;
+                    position[(axis + 2) % 3] = v;
+                    let light = self.border_light[border_cell(position).unwrap()];
+                    samples.set(position, LightChannel::Block, light & 0xf);
+                    samples.set(position, LightChannel::Sky, light >> 4);
+                }
             }
         }
 
-        false
+        samples
+    }
+
+    pub fn tint(&self, x: u32, z: u32) -> BiomeTint {
+        self.tints[(x * 16 + z) as usize]
     }
 
+    pub fn set_tint(&mut self, x: u32, z: u32, tint: BiomeTint) {
+        self.tints[(x * 16 + z) as usize] = tint;
+    }
+
+    pub fn grass_tints(&self) -> Vec<[f32; 3]> {
+        self.tints.iter().map(|tint| tint.grass).collect()
+    }
+
+    // Metadata and light of every cell, in the order of Block's position bits.
+    pub fn cell_data(&self) -> impl Iterator<Item = u32> + '_ {
+        self.cell_data.iter()
+    }
+
+    pub fn restore_cell_data<I: IntoIterator<Item = u32>>(&mut self, data: I) {
+        for (idx, data) in data.into_iter().take(CELLS).enumerate() {
+            self.cell_data.set(idx, data);
+        }
+    }
+
+    // Replaces the block already there, if any.
     pub fn add_block(&mut self, block: Block) {
-        self.blocks.push(block);
-        let block_pos = block.position();
-        self.instances.push(Instance::new(
-            Vec3A::new(block_pos.x as f32, block_pos.y as f32, block_pos.z as f32)
-                + (self.position * Vec3A::new(16.0, 16.0, 16.0)),
-        ))
+        self.block_changed(block.position());
+        self.store(block.position(), Some(block));
+    }
+
+    fn block_changed(&mut self, position: UVec3) {
+        self.changed.push(position);
+        self.clear_decorations(position);
+    }
+
+    // Decorations go away with the block they grow on, or when their cell gets taken.
+    fn clear_decorations(&mut self, position: UVec3) {
+        self.decorations.retain(|decoration| {
+            decoration.position != position && decoration.position != position + UVec3::Y
+        });
     }
 
     pub fn add_block_data<V: Into<UVec3>>(&mut self, position: V, id: u16) {
@@ -144,16 +428,150 @@ impl Chunk {
 
     pub fn remove_block<V: Into<UVec3>>(&mut self, position: V) {
         let position: UVec3 = position.into();
-        let mut idx = None;
-        for (i, block) in self.blocks.iter().enumerate() {
-            if block.position() == position {
-                idx = Some(i);
-            }
+        self.block_changed(position);
+        self.store(position, None);
+    }
+
+    pub fn set_block_id<V: Into<UVec3>>(&mut self, position: V, id: u16) {
+        let position: UVec3 = position.into();
+        self.block_changed(position);
+        if let Some(block) = self.get_block(position) {
+            self.store(position, Some(block.with_id(id)));
         }
+    }
+
+    pub fn block_id<V: Into<UVec3>>(&self, position: V) -> Option<u16> {
+        self.get_block(position).map(|block| block.id())
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = Block> + '_ {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter(|&(_, state)| state != AIR)
+            .map(|(idx, state)| Block::new(state | idx as u32))
+    }
 
-        if let Some(i) = idx {
-            self.blocks.swap_remove(i);
+    pub fn block_count(&self) -> usize {
+        self.blocks.count(|state| state != AIR)
+    }
+
+    pub fn falling_blocks(&self) -> &[FallingBlock] {
+        &self.falling
+    }
+
+    pub fn add_falling_block(&mut self, falling: FallingBlock) {
+        self.falling.push(falling);
+    }
+
+    pub fn decorations(&self) -> &[Decoration] {
+        &self.decorations
+    }
+
+    // Replaces the decoration already in that cell, if any.
+    pub fn add_decoration(&mut self, decoration: Decoration) {
+        self.remove_decoration(decoration.position);
+        self.decorations.push(decoration);
+    }
+
+    pub fn remove_decoration(&mut self, position: UVec3) {
+        self.decorations
+            .retain(|decoration| decoration.position != position);
+    }
+
+    // Restores a saved chunk, blocks come back as they were without waking anything up.
+    pub fn restore(id: Uuid, position: Vec3A, blocks: Vec<Block>) -> Self {
+        let mut chunk = Self::new(id, position);
+        for block in blocks {
+            chunk.store(block.position(), Some(block));
+        }
+
+        chunk
+    }
+
+    pub fn chunk_position(&self) -> Vec3A {
+        self.position
+    }
+
+    // Solid blocks for meshing, water gets drawn on its own.
+    pub fn occupancy(&self) -> Occupancy {
+        let mut occupancy = Occupancy::new();
+        for block in self.blocks().filter(|block| block.id() != WATER_ID) {
+            occupancy.set(block.position().as_ivec3());
         }
+
+        occupancy
+    }
+
+    pub fn mesh_origin(&self) -> Vec3A {
+        self.position * Vec3A::splat(16.0)
+    }
+
+    // Used by the next setup instead of meshing there.
+    pub fn set_mesh(&self, mesh: ChunkMesh) {
+        *self.prebuilt_mesh.lock().unwrap() = Some(mesh);
+    }
+
+    // Drops the changed cells, generated chunks start out settled and their
+    // fluids only wake up once a block gets edited.
+    pub fn settle_fluids(&mut self) {
+        self.changed.clear();
+    }
+
+    // Moves the falling blocks by one fixed tick, true when any of them landed.
+    fn update_falling(&mut self, dt: f32) -> bool {
+        let blocks = &self.blocks;
+        let mut landed_at = HashSet::new();
+        let solid = |position: UVec3, landed_at: &HashSet<UVec3>| {
+            landed_at.contains(&position)
+                || block_in(blocks, position).is_some_and(|block| block_info(block.id()).is_solid())
+        };
+
+        // Lowest first, so blocks stacked in a column land on top of each other.
+        self.falling
+            .sort_by(|a, b| a.position.y.total_cmp(&b.position.y));
+        let mut landed = vec![];
+        self.falling.retain_mut(|falling| {
+            falling.velocity = (falling.velocity + GRAVITY * dt).min(TERMINAL_VELOCITY);
+            let target = falling.position.y - falling.velocity * dt;
+
+            // Sweep every cell passed this tick so fast blocks can't tunnel through floors.
+            let cell = falling.position.round().as_uvec3();
+            let mut y = cell.y;
+            while target < y as f32 {
+                if y == 0 || solid(UVec3::new(cell.x, y - 1, cell.z), &landed_at) {
+                    // Blocks falling faster catch up with the ones below, those
+                    // may have landed in the cell already.
+                    let mut position = UVec3::new(cell.x, y, cell.z);
+                    while landed_at.contains(&position) {
+                        position.y += 1;
+                    }
+                    landed_at.insert(position);
+                    landed.push((position, falling.id));
+                    return false;
+                }
+                y -= 1;
+            }
+
+            falling.position.y = target;
+            true
+        });
+
+        for (position, id) in landed.iter() {
+            self.add_block_data(*position, *id);
+        }
+
+        !landed.is_empty()
+    }
+
+    fn falling_data(&self) -> Vec<u8> {
+        let origin = self.position * Vec3A::splat(16.0);
+        let instances = self
+            .falling
+            .iter()
+            .map(|falling| InstanceRaw::new((origin + falling.position).extend(falling.velocity)))
+            .collect::<Vec<InstanceRaw>>();
+        bytemuck::cast_slice(&instances).to_vec()
     }
 }
 
@@ -170,52 +588,300 @@ impl Model for Chunk {
         &self.position
     }
 
-    fn setup(&self) -> CommandBuffer<NCommandSetup> {
+    fn tick(&mut self, dt: f32) -> CommandBuffer<NCommandUpdate> {
         let mut buffer = CommandBuffer::new();
 
-        let _position_buffer = Rc::new(RefCell::new(
-            bytemuck::cast_slice::<_, u8>(&[self.position.to_array()]).to_vec(),
-        ));
-
-        let mut data = self.block_data.borrow_mut();
-        data.clear();
-        let instances = self
-            .instances
-            .iter()
-            .map(|instance| instance.to_raw())
-            .collect::<Vec<InstanceRaw>>();
-        for b in bytemuck::cast_slice(&instances) {
-            data.push(*b);
+        let rebuild = !self.falling.is_empty() && self.update_falling(dt);
+
+        let uploaded = *self.falling_instances.get_mut() as usize;
+        if rebuild {
+            buffer.push(NCommandUpdate::MarkDirty(self.id.into()));
+        } else if let Some(idx) = self
+            .falling_buffer
+            .get_mut()
+            .unwrap()
+            .filter(|_| uploaded == self.falling.len())
+        {
+            // Only the falling blocks moved, their instances are enough to update.
+            // While a remesh is pending they may not match what got uploaded.
+            buffer.push(NCommandUpdate::UpdateBuffer(
+                self.id.into(),
+                idx,
+                self.falling_data(),
+            ));
         }
 
+        buffer
+    }
+
+    fn setup(&self) -> CommandBuffer<NCommandSetup> {
+        let mut buffer = CommandBuffer::new();
+
+        let mesh = self
+            .prebuilt_mesh
+            .lock()
+            .unwrap()
+            .take()
+            .unwrap_or_else(|| {
+                mesh_chunk(
+                    &self.occupancy(),
+                    &self.light_samples(),
+                    &self.grass_tints(),
+                    self.mesh_origin(),
+                )
+            });
+        self.index_count
+            .store(mesh.indices.len() as u32, Ordering::Relaxed);
+
         buffer.push(NCommandSetup::CreateBuffer(
-            self.block_data.clone(),
+            bytemuck::cast_slice(&mesh.vertices).to_vec(),
             BufferUsages::VERTEX,
         ));
+        buffer.push(NCommandSetup::CreateBuffer(
+            bytemuck::cast_slice(&mesh.ao).to_vec(),
+            BufferUsages::VERTEX,
+        ));
+        buffer.push(NCommandSetup::CreateBuffer(
+            bytemuck::cast_slice(&mesh.indices).to_vec(),
+            BufferUsages::INDEX,
+        ));
         buffer.push(NCommandSetup::CreatePipeline(
             vec![],
-            include_str!("../shaders/chunk_instance.wgsl"),
-            vec![InstanceRaw::desc()],
+            include_str!("../shaders/chunk.wgsl"),
+            vec![AoVertex::desc()],
             true,
+            RenderLayer::Opaque,
+            vec![],
         ));
 
+        let origin = self.mesh_origin();
+        let water = self
+            .blocks()
+            .filter(|block| block.id() == WATER_ID)
+            .collect::<Vec<_>>();
+        self.water_instances
+            .store(water.len() as u32, Ordering::Relaxed);
+
+        if !water.is_empty() {
+            // Water with more water on top reaches the top of the block whatever its level.
+            let instances =
+                water
+                    .iter()
+                    .map(|block| {
+                        let covered = self
+                            .block_id(block.position() + UVec3::Y)
+                            .is_some_and(|id| id == WATER_ID);
+                        Instance::new(origin + block.position().as_vec3a())
+                            .to_fluid_raw(if covered { 1.0 } else { block.fluid_height() })
+                    })
+                    .collect::<Vec<InstanceRaw>>();
+            buffer.push(NCommandSetup::CreateBuffer(
+                bytemuck::cast_slice(&instances).to_vec(),
+                BufferUsages::VERTEX,
+            ));
+            buffer.push(NCommandSetup::CreatePipeline(
+                vec![],
+                include_str!("../shaders/water.wgsl"),
+                vec![InstanceRaw::desc()],
+                true,
+                RenderLayer::Transparent,
+                vec![],
+            ));
+        }
+
+        let mut falling_buffer = self.falling_buffer.lock().unwrap();
+        *falling_buffer = None;
+        self.falling_instances
+            .store(self.falling.len() as u32, Ordering::Relaxed);
+        if !self.falling.is_empty() {
+            let water_buffers = if water.is_empty() { 0 } else { 1 };
+            *falling_buffer = Some(3 + water_buffers);
+
+            buffer.push(NCommandSetup::CreateBuffer(
+                self.falling_data(),
+                BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            ));
+            buffer.push(NCommandSetup::CreatePipeline(
+                vec![],
+                include_str!("../shaders/falling_block.wgsl"),
+                vec![InstanceRaw::desc()],
+                true,
+                RenderLayer::Opaque,
+                vec![],
+            ));
+        }
+
+        // Only the decorations still standing on something get drawn.
+        let decorations = self
+            .decorations
+            .iter()
+            .filter(|decoration| {
+                !self.exists_block(decoration.position)
+                    && decoration.position.y > 0
+                    && self
+                        .block_id(decoration.position - UVec3::Y)
+                        .is_some_and(|id| block_info(id).is_solid())
+            })
+            .map(|decoration| {
+                let position = decoration.position;
+                decoration.to_raw(origin, self.tint(position.x, position.z).foliage)
+            })
+            .collect::<Vec<FoliageInstance>>();
+        let mut foliage_buffer = self.foliage_buffer.lock().unwrap();
+        *foliage_buffer = None;
+        self.decoration_instances
+            .store(decorations.len() as u32, Ordering::Relaxed);
+        if !decorations.is_empty() {
+            let water_buffers = if water.is_empty() { 0 } else { 1 };
+            let falling_buffers = if self.falling.is_empty() { 0 } else { 1 };
+            *foliage_buffer = Some(3 + water_buffers + falling_buffers);
+
+            buffer.push(NCommandSetup::CreateBuffer(
+                bytemuck::cast_slice(&decorations).to_vec(),
+                BufferUsages::VERTEX,
+            ));
+            buffer.push(NCommandSetup::CreatePipeline(
+                vec![],
+                include_str!("../shaders/foliage.wgsl"),
+                vec![FoliageInstance::desc()],
+                false,
+                RenderLayer::Opaque,
+                vec![],
+            ));
+        }
+
         buffer
     }
 
     fn render(&self) -> CommandBuffer<NCommandRender> {
         let mut buffer = CommandBuffer::new();
 
-        buffer.push(NCommandRender::SetPipeline(0));
-        buffer.push(NCommandRender::SetVertexBuffer(1, 0));
-        buffer.push(NCommandRender::DrawModelIndexed(
-            0,
-            self.blocks.len() as u32,
-            &[],
-        ));
+        let index_count = self.index_count.load(Ordering::Relaxed);
+        if index_count > 0 {
+            buffer.push(NCommandRender::SetPipeline(0));
+            buffer.push(NCommandRender::SetModelMaterial(0, 0, 0));
+            buffer.push(NCommandRender::SetCameraBindGroup(1));
+            buffer.push(NCommandRender::SetVertexBuffer(0, 0));
+            buffer.push(NCommandRender::SetVertexBuffer(1, 1));
+            buffer.push(NCommandRender::SetIndexBuffer(2, IndexFormat::Uint32));
+            buffer.push(NCommandRender::DrawIndexedCulled(index_count));
+        }
+
+        let water_count = self.water_instances.load(Ordering::Relaxed);
+        let falling_buffer = *self.falling_buffer.lock().unwrap();
+        let mut pipeline = if water_count > 0 { 2 } else { 1 };
+        if let Some(idx) = falling_buffer {
+            buffer.push(NCommandRender::SetPipeline(pipeline));
+            buffer.push(NCommandRender::SetVertexBuffer(1, idx));
+            buffer.push(NCommandRender::DrawModelIndexed(
+                0,
+                self.falling_instances.load(Ordering::Relaxed),
+                BindGroupList::new(),
+            ));
+            pipeline += 1;
+        }
+
+        if let Some(idx) = *self.foliage_buffer.lock().unwrap() {
+            buffer.push(NCommandRender::SetPipeline(pipeline));
+            buffer.push(NCommandRender::SetCameraBindGroup(0));
+            buffer.push(NCommandRender::SetVertexBuffer(0, idx));
+            buffer.push(NCommandRender::Draw(
+                FOLIAGE_VERTICES,
+                self.decoration_instances.load(Ordering::Relaxed),
+            ));
+        }
+
+        if water_count > 0 {
+            buffer.push(NCommandRender::SetLayer(RenderLayer::Transparent));
+            buffer.push(NCommandRender::SetPipeline(1));
+            buffer.push(NCommandRender::SetVertexBuffer(1, 3));
+            buffer.push(NCommandRender::DrawModelIndexed(
+                0,
+                water_count,
+                BindGroupList::new(),
+            ));
+        }
 
         buffer
     }
-}
 
-unsafe impl Send for Chunk {}
-unsafe impl Sync for Chunk {}
+    // Everything render looks at gets written by setup.
+    fn static_render(&self) -> bool {
+        true
+    }
+
+    fn save(&self) -> Option<Vec<u8>> {
+        Some(encode_chunk(self))
+    }
+
+    fn block(&self, position: UVec3) -> Option<u16> {
+        self.block_id(position)
+    }
+
+    fn set_block(&mut self, position: UVec3, id: Option<u16>) -> bool {
+        match (self.block_id(position), id) {
+            (Some(_), Some(id)) => self.set_block_id(position, id),
+            (None, Some(id)) => self.add_block_data(position, id),
+            (Some(_), None) => self.remove_block(position),
+            (None, None) => return false,
+        }
+
+        true
+    }
+
+    fn light_level(&self, position: UVec3, channel: LightChannel) -> u8 {
+        match channel {
+            LightChannel::Block => self.block_light(position),
+            LightChannel::Sky => self.sky_light(position),
+        }
+    }
+
+    fn set_light_level(&mut self, position: IVec3, channel: LightChannel, level: u8) {
+        if let Some(idx) = border_cell(position) {
+            let shift = match channel {
+                LightChannel::Block => 0,
+                LightChannel::Sky => 4,
+            };
+            let light = self.border_light[idx] & !(0xf << shift);
+            self.border_light[idx] = light | level.min(MAX_LIGHT) << shift;
+        } else if position.cmpge(IVec3::ZERO).all() {
+            let position = position.as_uvec3();
+            match channel {
+                LightChannel::Block => self.set_block_light(position, level),
+                LightChannel::Sky => self.set_sky_light(position, level),
+            }
+        }
+    }
+
+    fn height(&self, x: u32, z: u32) -> u32 {
+        self.heightmap[(x * 16 + z) as usize] as u32
+    }
+
+    fn decorated(&self) -> bool {
+        self.decorated
+    }
+
+    fn set_decorated(&mut self) {
+        self.decorated = true;
+    }
+
+    fn take_changed(&mut self) -> Vec<UVec3> {
+        mem::take(&mut self.changed)
+    }
+
+    fn block_state(&self, position: UVec3) -> Option<u32> {
+        self.get_block(position).map(|block| block.data())
+    }
+
+    fn set_block_state(&mut self, position: UVec3, state: Option<u32>) -> bool {
+        let block = state.map(|state| Block::new(state).with_position(position));
+        if cell(position).is_none() || self.block_state(position) == block.map(|block| block.data())
+        {
+            return false;
+        }
+
+        self.clear_decorations(position);
+        self.store(position, block);
+        true
+    }
+}
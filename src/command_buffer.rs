@@ -1,73 +1,329 @@
-use glam::Vec3A;
-use std::{cell::RefCell, rc::Rc, vec::IntoIter};
+use glam::{IVec3, Mat4, Vec2, Vec3A};
+use smallvec::SmallVec;
+use std::cell::RefCell;
+use std::mem;
+use std::ops::Deref;
+use std::thread::LocalKey;
 use uuid::Uuid;
-use wgpu::{BindGroupLayoutEntry, BufferUsages, IndexFormat, VertexBufferLayout};
+use wgpu::{
+    BindGroupLayoutEntry, BufferUsages, IndexFormat, PresentMode, PushConstantRange, ShaderStages,
+    VertexBufferLayout,
+};
+use winit::dpi::PhysicalSize;
 
-use crate::app::{Actor, Model};
+use crate::app::{Actor, ActorRef, FullscreenMode, Model, ModelRef};
+use crate::debug_draw::DebugShape;
+use crate::particles::ParticleEmitter;
 
 pub type Index = usize;
 pub type ID = Uuid;
 pub type NModel = Box<dyn Model + Send + Sync>;
 pub type NActor = Box<dyn Actor + Send>;
+// Bind groups of a model, few enough to stay inline in the command.
+pub type BindGroupList = SmallVec<[Index; 4]>;
 
-pub trait NCommand {}
+// Buffers kept around for reuse per command type and thread, past these they're dropped. Big
+// ones aren't kept at all so a single huge frame doesn't stick around.
+const MAX_POOLED_BUFFERS: usize = 4096;
+const MAX_POOLED_CAPACITY: usize = 1024;
+// Words set by a single SetPushConstants, bigger blocks take one per 16 bytes.
+// Words and not bytes since backends read the data as u32s.
+pub const PUSH_CONSTANT_WORDS: usize = 4;
 
+pub trait NCommand: Sized + 'static {
+    fn pool() -> &'static LocalKey<CommandPool<Self>>;
+}
+
+// Emptied command Vecs of a single thread, so rayon workers filling buffers
+// don't wait on each other. A Vec goes back to the pool of the thread dropping
+// it, which is where the next buffers get made more often than not.
+pub struct CommandPool<N> {
+    free: RefCell<Vec<Vec<N>>>,
+}
+
+impl<N> CommandPool<N> {
+    pub const fn new() -> Self {
+        Self {
+            free: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn take(&self) -> Vec<N> {
+        self.free.borrow_mut().pop().unwrap_or_default()
+    }
+
+    fn give_back(&self, mut commands: Vec<N>) {
+        if commands.capacity() == 0 || commands.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+
+        commands.clear();
+        let mut free = self.free.borrow_mut();
+        if free.len() < MAX_POOLED_BUFFERS {
+            free.push(commands);
+        }
+    }
+
+    pub fn pooled(&self) -> usize {
+        self.free.borrow().len()
+    }
+}
+
+impl<N> Default for CommandPool<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RenderLayer {
+    Opaque,
+    Transparent,
+}
+
+// Uniforms of the app models can bind next to their own resources.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GlobalResource {
+    Camera,
+    // The sun and ambient light, both in the environment uniform.
+    Lights,
+    Time,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum NResource {
     Buffer(Index),
+    // A buffer of the model created with STORAGE usage.
+    StorageBuffer(Index),
+    // Loaded from the vfs on first use and shared by every model naming it.
+    Texture(String),
+    // Filtered like the model textures.
+    Sampler,
+    Global(GlobalResource),
 }
 
 pub enum NCommandUpdate {
     CreateModel(NModel),
     CreateActor(NActor),
-    RemoveModel(ID),
-    RemoveActor(ID),
+    // Either the id or the handle add_model or add_actor gave back.
+    RemoveModel(ModelRef),
+    RemoveActor(ActorRef),
     MoveCamera(Vec3A),
+    // Puts the camera at the position right away, without smoothing towards it.
+    TeleportCamera(Vec3A),
     RotateCamera(f32, f32),
+    // Puts the camera `distance` away from the target looking at it with the
+    // given yaw and pitch, closer if terrain is in the way.
+    OrbitCamera(Vec3A, f32, f32, f32),
+    // Widens the field of view by this many radians, narrows it when negative.
     FovCamera(f32),
-    UpdateBuffer(ID, Index),
+    SetFov(f32),
+    SetTimeOfDay(f32),
+    // Stops model ticks, the day cycle and the weather, actors and rendering go on.
+    SetPaused(bool),
+    // How fast simulated time runs compared to real time.
+    SetTimeScale(f32),
+    // Runs one tick of the simulation, even while paused.
+    StepSimulation,
+    // Cloud coverage and wind the weather slowly changes to.
+    SetWeather(f32, Vec2),
+    // None renders as fast as the present mode allows.
+    SetTargetFps(Option<u32>),
+    SetPresentMode(PresentMode),
+    SetFullscreen(FullscreenMode),
+    GrabCursor(bool),
+    SetResizable(bool),
+    SetWindowSizeLimits(Option<PhysicalSize<u32>>, Option<PhysicalSize<u32>>),
+    SwitchDimension(String),
+    // Block at a world position in the current dimension, None clears it.
+    SetBlock(IVec3, Option<u16>),
+    SpawnParticles(ParticleEmitter),
+    // Drawn in the next frame only, with the given color.
+    DebugDraw(DebugShape, [f32; 4]),
+    // Sends the keyboard to InputState's typed text instead of keys and actions.
+    CaptureText(bool),
+    // Text of the debug console, None closes it.
+    SetConsole(Option<String>),
+    // Writes App::export_repro_bundle's zip.
+    ExportReproBundle,
+    // Local transform of a model, relative to its parent if it has one.
+    SetTransform(ModelRef, Mat4),
+    SetParent(ModelRef, Option<ModelRef>),
+    // New contents of one of the model's buffers.
+    UpdateBuffer(ModelRef, Index, Vec<u8>),
+    RebuildModel(ModelRef),
+    // Set up again in a later frame, along with a few other dirty models, writing
+    // over the buffers it already has.
+    MarkDirty(ModelRef),
 }
 
-impl NCommand for NCommandUpdate {}
+impl NCommand for NCommandUpdate {
+    fn pool() -> &'static LocalKey<CommandPool<Self>> {
+        thread_local!(static POOL: CommandPool<NCommandUpdate> = const { CommandPool::new() });
+        &POOL
+    }
+}
 
 pub enum NCommandSetup {
-    CreateBuffer(Rc<RefCell<Vec<u8>>>, BufferUsages),
+    CreateBuffer(Vec<u8>, BufferUsages),
     CreateBindGroup(Vec<BindGroupLayoutEntry>, Vec<NResource>),
     CreatePipeline(
         Vec<Index>,
         &'static str,
         Vec<VertexBufferLayout<'static>>,
         bool,
+        RenderLayer,
+        // Only used where the adapter supports push constants.
+        Vec<PushConstantRange>,
     ),
-    SharePipeline(&'static ID, Index),
+    // Identical pipelines get shared on their own, this reuses one of another
+    // model without repeating how it was created.
+    SharePipeline(ID, Index),
+    // Gives the model a transform uniform, bound right after the camera in the
+    // pipelines created after it and kept across rebuilds.
+    CreateTransform(Mat4),
+    // Records the commands into a render bundle once set up, replayed by
+    // NCommandRender::ExecuteBundle with the index of the bundle in the model.
+    CreateBundle(Vec<NCommandRender>),
 }
 
-impl NCommand for NCommandSetup {}
+impl NCommand for NCommandSetup {
+    fn pool() -> &'static LocalKey<CommandPool<Self>> {
+        thread_local!(static POOL: CommandPool<NCommandSetup> = const { CommandPool::new() });
+        &POOL
+    }
+}
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum NCommandRender {
+    SetLayer(RenderLayer),
     SetPipeline(Index),
     SetVertexBuffer(u32, Index),
     SetIndexBuffer(Index, IndexFormat),
     SetBindGroup(u32, Index),
+    SetModelMaterial(u32, Index, Index),
+    SetCameraBindGroup(u32),
+    SetTransformBindGroup(u32),
+    // Vertex and instance counts, for geometry the shader makes up from the vertex index.
+    Draw(u32, u32),
     DrawIndexed(u32, u32),
-    DrawModelIndexed(Index, u32, &'static [Index]),
+    // Single instance indexed draw the app may cull on the GPU through an indirect draw.
+    DrawIndexedCulled(u32),
+    DrawModelIndexed(Index, u32, BindGroupList),
+    // Leaves the pipeline, bind groups and buffers unset for the commands after it.
+    ExecuteBundle(Index),
+    // Ignored where the adapter doesn't support push constants.
+    SetPushConstants(ShaderStages, u32, [u32; PUSH_CONSTANT_WORDS]),
+}
+
+impl NCommand for NCommandRender {
+    fn pool() -> &'static LocalKey<CommandPool<Self>> {
+        thread_local!(static POOL: CommandPool<NCommandRender> = const { CommandPool::new() });
+        &POOL
+    }
 }
 
-impl NCommand for NCommandRender {}
+impl CommandBuffer<NCommandRender> {
+    pub fn split_layers(self) -> (Self, Self) {
+        let mut opaque = Self::new();
+        let mut transparent = Self::new();
+        let mut layer = RenderLayer::Opaque;
 
+        for command in self {
+            match command {
+                NCommandRender::SetLayer(l) => layer = l,
+                command => match layer {
+                    RenderLayer::Opaque => opaque.push(command),
+                    RenderLayer::Transparent => transparent.push(command),
+                },
+            }
+        }
+
+        (opaque, transparent)
+    }
+}
+
+// Buffers dropped while their thread shuts down don't have a pool left to go to.
+fn give_back<N: NCommand>(commands: Vec<N>) {
+    let _ = N::pool().try_with(|pool| pool.give_back(commands));
+}
+
+// Takes its Vec from the command type's pool and hands it back once dropped, so
+// the buffers every model and actor fills each frame don't allocate once warm.
 pub struct CommandBuffer<N: NCommand> {
     commands: Vec<N>,
 }
 
 impl<N: NCommand> CommandBuffer<N> {
     pub fn new() -> Self {
-        Self { commands: vec![] }
+        Self {
+            commands: N::pool().try_with(CommandPool::take).unwrap_or_default(),
+        }
     }
 
     pub fn push(&mut self, command: N) {
         self.commands.push(command);
     }
 
-    pub fn iter_command(self) -> IntoIter<N> {
-        self.commands.into_iter()
+    pub fn iter_command(mut self) -> Commands<N> {
+        // Reversed so popping hands them out in order without moving the rest.
+        let mut commands = mem::take(&mut self.commands);
+        commands.reverse();
+        Commands { commands }
+    }
+}
+
+impl<N: NCommand> Default for CommandBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<N: NCommand> Deref for CommandBuffer<N> {
+    type Target = [N];
+
+    fn deref(&self) -> &Self::Target {
+        &self.commands
+    }
+}
+
+impl<N: NCommand> Drop for CommandBuffer<N> {
+    fn drop(&mut self) {
+        give_back(mem::take(&mut self.commands));
+    }
+}
+
+impl<N: NCommand> IntoIterator for CommandBuffer<N> {
+    type Item = N;
+    type IntoIter = Commands<N>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_command()
+    }
+}
+
+// Commands of a buffer in the order they were pushed, whatever isn't taken gets
+// dropped along with it and the Vec goes back to the pool.
+pub struct Commands<N: NCommand> {
+    commands: Vec<N>,
+}
+
+impl<N: NCommand> Iterator for Commands<N> {
+    type Item = N;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.commands.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.commands.len(), Some(self.commands.len()))
+    }
+}
+
+impl<N: NCommand> ExactSizeIterator for Commands<N> {}
+
+impl<N: NCommand> Drop for Commands<N> {
+    fn drop(&mut self) {
+        give_back(mem::take(&mut self.commands));
     }
 }
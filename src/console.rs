@@ -0,0 +1,303 @@
+use anyhow::{anyhow, Result};
+use glam::{IVec3, Vec3A};
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::time::Duration;
+use uuid::Uuid;
+use winit::keyboard::KeyCode;
+
+use crate::action_map::{Binding, TOGGLE_CONSOLE};
+use crate::app::Actor;
+use crate::camera::CameraPose;
+use crate::chunks::{Chunk, STONE_ID};
+use crate::command_buffer::{CommandBuffer, NCommandUpdate};
+use crate::input::{InputState, TextInput};
+use crate::world_view::WorldView;
+
+// Output lines kept above the prompt.
+const CONSOLE_LINES: usize = 12;
+// How far in front of the camera `spawn chunk` puts the chunk, and its size.
+const SPAWN_DISTANCE: f32 = 6.0;
+const SPAWN_SIZE: u32 = 4;
+
+// What commands get to look at besides their arguments.
+pub struct ConsoleContext {
+    pub camera: CameraPose,
+}
+
+pub type ConsoleHandler =
+    Box<dyn Fn(&[&str], &ConsoleContext) -> Result<Vec<NCommandUpdate>> + Send + Sync>;
+
+struct ConsoleCommand {
+    usage: String,
+    handler: ConsoleHandler,
+}
+
+fn parse_arg<T: FromStr>(args: &[&str], idx: usize, name: &str) -> Result<T> {
+    let arg = args
+        .get(idx)
+        .ok_or_else(|| anyhow!("missing argument `{name}`"))?;
+    arg.parse()
+        .map_err(|_| anyhow!("`{arg}` isn't a valid {name}"))
+}
+
+// Console commands by name. A line runs the command named by its first word
+// with the rest of the words as arguments.
+#[derive(Default)]
+pub struct ConsoleCommands {
+    commands: HashMap<String, ConsoleCommand>,
+}
+
+impl ConsoleCommands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_defaults() -> Self {
+        let mut commands = Self::new();
+        commands.register("tp", "tp <x> <y> <z>", |args, _| {
+            let position = Vec3A::new(
+                parse_arg(args, 0, "x")?,
+                parse_arg(args, 1, "y")?,
+                parse_arg(args, 2, "z")?,
+            );
+            Ok(vec![NCommandUpdate::TeleportCamera(position)])
+        });
+        commands.register("set_block", "set_block <x> <y> <z> <id|air>", |args, _| {
+            let position = IVec3::new(
+                parse_arg(args, 0, "x")?,
+                parse_arg(args, 1, "y")?,
+                parse_arg(args, 2, "z")?,
+            );
+            let id = match args.get(3) {
+                Some(&"air") => None,
+                _ => Some(parse_arg(args, 3, "block id")?),
+            };
+            Ok(vec![NCommandUpdate::SetBlock(position, id)])
+        });
+        commands.register("fov", "fov <degrees>", |args, _| {
+            let fov = parse_arg::<f32>(args, 0, "field of view")?;
+            Ok(vec![NCommandUpdate::SetFov(fov.to_radians())])
+        });
+        commands.register("fps", "fps <limit|off>", |args, _| {
+            let fps = match args.first() {
+                Some(&"off") => None,
+                _ => Some(parse_arg::<u32>(args, 0, "frame rate limit")?),
+            };
+            Ok(vec![NCommandUpdate::SetTargetFps(fps)])
+        });
+        commands.register("pause", "pause", |_, _| {
+            Ok(vec![NCommandUpdate::SetPaused(true)])
+        });
+        commands.register("resume", "resume", |_, _| {
+            Ok(vec![NCommandUpdate::SetPaused(false)])
+        });
+        commands.register("step", "step [ticks]", |args, _| {
+            let ticks = match args.first() {
+                Some(_) => parse_arg::<u32>(args, 0, "tick count")?,
+                None => 1,
+            };
+            Ok((0..ticks).map(|_| NCommandUpdate::StepSimulation).collect())
+        });
+        commands.register("timescale", "timescale <scale>", |args, _| {
+            let scale = parse_arg::<f32>(args, 0, "time scale")?;
+            Ok(vec![NCommandUpdate::SetTimeScale(scale)])
+        });
+        commands.register("repro", "repro", |_, _| {
+            Ok(vec![NCommandUpdate::ExportReproBundle])
+        });
+        commands.register("spawn", "spawn chunk", |args, context| match args.first() {
+            Some(&"chunk") => {
+                let pose = context.camera;
+                let position = pose.position + pose.forward() * SPAWN_DISTANCE;
+                let mut chunk = Chunk::new(Uuid::new_v4(), position / 16.0);
+                for x in 0..SPAWN_SIZE {
+                    for y in 0..SPAWN_SIZE {
+                        for z in 0..SPAWN_SIZE {
+                            chunk.add_block_data((x, y, z), STONE_ID);
+                        }
+                    }
+                }
+                chunk.settle_fluids();
+                Ok(vec![NCommandUpdate::CreateModel(Box::new(chunk))])
+            }
+            Some(kind) => Err(anyhow!("can't spawn `{kind}`")),
+            None => Err(anyhow!("missing argument `kind`")),
+        });
+
+        commands
+    }
+
+    // Replaces the command if one has the same name.
+    pub fn register<F>(&mut self, name: &str, usage: &str, handler: F)
+    where
+        F: Fn(&[&str], &ConsoleContext) -> Result<Vec<NCommandUpdate>> + Send + Sync + 'static,
+    {
+        self.commands.insert(
+            name.to_string(),
+            ConsoleCommand {
+                usage: usage.to_string(),
+                handler: Box::new(handler),
+            },
+        );
+    }
+
+    pub fn unregister(&mut self, name: &str) {
+        self.commands.remove(name);
+    }
+
+    pub fn usages(&self) -> Vec<&str> {
+        let mut usages = self
+            .commands
+            .values()
+            .map(|command| command.usage.as_str())
+            .collect::<Vec<_>>();
+        usages.sort();
+        usages
+    }
+
+    pub fn run(&self, line: &str, context: &ConsoleContext) -> Result<Vec<NCommandUpdate>> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let Some((name, args)) = words.split_first() else {
+            return Ok(vec![]);
+        };
+        let command = self
+            .commands
+            .get(*name)
+            .ok_or_else(|| anyhow!("unknown command `{name}`, try `help`"))?;
+
+        (command.handler)(args, context).map_err(|e| anyhow!("{e}, usage: {}", command.usage))
+    }
+}
+
+// Drops down on the console action and takes over the keyboard while open.
+// Enter runs the line, Escape or the console action closes it again.
+pub struct DebugConsole {
+    id: Uuid,
+    commands: ConsoleCommands,
+    open: bool,
+    line: String,
+    // What the IME is composing, shown after the line until it gets committed.
+    preedit: String,
+    output: VecDeque<String>,
+}
+
+impl DebugConsole {
+    pub fn new(commands: ConsoleCommands) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            commands,
+            open: false,
+            line: String::new(),
+            preedit: String::new(),
+            output: VecDeque::with_capacity(CONSOLE_LINES),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    fn print(&mut self, text: String) {
+        for line in text.lines() {
+            if self.output.len() == CONSOLE_LINES {
+                self.output.pop_front();
+            }
+            self.output.push_back(line.to_string());
+        }
+    }
+
+    fn execute(&mut self, buffer: &mut CommandBuffer<NCommandUpdate>, world: &WorldView) {
+        let line = std::mem::take(&mut self.line);
+        self.print(format!("> {line}"));
+        if line.trim() == "help" {
+            let usages = self.commands.usages().join("\n");
+            self.print(usages);
+            return;
+        }
+
+        let context = ConsoleContext {
+            camera: world.camera(),
+        };
+        match self.commands.run(&line, &context) {
+            Ok(commands) => {
+                for command in commands {
+                    buffer.push(command);
+                }
+            }
+            Err(e) => self.print(e.to_string()),
+        }
+    }
+
+    fn text(&self) -> String {
+        let mut text = self
+            .output
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+        text.push_str(&format!("> {}{}_", self.line, self.preedit));
+        text
+    }
+}
+
+impl Actor for DebugConsole {
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    fn update(
+        &mut self,
+        _dt: &Duration,
+        inputs: &InputState,
+        world: &WorldView,
+    ) -> CommandBuffer<NCommandUpdate> {
+        let mut buffer = CommandBuffer::new();
+        if !self.open {
+            if inputs.is_action_just_pressed(TOGGLE_CONSOLE) {
+                self.open = true;
+                buffer.push(NCommandUpdate::CaptureText(true));
+                buffer.push(NCommandUpdate::SetConsole(Some(self.text())));
+            }
+            return buffer;
+        }
+
+        let toggle = inputs.action_map().bindings(TOGGLE_CONSOLE);
+        let mut changed = false;
+        for input in inputs.typed() {
+            match *input {
+                TextInput::Key(KeyCode::Escape) => self.open = false,
+                TextInput::Key(key) if toggle.contains(&Binding::Key(key)) => self.open = false,
+                TextInput::Key(KeyCode::Backspace) => {
+                    changed |= self.line.pop().is_some();
+                }
+                TextInput::Key(KeyCode::Enter | KeyCode::NumpadEnter) => {
+                    self.execute(&mut buffer, world);
+                    changed = true;
+                }
+                TextInput::Key(_) => {}
+                TextInput::Char(c) => {
+                    self.line.push(c);
+                    changed = true;
+                }
+            }
+            if !self.open {
+                self.line.clear();
+                self.preedit.clear();
+                buffer.push(NCommandUpdate::CaptureText(false));
+                buffer.push(NCommandUpdate::SetConsole(None));
+                return buffer;
+            }
+        }
+
+        let preedit = inputs.preedit().map_or("", |(text, _)| text);
+        if preedit != self.preedit {
+            self.preedit = preedit.to_string();
+            changed = true;
+        }
+
+        if changed {
+            buffer.push(NCommandUpdate::SetConsole(Some(self.text())));
+        }
+        buffer
+    }
+}
@@ -0,0 +1,221 @@
+use bytemuck::{Pod, Zeroable};
+use std::time::Duration;
+use winit::keyboard::{Key, NamedKey, SmolStr};
+
+use crate::input::InputState;
+
+const TOAST_DURATION: f32 = 2.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DebugFlag {
+    Aabbs,
+    ChunkBorders,
+    Hitboxes,
+    PipelineStats,
+    Profiler,
+    Wireframe,
+    Custom(&'static str),
+}
+
+impl DebugFlag {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebugFlag::Aabbs => "AABBs",
+            DebugFlag::ChunkBorders => "Chunk borders",
+            DebugFlag::Hitboxes => "Hitboxes",
+            DebugFlag::PipelineStats => "Pipeline stats",
+            DebugFlag::Profiler => "Profiler",
+            DebugFlag::Wireframe => "Wireframe",
+            DebugFlag::Custom(name) => name,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    None,
+    LightLevels,
+    AmbientOcclusion,
+    Normals,
+    Overdraw,
+}
+
+impl DebugView {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebugView::None => "None",
+            DebugView::LightLevels => "Light levels",
+            DebugView::AmbientOcclusion => "Ambient occlusion",
+            DebugView::Normals => "Normals",
+            DebugView::Overdraw => "Overdraw",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            DebugView::None => DebugView::LightLevels,
+            DebugView::LightLevels => DebugView::AmbientOcclusion,
+            DebugView::AmbientOcclusion => DebugView::Normals,
+            DebugView::Normals => DebugView::Overdraw,
+            DebugView::Overdraw => DebugView::None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct DebugUniform {
+    view: u32,
+    width: u32,
+    _padding: [u32; 2],
+}
+
+impl DebugUniform {
+    pub fn new(view: DebugView, width: u32) -> Self {
+        Self {
+            view: view as u32,
+            width,
+            _padding: [0; 2],
+        }
+    }
+}
+
+pub struct DebugKey {
+    key: Key,
+    flag: DebugFlag,
+    enabled: bool,
+}
+
+pub struct Toast {
+    text: String,
+    remaining: f32,
+}
+
+pub struct DebugKeys {
+    modifier: Key,
+    view_key: Key,
+    view: DebugView,
+    keys: Vec<DebugKey>,
+    toasts: Vec<Toast>,
+    dirty: bool,
+}
+
+impl DebugKeys {
+    pub fn new() -> Self {
+        Self {
+            modifier: Key::Named(NamedKey::F3),
+            view_key: Key::Character(SmolStr::new("v")),
+            view: DebugView::None,
+            keys: vec![],
+            toasts: vec![],
+            dirty: false,
+        }
+    }
+
+    pub fn with_defaults() -> Self {
+        let mut debug_keys = Self::new();
+        debug_keys.register(Key::Character(SmolStr::new("b")), DebugFlag::Aabbs);
+        debug_keys.register(Key::Character(SmolStr::new("g")), DebugFlag::ChunkBorders);
+        debug_keys.register(Key::Character(SmolStr::new("h")), DebugFlag::Hitboxes);
+        debug_keys.register(Key::Character(SmolStr::new("p")), DebugFlag::PipelineStats);
+        debug_keys.register(Key::Character(SmolStr::new("f")), DebugFlag::Profiler);
+        debug_keys.register(Key::Character(SmolStr::new("l")), DebugFlag::Wireframe);
+
+        debug_keys
+    }
+
+    pub fn register(&mut self, key: Key, flag: DebugFlag) {
+        if let Some(debug_key) = self.keys.iter_mut().find(|k| k.flag == flag) {
+            debug_key.key = key;
+        } else {
+            self.keys.push(DebugKey {
+                key,
+                flag,
+                enabled: false,
+            });
+        }
+    }
+
+    pub fn is_enabled(&self, flag: DebugFlag) -> bool {
+        self.keys
+            .iter()
+            .any(|debug_key| debug_key.flag == flag && debug_key.enabled)
+    }
+
+    pub fn set_enabled(&mut self, flag: DebugFlag, enabled: bool) {
+        if let Some(debug_key) = self.keys.iter_mut().find(|k| k.flag == flag) {
+            debug_key.enabled = enabled;
+            let state = if enabled { "on" } else { "off" };
+            self.toast(format!("{}: {}", flag.name(), state));
+        }
+    }
+
+    pub fn view(&self) -> DebugView {
+        self.view
+    }
+
+    pub fn set_view(&mut self, view: DebugView) {
+        self.view = view;
+        self.toast(format!("Debug view: {}", view.name()));
+    }
+
+    pub fn toggle(&mut self, flag: DebugFlag) {
+        self.set_enabled(flag, !self.is_enabled(flag));
+    }
+
+    pub fn toast<S: Into<String>>(&mut self, text: S) {
+        self.toasts.push(Toast {
+            text: text.into(),
+            remaining: TOAST_DURATION,
+        });
+        self.dirty = true;
+    }
+
+    pub fn update(&mut self, dt: &Duration, input_state: &InputState) {
+        if input_state.is_key_pressed(&self.modifier) {
+            let pressed = self
+                .keys
+                .iter()
+                .filter(|debug_key| input_state.is_key_just_pressed(&debug_key.key))
+                .map(|debug_key| debug_key.flag)
+                .collect::<Vec<DebugFlag>>();
+            for flag in pressed {
+                self.toggle(flag);
+            }
+
+            if input_state.is_key_just_pressed(&self.view_key) {
+                self.set_view(self.view.next());
+            }
+        }
+
+        let dt = dt.as_secs_f32();
+        let count = self.toasts.len();
+        for toast in self.toasts.iter_mut() {
+            toast.remaining -= dt;
+        }
+        self.toasts.retain(|toast| toast.remaining > 0.0);
+        if self.toasts.len() != count {
+            self.dirty = true;
+        }
+    }
+
+    pub fn toasts_text(&self) -> String {
+        self.toasts
+            .iter()
+            .map(|toast| toast.text.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n")
+    }
+
+    pub fn take_dirty(&mut self) -> bool {
+        let dirty = self.dirty;
+        self.dirty = false;
+        dirty
+    }
+}
+
+impl Default for DebugKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
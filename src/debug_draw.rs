@@ -0,0 +1,276 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4};
+use std::f32::consts::TAU;
+use std::mem;
+use wgpu::{
+    BindGroup, BindGroupLayout, BlendState, Buffer, BufferAddress, BufferDescriptor, BufferUsages,
+    ColorTargetState, ColorWrites, DepthBiasState, DepthStencilState, Device, FragmentState,
+    FrontFace, MultisampleState, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor,
+    ShaderModuleDescriptor, ShaderSource, StencilState, VertexAttribute, VertexBufferLayout,
+    VertexState, VertexStepMode,
+};
+
+use crate::depth_compare;
+use crate::frustum::Aabb;
+use crate::post_process::HDR_FORMAT;
+use crate::texture::Texture;
+
+const SPHERE_SEGMENTS: usize = 24;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl DebugVertex {
+    const ATTRIBUTES: [VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: mem::size_of::<DebugVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// What actors can ask to have drawn through NCommandUpdate::DebugDraw.
+#[derive(Copy, Clone, Debug)]
+pub enum DebugShape {
+    Line(Vec3, Vec3),
+    Aabb(Aabb),
+    Sphere(Vec3, f32),
+    // The frustum of a view projection matrix.
+    Frustum(Mat4),
+}
+
+fn create_pipeline(
+    device: &Device,
+    camera_layout: &BindGroupLayout,
+    sample_count: u32,
+    reverse_z: bool,
+) -> RenderPipeline {
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("debug_draw_pipeline_layout"),
+        bind_group_layouts: &[camera_layout],
+        push_constant_ranges: &[],
+    });
+    let shader = device.create_shader_module(ShaderModuleDescriptor {
+        label: Some("debug_draw_shader"),
+        source: ShaderSource::Wgsl(include_str!("../shaders/debug_draw.wgsl").into()),
+    });
+
+    // Lines get hidden by what's in front of them but don't hide anything themselves.
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("debug_draw_pipeline"),
+        layout: Some(&layout),
+        vertex: VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[DebugVertex::desc()],
+        },
+        fragment: Some(FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(ColorTargetState {
+                format: HDR_FORMAT,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::LineList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: depth_compare(reverse_z),
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+// Immediate mode lines. Everything drawn during a frame shows up in that frame's
+// render and gets cleared right after.
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+    pipeline: RenderPipeline,
+    buffer: Buffer,
+    capacity: usize,
+    count: u32,
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        sample_count: u32,
+        reverse_z: bool,
+    ) -> Self {
+        let capacity = 1024;
+        Self {
+            vertices: vec![],
+            pipeline: create_pipeline(device, camera_layout, sample_count, reverse_z),
+            buffer: Self::create_buffer(device, capacity),
+            capacity,
+            count: 0,
+        }
+    }
+
+    fn create_buffer(device: &Device, capacity: usize) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Debug Draw Buffer"),
+            size: (capacity * mem::size_of::<DebugVertex>()) as BufferAddress,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        sample_count: u32,
+        reverse_z: bool,
+    ) {
+        self.pipeline = create_pipeline(device, camera_layout, sample_count, reverse_z);
+    }
+
+    // Lines waiting for the next render.
+    pub fn len(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) {
+        // Points at infinity, like the far plane of a reversed depth frustum.
+        if !start.is_finite() || !end.is_finite() {
+            return;
+        }
+
+        self.vertices.push(DebugVertex {
+            position: start.to_array(),
+            color,
+        });
+        self.vertices.push(DebugVertex {
+            position: end.to_array(),
+            color,
+        });
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        let corners = aabb.corners();
+        self.box_edges(|i| corners[i], color);
+    }
+
+    // Three circles around the center, one per axis.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: [f32; 4]) {
+        for axis in 0..3 {
+            let point = |i: usize| {
+                let angle = i as f32 / SPHERE_SEGMENTS as f32 * TAU;
+                let (sin, cos) = angle.sin_cos();
+                let offset = match axis {
+                    0 => Vec3::new(0.0, cos, sin),
+                    1 => Vec3::new(cos, 0.0, sin),
+                    _ => Vec3::new(cos, sin, 0.0),
+                };
+                center + offset * radius
+            };
+            for i in 0..SPHERE_SEGMENTS {
+                self.line(point(i), point(i + 1), color);
+            }
+        }
+    }
+
+    pub fn frustum(&mut self, view_proj: Mat4, color: [f32; 4]) {
+        let inverse = view_proj.inverse();
+        let corner = |i: usize| {
+            let ndc = Vec4::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+                1.0,
+            );
+            let point = inverse * ndc;
+            point.truncate() / point.w
+        };
+        self.box_edges(corner, color);
+    }
+
+    pub fn ray(&mut self, origin: Vec3, direction: Vec3, length: f32, color: [f32; 4]) {
+        self.line(
+            origin,
+            origin + direction.normalize_or_zero() * length,
+            color,
+        );
+    }
+
+    pub fn shape(&mut self, shape: DebugShape, color: [f32; 4]) {
+        match shape {
+            DebugShape::Line(start, end) => self.line(start, end, color),
+            DebugShape::Aabb(aabb) => self.aabb(&aabb, color),
+            DebugShape::Sphere(center, radius) => self.sphere(center, radius, color),
+            DebugShape::Frustum(view_proj) => self.frustum(view_proj, color),
+        }
+    }
+
+    // The 12 edges between 8 corners numbered by their bits, x first.
+    fn box_edges<F: Fn(usize) -> Vec3>(&mut self, corner: F, color: [f32; 4]) {
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corner(i), corner(i | bit), color);
+                }
+            }
+        }
+    }
+
+    pub fn upload(&mut self, device: &Device, queue: &Queue) {
+        self.count = self.vertices.len() as u32;
+        if self.vertices.is_empty() {
+            return;
+        }
+        if self.vertices.len() > self.capacity {
+            self.capacity = self.vertices.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+
+        queue.write_buffer(&self.buffer, 0, cast_slice(&self.vertices));
+        self.vertices.clear();
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        if self.count == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..self.count, 0..1);
+    }
+}
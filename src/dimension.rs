@@ -0,0 +1,289 @@
+use glam::{IVec3, Vec2, Vec3A};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::app::Model;
+use crate::chunks::Chunk;
+use crate::logging;
+use crate::save::decode_chunk;
+use crate::workers::{WorkerKind, WorkerPools};
+use crate::worldgen::WorldGenerator;
+
+// How much farther than they are chunks right behind the camera count when
+// ordering loads, ones to the side get half of it.
+const BEHIND_PENALTY: f32 = 2.0;
+
+// Lower loads first. Distance from the center chunk, scaled up the further the
+// chunk is from the direction the camera looks in, so turning around fills the
+// view before what's now behind. The chunks right around the center always go
+// first.
+pub fn load_priority(offset: IVec3, forward: Vec2) -> f32 {
+    let offset = Vec2::new(offset.x as f32, offset.z as f32);
+    let distance = offset.length();
+    if distance <= 1.5 {
+        return distance;
+    }
+
+    let facing = offset.dot(forward.normalize_or_zero()) / distance;
+    distance * (1.0 + BEHIND_PENALTY * (1.0 - facing) * 0.5)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DimensionSettings {
+    pub time_of_day: f32,
+    // Length of a day in seconds, 0.0 keeps the time of day frozen.
+    pub cycle_length: f32,
+    pub fog_density: f32,
+    pub spawn: Vec3A,
+}
+
+impl Default for DimensionSettings {
+    fn default() -> Self {
+        Self {
+            time_of_day: 0.4,
+            cycle_length: 600.0,
+            fog_density: 0.002,
+            spawn: Vec3A::new(0.0, 5.0, 10.0),
+        }
+    }
+}
+
+// A world with its own generator and environment. It keeps track of the chunks
+// it has loaded into the app and where the player was, so switching away and
+// back resumes streaming from the same place.
+pub struct Dimension {
+    name: String,
+    generator: WorldGenerator,
+    settings: DimensionSettings,
+    load_radius: i32,
+    loaded: HashMap<IVec3, Uuid>,
+    player_position: Option<Vec3A>,
+    save_dir: Option<PathBuf>,
+    remote: bool,
+}
+
+impl Dimension {
+    pub fn new<S: Into<String>>(
+        name: S,
+        generator: WorldGenerator,
+        settings: DimensionSettings,
+        load_radius: i32,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            generator,
+            settings,
+            load_radius,
+            loaded: HashMap::new(),
+            player_position: None,
+            save_dir: None,
+            remote: false,
+        }
+    }
+
+    // Chunks get saved in `save_dir` when they unload and are loaded back from
+    // there instead of being generated again.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_save_dir<P: Into<PathBuf>>(mut self, save_dir: P) -> Self {
+        self.save_dir = Some(save_dir.into());
+        self
+    }
+
+    // Chunks get inserted from outside, like from a server through
+    // net::Client, instead of streaming in around the player.
+    pub fn remote(mut self) -> Self {
+        self.remote = true;
+        self
+    }
+
+    pub fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    pub fn saves(&self) -> bool {
+        self.save_dir.is_some()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn generator(&self) -> &WorldGenerator {
+        &self.generator
+    }
+
+    pub fn settings(&self) -> &DimensionSettings {
+        &self.settings
+    }
+
+    pub fn settings_mut(&mut self) -> &mut DimensionSettings {
+        &mut self.settings
+    }
+
+    pub fn load_radius(&self) -> i32 {
+        self.load_radius
+    }
+
+    pub fn set_load_radius(&mut self, load_radius: i32) {
+        self.load_radius = load_radius;
+    }
+
+    pub fn loaded_chunks(&self) -> usize {
+        self.loaded.len()
+    }
+
+    // Where the player enters the dimension, the spawn until they leave it once.
+    pub fn entry_position(&self) -> Vec3A {
+        self.player_position.unwrap_or(self.settings.spawn)
+    }
+
+    pub fn set_player_position(&mut self, position: Vec3A) {
+        self.player_position = Some(position);
+    }
+
+    pub fn loaded(&self) -> impl Iterator<Item = (&IVec3, &Uuid)> {
+        self.loaded.iter()
+    }
+
+    pub fn chunk_id(&self, chunk_position: IVec3) -> Option<&Uuid> {
+        self.loaded.get(&chunk_position)
+    }
+
+    // For chunks that came from elsewhere, see App::insert_chunk.
+    pub fn insert_loaded(&mut self, chunk_position: IVec3, id: Uuid) {
+        self.loaded.insert(chunk_position, id);
+    }
+
+    pub fn remove_loaded(&mut self, chunk_position: IVec3) -> Option<Uuid> {
+        self.loaded.remove(&chunk_position)
+    }
+
+    fn chunk_path(&self, chunk_position: IVec3) -> Option<PathBuf> {
+        self.save_dir.as_ref().map(|dir| {
+            dir.join(format!(
+                "{}.{}.{}.chunk",
+                chunk_position.x, chunk_position.y, chunk_position.z
+            ))
+        })
+    }
+
+    fn read_saved(&self, chunk_position: IVec3) -> Option<Chunk> {
+        self.chunk_path(chunk_position)
+            .filter(|path| path.exists())
+            .and_then(|path| {
+                match fs::read(&path)
+                    .map_err(Into::into)
+                    .and_then(|data| decode_chunk(&data))
+                {
+                    Ok(mut chunk) => {
+                        self.generator.tint_chunk(&mut chunk);
+                        Some(chunk)
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            target: logging::CHUNKS,
+                            "Couldn't load {}: {e}",
+                            path.display()
+                        );
+                        None
+                    }
+                }
+            })
+    }
+
+    // Saved chunks win over generating them, a broken save gets generated again.
+    pub fn load_chunk(&mut self, chunk_position: IVec3) -> Chunk {
+        let chunk = self
+            .read_saved(chunk_position)
+            .unwrap_or_else(|| self.generator.generate(chunk_position));
+        self.loaded.insert(chunk_position, *chunk.id());
+        chunk
+    }
+
+    // Like load_chunk for many chunks at once, reading saves on the IO pool and
+    // generating the rest on the generation pool. Chunks come back in order.
+    pub fn load_chunks(&mut self, chunk_positions: &[IVec3], workers: &WorkerPools) -> Vec<Chunk> {
+        let saved = workers.install(WorkerKind::Io, || {
+            chunk_positions
+                .par_iter()
+                .map(|position| self.read_saved(*position))
+                .collect::<Vec<_>>()
+        });
+        let chunks = workers.install(WorkerKind::Generation, || {
+            saved
+                .into_par_iter()
+                .zip(chunk_positions)
+                .map(|(saved, position)| {
+                    saved.unwrap_or_else(|| self.generator.generate(*position))
+                })
+                .collect::<Vec<_>>()
+        });
+        for (position, chunk) in chunk_positions.iter().zip(chunks.iter()) {
+            self.loaded.insert(*position, *chunk.id());
+        }
+
+        chunks
+    }
+
+    pub fn store_chunk(&self, chunk_position: IVec3, data: &[u8]) {
+        let Some(path) = self.chunk_path(chunk_position) else {
+            return;
+        };
+
+        let result =
+            fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(&path, data));
+        if let Err(e) = result {
+            tracing::warn!(target: logging::CHUNKS, "Couldn't save {}: {e}", path.display());
+        }
+    }
+
+    // Chunks missing around `center`, in load_priority order for a camera looking
+    // along `forward`, and the ids of the loaded chunks that fell out of the load
+    // radius. Chunks only span a single layer. Remote dimensions never stream.
+    pub fn plan_streaming(
+        &mut self,
+        center: IVec3,
+        forward: Vec2,
+    ) -> (Vec<IVec3>, Vec<(IVec3, Uuid)>) {
+        if self.remote {
+            return (vec![], vec![]);
+        }
+
+        let center = IVec3::new(center.x, 0, center.z);
+        let in_range = |position: &IVec3| {
+            let offset = (*position - center).abs();
+            offset.x <= self.load_radius && offset.z <= self.load_radius
+        };
+
+        let mut unload = vec![];
+        self.loaded.retain(|position, id| {
+            let keep = in_range(position);
+            if !keep {
+                unload.push((*position, *id));
+            }
+            keep
+        });
+
+        let mut load = vec![];
+        for x in -self.load_radius..=self.load_radius {
+            for z in -self.load_radius..=self.load_radius {
+                let position = center + IVec3::new(x, 0, z);
+                if !self.loaded.contains_key(&position) {
+                    load.push(position);
+                }
+            }
+        }
+        load.sort_by(|a, b| {
+            load_priority(*a - center, forward).total_cmp(&load_priority(*b - center, forward))
+        });
+
+        (load, unload)
+    }
+
+    pub fn unload_all(&mut self) -> Vec<(IVec3, Uuid)> {
+        self.loaded.drain().collect()
+    }
+}
@@ -0,0 +1,136 @@
+use egui::epaint::ClippedPrimitive;
+use egui::{Context, RawInput, TexturesDelta, ViewportId};
+use egui_wgpu::{Renderer, ScreenDescriptor};
+use web_time::Instant;
+use wgpu::{CommandEncoder, Device, Queue, RenderPass, TextureFormat};
+use winit::dpi::PhysicalSize;
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+use crate::command_buffer::{CommandBuffer, NCommandUpdate};
+
+// Builds egui windows and panels every frame. Commands it returns get run by
+// the app like an actor's.
+pub trait UiActor {
+    fn ui(&mut self, ctx: &Context) -> CommandBuffer<NCommandUpdate>;
+}
+
+impl<F: FnMut(&Context)> UiActor for F {
+    fn ui(&mut self, ctx: &Context) -> CommandBuffer<NCommandUpdate> {
+        self(ctx);
+        CommandBuffer::new()
+    }
+}
+
+// egui on top of everything else, drawn in the UI pass after the text labels.
+// Without a window, as in headless apps, it gets no input and a scale of 1.0.
+pub struct EguiLayer {
+    ctx: Context,
+    state: Option<egui_winit::State>,
+    renderer: Renderer,
+    actors: Vec<Box<dyn UiActor>>,
+    paint_jobs: Vec<ClippedPrimitive>,
+    textures: TexturesDelta,
+    screen: ScreenDescriptor,
+    started: Instant,
+}
+
+impl EguiLayer {
+    pub fn new(device: &Device, format: TextureFormat, window: Option<&Window>) -> Self {
+        let ctx = Context::default();
+        let state = window.map(|window| {
+            egui_winit::State::new(
+                ctx.clone(),
+                ViewportId::ROOT,
+                window,
+                Some(window.scale_factor() as f32),
+                Some(device.limits().max_texture_dimension_2d as usize),
+            )
+        });
+
+        Self {
+            ctx,
+            state,
+            renderer: Renderer::new(device, format, None, 1),
+            actors: vec![],
+            paint_jobs: vec![],
+            textures: TexturesDelta::default(),
+            screen: ScreenDescriptor {
+                size_in_pixels: [0, 0],
+                pixels_per_point: 1.0,
+            },
+            started: Instant::now(),
+        }
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    pub fn add_actor(&mut self, actor: Box<dyn UiActor>) {
+        self.actors.push(actor);
+    }
+
+    // True when egui used the event, pointers over a window or typing in a field,
+    // so the rest of the app shouldn't see it.
+    pub fn on_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.state
+            .as_mut()
+            .is_some_and(|state| state.on_window_event(window, event).consumed)
+    }
+
+    // Runs the actors and lays out what they built, to be drawn on render.
+    pub fn run(
+        &mut self,
+        window: Option<&Window>,
+        size: PhysicalSize<u32>,
+    ) -> Vec<CommandBuffer<NCommandUpdate>> {
+        let input = match (self.state.as_mut(), window) {
+            (Some(state), Some(window)) => state.take_egui_input(window),
+            _ => RawInput {
+                screen_rect: Some(egui::Rect::from_min_size(
+                    egui::Pos2::ZERO,
+                    egui::vec2(size.width as f32, size.height as f32),
+                )),
+                time: Some(self.started.elapsed().as_secs_f64()),
+                ..Default::default()
+            },
+        };
+
+        let mut buffers = vec![];
+        let actors = &mut self.actors;
+        let output = self.ctx.run(input, |ctx| {
+            buffers.extend(actors.iter_mut().map(|actor| actor.ui(ctx)));
+        });
+        if let (Some(state), Some(window)) = (self.state.as_mut(), window) {
+            state.handle_platform_output(window, output.platform_output);
+        }
+
+        self.paint_jobs = self.ctx.tessellate(output.shapes, output.pixels_per_point);
+        self.textures.append(output.textures_delta);
+        self.screen = ScreenDescriptor {
+            size_in_pixels: [size.width, size.height],
+            pixels_per_point: output.pixels_per_point,
+        };
+
+        buffers
+    }
+
+    // Uploads textures and buffers, before the UI pass starts.
+    pub fn prepare(&mut self, device: &Device, queue: &Queue, encoder: &mut CommandEncoder) {
+        let textures = std::mem::take(&mut self.textures);
+        for (id, delta) in textures.set.iter() {
+            self.renderer.update_texture(device, queue, *id, delta);
+        }
+        self.renderer
+            .update_buffers(device, queue, encoder, &self.paint_jobs, &self.screen);
+        for id in textures.free.iter() {
+            self.renderer.free_texture(id);
+        }
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        self.renderer
+            .render(render_pass, &self.paint_jobs, &self.screen);
+    }
+}
@@ -0,0 +1,401 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use web_time::Instant;
+use winit::dpi::PhysicalSize;
+use winit::keyboard::NamedKey;
+use winit::{
+    event::*,
+    event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget},
+    keyboard,
+    window::WindowBuilder,
+};
+
+use crate::app::App;
+use crate::capabilities::AdapterRequest;
+use crate::logging;
+use crate::repro;
+use crate::settings::GraphicsSettings;
+use crate::texture_streaming::DEFAULT_TEXTURE_BUDGET;
+use crate::vfs::AssetSource;
+use crate::workers::WorkerConfig;
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    crate::soak::{SoakPilot, SoakTest},
+    std::env,
+    std::path::PathBuf,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const SOAK_SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+// Canvases start out without a size on the web, this is used until the page resizes it.
+#[cfg(target_arch = "wasm32")]
+const CANVAS_SIZE: PhysicalSize<u32> = PhysicalSize::new(1280, 720);
+
+// What a game built on the engine plugs in. `init` runs once the app is set up,
+// before the first frame, `update` every frame before the app's own.
+// `device_recovered` runs after the app moved to a new device, to add back
+// what `App::recover_device` couldn't carry over.
+pub trait Game {
+    fn init(&mut self, app: &mut App) -> Result<()>;
+
+    fn update(&mut self, _app: &mut App, _dt: Duration) {}
+
+    fn device_recovered(&mut self, _app: &mut App) {}
+}
+
+// Opens the window, sets the app up and drives it with a `Game`:
+// `Engine::new().with_title("My game").run(MyGame)`. There are no files on
+// the web, the config files and asset overrides are only read natively.
+pub struct Engine {
+    title: String,
+    size: Option<PhysicalSize<u32>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    graphics_config: PathBuf,
+    graphics_settings: Option<GraphicsSettings>,
+    adapter_request: AdapterRequest,
+    #[cfg(not(target_arch = "wasm32"))]
+    asset_overrides: Option<PathBuf>,
+    asset_sources: Vec<(Box<dyn AssetSource>, i32)>,
+    #[cfg(not(target_arch = "wasm32"))]
+    worker_config: PathBuf,
+    #[cfg(not(target_arch = "wasm32"))]
+    worker_calibration: PathBuf,
+    texture_budget: Option<u64>,
+    log_filters: Option<String>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            title: "VoxelTest".to_string(),
+            size: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            graphics_config: PathBuf::from("graphics.cfg"),
+            graphics_settings: None,
+            adapter_request: AdapterRequest::default(),
+            // Files in here override the embedded assets with the same path, so
+            // textures and models can be swapped without rebuilding.
+            #[cfg(not(target_arch = "wasm32"))]
+            asset_overrides: Some(PathBuf::from("assets")),
+            asset_sources: vec![],
+            // Hand set pool sizes, and where the benchmark keeps the ones it picked.
+            #[cfg(not(target_arch = "wasm32"))]
+            worker_config: PathBuf::from("workers.cfg"),
+            #[cfg(not(target_arch = "wasm32"))]
+            worker_calibration: PathBuf::from("workers.calibration"),
+            texture_budget: Some(DEFAULT_TEXTURE_BUDGET),
+            log_filters: None,
+        }
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn with_size(mut self, width: u32, height: u32) -> Self {
+        self.size = Some(PhysicalSize::new(width, height));
+        self
+    }
+
+    // Used instead of the ones in the graphics config file.
+    pub fn with_graphics_settings(mut self, settings: GraphicsSettings) -> Self {
+        self.graphics_settings = Some(settings);
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_graphics_config<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.graphics_config = path.into();
+        self
+    }
+
+    pub fn with_adapter(mut self, request: AdapterRequest) -> Self {
+        self.adapter_request = request;
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load_graphics_settings(&self) -> GraphicsSettings {
+        if let Some(settings) = self.graphics_settings {
+            return settings;
+        }
+        if !self.graphics_config.exists() {
+            return GraphicsSettings::default();
+        }
+
+        GraphicsSettings::load(&self.graphics_config).unwrap_or_else(|e| {
+            tracing::warn!("Couldn't load {}: {e}", self.graphics_config.display());
+            GraphicsSettings::default()
+        })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn load_graphics_settings(&self) -> GraphicsSettings {
+        self.graphics_settings.unwrap_or_default()
+    }
+
+    // None to only load the embedded assets.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_asset_overrides<P: Into<PathBuf>>(mut self, dir: Option<P>) -> Self {
+        self.asset_overrides = dir.map(Into::into);
+        self
+    }
+
+    // Mounted in the app's file system before `Game::init`, for assets loaded
+    // ahead of time where there's no directory to read them from.
+    pub fn with_asset_source<S: AssetSource + 'static>(mut self, source: S, priority: i32) -> Self {
+        self.asset_sources.push((Box::new(source), priority));
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_worker_config<P: Into<PathBuf>>(mut self, config: P, calibration: P) -> Self {
+        self.worker_config = config.into();
+        self.worker_calibration = calibration.into();
+        self
+    }
+
+    // None streams no textures, they all stay resident.
+    pub fn with_texture_budget(mut self, budget: Option<u64>) -> Self {
+        self.texture_budget = budget;
+        self
+    }
+
+    // Filters by subsystem on top of RUST_LOG's, like `render=debug,chunks=warn`.
+    pub fn with_log_filters(mut self, filters: &str) -> Self {
+        self.log_filters = Some(filters.to_string());
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn setup_files(&mut self, app: &mut App) -> Result<()> {
+        let worker_config = if self.worker_config.exists() {
+            WorkerConfig::load(&self.worker_config).unwrap_or_else(|e| {
+                tracing::warn!("Couldn't load {}: {e}", self.worker_config.display());
+                WorkerConfig::default()
+            })
+        } else {
+            WorkerConfig::default()
+        };
+        app.configure_workers(&worker_config, &self.worker_calibration)?;
+        if let Some(dir) = self.asset_overrides.as_deref().filter(|dir| dir.is_dir()) {
+            app.vfs_mut().mount_dir(dir, 1)?;
+            tracing::info!(
+                target: logging::ASSETS,
+                "Loading assets from {} before the embedded ones",
+                dir.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    // Plain wasm32 runs the workers on the page's thread, see WorkerPools.
+    #[cfg(target_arch = "wasm32")]
+    fn setup_files(&mut self, app: &mut App) -> Result<()> {
+        app.configure_workers(&WorkerConfig::default(), "")
+    }
+
+    fn setup(&mut self, app: &mut App) -> Result<()> {
+        self.setup_files(app)?;
+        app.set_texture_streaming(self.texture_budget)?;
+        for (source, priority) in self.asset_sources.drain(..) {
+            app.vfs_mut().mount_boxed(source, priority);
+        }
+
+        Ok(())
+    }
+
+    // Blocks until the window gets closed or Escape is pressed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run<G: Game + 'static>(self, game: G) -> Result<()> {
+        pollster::block_on(self.start(game))
+    }
+
+    // Returns right away, the page's event loop drives the game from then on.
+    #[cfg(target_arch = "wasm32")]
+    pub fn run<G: Game + 'static>(self, game: G) -> Result<()> {
+        wasm_bindgen_futures::spawn_local(async move {
+            if let Err(e) = self.start(game).await {
+                tracing::error!("{e}");
+            }
+        });
+        Ok(())
+    }
+
+    async fn start<G: Game + 'static>(mut self, mut game: G) -> Result<()> {
+        match &self.log_filters {
+            Some(filters) => repro::init_logging_with(filters),
+            None => repro::init_logging(),
+        }
+
+        let event_loop = EventLoop::new()?;
+        let mut builder = WindowBuilder::new().with_title(&self.title);
+        #[cfg(target_arch = "wasm32")]
+        let size = self.size.or(Some(CANVAS_SIZE));
+        #[cfg(not(target_arch = "wasm32"))]
+        let size = self.size;
+        if let Some(size) = size {
+            builder = builder.with_inner_size(size);
+        }
+        let window = Arc::new(builder.build(&event_loop)?);
+        #[cfg(target_arch = "wasm32")]
+        attach_canvas(&window)?;
+
+        let app = App::new(
+            window.clone(),
+            self.load_graphics_settings(),
+            &self.adapter_request,
+        )
+        .await;
+        // wgpu's errors aren't Send or Sync on wasm32, so they can't go in an anyhow::Error.
+        #[cfg(target_arch = "wasm32")]
+        let mut app = app.map_err(|e| anyhow::anyhow!("{e}"))?;
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut app = app?;
+        self.setup(&mut app)?;
+        game.init(&mut app)?;
+
+        // VOXELTEST_SOAK=<dir> flies around unattended and writes frame time and
+        // resource snapshots there, for VOXELTEST_SOAK_HOURS or until closed.
+        #[cfg(not(target_arch = "wasm32"))]
+        let mut soak = match env::var_os("VOXELTEST_SOAK") {
+            Some(dir) => {
+                let duration = env::var("VOXELTEST_SOAK_HOURS")
+                    .ok()
+                    .and_then(|hours| hours.parse::<f32>().ok())
+                    .map(|hours| Duration::from_secs_f32(hours * 3600.0));
+                app.add_actor(Box::new(SoakPilot::new(16.0, 256.0)));
+                Some(SoakTest::new(dir, SOAK_SNAPSHOT_INTERVAL, duration)?)
+            }
+            None => None,
+        };
+        let mut last_render_time = Instant::now();
+
+        let handler = move |event: Event<()>, event_loop: &EventLoopWindowTarget<()>| {
+            match event {
+                Event::WindowEvent {
+                    ref event,
+                    window_id,
+                } if window_id == window.id() && !app.input(event) => match event {
+                    WindowEvent::CloseRequested
+                    | WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                state: ElementState::Pressed,
+                                logical_key: keyboard::Key::Named(NamedKey::Escape),
+                                ..
+                            },
+                        ..
+                    } => event_loop.exit(),
+                    WindowEvent::Resized(size) => {
+                        app.resize(size);
+                    }
+                    WindowEvent::RedrawRequested => {
+                        if app.is_device_lost() {
+                            match recover(&mut app) {
+                                Ok(_) => game.device_recovered(&mut app),
+                                Err(e) => {
+                                    tracing::error!(
+                                        target: logging::RENDER,
+                                        "Couldn't recover from the device loss: {e}"
+                                    );
+                                    event_loop.exit();
+                                    return;
+                                }
+                            }
+                        }
+                        let now = Instant::now();
+                        let dt = now - last_render_time;
+                        last_render_time = now;
+                        game.update(&mut app, dt);
+                        app.update(dt);
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if let Some(soak) = soak.as_mut() {
+                            if !soak.record_frame(dt, app.resource_counts()) {
+                                event_loop.exit();
+                            }
+                        }
+                        match app.render() {
+                            Ok(_) => {}
+                            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                                app.resize(&app.size())
+                            }
+                            Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
+                            Err(wgpu::SurfaceError::Timeout) => {
+                                tracing::warn!(target: logging::RENDER, "Surface timeout")
+                            }
+                        }
+                    }
+                    _ => {}
+                },
+                Event::AboutToWait => {
+                    // Waits out the rest of the frame instead of rendering frames
+                    // nobody asked for.
+                    let next_frame = app
+                        .frame_interval()
+                        .map(|interval| last_render_time + interval)
+                        .filter(|&next_frame| next_frame > Instant::now());
+                    match next_frame {
+                        Some(next_frame) => {
+                            event_loop.set_control_flow(ControlFlow::WaitUntil(next_frame))
+                        }
+                        None => {
+                            event_loop.set_control_flow(ControlFlow::Poll);
+                            window.request_redraw();
+                        }
+                    }
+                }
+                _ => {}
+            }
+        };
+
+        #[cfg(not(target_arch = "wasm32"))]
+        event_loop.run(handler)?;
+        #[cfg(target_arch = "wasm32")]
+        {
+            use winit::platform::web::EventLoopExtWebSys;
+            event_loop.spawn(handler);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn recover(app: &mut App) -> Result<()> {
+    pollster::block_on(app.recover_device())?;
+    Ok(())
+}
+
+// The page can't wait on the new device inside an event, it has to be reloaded.
+#[cfg(target_arch = "wasm32")]
+fn recover(_app: &mut App) -> Result<()> {
+    Err(anyhow::anyhow!("reload the page to get a new device"))
+}
+
+// Puts the window's canvas at the end of the page.
+#[cfg(target_arch = "wasm32")]
+fn attach_canvas(window: &winit::window::Window) -> Result<()> {
+    use anyhow::anyhow;
+    use winit::platform::web::WindowExtWebSys;
+
+    let canvas = window
+        .canvas()
+        .ok_or_else(|| anyhow!("the window has no canvas"))?;
+    web_sys::window()
+        .and_then(|page| page.document())
+        .and_then(|document| document.body())
+        .and_then(|body| body.append_child(&canvas).ok())
+        .ok_or_else(|| anyhow!("couldn't add the canvas to the page"))?;
+
+    Ok(())
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,164 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+use std::f32::consts::{PI, TAU};
+
+const DAY_TOP: Vec3 = Vec3::new(0.1, 0.3, 0.7);
+const DAY_HORIZON: Vec3 = Vec3::new(0.6, 0.75, 0.9);
+const NIGHT_TOP: Vec3 = Vec3::new(0.01, 0.01, 0.04);
+const NIGHT_HORIZON: Vec3 = Vec3::new(0.04, 0.05, 0.1);
+const SUNSET_HORIZON: Vec3 = Vec3::new(0.9, 0.5, 0.3);
+const GROUND: Vec3 = Vec3::new(0.25, 0.25, 0.3);
+
+pub struct TimeOfDay {
+    time: f32,
+    cycle_length: f32,
+}
+
+impl TimeOfDay {
+    // `time` goes from 0.0 to 1.0: 0.0 is midnight, 0.25 sunrise, 0.5 noon and 0.75 sunset.
+    pub fn new(time: f32, cycle_length: f32) -> Self {
+        Self {
+            time: time.rem_euclid(1.0),
+            cycle_length,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if self.cycle_length > 0.0 {
+            self.time = (self.time + dt / self.cycle_length).rem_euclid(1.0);
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time.rem_euclid(1.0);
+    }
+
+    pub fn cycle_length(&self) -> f32 {
+        self.cycle_length
+    }
+
+    pub fn set_cycle_length(&mut self, cycle_length: f32) {
+        self.cycle_length = cycle_length;
+    }
+
+    pub fn sun_angle(&self) -> f32 {
+        (self.time - 0.25) * TAU
+    }
+
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = self.sun_angle();
+        Vec3::new(angle.cos(), angle.sin(), 0.2).normalize()
+    }
+
+    // Sin of the angle above the horizon, negative at night.
+    pub fn sun_elevation(&self) -> f32 {
+        self.sun_direction().y
+    }
+
+    // The moon sits opposite to the sun.
+    pub fn moon_angle(&self) -> f32 {
+        self.sun_angle() + PI
+    }
+
+    pub fn moon_direction(&self) -> Vec3 {
+        let angle = self.moon_angle();
+        Vec3::new(angle.cos(), angle.sin(), 0.2).normalize()
+    }
+
+    // Stars fade in once the sun is below the horizon.
+    pub fn star_visibility(&self) -> f32 {
+        1.0 - smoothstep(-0.25, 0.0, self.sun_elevation())
+    }
+
+    pub fn sun_intensity(&self) -> f32 {
+        smoothstep(-0.1, 0.2, self.sun_angle().sin())
+    }
+
+    pub fn sky_colors(&self) -> (Vec3, Vec3, Vec3) {
+        let daylight = self.sun_intensity();
+        let sunset = 1.0 - smoothstep(0.0, 0.35, self.sun_angle().sin().abs());
+
+        let top = NIGHT_TOP.lerp(DAY_TOP, daylight);
+        let horizon = NIGHT_HORIZON
+            .lerp(DAY_HORIZON, daylight)
+            .lerp(SUNSET_HORIZON, sunset * 0.6);
+        let ground = GROUND * (0.2 + daylight * 0.8);
+
+        (top, horizon, ground)
+    }
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct EnvironmentUniform {
+    sun_direction: [f32; 4],
+    sun_color: [f32; 4],
+    ambient_color: [f32; 4],
+    // rgb is the fog color, w the density of the exponential haze.
+    fog_color: [f32; 4],
+    // x and y are the distances where the linear fog starts and becomes opaque.
+    fog_range: [f32; 4],
+    // Sun angle, moon angle, star visibility and sun elevation.
+    celestial: [f32; 4],
+    // Cloud coverage, the wind offset of the clouds and their altitude.
+    clouds: [f32; 4],
+}
+
+impl EnvironmentUniform {
+    pub fn new() -> Self {
+        Self {
+            sun_direction: [0.0, 1.0, 0.0, 1.0],
+            sun_color: [1.0, 0.95, 0.85, 1.0],
+            ambient_color: [0.35, 0.38, 0.45, 1.0],
+            fog_color: [0.6, 0.75, 0.9, 0.002],
+            fog_range: [0.0, 0.0, 0.0, 0.0],
+            celestial: [0.0; 4],
+            clouds: [0.0; 4],
+        }
+    }
+
+    pub fn set_fog_range(&mut self, start: f32, end: f32) {
+        self.fog_range = [start, end, 0.0, 0.0];
+    }
+
+    pub fn set_fog_density(&mut self, density: f32) {
+        self.fog_color[3] = density;
+    }
+
+    pub fn set_clouds(&mut self, clouds: [f32; 4]) {
+        self.clouds = clouds;
+    }
+
+    pub fn update(&mut self, time_of_day: &TimeOfDay) {
+        let direction = time_of_day.sun_direction();
+        self.sun_direction = direction.extend(time_of_day.sun_intensity()).to_array();
+        self.celestial = [
+            time_of_day.sun_angle(),
+            time_of_day.moon_angle(),
+            time_of_day.star_visibility(),
+            time_of_day.sun_elevation(),
+        ];
+        let (_, horizon, _) = time_of_day.sky_colors();
+        self.ambient_color = (horizon * 0.5)
+            .max(Vec3::splat(0.05))
+            .extend(1.0)
+            .to_array();
+        // Fog fades into the sky horizon so far chunks blend into the background.
+        self.fog_color = horizon.extend(self.fog_color[3]).to_array();
+    }
+}
+
+impl Default for EnvironmentUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use wgpu::{CreateSurfaceError, RequestDeviceError};
+
+// What the app's public APIs fail with, so games can tell a missing asset from
+// a broken model without matching on messages.
+#[derive(Debug)]
+pub enum EngineError {
+    // Nothing the adapter request allows is there.
+    NoAdapter,
+    Device(RequestDeviceError),
+    Surface(CreateSurfaceError),
+    // An asset that's missing or couldn't be loaded, and why.
+    Asset(String, anyhow::Error),
+    // A model command pointing past the buffers, bind groups, pipelines or
+    // registered models there are.
+    InvalidIndex {
+        kind: &'static str,
+        index: usize,
+        len: usize,
+    },
+}
+
+impl EngineError {
+    pub fn check_index(kind: &'static str, index: usize, len: usize) -> Result<(), Self> {
+        if index < len {
+            Ok(())
+        } else {
+            Err(Self::InvalidIndex { kind, index, len })
+        }
+    }
+}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::NoAdapter => write!(f, "no graphics adapter available"),
+            EngineError::Device(e) => write!(f, "couldn't get a device: {e}"),
+            EngineError::Surface(e) => write!(f, "couldn't create the surface: {e}"),
+            EngineError::Asset(name, e) => write!(f, "couldn't load {name}: {e}"),
+            EngineError::InvalidIndex { kind, index, len } => {
+                write!(f, "{kind} {index} doesn't exist, there are {len}")
+            }
+        }
+    }
+}
+
+impl Error for EngineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EngineError::Device(e) => Some(e),
+            EngineError::Surface(e) => Some(e),
+            EngineError::Asset(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<RequestDeviceError> for EngineError {
+    fn from(e: RequestDeviceError) -> Self {
+        EngineError::Device(e)
+    }
+}
+
+impl From<CreateSurfaceError> for EngineError {
+    fn from(e: CreateSurfaceError) -> Self {
+        EngineError::Surface(e)
+    }
+}
@@ -0,0 +1,242 @@
+use glam::IVec3;
+
+use crate::block_ticks::TickContext;
+use crate::chunks::Block;
+use crate::registry::BlockBehavior;
+use crate::world_edit::split_position;
+
+pub const MAX_FLUID_LEVEL: u8 = 7;
+// Fixed ticks between a fluid cell waking up and it moving, see BlockBehavior::delay.
+pub const FLUID_TICK_INTERVAL: u32 = 5;
+
+const HORIZONTAL: [IVec3; 4] = [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+// Level 0 is a full block, every step away from the source lowers the surface
+// until MAX_FLUID_LEVEL. Falling fluid is always full and spreads like a source
+// once it lands, but disappears as soon as nothing feeds it from above.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FluidCell {
+    Empty,
+    Solid,
+    Fluid { level: u8, falling: bool },
+}
+
+impl FluidCell {
+    pub const SOURCE: FluidCell = FluidCell::Fluid {
+        level: 0,
+        falling: false,
+    };
+
+    fn is_fluid(&self) -> bool {
+        matches!(self, FluidCell::Fluid { .. })
+    }
+
+    fn is_source(&self) -> bool {
+        *self == FluidCell::SOURCE
+    }
+
+    fn spread_level(&self) -> Option<u8> {
+        match *self {
+            FluidCell::Fluid { falling: true, .. } => Some(0),
+            FluidCell::Fluid { level, .. } => Some(level),
+            _ => None,
+        }
+    }
+}
+
+// Fluid spreading, the tick of the fluid block `id`. Each tick recomputes the
+// cell and the empty ones around it that it can flow into from what's around
+// them, so a fluid spreads by one block per tick, across chunk borders too.
+// Edits wake the cells next to them, in the chunks around as well, which keeps
+// the flow going. Chunks that aren't loaded count as solid.
+pub struct FluidBehavior {
+    id: u16,
+}
+
+impl FluidBehavior {
+    pub fn new(id: u16) -> Self {
+        Self { id }
+    }
+
+    fn cell(&self, ctx: &TickContext<'_>, position: IVec3) -> FluidCell {
+        if !ctx.chunk_loaded(split_position(position).0) {
+            return FluidCell::Solid;
+        }
+
+        match ctx.block_state(position).map(Block::new) {
+            None => FluidCell::Empty,
+            Some(block) if block.id() == self.id => FluidCell::Fluid {
+                level: block.fluid_level(),
+                falling: block.is_falling(),
+            },
+            Some(_) => FluidCell::Solid,
+        }
+    }
+
+    fn state(&self, cell: FluidCell) -> Option<u32> {
+        match cell {
+            FluidCell::Fluid { level, falling } => Some(
+                Block::default()
+                    .with_id(self.id)
+                    .with_fluid(level, falling)
+                    .data(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl BlockBehavior for FluidBehavior {
+    fn delay(&self) -> u32 {
+        FLUID_TICK_INTERVAL
+    }
+
+    fn tick(&self, ctx: &mut TickContext<'_>, position: IVec3) {
+        let targets = [position, position - IVec3::Y]
+            .into_iter()
+            .chain(HORIZONTAL.map(|offset| position + offset));
+        for target in targets {
+            let current = self.cell(ctx, target);
+            if target != position && current != FluidCell::Empty {
+                continue;
+            }
+
+            let next = next_state(|position| self.cell(ctx, position), target);
+            if next != current {
+                ctx.set_block_state(target, self.state(next));
+            }
+        }
+    }
+}
+
+// Fluid only spreads sideways once it can't flow any further down.
+fn can_spread_sideways<F: Fn(IVec3) -> FluidCell>(cell: &F, position: IVec3) -> bool {
+    let below = cell(position - IVec3::Y);
+    below == FluidCell::Solid || below.is_source()
+}
+
+fn next_state<F: Fn(IVec3) -> FluidCell>(cell: F, position: IVec3) -> FluidCell {
+    let current = cell(position);
+    if current == FluidCell::Solid || current.is_source() {
+        return current;
+    }
+
+    if cell(position + IVec3::Y).is_fluid() {
+        return FluidCell::Fluid {
+            level: 0,
+            falling: true,
+        };
+    }
+
+    let mut sources = 0;
+    let mut level = None;
+    for offset in HORIZONTAL {
+        let neighbour = cell(position + offset);
+        if neighbour.is_source() {
+            sources += 1;
+        }
+        if !can_spread_sideways(&cell, position + offset) {
+            continue;
+        }
+        if let Some(spread) = neighbour.spread_level() {
+            level = Some(level.map_or(spread + 1, |level: u8| level.min(spread + 1)));
+        }
+    }
+
+    let below = cell(position - IVec3::Y);
+    if sources >= 2 && (below == FluidCell::Solid || below.is_source()) {
+        return FluidCell::SOURCE;
+    }
+
+    match level {
+        Some(level) if level <= MAX_FLUID_LEVEL => FluidCell::Fluid {
+            level,
+            falling: false,
+        },
+        _ => FluidCell::Empty,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::{STONE_ID, WATER_ID};
+    use crate::seed::WorldSeed;
+    use crate::structures::BlockAccess;
+    use crate::world::World;
+    use crate::worldgen::WorldGenerator;
+
+    fn level(world: &World, position: IVec3) -> Option<u8> {
+        let block = Block::new(world.chunks().block_state(position)?);
+        (block.id() == WATER_ID).then(|| block.fluid_level())
+    }
+
+    #[test]
+    fn water_flows_into_the_next_chunk() {
+        let mut world = World::with_generator(WorldGenerator::new(WorldSeed::new(1)));
+        world.generate_region(IVec3::ZERO, IVec3::X);
+        for x in 4..28 {
+            for z in 0..16 {
+                world
+                    .set_block(IVec3::new(x, 4, z), Some(STONE_ID))
+                    .unwrap();
+            }
+        }
+        world
+            .set_block(IVec3::new(13, 5, 8), Some(WATER_ID))
+            .unwrap();
+        for _ in 0..200 {
+            world.tick();
+        }
+
+        assert_eq!(level(&world, IVec3::new(13, 5, 8)), Some(0));
+        assert_eq!(level(&world, IVec3::new(15, 5, 8)), Some(2));
+        assert_eq!(level(&world, IVec3::new(16, 5, 8)), Some(3));
+        assert_eq!(level(&world, IVec3::new(20, 5, 8)), Some(7));
+        assert_eq!(level(&world, IVec3::new(21, 5, 8)), None);
+        assert_eq!(level(&world, IVec3::new(16, 5, 10)), Some(5));
+    }
+
+    #[test]
+    fn water_drains_from_the_next_chunk() {
+        let mut world = World::with_generator(WorldGenerator::new(WorldSeed::new(1)));
+        world.generate_region(IVec3::ZERO, IVec3::X);
+        for x in 4..28 {
+            world
+                .set_block(IVec3::new(x, 4, 8), Some(STONE_ID))
+                .unwrap();
+        }
+        world
+            .set_block(IVec3::new(14, 5, 8), Some(WATER_ID))
+            .unwrap();
+        for _ in 0..200 {
+            world.tick();
+        }
+        assert_eq!(level(&world, IVec3::new(18, 5, 8)), Some(4));
+
+        world.set_block(IVec3::new(14, 5, 8), None).unwrap();
+        for _ in 0..200 {
+            world.tick();
+        }
+        assert!((4..28).all(|x| level(&world, IVec3::new(x, 5, 8)).is_none()));
+    }
+
+    #[test]
+    fn blocks_placed_over_water_drop_its_state() {
+        let mut world = World::with_generator(WorldGenerator::new(WorldSeed::new(1)));
+        let position = IVec3::new(8, 5, 8);
+        world.generate_region(IVec3::ZERO, IVec3::ZERO);
+        world.set_block(position, Some(WATER_ID)).unwrap();
+        let water = Block::default().with_id(WATER_ID).with_fluid(3, true);
+        world
+            .chunks_mut()
+            .set_block_state(position, Some(water.data()));
+        assert_eq!(level(&world, position), Some(3));
+
+        world.set_block(position, Some(STONE_ID)).unwrap();
+        let stone = Block::default()
+            .with_position(split_position(position).1)
+            .with_id(STONE_ID);
+        assert_eq!(world.chunks().block_state(position), Some(stone.data()));
+    }
+}
@@ -0,0 +1,85 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{UVec3, Vec3A};
+use std::mem::size_of;
+use wgpu::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode};
+
+use crate::model::Vertex;
+
+// Two crossed quads, each drawn from both sides.
+pub const FOLIAGE_VERTICES: u32 = 24;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DecorationKind {
+    Grass,
+    Flower,
+}
+
+impl DecorationKind {
+    pub fn id(self) -> u8 {
+        match self {
+            DecorationKind::Grass => 0,
+            DecorationKind::Flower => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(DecorationKind::Grass),
+            1 => Some(DecorationKind::Flower),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct FoliageInstance {
+    position: [f32; 4],
+    tint: [f32; 4],
+}
+
+impl Vertex for FoliageInstance {
+    fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: size_of::<FoliageInstance>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &[
+                VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: VertexFormat::Float32x4,
+                },
+                VertexAttribute {
+                    offset: size_of::<[f32; 4]>() as BufferAddress,
+                    shader_location: 6,
+                    format: VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// Something small growing out of the top of a block, taking the empty cell
+// above it without being a block itself. Positions are local to the chunk.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Decoration {
+    pub kind: DecorationKind,
+    pub position: UVec3,
+}
+
+impl Decoration {
+    pub fn new(kind: DecorationKind, position: UVec3) -> Self {
+        Self { kind, position }
+    }
+
+    // The w is the kind, for the shader to pick its shape. Leaves and stems
+    // get the biome's foliage tint.
+    pub fn to_raw(&self, origin: Vec3A, tint: [f32; 3]) -> FoliageInstance {
+        FoliageInstance {
+            position: (origin + self.position.as_vec3a())
+                .extend(self.kind.id() as f32)
+                .to_array(),
+            tint: [tint[0], tint[1], tint[2], 1.0],
+        }
+    }
+}
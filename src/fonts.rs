@@ -0,0 +1,160 @@
+use glyphon::fontdb::{Database, Query, ID};
+use glyphon::{Attrs, Family, FontSystem, Stretch, Style, Weight};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::assets::Res;
+use crate::logging;
+
+pub const DEFAULT_FAMILY: &str = "DejaVu Sans";
+const DEFAULT_FONT: &str = "res/fonts/DejaVuSans.ttf";
+
+#[derive(Clone, Debug)]
+pub struct FontSettings {
+    pub family: String,
+    // Tried in order for every character the primary family has no glyph for.
+    pub fallbacks: Vec<String>,
+    pub font_files: Vec<PathBuf>,
+    pub load_system_fonts: bool,
+    // Picks the script specific fallbacks of the shaper, the system locale is used when unset.
+    pub locale: Option<String>,
+}
+
+impl Default for FontSettings {
+    fn default() -> Self {
+        Self {
+            family: DEFAULT_FAMILY.to_string(),
+            fallbacks: [
+                "Noto Sans",
+                "Noto Sans CJK SC",
+                "Noto Sans Arabic",
+                "Noto Sans Hebrew",
+                "Noto Sans Devanagari",
+                "Noto Sans Thai",
+                "Noto Sans Symbols",
+                "Noto Sans Symbols 2",
+                "Noto Color Emoji",
+                "Noto Emoji",
+                "Symbola",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            font_files: vec![],
+            load_system_fonts: true,
+            locale: None,
+        }
+    }
+}
+
+pub struct FontChain {
+    families: Vec<(String, ID)>,
+    coverage: HashMap<char, usize>,
+}
+
+impl FontChain {
+    pub fn load(settings: &FontSettings) -> (FontSystem, Self) {
+        let (system_locale, mut db) = if settings.load_system_fonts {
+            FontSystem::new().into_locale_and_db()
+        } else {
+            (String::from("en-US"), Database::new())
+        };
+
+        db.load_font_data(Res::get(DEFAULT_FONT).unwrap().data.into_owned());
+        for path in settings.font_files.iter() {
+            if let Err(err) = db.load_font_file(path) {
+                tracing::warn!(
+                    target: logging::ASSETS,
+                    "Couldn't load font {}: {err}",
+                    path.display()
+                );
+            }
+        }
+        db.set_sans_serif_family(settings.family.as_str());
+
+        let families = std::iter::once(&settings.family)
+            .chain(settings.fallbacks.iter())
+            .filter_map(|family| {
+                let id = db.query(&Query {
+                    families: &[Family::Name(family)],
+                    weight: Weight::NORMAL,
+                    stretch: Stretch::Normal,
+                    style: Style::Normal,
+                });
+                if id.is_none() {
+                    tracing::debug!(
+                        target: logging::ASSETS,
+                        "Font family {family} not found, skipping it"
+                    );
+                }
+                id.map(|id| (family.clone(), id))
+            })
+            .collect();
+
+        let locale = settings.locale.clone().unwrap_or(system_locale);
+        let font_system = FontSystem::new_with_locale_and_db(locale, db);
+
+        (
+            font_system,
+            Self {
+                families,
+                coverage: HashMap::new(),
+            },
+        )
+    }
+
+    fn family_for(&mut self, font_system: &mut FontSystem, c: char) -> Option<usize> {
+        if let Some(&idx) = self.coverage.get(&c) {
+            return Some(idx);
+        }
+
+        let idx = self.families.iter().position(|(_, id)| {
+            font_system
+                .get_font(*id)
+                .is_some_and(|font| font.as_swash().charmap().map(c) != 0)
+        })?;
+        self.coverage.insert(c, idx);
+
+        Some(idx)
+    }
+
+    // Splits `text` in runs that each use the first family of the chain able to draw
+    // them. Characters nobody covers stay with the primary family and are left to
+    // the shaper's own fallback.
+    pub fn spans<'a>(
+        &mut self,
+        font_system: &mut FontSystem,
+        text: &'a str,
+    ) -> Vec<(&'a str, usize)> {
+        let mut spans: Vec<(&'a str, usize)> = vec![];
+        let mut start = 0;
+        let mut current = None;
+
+        for (offset, c) in text.char_indices() {
+            if c.is_whitespace() || c.is_control() {
+                continue;
+            }
+
+            let idx = self.family_for(font_system, c).unwrap_or(0);
+            match current {
+                Some(family) if family != idx => {
+                    spans.push((&text[start..offset], family));
+                    start = offset;
+                    current = Some(idx);
+                }
+                None => current = Some(idx),
+                _ => {}
+            }
+        }
+        spans.push((&text[start..], current.unwrap_or(0)));
+
+        spans
+    }
+
+    pub fn attrs(&self, idx: usize) -> Attrs<'_> {
+        match self.families.get(idx) {
+            Some((family, _)) => Attrs::new().family(Family::Name(family)),
+            None => Attrs::new().family(Family::SansSerif),
+        }
+    }
+}
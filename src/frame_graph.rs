@@ -0,0 +1,129 @@
+use wgpu::{
+    BindGroup, BindGroupLayout, CommandEncoder, Device, LoadOp, Operations, Queue, RenderPass,
+    RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor, StoreOp,
+    TextureFormat, TextureView,
+};
+
+use crate::app::NModel;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PassStage {
+    AfterOpaque,
+    AfterTransparent,
+}
+
+pub struct GlobalBindGroups<'a> {
+    pub camera: &'a BindGroup,
+    pub camera_layout: &'a BindGroupLayout,
+}
+
+// The scene's targets passes draw into, with what their pipelines need to
+// match them.
+#[derive(Copy, Clone)]
+pub struct FrameTargets<'a> {
+    pub color_view: &'a TextureView,
+    pub resolve_target: Option<&'a TextureView>,
+    pub depth_view: &'a TextureView,
+    pub color_format: TextureFormat,
+    pub sample_count: u32,
+    pub reverse_z: bool,
+}
+
+pub struct FrameGraphBuilder<'a> {
+    device: &'a Device,
+    queue: &'a Queue,
+    encoder: &'a mut CommandEncoder,
+    targets: FrameTargets<'a>,
+}
+
+impl<'a> FrameGraphBuilder<'a> {
+    pub fn new(
+        device: &'a Device,
+        queue: &'a Queue,
+        encoder: &'a mut CommandEncoder,
+        targets: FrameTargets<'a>,
+    ) -> Self {
+        Self {
+            device,
+            queue,
+            encoder,
+            targets,
+        }
+    }
+
+    pub fn device(&self) -> &Device {
+        self.device
+    }
+
+    pub fn queue(&self) -> &Queue {
+        self.queue
+    }
+
+    pub fn encoder(&mut self) -> &mut CommandEncoder {
+        self.encoder
+    }
+
+    pub fn color_view(&self) -> &TextureView {
+        self.targets.color_view
+    }
+
+    // The multisampled color target gets resolved here at the end of every pass,
+    // `None` when MSAA is disabled and passes draw straight into `color_view`.
+    pub fn resolve_target(&self) -> Option<&TextureView> {
+        self.targets.resolve_target
+    }
+
+    pub fn depth_view(&self) -> &TextureView {
+        self.targets.depth_view
+    }
+
+    pub fn color_format(&self) -> TextureFormat {
+        self.targets.color_format
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.targets.sample_count
+    }
+
+    // Pipelines drawing into the depth buffer have to compare with depth_compare.
+    pub fn reverse_z(&self) -> bool {
+        self.targets.reverse_z
+    }
+
+    pub fn begin_pass(&mut self, label: &str) -> RenderPass<'_> {
+        self.encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: self.targets.color_view,
+                resolve_target: self.targets.resolve_target,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: self.targets.depth_view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        })
+    }
+}
+
+pub trait RenderPassProvider {
+    fn stage(&self) -> PassStage {
+        PassStage::AfterTransparent
+    }
+
+    fn render(
+        &mut self,
+        builder: &mut FrameGraphBuilder,
+        globals: &GlobalBindGroups,
+        models: &[&NModel],
+    );
+}
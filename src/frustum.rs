@@ -1,33 +1,13 @@
-use glam::{Mat4, Vec3};
-use std::mem;
+use glam::{Mat4, Vec3, Vec4};
 
+// The six planes around what a view projection matrix sees, pointing inwards
+// and normalized, so a plane's w plus its dot with a point is how far in the
+// point is.
 pub struct FrustumCuller {
-    nx_x: f32,
-    nx_y: f32,
-    nx_z: f32,
-    nx_w: f32,
-    px_x: f32,
-    px_y: f32,
-    px_z: f32,
-    px_w: f32,
-    ny_x: f32,
-    ny_y: f32,
-    ny_z: f32,
-    ny_w: f32,
-    py_x: f32,
-    py_y: f32,
-    py_z: f32,
-    py_w: f32,
-    nz_x: f32,
-    nz_y: f32,
-    nz_z: f32,
-    nz_w: f32,
-    pz_x: f32,
-    pz_y: f32,
-    pz_z: f32,
-    pz_w: f32,
+    planes: [Vec4; 6],
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Aabb {
     min: Vec3,
     max: Vec3,
@@ -38,154 +18,303 @@ impl Aabb {
     pub fn from_params(min: Vec3, max: Vec3) -> Self {
         Self { min, max }
     }
+
+    #[inline]
+    pub fn min(&self) -> Vec3 {
+        self.min
+    }
+
+    #[inline]
+    pub fn max(&self) -> Vec3 {
+        self.max
+    }
+
+    #[inline]
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    // Half the size along each axis.
+    #[inline]
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        std::array::from_fn(|i| {
+            Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            )
+        })
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    // Boxes only touching on a face count as intersecting.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.cmple(other.max).all() && other.min.cmple(self.max).all()
+    }
+
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.min.cmple(point).all() && point.cmple(self.max).all()
+    }
+
+    // Box around all 8 corners after the transform.
+    pub fn transform(&self, matrix: Mat4) -> Self {
+        let mut min = Vec3::splat(f32::INFINITY);
+        let mut max = Vec3::splat(f32::NEG_INFINITY);
+        for corner in self.corners() {
+            let corner = matrix.transform_point3(corner);
+            min = min.min(corner);
+            max = max.max(corner);
+        }
+
+        Self { min, max }
+    }
+
+    // How far along the ray it enters the box, in lengths of `direction`. 0
+    // when the ray starts inside.
+    pub fn ray_intersect(&self, origin: Vec3, direction: Vec3) -> Option<f32> {
+        let mut enter = 0.0_f32;
+        let mut exit = f32::INFINITY;
+        for axis in 0..3 {
+            let (min, max) = (self.min[axis], self.max[axis]);
+            if direction[axis] == 0.0 {
+                // Parallel to this pair of faces, it has to start between them.
+                if origin[axis] < min || origin[axis] > max {
+                    return None;
+                }
+                continue;
+            }
+
+            let a = (min - origin[axis]) / direction[axis];
+            let b = (max - origin[axis]) / direction[axis];
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+            if enter > exit {
+                return None;
+            }
+        }
+
+        Some(enter)
+    }
 }
 
 impl FrustumCuller {
+    // Left, right, bottom, top, near and far, for wgpu's 0 to 1 depth range.
+    // With an infinite reversed projection near and far swap places, and the
+    // one at infinity has no normal and lets everything through.
     pub fn from_matrix(m: Mat4) -> Self {
-        let mut culler: Self = unsafe { mem::zeroed() };
-
-        culler.nx_x = m.x_axis.w + m.x_axis.x;
-        culler.nx_y = m.y_axis.w + m.y_axis.x;
-        culler.nx_z = m.z_axis.w + m.z_axis.x;
-        culler.nx_w = m.w_axis.w + m.w_axis.x;
-        culler.px_x = m.x_axis.w - m.x_axis.x;
-        culler.px_y = m.y_axis.w - m.y_axis.x;
-        culler.px_z = m.z_axis.w - m.z_axis.x;
-        culler.px_w = m.w_axis.w - m.w_axis.x;
-        culler.ny_x = m.x_axis.w + m.x_axis.y;
-        culler.ny_y = m.y_axis.w + m.y_axis.y;
-        culler.ny_z = m.z_axis.w + m.z_axis.y;
-        culler.ny_w = m.w_axis.w + m.w_axis.y;
-        culler.py_x = m.x_axis.w - m.x_axis.y;
-        culler.py_y = m.y_axis.w - m.y_axis.y;
-        culler.py_z = m.z_axis.w - m.z_axis.y;
-        culler.py_w = m.w_axis.w - m.w_axis.y;
-        culler.nz_x = m.x_axis.w + m.x_axis.z;
-        culler.nz_y = m.y_axis.w + m.y_axis.z;
-        culler.nz_z = m.z_axis.w + m.z_axis.z;
-        culler.nz_w = m.w_axis.w + m.w_axis.z;
-        culler.pz_x = m.x_axis.w - m.x_axis.z;
-        culler.pz_y = m.y_axis.w - m.y_axis.z;
-        culler.pz_z = m.z_axis.w - m.z_axis.z;
-        culler.pz_w = m.w_axis.w - m.w_axis.z;
-
-        culler
-    }
-
-    pub fn test_bounding_box(&self, aab: &Aabb) -> bool {
-        self.nx_x
-            * if self.nx_x < 0.0 {
-                aab.min.x
-            } else {
-                aab.max.x
+        let (x, y, z, w) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(normalize_plane);
+
+        Self { planes }
+    }
+
+    // Same planes as the CPU test, laid out for the culling compute shader.
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        self.planes.map(|plane| plane.to_array())
+    }
+
+    // Only the corner furthest along each plane's normal needs to be inside it.
+    pub fn test_bounding_box(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            let corner = Vec3::select(normal.cmplt(Vec3::ZERO), aabb.min, aabb.max);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn test_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(center) + plane.w >= -radius)
+    }
+
+    // The box test for when there's also a sphere around the box. Planes the
+    // sphere is all in front of or behind settle it, only the ones cutting
+    // through it need the box.
+    pub fn test_bounds(&self, aabb: &Aabb, center: Vec3, radius: f32) -> bool {
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            let distance = normal.dot(center) + plane.w;
+            if distance.abs() >= radius {
+                return distance >= 0.0;
             }
-            + self.nx_y
-                * if self.nx_y < 0.0 {
-                    aab.min.y
-                } else {
-                    aab.max.y
-                }
-            + self.nx_z
-                * if self.nx_z < 0.0 {
-                    aab.min.z
-                } else {
-                    aab.max.z
-                }
-            >= -self.nx_w
-            && self.px_x
-                * if self.px_x < 0.0 {
-                    aab.min.x
-                } else {
-                    aab.max.x
-                }
-                + self.px_y
-                    * if self.px_y < 0.0 {
-                        aab.min.y
-                    } else {
-                        aab.max.y
-                    }
-                + self.px_z
-                    * if self.px_z < 0.0 {
-                        aab.min.z
-                    } else {
-                        aab.max.z
-                    }
-                >= -self.px_w
-            && self.ny_x
-                * if self.ny_x < 0.0 {
-                    aab.min.x
-                } else {
-                    aab.max.x
-                }
-                + self.ny_y
-                    * if self.ny_y < 0.0 {
-                        aab.min.y
-                    } else {
-                        aab.max.y
-                    }
-                + self.ny_z
-                    * if self.ny_z < 0.0 {
-                        aab.min.z
-                    } else {
-                        aab.max.z
-                    }
-                >= -self.ny_w
-            && self.py_x
-                * if self.py_x < 0.0 {
-                    aab.min.x
-                } else {
-                    aab.max.x
-                }
-                + self.py_y
-                    * if self.py_y < 0.0 {
-                        aab.min.y
-                    } else {
-                        aab.max.y
-                    }
-                + self.py_z
-                    * if self.py_z < 0.0 {
-                        aab.min.z
-                    } else {
-                        aab.max.z
-                    }
-                >= -self.py_w
-            && self.nz_x
-                * if self.nz_x < 0.0 {
-                    aab.min.x
-                } else {
-                    aab.max.x
-                }
-                + self.nz_y
-                    * if self.nz_y < 0.0 {
-                        aab.min.y
-                    } else {
-                        aab.max.y
-                    }
-                + self.nz_z
-                    * if self.nz_z < 0.0 {
-                        aab.min.z
-                    } else {
-                        aab.max.z
-                    }
-                >= -self.nz_w
-            && self.pz_x
-                * if self.pz_x < 0.0 {
-                    aab.min.x
-                } else {
-                    aab.max.x
-                }
-                + self.pz_y
-                    * if self.pz_y < 0.0 {
-                        aab.min.y
-                    } else {
-                        aab.max.y
-                    }
-                + self.pz_z
-                    * if self.pz_z < 0.0 {
-                        aab.min.z
-                    } else {
-                        aab.max.z
-                    }
-                >= -self.pz_w
+
+            let corner = Vec3::select(normal.cmplt(Vec3::ZERO), aabb.min, aabb.max);
+            normal.dot(corner) + plane.w >= 0.0
+        })
+    }
+
+    pub fn test_point(&self, point: Vec3) -> bool {
+        self.test_sphere(point, 0.0)
+    }
+}
+
+// Planes of degenerate matrices have no normal to scale by, they're kept as
+// they are instead of turning into NaNs.
+fn normalize_plane(plane: Vec4) -> Vec4 {
+    let length = plane.truncate().length();
+    if length > f32::EPSILON {
+        plane / length
+    } else {
+        plane
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn culler() -> FrustumCuller {
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        let proj = Mat4::perspective_rh(90_f32.to_radians(), 1.0, 0.1, 100.0);
+        FrustumCuller::from_matrix(proj * view)
+    }
+
+    fn cube(center: Vec3, half: f32) -> Aabb {
+        Aabb::from_params(center - half, center + half)
+    }
+
+    #[test]
+    fn aabb_union_and_intersection() {
+        let a = Aabb::from_params(Vec3::ZERO, Vec3::ONE);
+        let b = Aabb::from_params(Vec3::splat(0.5), Vec3::splat(2.0));
+        let c = Aabb::from_params(Vec3::new(3.0, 0.0, 0.0), Vec3::new(4.0, 1.0, 1.0));
+        assert_eq!(a.union(&b), Aabb::from_params(Vec3::ZERO, Vec3::splat(2.0)));
+        assert_eq!(a.union(&c).extents(), Vec3::new(2.0, 0.5, 0.5));
+        assert!(a.intersects(&b) && b.intersects(&a));
+        assert!(!a.intersects(&c));
+        // Sharing only a face.
+        assert!(a.intersects(&Aabb::from_params(Vec3::X, Vec3::new(2.0, 1.0, 1.0))));
+        assert!(a.contains_point(Vec3::splat(0.5)) && a.contains_point(Vec3::ONE));
+        assert!(!a.contains_point(Vec3::new(0.5, 1.5, 0.5)));
+    }
+
+    #[test]
+    fn aabb_transform() {
+        let a = Aabb::from_params(Vec3::ZERO, Vec3::ONE);
+        let moved = a.transform(Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(moved.min(), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(moved.center(), Vec3::new(1.5, 2.5, 3.5));
+        let rotated = a.transform(Mat4::from_rotation_z(45_f32.to_radians()));
+        assert!((rotated.extents().x - 2_f32.sqrt() * 0.5).abs() < 1e-5);
+        assert!((rotated.extents().z - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn aabb_ray() {
+        let a = Aabb::from_params(Vec3::splat(-1.0), Vec3::ONE);
+        assert_eq!(
+            a.ray_intersect(Vec3::new(-5.0, 0.0, 0.0), Vec3::X),
+            Some(4.0)
+        );
+        assert_eq!(
+            a.ray_intersect(Vec3::new(-5.0, 0.0, 0.0), Vec3::X * 2.0),
+            Some(2.0)
+        );
+        assert_eq!(a.ray_intersect(Vec3::ZERO, Vec3::Y), Some(0.0));
+        assert_eq!(
+            a.ray_intersect(Vec3::new(-5.0, 0.0, 0.0), Vec3::NEG_X),
+            None
+        );
+        assert_eq!(a.ray_intersect(Vec3::new(-5.0, 2.0, 0.0), Vec3::X), None);
+        // Grazing an edge, and parallel along a face.
+        assert_eq!(
+            a.ray_intersect(Vec3::new(-5.0, 1.0, 0.0), Vec3::X),
+            Some(4.0)
+        );
+        let diagonal = Vec3::ONE.normalize();
+        let hit = a.ray_intersect(Vec3::splat(-5.0), diagonal).unwrap();
+        assert!((hit - 4.0 * 3_f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn planes_are_normalized() {
+        for plane in culler().planes() {
+            let length = Vec4::from_array(plane).truncate().length();
+            assert!((length - 1.0).abs() < 1e-5, "{plane:?}");
+        }
+    }
+
+    #[test]
+    fn points() {
+        let culler = culler();
+        assert!(culler.test_point(Vec3::new(0.0, 0.0, -10.0)));
+        assert!(culler.test_point(Vec3::new(9.0, -9.0, -10.0)));
+        assert!(!culler.test_point(Vec3::new(11.0, 0.0, -10.0)));
+        assert!(!culler.test_point(Vec3::new(0.0, 0.0, 10.0)));
+        assert!(!culler.test_point(Vec3::new(0.0, 0.0, -0.05)));
+        assert!(!culler.test_point(Vec3::new(0.0, 0.0, -101.0)));
+    }
+
+    #[test]
+    fn boxes() {
+        let culler = culler();
+        assert!(culler.test_bounding_box(&cube(Vec3::new(0.0, 0.0, -10.0), 1.0)));
+        assert!(!culler.test_bounding_box(&cube(Vec3::new(0.0, 0.0, 10.0), 1.0)));
+        // Straddling the left plane, and the camera itself.
+        assert!(culler.test_bounding_box(&cube(Vec3::new(-10.5, 0.0, -10.0), 1.0)));
+        assert!(culler.test_bounding_box(&cube(Vec3::ZERO, 1.0)));
+        assert!(!culler.test_bounding_box(&cube(Vec3::new(-12.5, 0.0, -10.0), 1.0)));
+        assert!(!culler.test_bounding_box(&cube(Vec3::new(0.0, 0.0, -110.0), 1.0)));
+    }
+
+    #[test]
+    fn spheres() {
+        let culler = culler();
+        // 1 away from the right plane, measured along its normal.
+        let outside = Vec3::new(10.0 + 2_f32.sqrt(), 0.0, -10.0);
+        assert!(!culler.test_sphere(outside, 0.9));
+        assert!(culler.test_sphere(outside, 1.1));
+        assert!(!culler.test_sphere(Vec3::new(0.0, 0.0, 5.0), 4.0));
+        assert!(culler.test_sphere(Vec3::new(0.0, 0.0, 5.0), 6.0));
+    }
+
+    #[test]
+    fn bounds_agree_with_boxes() {
+        let culler = culler();
+        for x in -15..=15 {
+            for z in -120..=15 {
+                let aabb = cube(Vec3::new(x as f32, 0.5, z as f32), 0.75);
+                let radius = aabb.extents().length();
+                assert_eq!(
+                    culler.test_bounds(&aabb, aabb.center(), radius),
+                    culler.test_bounding_box(&aabb),
+                    "{aabb:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn infinite_reverse_z() {
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        let proj = Mat4::perspective_infinite_reverse_rh(90_f32.to_radians(), 1.0, 0.1);
+        let culler = FrustumCuller::from_matrix(proj * view);
+        assert!(culler.test_point(Vec3::new(0.0, 0.0, -1e6)));
+        assert!(!culler.test_point(Vec3::new(0.0, 0.0, -0.05)));
+        assert!(!culler.test_point(Vec3::new(0.0, 0.0, 1e6)));
+    }
+
+    #[test]
+    fn degenerate_matrices() {
+        for m in [
+            Mat4::ZERO,
+            Mat4::from_cols(Vec4::ZERO, Vec4::Y, Vec4::Z, Vec4::W),
+        ] {
+            let culler = FrustumCuller::from_matrix(m);
+            assert!(culler.planes().iter().flatten().all(|v| v.is_finite()));
+            culler.test_bounding_box(&cube(Vec3::ZERO, 1.0));
+        }
+        assert!(FrustumCuller::from_matrix(Mat4::ZERO).test_point(Vec3::ONE));
     }
 }
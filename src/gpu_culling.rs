@@ -0,0 +1,282 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::Vec3A;
+use std::collections::HashMap;
+use uuid::Uuid;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingType, Buffer, BufferAddress, BufferBindingType, BufferDescriptor,
+    BufferUsages, CommandEncoder, ComputePassDescriptor, ComputePipeline,
+    ComputePipelineDescriptor, Device, PipelineLayoutDescriptor, Queue, ShaderModuleDescriptor,
+    ShaderSource, ShaderStages,
+};
+
+use crate::frustum::FrustumCuller;
+
+const WORKGROUP_SIZE: u32 = 64;
+const MIN_CAPACITY: usize = 256;
+// index_count, instance_count, first_index, base_vertex, first_instance
+pub const DRAW_ARGS_SIZE: BufferAddress = 5 * 4;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct CullEntry {
+    aabb_min: [f32; 4],
+    aabb_max: [f32; 4],
+    position: [f32; 4],
+    index_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    _padding: u32,
+}
+
+impl CullEntry {
+    pub fn new(
+        aabb_min: [f32; 3],
+        aabb_max: [f32; 3],
+        position: [f32; 3],
+        index_count: u32,
+    ) -> Self {
+        Self {
+            aabb_min: [aabb_min[0], aabb_min[1], aabb_min[2], 0.0],
+            aabb_max: [aabb_max[0], aabb_max[1], aabb_max[2], 0.0],
+            position: [position[0], position[1], position[2], 0.0],
+            index_count,
+            first_index: 0,
+            base_vertex: 0,
+            _padding: 0,
+        }
+    }
+
+    // Where the geometry starts when the model is drawn from a shared buffer.
+    pub fn with_offsets(mut self, first_index: u32, base_vertex: i32) -> Self {
+        self.first_index = first_index;
+        self.base_vertex = base_vertex;
+        self
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    camera: [f32; 4],
+    count: u32,
+    _padding: [u32; 3],
+}
+
+// Frustum and distance culling done in a compute pass. Every culled draw gets a
+// slot in the indirect buffer, the shader writes its arguments with an instance
+// count of 0 when the model isn't visible.
+pub struct GpuCuller {
+    capacity: usize,
+    count: u32,
+    slots: HashMap<Uuid, u32>,
+    dirty: bool,
+    uniform_buffer: Buffer,
+    entries_buffer: Buffer,
+    indirect_buffer: Buffer,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: ComputePipeline,
+}
+
+impl GpuCuller {
+    pub fn new(device: &Device) -> Self {
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Cull Uniform Buffer"),
+            contents: cast_slice(&[CullUniform::zeroed()]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+            ],
+            label: Some("cull_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("cull_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("cull_shader"),
+            source: ShaderSource::Wgsl(include_str!("../shaders/cull.wgsl").into()),
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("cull_pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        let (entries_buffer, indirect_buffer) = Self::create_buffers(device, MIN_CAPACITY);
+        let bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            &uniform_buffer,
+            &entries_buffer,
+            &indirect_buffer,
+        );
+
+        Self {
+            capacity: MIN_CAPACITY,
+            count: 0,
+            slots: HashMap::new(),
+            dirty: true,
+            uniform_buffer,
+            entries_buffer,
+            indirect_buffer,
+            layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_buffers(device: &Device, capacity: usize) -> (Buffer, Buffer) {
+        let entries_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Cull Entries Buffer"),
+            size: (capacity * std::mem::size_of::<CullEntry>()) as BufferAddress,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let indirect_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Cull Indirect Buffer"),
+            size: capacity as BufferAddress * DRAW_ARGS_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+
+        (entries_buffer, indirect_buffer)
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        uniform_buffer: &Buffer,
+        entries_buffer: &Buffer,
+        indirect_buffer: &Buffer,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: entries_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: indirect_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("cull_bind_group"),
+        })
+    }
+
+    // Slots follow the model list, so any change to it has to rebuild the entries.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn upload(&mut self, device: &Device, queue: &Queue, entries: Vec<(Uuid, CullEntry)>) {
+        if entries.len() > self.capacity {
+            self.capacity = entries.len().next_power_of_two();
+            let (entries_buffer, indirect_buffer) = Self::create_buffers(device, self.capacity);
+            self.bind_group = Self::create_bind_group(
+                device,
+                &self.layout,
+                &self.uniform_buffer,
+                &entries_buffer,
+                &indirect_buffer,
+            );
+            self.entries_buffer = entries_buffer;
+            self.indirect_buffer = indirect_buffer;
+        }
+
+        let (ids, entries): (Vec<_>, Vec<_>) = entries.into_iter().unzip();
+        self.slots = ids
+            .into_iter()
+            .enumerate()
+            .map(|(slot, id)| (id, slot as u32))
+            .collect();
+        self.count = entries.len() as u32;
+        if !entries.is_empty() {
+            queue.write_buffer(&self.entries_buffer, 0, cast_slice(&entries));
+        }
+        self.dirty = false;
+    }
+
+    pub fn dispatch(
+        &self,
+        queue: &Queue,
+        encoder: &mut CommandEncoder,
+        frustum: &FrustumCuller,
+        camera_position: Vec3A,
+        max_distance: f32,
+    ) {
+        if self.count == 0 {
+            return;
+        }
+
+        let uniform = CullUniform {
+            planes: frustum.planes(),
+            camera: camera_position.extend(max_distance * max_distance).into(),
+            count: self.count,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, cast_slice(&[uniform]));
+
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Cull Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    // Byte offset of the draw arguments for the model, if it has a slot.
+    pub fn draw_offset(&self, id: &Uuid) -> Option<BufferAddress> {
+        self.slots
+            .get(id)
+            .map(|&slot| slot as BufferAddress * DRAW_ARGS_SIZE)
+    }
+
+    pub fn indirect_buffer(&self) -> &Buffer {
+        &self.indirect_buffer
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::slice::{Iter, IterMut};
+use uuid::Uuid;
+
+// Index of a slot and the generation it had when the handle was given out. Once
+// what it points to is removed the slot's generation moves on and the handle
+// stops resolving, even after the slot gets reused.
+pub struct Handle<T: ?Sized> {
+    index: u32,
+    generation: u32,
+    marker: PhantomData<fn() -> Box<T>>,
+}
+
+impl<T: ?Sized> Handle<T> {
+    fn new(index: u32, generation: u32) -> Self {
+        Self {
+            index,
+            generation,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Handle<T> {}
+
+impl<T: ?Sized> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T: ?Sized> Eq for Handle<T> {}
+
+impl<T: ?Sized> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+// What commands accept to point at something, the handle skips the id lookup.
+pub enum HandleRef<T: ?Sized> {
+    Id(Uuid),
+    Handle(Handle<T>),
+}
+
+impl<T: ?Sized> Clone for HandleRef<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for HandleRef<T> {}
+
+impl<T: ?Sized> fmt::Debug for HandleRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleRef::Id(id) => write!(f, "Id({id})"),
+            HandleRef::Handle(handle) => handle.fmt(f),
+        }
+    }
+}
+
+impl<T: ?Sized> From<Uuid> for HandleRef<T> {
+    fn from(id: Uuid) -> Self {
+        HandleRef::Id(id)
+    }
+}
+
+impl<T: ?Sized> From<Handle<T>> for HandleRef<T> {
+    fn from(handle: Handle<T>) -> Self {
+        HandleRef::Handle(handle)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Slot {
+    generation: u32,
+    // Where the value is in the packed Vec, None while the slot is free or detached.
+    index: Option<usize>,
+}
+
+// Values packed in a Vec for iterating, reachable through their id or the
+// handle they were inserted with. Removing swaps the last value into the hole,
+// so order isn't kept.
+pub struct HandleMap<T> {
+    values: Vec<T>,
+    // Id and slot of every value, in the same order.
+    entries: Vec<(Uuid, u32)>,
+    ids: HashMap<Uuid, usize>,
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+impl<T> HandleMap<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.ids.contains_key(id)
+    }
+
+    // Replaces the value with the same id if there's one, keeping its handle.
+    pub fn insert(&mut self, id: Uuid, value: T) -> Handle<T> {
+        if let Some(&idx) = self.ids.get(&id) {
+            self.values[idx] = value;
+            return self.handle_at(idx);
+        }
+
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    index: None,
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+        self.place(id, slot, value);
+        self.handle_at(self.values.len() - 1)
+    }
+
+    fn place(&mut self, id: Uuid, slot: u32, value: T) {
+        let idx = self.values.len();
+        self.slots[slot as usize].index = Some(idx);
+        self.ids.insert(id, idx);
+        self.entries.push((id, slot));
+        self.values.push(value);
+    }
+
+    fn handle_at(&self, idx: usize) -> Handle<T> {
+        let slot = self.entries[idx].1;
+        Handle::new(slot, self.slots[slot as usize].generation)
+    }
+
+    fn index_of(&self, handle: Handle<T>) -> Option<usize> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.index)
+    }
+
+    fn index_of_ref(&self, target: HandleRef<T>) -> Option<usize> {
+        match target {
+            HandleRef::Id(id) => self.ids.get(&id).copied(),
+            HandleRef::Handle(handle) => self.index_of(handle),
+        }
+    }
+
+    pub fn handle(&self, id: &Uuid) -> Option<Handle<T>> {
+        self.ids.get(id).map(|&idx| self.handle_at(idx))
+    }
+
+    pub fn resolve(&self, target: HandleRef<T>) -> Option<Handle<T>> {
+        self.index_of_ref(target).map(|idx| self.handle_at(idx))
+    }
+
+    pub fn id(&self, target: HandleRef<T>) -> Option<Uuid> {
+        self.index_of_ref(target).map(|idx| self.entries[idx].0)
+    }
+
+    pub fn get(&self, target: HandleRef<T>) -> Option<&T> {
+        self.index_of_ref(target).map(|idx| &self.values[idx])
+    }
+
+    pub fn get_mut(&mut self, target: HandleRef<T>) -> Option<&mut T> {
+        self.index_of_ref(target).map(|idx| &mut self.values[idx])
+    }
+
+    // Takes the value out, the slot stays taken for `attach` to put it back
+    // under the same handle.
+    pub fn detach(&mut self, target: HandleRef<T>) -> Option<(Handle<T>, Uuid, T)> {
+        let idx = self.index_of_ref(target)?;
+        let handle = self.handle_at(idx);
+        let (id, value) = self.take(idx);
+        Some((handle, id, value))
+    }
+
+    // Back into the slot it was detached from, or a new one if that handle is gone.
+    pub fn attach(&mut self, handle: Handle<T>, id: Uuid, value: T) -> Handle<T> {
+        let detached = self
+            .slots
+            .get(handle.index as usize)
+            .is_some_and(|slot| slot.generation == handle.generation && slot.index.is_none());
+        if !detached || self.ids.contains_key(&id) {
+            return self.insert(id, value);
+        }
+
+        self.place(id, handle.index, value);
+        handle
+    }
+
+    pub fn remove(&mut self, target: HandleRef<T>) -> Option<T> {
+        let (handle, _, value) = self.detach(target)?;
+        self.release(handle);
+        Some(value)
+    }
+
+    // Frees the slot of a detached value for good.
+    pub fn release(&mut self, handle: Handle<T>) {
+        let Some(slot) = self.slots.get_mut(handle.index as usize) else {
+            return;
+        };
+        if slot.generation == handle.generation && slot.index.is_none() {
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(handle.index);
+        }
+    }
+
+    fn take(&mut self, idx: usize) -> (Uuid, T) {
+        let (id, slot) = self.entries.swap_remove(idx);
+        let value = self.values.swap_remove(idx);
+        self.ids.remove(&id);
+        self.slots[slot as usize].index = None;
+        if let Some(&(moved, moved_slot)) = self.entries.get(idx) {
+            self.ids.insert(moved, idx);
+            self.slots[moved_slot as usize].index = Some(idx);
+        }
+        (id, value)
+    }
+
+    // Everything detached at once, to be attached back one by one.
+    pub fn detach_all(&mut self) -> Vec<(Handle<T>, Uuid, T)> {
+        let handles = (0..self.values.len())
+            .map(|idx| self.handle_at(idx))
+            .collect::<Vec<_>>();
+        for slot in self.slots.iter_mut() {
+            slot.index = None;
+        }
+        self.ids.clear();
+        let entries = std::mem::take(&mut self.entries);
+        let values = std::mem::take(&mut self.values);
+
+        handles
+            .into_iter()
+            .zip(entries)
+            .zip(values)
+            .map(|((handle, (id, _)), value)| (handle, id, value))
+            .collect()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.values.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        self.values.iter_mut()
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.values
+    }
+}
+
+impl<T> Default for HandleMap<T> {
+    fn default() -> Self {
+        Self {
+            values: vec![],
+            entries: vec![],
+            ids: HashMap::new(),
+            slots: vec![],
+            free: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_handles_stop_resolving() {
+        let mut map = HandleMap::new();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let first = map.insert(a, "a");
+        let second = map.insert(b, "b");
+
+        assert_eq!(map.remove(first.into()), Some("a"));
+        assert_eq!(map.get(first.into()), None);
+        assert_eq!(map.get(a.into()), None);
+        // The last value got swapped into the hole and still resolves.
+        assert_eq!(map.get(second.into()), Some(&"b"));
+
+        // Same slot, next generation.
+        let c = Uuid::new_v4();
+        let third = map.insert(c, "c");
+        assert_eq!(third.index, first.index);
+        assert_ne!(third, first);
+        assert_eq!(map.get(first.into()), None);
+        assert_eq!(map.resolve(first.into()), None);
+        assert_eq!(map.get(third.into()), Some(&"c"));
+        assert_eq!(map.id(third.into()), Some(c));
+    }
+
+    #[test]
+    fn detached_values_keep_their_handle() {
+        let mut map = HandleMap::new();
+        let id = Uuid::new_v4();
+        let handle = map.insert(id, 1);
+        let (detached, detached_id, value) = map.detach(handle.into()).unwrap();
+        assert_eq!(map.get(handle.into()), None);
+
+        assert_eq!(map.attach(detached, detached_id, value + 1), handle);
+        assert_eq!(map.get(handle.into()), Some(&2));
+    }
+}
@@ -1,10 +1,18 @@
-use winit::event::KeyEvent;
+use std::collections::VecDeque;
+use web_time::Instant;
+use winit::event::{Ime, KeyEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::{
     dpi::PhysicalPosition,
-    event::{ElementState, MouseScrollDelta, WindowEvent},
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
     keyboard,
 };
 
+use crate::action_map::{ActionMap, Binding};
+
+// Input events kept for reproduction bundles.
+const RECORDED_EVENTS: usize = 4096;
+
 // TODO: Implement all the needed functions
 
 #[derive(PartialEq, Eq)]
@@ -22,13 +30,61 @@ impl Key {
     }
 }
 
+// What got typed while text input is captured, in order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TextInput {
+    Char(char),
+    // Every press, key repeats included.
+    Key(KeyCode),
+}
+
 pub struct InputState {
     keys: Vec<Key>,
     keys_released: Vec<keyboard::Key>,
+    // Physical keys with whether they were already down last frame.
+    physical_keys: Vec<(KeyCode, bool)>,
+    physical_released: Vec<KeyCode>,
+    mouse_buttons: Vec<(MouseButton, bool)>,
+    mouse_released: Vec<MouseButton>,
+    action_map: ActionMap,
     mouse_delta: (f32, f32),
     last_mouse_position: (f32, f32),
     mouse_sample: u32,
     mouse_scroll: f32,
+    // While set, the keyboard only types, keys and actions read as released.
+    text_capture: bool,
+    typed: Vec<TextInput>,
+    // Text the IME is still composing, with the selected byte range in it.
+    preedit: Option<(String, Option<(usize, usize)>)>,
+    started: Instant,
+    recording: VecDeque<String>,
+}
+
+// One line per event. Typed text is left out, only the keys pressed for it.
+fn describe(event: &WindowEvent) -> Option<String> {
+    match event {
+        WindowEvent::KeyboardInput {
+            event:
+                KeyEvent {
+                    physical_key,
+                    state,
+                    repeat,
+                    ..
+                },
+            ..
+        } => Some(format!(
+            "key {physical_key:?} {state:?}{}",
+            if *repeat { " repeat" } else { "" }
+        )),
+        WindowEvent::CursorMoved { position, .. } => {
+            Some(format!("cursor {:.0} {:.0}", position.x, position.y))
+        }
+        WindowEvent::MouseInput { state, button, .. } => {
+            Some(format!("mouse {button:?} {state:?}"))
+        }
+        WindowEvent::MouseWheel { delta, .. } => Some(format!("scroll {delta:?}")),
+        _ => None,
+    }
 }
 
 impl InputState {
@@ -36,13 +92,28 @@ impl InputState {
         Self {
             keys: vec![],
             keys_released: vec![],
+            physical_keys: vec![],
+            physical_released: vec![],
+            mouse_buttons: vec![],
+            mouse_released: vec![],
+            action_map: ActionMap::with_defaults(),
             mouse_delta: (0.0, 0.0),
             last_mouse_position: (0.0, 0.0),
             mouse_sample: 0,
             mouse_scroll: 0.0,
+            text_capture: false,
+            typed: vec![],
+            preedit: None,
+            started: Instant::now(),
+            recording: VecDeque::with_capacity(RECORDED_EVENTS),
         }
     }
 
+    // The latest input events, oldest first, with seconds since startup.
+    pub fn recording(&self) -> impl Iterator<Item = &String> {
+        self.recording.iter()
+    }
+
     pub fn update(&mut self) {
         for key in self.keys.iter_mut() {
             if !key.previous {
@@ -50,10 +121,57 @@ impl InputState {
             }
         }
 
+        for (_, previous) in self.physical_keys.iter_mut() {
+            *previous = true;
+        }
+        for (_, previous) in self.mouse_buttons.iter_mut() {
+            *previous = true;
+        }
+
         self.keys_released.clear();
+        self.physical_released.clear();
+        self.mouse_released.clear();
         self.mouse_delta = (0.0, 0.0);
         self.mouse_sample = 0;
         self.mouse_scroll = 0.0;
+        self.typed.clear();
+    }
+
+    pub fn text_capture(&self) -> bool {
+        self.text_capture
+    }
+
+    // Held keys get dropped when capturing starts, their releases would be
+    // typed instead of seen.
+    pub fn set_text_capture(&mut self, capture: bool) {
+        if capture && !self.text_capture {
+            self.keys.clear();
+            self.physical_keys.clear();
+        }
+        self.text_capture = capture;
+        self.typed.clear();
+        self.preedit = None;
+    }
+
+    pub fn typed(&self) -> &[TextInput] {
+        &self.typed
+    }
+
+    // The text committed this frame, typed or from the IME.
+    pub fn typed_text(&self) -> String {
+        self.typed
+            .iter()
+            .filter_map(|input| match input {
+                TextInput::Char(c) => Some(*c),
+                TextInput::Key(_) => None,
+            })
+            .collect()
+    }
+
+    pub fn preedit(&self) -> Option<(&str, Option<(usize, usize)>)> {
+        self.preedit
+            .as_ref()
+            .map(|(text, cursor)| (text.as_str(), *cursor))
     }
 
     pub fn contains(&self, key: &Key) -> bool {
@@ -77,13 +195,80 @@ impl InputState {
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
+        if let Some(description) = describe(event) {
+            if self.recording.len() == RECORDED_EVENTS {
+                self.recording.pop_front();
+            }
+            self.recording.push_back(format!(
+                "{:.3} {description}",
+                self.started.elapsed().as_secs_f32()
+            ));
+        }
+
         match event {
             WindowEvent::KeyboardInput {
-                event: KeyEvent {
-                    logical_key, state, ..
-                },
+                event:
+                    KeyEvent {
+                        physical_key,
+                        state: ElementState::Pressed,
+                        text,
+                        ..
+                    },
+                ..
+            } if self.text_capture => {
+                if let PhysicalKey::Code(code) = physical_key {
+                    self.typed.push(TextInput::Key(*code));
+                }
+                if let Some(text) = text {
+                    self.typed.extend(
+                        text.chars()
+                            .filter(|c| !c.is_control())
+                            .map(TextInput::Char),
+                    );
+                }
+
+                true
+            }
+
+            WindowEvent::KeyboardInput { .. } if self.text_capture => true,
+
+            WindowEvent::Ime(ime) if self.text_capture => {
+                match ime {
+                    Ime::Preedit(text, cursor) if !text.is_empty() => {
+                        self.preedit = Some((text.clone(), *cursor));
+                    }
+                    Ime::Commit(text) => {
+                        self.preedit = None;
+                        self.typed.extend(text.chars().map(TextInput::Char));
+                    }
+                    _ => self.preedit = None,
+                }
+
+                true
+            }
+
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key,
+                        physical_key,
+                        state,
+                        ..
+                    },
                 ..
             } => {
+                if let PhysicalKey::Code(code) = physical_key {
+                    let idx = self.physical_keys.iter().position(|(k, _)| k == code);
+                    match (state, idx) {
+                        (ElementState::Pressed, None) => self.physical_keys.push((*code, false)),
+                        (ElementState::Released, Some(idx)) => {
+                            self.physical_keys.remove(idx);
+                            self.physical_released.push(*code);
+                        }
+                        _ => {}
+                    }
+                }
+
                 let key = Key::new(logical_key.clone());
                 if let ElementState::Pressed = state {
                     if !self.contains(&key) {
@@ -109,6 +294,20 @@ impl InputState {
                 true
             }
 
+            WindowEvent::MouseInput { state, button, .. } => {
+                let idx = self.mouse_buttons.iter().position(|(b, _)| b == button);
+                match (state, idx) {
+                    (ElementState::Pressed, None) => self.mouse_buttons.push((*button, false)),
+                    (ElementState::Released, Some(idx)) => {
+                        self.mouse_buttons.remove(idx);
+                        self.mouse_released.push(*button);
+                    }
+                    _ => {}
+                }
+
+                true
+            }
+
             WindowEvent::MouseWheel { delta, .. } => {
                 self.mouse_scroll = match delta {
                     MouseScrollDelta::LineDelta(_, scroll) => *scroll * 100.0,
@@ -138,6 +337,25 @@ impl InputState {
         self.mouse_scroll
     }
 
+    // Last known cursor position in physical pixels from the top left of the window.
+    pub fn cursor_position(&self) -> (f32, f32) {
+        self.last_mouse_position
+    }
+
+    pub fn is_mouse_button_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons.iter().any(|(b, _)| *b == button)
+    }
+
+    pub fn is_mouse_button_just_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_buttons
+            .iter()
+            .any(|(b, previous)| *b == button && !previous)
+    }
+
+    pub fn is_mouse_button_just_released(&self, button: MouseButton) -> bool {
+        self.mouse_released.contains(&button)
+    }
+
     pub fn is_key_pressed(&self, key: &keyboard::Key) -> bool {
         for k in &self.keys {
             if &k.keycode == key {
@@ -161,4 +379,85 @@ impl InputState {
     pub fn is_key_just_released(&self, key: &keyboard::Key) -> bool {
         self.keys_released.contains(key)
     }
+
+    pub fn is_physical_key_pressed(&self, key: KeyCode) -> bool {
+        self.physical_keys.iter().any(|(k, _)| *k == key)
+    }
+
+    pub fn is_physical_key_just_pressed(&self, key: KeyCode) -> bool {
+        self.physical_keys
+            .iter()
+            .any(|(k, previous)| *k == key && !previous)
+    }
+
+    pub fn is_physical_key_just_released(&self, key: KeyCode) -> bool {
+        self.physical_released.contains(&key)
+    }
+
+    pub fn action_map(&self) -> &ActionMap {
+        &self.action_map
+    }
+
+    pub fn action_map_mut(&mut self) -> &mut ActionMap {
+        &mut self.action_map
+    }
+
+    fn is_binding_pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.is_physical_key_pressed(key),
+            Binding::Mouse(button) => self.is_mouse_button_pressed(button),
+        }
+    }
+
+    fn is_binding_just_pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.is_physical_key_just_pressed(key),
+            Binding::Mouse(button) => self.is_mouse_button_just_pressed(button),
+        }
+    }
+
+    fn is_binding_just_released(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.is_physical_key_just_released(key),
+            Binding::Mouse(button) => self.is_mouse_button_just_released(button),
+        }
+    }
+
+    // Actions are all released while a text field has the keyboard, mouse bound
+    // ones included.
+    pub fn is_action_pressed(&self, action: &str) -> bool {
+        !self.text_capture
+            && self
+                .action_map
+                .bindings(action)
+                .iter()
+                .any(|binding| self.is_binding_pressed(*binding))
+    }
+
+    // Only once the first of its bindings goes down, holding another one doesn't retrigger it.
+    pub fn is_action_just_pressed(&self, action: &str) -> bool {
+        if self.text_capture {
+            return false;
+        }
+        let bindings = self.action_map.bindings(action);
+        bindings
+            .iter()
+            .any(|binding| self.is_binding_just_pressed(*binding))
+            && !bindings.iter().any(|binding| {
+                self.is_binding_pressed(*binding) && !self.is_binding_just_pressed(*binding)
+            })
+    }
+
+    pub fn is_action_just_released(&self, action: &str) -> bool {
+        if self.text_capture {
+            return false;
+        }
+        let bindings = self.action_map.bindings(action);
+        bindings
+            .iter()
+            .any(|binding| self.is_binding_just_released(*binding))
+            && !bindings
+                .iter()
+                .any(|binding| self.is_binding_pressed(*binding))
+    }
 }
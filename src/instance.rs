@@ -14,9 +14,9 @@ impl Instance {
         Self { position }
     }
 
-    pub fn to_raw(&self) -> InstanceRaw {
-        let model = Vec4::new(self.position.x, self.position.y, self.position.z, 1.0);
-        InstanceRaw::new(model)
+    // Fluids use w for the height of their surface inside the block.
+    pub fn to_fluid_raw(&self, height: f32) -> InstanceRaw {
+        InstanceRaw::new(self.position.extend(height))
     }
 }
 
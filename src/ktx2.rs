@@ -0,0 +1,246 @@
+use anyhow::{anyhow, Result};
+use wgpu::{AstcBlock, AstcChannel, Features, TextureFormat};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_SIZE: usize = 24;
+
+// Supercompression schemes, only uncompressed payloads can be uploaded as they are.
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow!("KTX2 file ends inside its header"))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<usize> {
+    data.get(offset..offset + 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        .ok_or_else(|| anyhow!("KTX2 file ends inside its header"))
+}
+
+// The Vulkan formats with a wgpu equivalent that make sense for model textures.
+fn texture_format(vk_format: u32) -> Option<TextureFormat> {
+    let astc = |channel| TextureFormat::Astc {
+        block: AstcBlock::B4x4,
+        channel,
+    };
+
+    Some(match vk_format {
+        37 => TextureFormat::Rgba8Unorm,
+        43 => TextureFormat::Rgba8UnormSrgb,
+        131 | 133 => TextureFormat::Bc1RgbaUnorm,
+        132 | 134 => TextureFormat::Bc1RgbaUnormSrgb,
+        135 => TextureFormat::Bc2RgbaUnorm,
+        136 => TextureFormat::Bc2RgbaUnormSrgb,
+        137 => TextureFormat::Bc3RgbaUnorm,
+        138 => TextureFormat::Bc3RgbaUnormSrgb,
+        145 => TextureFormat::Bc7RgbaUnorm,
+        146 => TextureFormat::Bc7RgbaUnormSrgb,
+        147 => TextureFormat::Etc2Rgb8Unorm,
+        148 => TextureFormat::Etc2Rgb8UnormSrgb,
+        149 => TextureFormat::Etc2Rgb8A1Unorm,
+        150 => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        151 => TextureFormat::Etc2Rgba8Unorm,
+        152 => TextureFormat::Etc2Rgba8UnormSrgb,
+        157 => astc(AstcChannel::Unorm),
+        158 => astc(AstcChannel::UnormSrgb),
+        _ => return None,
+    })
+}
+
+fn rgb565(color: u16) -> [u32; 3] {
+    let r = (color >> 11) as u32 & 0x1F;
+    let g = (color >> 5) as u32 & 0x3F;
+    let b = color as u32 & 0x1F;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+// The 16 texels of a BC1 color block, the 3 color mode only when BC1 allows it.
+fn decode_color_block(block: &[u8], allow_alpha: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+    let mix = |wa: u32, wb: u32| -> [u8; 4] {
+        let total = wa + wb;
+        [
+            ((a[0] * wa + b[0] * wb) / total) as u8,
+            ((a[1] * wa + b[1] * wb) / total) as u8,
+            ((a[2] * wa + b[2] * wb) / total) as u8,
+            255,
+        ]
+    };
+    let palette = if c0 > c1 || !allow_alpha {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0, 0, 0, 0]]
+    };
+
+    let indices = u32::from_le_bytes(block[4..8].try_into().unwrap());
+    std::array::from_fn(|i| palette[(indices >> (i * 2)) as usize & 0b11])
+}
+
+// BC3 alpha, 2 endpoints and 3 bit indices into the ramp between them.
+fn decode_alpha_block(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let palette: [u8; 8] = std::array::from_fn(|i| match i {
+        0 => a0 as u8,
+        1 => a1 as u8,
+        _ if a0 > a1 => ((a0 * (8 - i as u32) + a1 * (i as u32 - 1)) / 7) as u8,
+        6 => 0,
+        7 => 255,
+        _ => ((a0 * (6 - i as u32) + a1 * (i as u32 - 1)) / 5) as u8,
+    });
+
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    std::array::from_fn(|i| palette[(indices >> (i * 3)) as usize & 0b111])
+}
+
+pub struct Ktx2Level {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+// A 2D texture out of a KTX2 container, with its mips from the largest down.
+pub struct Ktx2 {
+    pub format: TextureFormat,
+    pub levels: Vec<Ktx2Level>,
+}
+
+impl Ktx2 {
+    pub fn is_ktx2(data: &[u8]) -> bool {
+        data.starts_with(&IDENTIFIER)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if !Self::is_ktx2(data) {
+            return Err(anyhow!("not a KTX2 file"));
+        }
+        let vk_format = read_u32(data, 12)?;
+        let width = read_u32(data, 20)?;
+        let height = read_u32(data, 24)?;
+        let depth = read_u32(data, 28)?;
+        let layers = read_u32(data, 32)?;
+        let faces = read_u32(data, 36)?;
+        let level_count = read_u32(data, 40)?.max(1) as usize;
+        let supercompression = read_u32(data, 44)?;
+
+        // Basis Universal files are either BasisLZ supercompressed or UASTC with an
+        // undefined format, and need transcoding first.
+        if supercompression == SUPERCOMPRESSION_BASIS_LZ || vk_format == 0 {
+            return Err(anyhow!(
+                "Basis Universal KTX2 textures aren't supported, encode them to BCn, ETC2 or ASTC"
+            ));
+        }
+        if supercompression != SUPERCOMPRESSION_NONE {
+            return Err(anyhow!(
+                "KTX2 supercompression scheme {supercompression} isn't supported"
+            ));
+        }
+        if width == 0 || height == 0 || depth > 0 || layers > 0 || faces != 1 {
+            return Err(anyhow!("only 2D KTX2 textures are supported"));
+        }
+        let format = texture_format(vk_format)
+            .ok_or_else(|| anyhow!("KTX2 format {vk_format} isn't supported"))?;
+
+        let levels = (0..level_count)
+            .map(|level| {
+                let index = HEADER_SIZE + level * LEVEL_SIZE;
+                let offset = read_u64(data, index)?;
+                let length = read_u64(data, index + 8)?;
+                let data = data
+                    .get(offset..offset + length)
+                    .ok_or_else(|| anyhow!("KTX2 level {level} is out of the file"))?;
+                Ok(Ktx2Level {
+                    width: (width >> level).max(1),
+                    height: (height >> level).max(1),
+                    data: data.to_vec(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { format, levels })
+    }
+
+    pub fn required_features(&self) -> Features {
+        self.format.required_features()
+    }
+
+    // Whether the levels can be decoded to RGBA8 when the adapter can't sample the
+    // format, only the cheap ones are.
+    pub fn can_decode(&self) -> bool {
+        matches!(
+            self.format.remove_srgb_suffix(),
+            TextureFormat::Rgba8Unorm
+                | TextureFormat::Bc1RgbaUnorm
+                | TextureFormat::Bc2RgbaUnorm
+                | TextureFormat::Bc3RgbaUnorm
+        )
+    }
+
+    pub fn decode_rgba(&self, level: usize) -> Result<Vec<u8>> {
+        let level = &self.levels[level];
+        let format = self.format.remove_srgb_suffix();
+        if format == TextureFormat::Rgba8Unorm {
+            return Ok(level.data.clone());
+        }
+        if !self.can_decode() {
+            return Err(anyhow!("{:?} textures can't be decoded", self.format));
+        }
+
+        let block_size = self.format.block_copy_size(None).unwrap() as usize;
+        let blocks_wide = level.width.div_ceil(4) as usize;
+        let blocks_high = level.height.div_ceil(4) as usize;
+        if level.data.len() < blocks_wide * blocks_high * block_size {
+            return Err(anyhow!("KTX2 level is shorter than its size"));
+        }
+
+        let width = level.width as usize;
+        let mut rgba = vec![0; width * level.height as usize * 4];
+        for (i, block) in level.data.chunks_exact(block_size).enumerate() {
+            let (bx, by) = (i % blocks_wide, i / blocks_wide);
+            if by >= blocks_high {
+                break;
+            }
+            let texels = match format {
+                TextureFormat::Bc1RgbaUnorm => decode_color_block(block, true),
+                TextureFormat::Bc2RgbaUnorm => {
+                    let mut texels = decode_color_block(&block[8..], false);
+                    for (j, texel) in texels.iter_mut().enumerate() {
+                        let alpha = (block[j / 2] >> ((j % 2) * 4)) & 0xF;
+                        texel[3] = alpha << 4 | alpha;
+                    }
+                    texels
+                }
+                _ => {
+                    let mut texels = decode_color_block(&block[8..], false);
+                    for (texel, alpha) in texels.iter_mut().zip(decode_alpha_block(block)) {
+                        texel[3] = alpha;
+                    }
+                    texels
+                }
+            };
+
+            for (j, texel) in texels.iter().enumerate() {
+                let (x, y) = (bx * 4 + j % 4, by * 4 + j / 4);
+                if x < width && y < level.height as usize {
+                    let idx = (y * width + x) * 4;
+                    rgba[idx..idx + 4].copy_from_slice(texel);
+                }
+            }
+        }
+
+        Ok(rgba)
+    }
+}
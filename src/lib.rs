@@ -1,50 +1,141 @@
 #![allow(non_snake_case)]
 
-use crate::app::App;
-use app::NModel;
-use camera::CameraController;
-use chunks::Chunk;
-use glam::{UVec3, Vec3A};
-use std::sync::Arc;
-use std::time::Instant;
-use uuid::Uuid;
+use command_buffer::RenderLayer;
 use wgpu::{
     BlendComponent, BlendState, ColorTargetState, ColorWrites, CompareFunction, DepthBiasState,
     DepthStencilState, Device, Face, FragmentState, FrontFace, MultisampleState, PipelineLayout,
     PolygonMode, PrimitiveState, PrimitiveTopology, RenderPipeline, RenderPipelineDescriptor,
     ShaderModuleDescriptor, StencilState, TextureFormat, VertexBufferLayout, VertexState,
 };
-use winit::keyboard::NamedKey;
-use winit::{
-    event::*,
-    event_loop::{ControlFlow, EventLoop},
-    keyboard,
-    window::WindowBuilder,
-};
 
-mod app;
+pub mod action_map;
+pub mod app;
+pub mod asset_cache;
 mod assets;
-mod camera;
-mod chunks;
+mod batching;
+pub mod bind_group_cache;
+pub mod biome;
+pub mod block_outline;
+pub mod block_ticks;
+mod buffer_pool;
+pub mod camera;
+pub mod capabilities;
+pub mod chunks;
 mod command_buffer;
-mod frustum;
+pub mod console;
+pub mod debug;
+pub mod debug_draw;
+pub mod dimension;
+#[cfg(feature = "egui")]
+pub mod egui_layer;
+pub mod engine;
+pub mod environment;
+pub mod error;
+mod fluid;
+pub mod foliage;
+pub mod fonts;
+pub mod frame_graph;
+pub mod frustum;
+mod gpu_culling;
+pub mod handle;
 mod input;
 mod instance;
+mod ktx2;
 mod light;
+pub mod lighting;
+pub mod logging;
+mod mesher;
 mod model;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod net;
+mod palette;
+pub mod particles;
+pub mod physics;
+pub mod pipeline_cache;
+pub mod post_process;
+pub mod profiler;
+pub mod registry;
+pub mod replication;
+pub mod repro;
 mod resource;
+pub mod save;
+pub mod schematic;
+pub mod seed;
+pub mod settings;
+pub mod sky;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod soak;
+pub mod structures;
+pub mod text;
 mod texture;
-mod ui;
+pub mod texture_streaming;
+mod time;
+pub mod transform;
+pub mod ui;
+pub mod vfs;
+pub mod viewport;
+pub mod weather;
+pub mod workers;
+pub mod world;
+pub mod world_edit;
+pub mod world_view;
+pub mod worldgen;
+
+// With reverse-Z the near plane is at depth 1.0 and the far one at 0.0, which
+// spreads the float precision evenly over the distance.
+pub fn depth_compare(reverse_z: bool) -> CompareFunction {
+    if reverse_z {
+        CompareFunction::Greater
+    } else {
+        CompareFunction::Less
+    }
+}
 
-pub fn create_render_pipeline(
-    device: &Device,
-    layout: &PipelineLayout,
-    color_format: TextureFormat,
-    depth_format: Option<TextureFormat>,
-    vertex_layouts: &[VertexBufferLayout],
-    shader: ShaderModuleDescriptor,
-) -> RenderPipeline {
+pub fn depth_clear_value(reverse_z: bool) -> f32 {
+    if reverse_z {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+// What create_render_pipeline needs to know about a pipeline, the rest is the
+// same for every one drawing into the scene or on top of it.
+pub struct PipelineDesc<'a> {
+    pub layout: &'a PipelineLayout,
+    pub color_format: TextureFormat,
+    pub depth_format: Option<TextureFormat>,
+    pub vertex_layouts: &'a [VertexBufferLayout<'a>],
+    pub shader: ShaderModuleDescriptor<'a>,
+    pub layer: RenderLayer,
+    pub polygon_mode: PolygonMode,
+    pub sample_count: u32,
+    pub reverse_z: bool,
+}
+
+pub fn create_render_pipeline(device: &Device, desc: PipelineDesc) -> RenderPipeline {
+    let PipelineDesc {
+        layout,
+        color_format,
+        depth_format,
+        vertex_layouts,
+        shader,
+        layer,
+        polygon_mode,
+        sample_count,
+        reverse_z,
+    } = desc;
     let shader = device.create_shader_module(shader);
+    let (blend, depth_write_enabled) = match layer {
+        RenderLayer::Opaque => (
+            BlendState {
+                alpha: BlendComponent::REPLACE,
+                color: BlendComponent::REPLACE,
+            },
+            true,
+        ),
+        RenderLayer::Transparent => (BlendState::ALPHA_BLENDING, false),
+    };
 
     device.create_render_pipeline(&RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
@@ -59,10 +150,7 @@ pub fn create_render_pipeline(
             entry_point: "fs_main",
             targets: &[Some(ColorTargetState {
                 format: color_format,
-                blend: Some(BlendState {
-                    alpha: BlendComponent::REPLACE,
-                    color: BlendComponent::REPLACE,
-                }),
+                blend: Some(blend),
                 write_mask: ColorWrites::ALL,
             })],
         }),
@@ -71,101 +159,22 @@ pub fn create_render_pipeline(
             strip_index_format: None,
             front_face: FrontFace::Ccw,
             cull_mode: Some(Face::Back),
-            polygon_mode: PolygonMode::Fill,
+            polygon_mode,
             unclipped_depth: false,
             conservative: false,
         },
         depth_stencil: depth_format.map(|format| DepthStencilState {
             format,
-            depth_write_enabled: true,
-            depth_compare: CompareFunction::Less,
+            depth_write_enabled,
+            depth_compare: depth_compare(reverse_z),
             stencil: StencilState::default(),
             bias: DepthBiasState::default(),
         }),
         multisample: MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
         multiview: None,
     })
 }
-
-pub async fn run() {
-    env_logger::init();
-
-    let event_loop = EventLoop::new().unwrap();
-    let window = Arc::new(
-        WindowBuilder::new()
-            .with_title("VoxelTest")
-            .build(&event_loop)
-            .unwrap(),
-    );
-    let mut app = App::new(window).await;
-    let camera_controller = Box::new(CameraController::new(4.0, 1.0, app.camera()));
-    app.add_actor(camera_controller);
-    app.register_model("cube.obj");
-    let radius = 32;
-    let half_radius = radius / 2;
-    for chunk_x in -half_radius..=half_radius {
-        for chunk_z in -half_radius..=half_radius {
-            let mut chunk = Chunk::new(
-                Uuid::new_v4(),
-                Vec3A::new(chunk_x as f32, 0., chunk_z as f32),
-            );
-            for x in 0..16 {
-                for z in 0..16 {
-                    chunk.add_block_data(UVec3::new(x, 0, z), 0);
-                }
-            }
-
-            app.add_model(NModel::new(Box::new(chunk)));
-        }
-    }
-    let mut last_render_time = Instant::now();
-
-    event_loop
-        .run(move |event, event_loop| {
-            event_loop.set_control_flow(ControlFlow::Poll);
-            match event {
-                Event::WindowEvent {
-                    ref event,
-                    window_id,
-                } if window_id == app.window().id() && !app.input(event) => match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                state: ElementState::Pressed,
-                                logical_key: keyboard::Key::Named(NamedKey::Escape),
-                                ..
-                            },
-                        ..
-                    } => event_loop.exit(),
-                    WindowEvent::Resized(size) => {
-                        app.resize(size);
-                    }
-                    WindowEvent::RedrawRequested => {
-                        let now = Instant::now();
-                        let dt = now - last_render_time;
-                        last_render_time = now;
-                        app.update(dt);
-                        match app.render() {
-                            Ok(_) => {}
-                            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                                app.resize(&app.size())
-                            }
-                            Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
-                            Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
-                        }
-                    }
-                    _ => {}
-                },
-                Event::AboutToWait => {
-                    app.window().request_redraw();
-                }
-                _ => {}
-            }
-        })
-        .unwrap();
-}
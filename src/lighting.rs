@@ -0,0 +1,434 @@
+use glam::IVec3;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::app::Model;
+use crate::chunks::{Chunk, MAX_LIGHT};
+use crate::mesher::CHUNK_SIZE;
+use crate::registry::block_info;
+use crate::structures::BlockAccess;
+use crate::world_edit::split_position;
+
+const NEIGHBOURS: [IVec3; 6] = [
+    IVec3::X,
+    IVec3::NEG_X,
+    IVec3::Y,
+    IVec3::NEG_Y,
+    IVec3::Z,
+    IVec3::NEG_Z,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LightChannel {
+    // From blocks that give off light, like lamps.
+    Block,
+    // From above, falling straight down through anything that isn't solid.
+    Sky,
+}
+
+const CHANNELS: [LightChannel; 2] = [LightChannel::Block, LightChannel::Sky];
+
+// Light of the loaded chunks, wherever they're kept. Positions are in world space.
+pub trait LightMap: BlockAccess {
+    // Light doesn't go into chunks that aren't loaded.
+    fn is_loaded(&self, position: IVec3) -> bool;
+
+    // World height of the cell above the highest block in the position's column
+    // of its chunk, sky light falls through everything above it untouched.
+    fn height(&self, position: IVec3) -> i32;
+
+    fn light(&self, position: IVec3, channel: LightChannel) -> u8;
+
+    // Has to go to the borders of the chunks next to the position too, see
+    // `for_each_copy`.
+    fn set_light(&mut self, position: IVec3, channel: LightChannel, level: u8);
+}
+
+// The chunk a position is in, and the chunks it's on the border of, with the
+// position local to each. Chunks keep the light just outside of them for
+// meshing, so every change has to reach them as well.
+pub fn for_each_copy<F: FnMut(IVec3, IVec3)>(position: IVec3, mut copy: F) {
+    let (chunk_position, local) = split_position(position);
+    let local = local.as_ivec3();
+    copy(chunk_position, local);
+    for axis in 0..3 {
+        let mut offset = IVec3::ZERO;
+        match local[axis] {
+            0 => offset[axis] = -1,
+            l if l == CHUNK_SIZE - 1 => offset[axis] = 1,
+            _ => continue,
+        }
+        copy(chunk_position + offset, local - offset * CHUNK_SIZE);
+    }
+}
+
+// Light goes through air and fluids, solid blocks stop it.
+fn is_transparent<M: LightMap>(map: &M, position: IVec3) -> bool {
+    map.block(position)
+        .is_none_or(|id| !block_info(id).is_solid())
+}
+
+fn emission<M: LightMap>(map: &M, position: IVec3, channel: LightChannel) -> u8 {
+    match channel {
+        LightChannel::Block => map.block(position).map_or(0, |id| block_info(id).light),
+        LightChannel::Sky => 0,
+    }
+}
+
+// Light a cell gets from its neighbour one step in `offset` away from it. Full
+// sky light keeps going down, only dimmed by what it goes through.
+fn next_level<M: LightMap>(
+    map: &M,
+    channel: LightChannel,
+    level: u8,
+    offset: IVec3,
+    next: IVec3,
+) -> u8 {
+    let filter = map.block(next).map_or(0, |id| block_info(id).filter);
+    if channel == LightChannel::Sky && offset == IVec3::NEG_Y && level == MAX_LIGHT {
+        level.saturating_sub(filter)
+    } else {
+        level.saturating_sub(1 + filter)
+    }
+}
+
+// Sets the light and remembers which chunk meshes it shows up in.
+fn set<M: LightMap>(
+    map: &mut M,
+    changed: &mut HashSet<IVec3>,
+    position: IVec3,
+    channel: LightChannel,
+    level: u8,
+) {
+    map.set_light(position, channel, level);
+    for_each_copy(position, |chunk_position, _| {
+        changed.insert(chunk_position);
+    });
+}
+
+// Breadth first from every queued position, each step one level darker.
+fn spread<M: LightMap>(
+    map: &mut M,
+    changed: &mut HashSet<IVec3>,
+    channel: LightChannel,
+    mut queue: VecDeque<IVec3>,
+) {
+    while let Some(position) = queue.pop_front() {
+        let level = map.light(position, channel);
+        if level <= 1 {
+            continue;
+        }
+
+        for offset in NEIGHBOURS {
+            let next = position + offset;
+            if !map.is_loaded(next) || !is_transparent(map, next) {
+                continue;
+            }
+            let next_level = next_level(map, channel, level, offset, next);
+            if map.light(next, channel) < next_level {
+                set(map, changed, next, channel, next_level);
+                queue.push_back(next);
+            }
+        }
+    }
+}
+
+// Darkens everything the removed light could have reached. Returns the lit
+// positions around the darkened area, which have to spread into it again.
+fn remove<M: LightMap>(
+    map: &mut M,
+    changed: &mut HashSet<IVec3>,
+    channel: LightChannel,
+    mut queue: VecDeque<(IVec3, u8)>,
+) -> VecDeque<IVec3> {
+    let mut relight = VecDeque::new();
+    while let Some((position, level)) = queue.pop_front() {
+        for offset in NEIGHBOURS {
+            let next = position + offset;
+            if !map.is_loaded(next) {
+                continue;
+            }
+
+            let light = map.light(next, channel);
+            // Full sky light below full sky light came straight down from it.
+            let from_above = channel == LightChannel::Sky
+                && offset == IVec3::NEG_Y
+                && level == MAX_LIGHT
+                && light == MAX_LIGHT;
+            if light != 0 && (light < level || from_above) {
+                set(map, changed, next, channel, 0);
+                queue.push_back((next, light));
+                // Sources in the dark area light up again right away.
+                let emission = emission(map, next, channel);
+                if emission > 0 {
+                    set(map, changed, next, channel, emission);
+                    relight.push_back(next);
+                }
+            } else if light >= level {
+                relight.push_back(next);
+            }
+        }
+    }
+
+    relight
+}
+
+// Relights around a block that changed, whether it got placed, broken or
+// replaced. Returns the chunks whose meshes show the change.
+pub fn update_block<M: LightMap>(map: &mut M, position: IVec3) -> HashSet<IVec3> {
+    let mut changed = HashSet::new();
+    for channel in CHANNELS {
+        let old = map.light(position, channel);
+        set(map, &mut changed, position, channel, 0);
+        let mut queue = remove(
+            map,
+            &mut changed,
+            channel,
+            VecDeque::from([(position, old)]),
+        );
+
+        let emission = emission(map, position, channel);
+        if emission > 0 {
+            set(map, &mut changed, position, channel, emission);
+            queue.push_back(position);
+        }
+        if is_transparent(map, position) {
+            queue.extend(NEIGHBOURS.map(|offset| position + offset));
+        }
+        spread(map, &mut changed, channel, queue);
+    }
+
+    changed
+}
+
+// Sky light falling down each column of a chunk, from the chunk above or from
+// the open sky when that one isn't loaded. Returns the lit cells that could
+// spread sideways into shade.
+fn light_columns<M: LightMap>(
+    map: &mut M,
+    changed: &mut HashSet<IVec3>,
+    origin: IVec3,
+) -> VecDeque<IVec3> {
+    let channel = LightChannel::Sky;
+    let columns = (0..CHUNK_SIZE)
+        .flat_map(|x| (0..CHUNK_SIZE).map(move |z| origin + IVec3::new(x, 0, z)))
+        .collect::<Vec<_>>();
+    let heights = columns
+        .iter()
+        .map(|&column| map.height(column))
+        .collect::<Vec<_>>();
+    let highest = heights.iter().copied().max().unwrap_or(origin.y);
+
+    let mut queue = VecDeque::new();
+    for (&column, &height) in columns.iter().zip(&heights) {
+        let above = column + IVec3::Y * CHUNK_SIZE;
+        if map.is_loaded(above) && map.light(above, channel) < MAX_LIGHT {
+            // Shade from above comes in with the border light instead.
+            continue;
+        }
+
+        let mut level = MAX_LIGHT;
+        for y in (0..CHUNK_SIZE).rev() {
+            let position = column + IVec3::Y * y;
+            if position.y < height {
+                if !is_transparent(map, position) {
+                    break;
+                }
+                level = next_level(map, channel, level, IVec3::NEG_Y, position);
+            }
+            if level == 0 {
+                break;
+            }
+
+            set(map, changed, position, channel, level);
+            if position.y <= highest {
+                queue.push_back(position);
+            }
+        }
+    }
+
+    queue
+}
+
+// Lights a chunk that just got loaded, from its own sources and the light of
+// the chunks around it, and swaps border light with them. Returns the chunks
+// whose meshes show the change.
+pub fn light_chunk<M: LightMap>(map: &mut M, chunk_position: IVec3) -> HashSet<IVec3> {
+    let mut changed = HashSet::new();
+    let origin = chunk_position * CHUNK_SIZE;
+    let mut queues = [VecDeque::new(), light_columns(map, &mut changed, origin)];
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let position = origin + IVec3::new(x, y, z);
+                let emission = emission(map, position, LightChannel::Block);
+                if emission > 0 {
+                    set(map, &mut changed, position, LightChannel::Block, emission);
+                    queues[0].push_back(position);
+                }
+            }
+        }
+    }
+
+    let mut unloaded = vec![];
+    for normal in NEIGHBOURS {
+        // Its cells along this side, and the ones across it.
+        let axis = (0..3).find(|&axis| normal[axis] != 0).unwrap();
+        let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+        for a in 0..CHUNK_SIZE {
+            for b in 0..CHUNK_SIZE {
+                let mut local = IVec3::ZERO;
+                local[axis] = if normal[axis] > 0 { CHUNK_SIZE - 1 } else { 0 };
+                local[u] = a;
+                local[v] = b;
+                let inside = origin + local;
+                let outside = inside + normal;
+                if !map.is_loaded(outside) {
+                    unloaded.push(outside);
+                    continue;
+                }
+
+                for (channel, queue) in CHANNELS.into_iter().zip(&mut queues) {
+                    let level = map.light(inside, channel);
+                    map.set_light(inside, channel, level);
+                    if level > 0 {
+                        changed.insert(chunk_position + normal);
+                    }
+                    let level = map.light(outside, channel);
+                    map.set_light(outside, channel, level);
+                    if level > 0 {
+                        queue.push_back(outside);
+                    }
+                }
+            }
+        }
+    }
+    for (channel, queue) in CHANNELS.into_iter().zip(queues) {
+        spread(map, &mut changed, channel, queue);
+    }
+
+    // Chunks next to it that aren't loaded are open to the sky, like the ones
+    // above, until they load.
+    for outside in unloaded {
+        map.set_light(outside, LightChannel::Sky, MAX_LIGHT);
+    }
+
+    changed
+}
+
+impl LightMap for HashMap<IVec3, Chunk> {
+    fn is_loaded(&self, position: IVec3) -> bool {
+        self.contains_key(&split_position(position).0)
+    }
+
+    fn height(&self, position: IVec3) -> i32 {
+        let (chunk_position, local) = split_position(position);
+        let height = self
+            .get(&chunk_position)
+            .map_or(0, |chunk| chunk.height(local.x, local.z));
+        chunk_position.y * CHUNK_SIZE + height as i32
+    }
+
+    fn light(&self, position: IVec3, channel: LightChannel) -> u8 {
+        let (chunk_position, local) = split_position(position);
+        self.get(&chunk_position)
+            .map_or(0, |chunk| chunk.light_level(local, channel))
+    }
+
+    fn set_light(&mut self, position: IVec3, channel: LightChannel, level: u8) {
+        for_each_copy(position, |chunk_position, local| {
+            if let Some(chunk) = self.get_mut(&chunk_position) {
+                chunk.set_light_level(local, channel, level);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::{LAMP_ID, STONE_ID};
+    use crate::seed::WorldSeed;
+    use crate::worldgen::WorldGenerator;
+    use glam::Vec3A;
+    use uuid::Uuid;
+
+    fn lit(chunks: &mut HashMap<IVec3, Chunk>) {
+        let positions = chunks.keys().copied().collect::<Vec<_>>();
+        for chunk_position in positions {
+            light_chunk(chunks, chunk_position);
+        }
+    }
+
+    fn levels(chunks: &HashMap<IVec3, Chunk>) -> Vec<(IVec3, u8, u8)> {
+        let mut levels = chunks
+            .keys()
+            .flat_map(|&chunk_position| {
+                (0..CHUNK_SIZE.pow(3)).map(move |idx| {
+                    let local = IVec3::new(idx / 256, idx / 16 % 16, idx % 16);
+                    chunk_position * CHUNK_SIZE + local
+                })
+            })
+            .map(|position| {
+                (
+                    position,
+                    chunks.light(position, LightChannel::Block),
+                    chunks.light(position, LightChannel::Sky),
+                )
+            })
+            .collect::<Vec<_>>();
+        levels.sort_by_key(|(position, _, _)| (position.x, position.y, position.z));
+        levels
+    }
+
+    #[test]
+    fn removing_a_light_restores_the_old_levels() {
+        let generator = WorldGenerator::with_default_stages(WorldSeed::new(0));
+        let mut chunks = [IVec3::ZERO, IVec3::X]
+            .into_iter()
+            .map(|position| (position, generator.generate(position)))
+            .collect::<HashMap<_, _>>();
+        lit(&mut chunks);
+        let before = levels(&chunks);
+
+        // In the air right at the border, so the light crosses into the next chunk.
+        let lamp = (0..CHUNK_SIZE)
+            .rev()
+            .map(|y| IVec3::new(CHUNK_SIZE - 1, y, 8))
+            .find(|&position| {
+                chunks.block(position).is_none() && chunks.block(position - IVec3::Y).is_some()
+            })
+            .unwrap();
+        chunks.set_block(lamp, Some(LAMP_ID));
+        update_block(&mut chunks, lamp);
+        assert_eq!(chunks.light(lamp + IVec3::X, LightChannel::Block), 13);
+        assert_ne!(levels(&chunks), before);
+
+        chunks.set_block(lamp, None);
+        update_block(&mut chunks, lamp);
+        assert_eq!(levels(&chunks), before);
+    }
+
+    #[test]
+    fn sky_light_stops_under_an_overhang() {
+        let mut chunk = Chunk::new(Uuid::new_v4(), Vec3A::ZERO);
+        for x in 0..8 {
+            for z in 0..CHUNK_SIZE as u32 {
+                chunk.set_block(glam::UVec3::new(x, 10, z), Some(STONE_ID));
+            }
+        }
+        let mut chunks = HashMap::from([(IVec3::ZERO, chunk)]);
+        lit(&mut chunks);
+
+        let sky = |x| chunks.light(IVec3::new(x, 5, 8), LightChannel::Sky);
+        assert_eq!(
+            chunks.light(IVec3::new(4, 11, 8), LightChannel::Sky),
+            MAX_LIGHT
+        );
+        assert_eq!(chunks.light(IVec3::new(4, 10, 8), LightChannel::Sky), 0);
+        // Open sky next to it falls all the way down, under the overhang it
+        // only comes in from the side.
+        assert_eq!(sky(12), MAX_LIGHT);
+        assert_eq!(sky(7), MAX_LIGHT - 1);
+        assert_eq!(sky(1), MAX_LIGHT - 7);
+    }
+}
@@ -0,0 +1,18 @@
+use anyhow::Result;
+use tracing::Subscriber;
+
+// Targets the engine logs under, to filter by subsystem with RUST_LOG or
+// `repro::init_logging_with`, like `render=debug,chunks=warn`.
+pub const RENDER: &str = "render";
+pub const CHUNKS: &str = "chunks";
+pub const ASSETS: &str = "assets";
+pub const INPUT: &str = "input";
+pub const NET: &str = "net";
+
+// Events and spans go to the log while no subscriber is set. One set here gets
+// them instead, with their fields, for file logging or an external profiler.
+// Only the first one can be set.
+pub fn set_subscriber<S: Subscriber + Send + Sync + 'static>(subscriber: S) -> Result<()> {
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(())
+}
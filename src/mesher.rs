@@ -0,0 +1,284 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{IVec3, Vec3A};
+use std::mem::size_of;
+use wgpu::{BufferAddress, VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode};
+
+use crate::chunks::MAX_LIGHT;
+use crate::lighting::LightChannel;
+use crate::model::{ModelVertex, Vertex};
+
+pub const CHUNK_SIZE: i32 = 16;
+// A chunk and the cells just outside of it.
+const PADDED_SIZE: i32 = CHUNK_SIZE + 2;
+const UNKNOWN_LIGHT: u8 = u8::MAX;
+
+// Normal, then the u and v axes of the face chosen so that u x v = normal,
+// which keeps the generated quads counter-clockwise when seen from outside.
+const FACES: [(IVec3, IVec3, IVec3); 6] = [
+    (IVec3::X, IVec3::Y, IVec3::Z),
+    (IVec3::NEG_X, IVec3::Z, IVec3::Y),
+    (IVec3::Y, IVec3::Z, IVec3::X),
+    (IVec3::NEG_Y, IVec3::X, IVec3::Z),
+    (IVec3::Z, IVec3::X, IVec3::Y),
+    (IVec3::NEG_Z, IVec3::Y, IVec3::X),
+];
+
+const CORNERS: [(i32, i32); 4] = [(0, 0), (1, 0), (1, 1), (0, 1)];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct AoVertex {
+    pub ao: f32,
+    // Block and sky light in front of the face around this corner, from 0.0 to 1.0.
+    pub light: f32,
+    pub sky: f32,
+    // Biome grass color on top faces, white everywhere else.
+    pub tint: [f32; 3],
+}
+
+impl Vertex for AoVertex {
+    fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: size_of::<AoVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &[
+                VertexAttribute {
+                    offset: 0,
+                    shader_location: 2,
+                    format: VertexFormat::Float32,
+                },
+                VertexAttribute {
+                    offset: size_of::<f32>() as BufferAddress,
+                    shader_location: 5,
+                    format: VertexFormat::Float32,
+                },
+                VertexAttribute {
+                    offset: size_of::<[f32; 2]>() as BufferAddress,
+                    shader_location: 6,
+                    format: VertexFormat::Float32,
+                },
+                VertexAttribute {
+                    offset: size_of::<[f32; 3]>() as BufferAddress,
+                    shader_location: 7,
+                    format: VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+// Light levels of a chunk's cells and of the cells just outside its sides. The
+// ones past its edges and corners aren't known and get left out.
+pub struct LightSamples {
+    block: Vec<u8>,
+    sky: Vec<u8>,
+}
+
+impl LightSamples {
+    pub fn new() -> Self {
+        let cells = (PADDED_SIZE * PADDED_SIZE * PADDED_SIZE) as usize;
+        Self {
+            block: vec![UNKNOWN_LIGHT; cells],
+            sky: vec![UNKNOWN_LIGHT; cells],
+        }
+    }
+
+    fn levels(&self, channel: LightChannel) -> &[u8] {
+        match channel {
+            LightChannel::Block => &self.block,
+            LightChannel::Sky => &self.sky,
+        }
+    }
+
+    fn index(position: IVec3) -> Option<usize> {
+        let padded = position + 1;
+        if padded.cmplt(IVec3::ZERO).any() || padded.cmpge(IVec3::splat(PADDED_SIZE)).any() {
+            return None;
+        }
+
+        Some((padded.x * PADDED_SIZE * PADDED_SIZE + padded.y * PADDED_SIZE + padded.z) as usize)
+    }
+
+    pub fn set(&mut self, position: IVec3, channel: LightChannel, level: u8) {
+        if let Some(idx) = Self::index(position) {
+            let levels = match channel {
+                LightChannel::Block => &mut self.block,
+                LightChannel::Sky => &mut self.sky,
+            };
+            levels[idx] = level.min(MAX_LIGHT);
+        }
+    }
+
+    pub fn get(&self, position: IVec3, channel: LightChannel) -> Option<u8> {
+        Self::index(position)
+            .map(|idx| self.levels(channel)[idx])
+            .filter(|&level| level != UNKNOWN_LIGHT)
+    }
+}
+
+impl Default for LightSamples {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Occupancy {
+    solid: Vec<bool>,
+}
+
+impl Occupancy {
+    pub fn new() -> Self {
+        Self {
+            solid: vec![false; (CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE) as usize],
+        }
+    }
+
+    fn index(position: IVec3) -> Option<usize> {
+        if position.cmplt(IVec3::ZERO).any() || position.cmpge(IVec3::splat(CHUNK_SIZE)).any() {
+            return None;
+        }
+
+        Some((position.x * CHUNK_SIZE * CHUNK_SIZE + position.y * CHUNK_SIZE + position.z) as usize)
+    }
+
+    pub fn set(&mut self, position: IVec3) {
+        if let Some(idx) = Self::index(position) {
+            self.solid[idx] = true;
+        }
+    }
+
+    pub fn is_solid(&self, position: IVec3) -> bool {
+        Self::index(position).is_some_and(|idx| self.solid[idx])
+    }
+}
+
+#[derive(Default)]
+pub struct ChunkMesh {
+    pub vertices: Vec<ModelVertex>,
+    pub ao: Vec<AoVertex>,
+    pub indices: Vec<u32>,
+}
+
+fn vertex_ao(side1: bool, side2: bool, corner: bool) -> f32 {
+    if side1 && side2 {
+        return 0.0;
+    }
+
+    (3 - side1 as u32 - side2 as u32 - corner as u32) as f32 / 3.0
+}
+
+// Smooth lighting, the average of the open cells touching the corner in front
+// of the face.
+fn vertex_light(
+    light: &LightSamples,
+    occupancy: &Occupancy,
+    cells: [IVec3; 4],
+    channel: LightChannel,
+) -> f32 {
+    let (sum, count) = cells
+        .into_iter()
+        .filter(|&cell| !occupancy.is_solid(cell))
+        .filter_map(|cell| light.get(cell, channel))
+        .fold((0, 0), |(sum, count), level| {
+            (sum + level as u32, count + 1)
+        });
+    if count == 0 {
+        return 0.0;
+    }
+
+    sum as f32 / count as f32 / MAX_LIGHT as f32
+}
+
+// Grass tints are per column, x major like the chunk's cells.
+pub fn mesh_chunk(
+    occupancy: &Occupancy,
+    light: &LightSamples,
+    grass: &[[f32; 3]],
+    origin: Vec3A,
+) -> ChunkMesh {
+    let mut mesh = ChunkMesh::default();
+
+    for x in 0..CHUNK_SIZE {
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let position = IVec3::new(x, y, z);
+                if !occupancy.is_solid(position) {
+                    continue;
+                }
+
+                for (normal, u, v) in FACES {
+                    if occupancy.is_solid(position + normal) {
+                        continue;
+                    }
+
+                    let center = origin + position.as_vec3a() + normal.as_vec3a() * 0.5;
+                    let tint = if normal == IVec3::Y {
+                        grass[(x * CHUNK_SIZE + z) as usize]
+                    } else {
+                        [1.0; 3]
+                    };
+                    let start = mesh.vertices.len() as u32;
+                    let mut ao = [0.0; 4];
+
+                    for (i, (a, b)) in CORNERS.into_iter().enumerate() {
+                        let su = a * 2 - 1;
+                        let sv = b * 2 - 1;
+                        let front = position + normal;
+                        let cells = [
+                            front,
+                            front + u * su,
+                            front + v * sv,
+                            front + u * su + v * sv,
+                        ];
+                        let side1 = occupancy.is_solid(cells[1]);
+                        let side2 = occupancy.is_solid(cells[2]);
+                        let corner = occupancy.is_solid(cells[3]);
+                        ao[i] = vertex_ao(side1, side2, corner);
+
+                        let corner_position = center
+                            + u.as_vec3a() * (a as f32 - 0.5)
+                            + v.as_vec3a() * (b as f32 - 0.5);
+                        mesh.vertices.push(ModelVertex {
+                            position: corner_position.to_array(),
+                            tex_coords: [a as f32, 1.0 - b as f32],
+                            normal: normal.as_vec3().to_array(),
+                            // Texture u runs along u, and up the texture is along v,
+                            // which is normal x u.
+                            tangent: u.as_vec3().extend(1.0).to_array(),
+                        });
+                        mesh.ao.push(AoVertex {
+                            ao: ao[i],
+                            light: vertex_light(light, occupancy, cells, LightChannel::Block),
+                            sky: vertex_light(light, occupancy, cells, LightChannel::Sky),
+                            tint,
+                        });
+                    }
+
+                    // Split the quad along the darker diagonal, otherwise the AO gradient
+                    // gets interpolated unevenly across the two triangles.
+                    if ao[0] + ao[2] <= ao[1] + ao[3] {
+                        mesh.indices.extend_from_slice(&[
+                            start,
+                            start + 1,
+                            start + 2,
+                            start,
+                            start + 2,
+                            start + 3,
+                        ]);
+                    } else {
+                        mesh.indices.extend_from_slice(&[
+                            start + 1,
+                            start + 2,
+                            start + 3,
+                            start + 1,
+                            start + 3,
+                            start,
+                        ]);
+                    }
+                }
+            }
+        }
+    }
+
+    mesh
+}
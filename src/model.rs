@@ -1,7 +1,9 @@
 use crate::texture::Texture;
+use crate::texture_streaming::StreamedTextureId;
 use bytemuck::{Pod, Zeroable};
 use std::mem::size_of;
 use std::ops::Range;
+use wgpu::util::RenderEncoder;
 use wgpu::{
     BindGroup, BindGroupLayout, Buffer, BufferAddress, Device, IndexFormat, RenderPass,
     VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode,
@@ -16,6 +18,9 @@ pub trait Vertex {
 pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    // The w is the sign of the bitangent, cross(normal, tangent) * w.
+    pub tangent: [f32; 4],
 }
 
 impl Vertex for ModelVertex {
@@ -34,6 +39,17 @@ impl Vertex for ModelVertex {
                     shader_location: 1,
                     format: VertexFormat::Float32x2,
                 },
+                // 2 is taken by the ambient occlusion of chunk meshes.
+                VertexAttribute {
+                    offset: size_of::<[f32; 5]>() as BufferAddress,
+                    shader_location: 3,
+                    format: VertexFormat::Float32x3,
+                },
+                VertexAttribute {
+                    offset: size_of::<[f32; 8]>() as BufferAddress,
+                    shader_location: 4,
+                    format: VertexFormat::Float32x4,
+                },
             ],
         }
     }
@@ -47,7 +63,9 @@ pub struct ObjModel {
 pub struct Material {
     pub name: String,
     pub diffuse_texture: Texture,
+    pub normal_texture: Texture,
     pub bind_group: BindGroup,
+    pub streamed: Option<StreamedTextureId>,
 }
 
 impl Material {
@@ -55,9 +73,41 @@ impl Material {
         device: &Device,
         name: &str,
         diffuse_texture: Texture,
+        normal_texture: Texture,
         layout: &BindGroupLayout,
     ) -> Self {
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let bind_group =
+            Self::create_bind_group(device, name, &diffuse_texture, &normal_texture, layout);
+
+        Self {
+            name: String::from(name),
+            diffuse_texture,
+            normal_texture,
+            bind_group,
+            streamed: None,
+        }
+    }
+
+    pub fn streamed(mut self, id: StreamedTextureId) -> Self {
+        self.streamed = Some(id);
+        self
+    }
+
+    // Swaps in a new diffuse texture, like the streamer does when mips change.
+    pub fn set_texture(&mut self, device: &Device, texture: Texture, layout: &BindGroupLayout) {
+        self.bind_group =
+            Self::create_bind_group(device, &self.name, &texture, &self.normal_texture, layout);
+        self.diffuse_texture = texture;
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        name: &str,
+        diffuse_texture: &Texture,
+        normal_texture: &Texture,
+        layout: &BindGroupLayout,
+    ) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
             entries: &[
                 wgpu::BindGroupEntry {
@@ -68,15 +118,17 @@ impl Material {
                     binding: 1,
                     resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&normal_texture.sampler),
+                },
             ],
             label: Some(name),
-        });
-
-        Self {
-            name: String::from(name),
-            diffuse_texture,
-            bind_group,
-        }
+        })
     }
 }
 
@@ -138,16 +190,14 @@ pub trait DrawLight<'a> {
     );
 }
 
-impl<'a, 'b> DrawModel<'b> for RenderPass<'a>
-where
-    'b: 'a,
-{
+// Render passes and render bundles alike.
+impl<'a, R: RenderEncoder<'a>> DrawModel<'a> for R {
     fn draw_mesh_instanced(
         &mut self,
-        mesh: &'b Mesh,
-        material: &'b Material,
+        mesh: &'a Mesh,
+        material: &'a Material,
         instances: Range<u32>,
-        camera_bind_group: &'b BindGroup,
+        camera_bind_group: &'a BindGroup,
         light_bind_group: Option<&'a BindGroup>,
         optional_bind_group: &[&'a BindGroup],
     ) {
@@ -169,9 +219,9 @@ where
 
     fn draw_model_instanced(
         &mut self,
-        model: &'b ObjModel,
+        model: &'a ObjModel,
         instances: Range<u32>,
-        camera_bind_group: &'b BindGroup,
+        camera_bind_group: &'a BindGroup,
         light_bind_group: Option<&'a BindGroup>,
         optional_bind_group: &[&'a BindGroup],
     ) {
@@ -0,0 +1,1103 @@
+use anyhow::{anyhow, Result};
+use glam::{IVec3, Vec3};
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::app::{App, Model, FIXED_TIMESTEP};
+use crate::logging;
+use crate::mesher::CHUNK_SIZE;
+use crate::replication::{step_player, EntityState, Interpolation, PlayerInput, Prediction};
+use crate::save::{decode_chunk, encode_chunk};
+use crate::seed::WorldSeed;
+use crate::world::World;
+use crate::world_edit::split_position;
+
+const MAGIC: &[u8; 4] = b"VXNT";
+pub const PROTOCOL_VERSION: u8 = 2;
+pub const DEFAULT_PORT: u16 = 25570;
+// Longer messages mean the other side is broken or doesn't speak the protocol.
+const MAX_MESSAGE_LEN: usize = 4 << 20;
+// How long a client waits for the server to answer its hello, and the server
+// for a new connection to send one.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// Chunks sent to a client at most in a single tick, nearest first.
+const CHUNKS_PER_TICK: usize = 8;
+// Chunks the center a client asks for may be away from where its player is on
+// the server, clients move their player ahead of the server's.
+const MAX_CENTER_OFFSET: i32 = 1;
+// Seconds of movement a client may bank by sending no inputs, past them its
+// inputs get cut short so sending more of them doesn't move it faster.
+const MAX_MOVEMENT_BANK: f32 = 1.0;
+
+const HELLO: u8 = 1;
+const WELCOME: u8 = 2;
+const REJECTED: u8 = 3;
+const POSITION: u8 = 4;
+const SET_BLOCK: u8 = 5;
+const CHUNK: u8 = 6;
+const UNLOAD_CHUNK: u8 = 7;
+const BLOCK_CHANGES: u8 = 8;
+const INPUTS: u8 = 9;
+const SNAPSHOT: u8 = 10;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    // Client to server, the first thing sent on a connection.
+    Hello {
+        version: u8,
+        name: String,
+    },
+    // Server to client, answering a hello it accepts.
+    Welcome {
+        seed: WorldSeed,
+        spawn: Vec3,
+        view_radius: i32,
+        // The client's own player in snapshots.
+        entity: u32,
+    },
+    // Server to client, right before it closes the connection.
+    Rejected(String),
+    // Client to server, the chunk the player is in. The server sends the
+    // chunks around it and unloads the ones that fell out of its view radius.
+    Position(IVec3),
+    // Client to server, an edit for the server to make. Only chunks the client
+    // got can be edited.
+    SetBlock {
+        position: IVec3,
+        id: Option<u16>,
+    },
+    // Server to client, a whole chunk in the save format.
+    Chunk(Vec<u8>),
+    UnloadChunk(IVec3),
+    // Server to client, blocks of chunks it sent that changed since, in the
+    // layout of Model::block_state.
+    BlockChanges(Vec<(IVec3, Option<u32>)>),
+    // Client to server, movement of its player since the last ones it sent.
+    Inputs(Vec<PlayerInput>),
+    // Server to client after every fixed tick, every player there is. The
+    // client's own one is as it was after the input `acknowledged`, 0 before
+    // the first.
+    Snapshot {
+        tick: u64,
+        acknowledged: u32,
+        entities: Vec<(u32, EntityState)>,
+    },
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(anyhow!("message is truncated"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn vec3(&mut self) -> Result<Vec3> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn ivec3(&mut self) -> Result<IVec3> {
+        Ok(IVec3::new(
+            self.u32()? as i32,
+            self.u32()? as i32,
+            self.u32()? as i32,
+        ))
+    }
+
+    // Optional values have a byte in front, 0 for None.
+    fn optional<T>(&mut self, read: impl FnOnce(&mut Self) -> Result<T>) -> Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => read(self).map(Some),
+        }
+    }
+}
+
+fn write_ivec3(data: &mut Vec<u8>, value: IVec3) {
+    for axis in value.to_array() {
+        data.extend_from_slice(&axis.to_le_bytes());
+    }
+}
+
+fn write_vec3(data: &mut Vec<u8>, value: Vec3) {
+    for axis in value.to_array() {
+        data.extend_from_slice(&axis.to_le_bytes());
+    }
+}
+
+// Strings past what the u16 length takes get cut short, on a char boundary so
+// they stay valid UTF-8.
+fn write_str(data: &mut Vec<u8>, value: &str) {
+    let mut len = value.len().min(u16::MAX as usize);
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    data.extend_from_slice(&(len as u16).to_le_bytes());
+    data.extend_from_slice(&value.as_bytes()[..len]);
+}
+
+pub fn encode_message(message: &Message) -> Vec<u8> {
+    let mut data = vec![];
+    match message {
+        Message::Hello { version, name } => {
+            data.push(HELLO);
+            data.extend_from_slice(MAGIC);
+            data.push(*version);
+            write_str(&mut data, name);
+        }
+        Message::Welcome {
+            seed,
+            spawn,
+            view_radius,
+            entity,
+        } => {
+            data.push(WELCOME);
+            data.extend_from_slice(&seed.value().to_le_bytes());
+            write_vec3(&mut data, *spawn);
+            data.extend_from_slice(&view_radius.to_le_bytes());
+            data.extend_from_slice(&entity.to_le_bytes());
+        }
+        Message::Rejected(reason) => {
+            data.push(REJECTED);
+            write_str(&mut data, reason);
+        }
+        Message::Position(chunk_position) => {
+            data.push(POSITION);
+            write_ivec3(&mut data, *chunk_position);
+        }
+        Message::SetBlock { position, id } => {
+            data.push(SET_BLOCK);
+            write_ivec3(&mut data, *position);
+            data.push(id.is_some() as u8);
+            if let Some(id) = id {
+                data.extend_from_slice(&id.to_le_bytes());
+            }
+        }
+        Message::Chunk(chunk) => {
+            data.push(CHUNK);
+            data.extend_from_slice(chunk);
+        }
+        Message::UnloadChunk(chunk_position) => {
+            data.push(UNLOAD_CHUNK);
+            write_ivec3(&mut data, *chunk_position);
+        }
+        Message::BlockChanges(changes) => {
+            data.push(BLOCK_CHANGES);
+            data.extend_from_slice(&(changes.len() as u32).to_le_bytes());
+            for (position, state) in changes {
+                write_ivec3(&mut data, *position);
+                data.push(state.is_some() as u8);
+                if let Some(state) = state {
+                    data.extend_from_slice(&state.to_le_bytes());
+                }
+            }
+        }
+        Message::Inputs(inputs) => {
+            data.push(INPUTS);
+            data.extend_from_slice(&(inputs.len() as u32).to_le_bytes());
+            for input in inputs {
+                data.extend_from_slice(&input.sequence.to_le_bytes());
+                write_vec3(&mut data, input.movement);
+                for value in [input.yaw, input.pitch, input.dt] {
+                    data.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        Message::Snapshot {
+            tick,
+            acknowledged,
+            entities,
+        } => {
+            data.push(SNAPSHOT);
+            data.extend_from_slice(&tick.to_le_bytes());
+            data.extend_from_slice(&acknowledged.to_le_bytes());
+            data.extend_from_slice(&(entities.len() as u32).to_le_bytes());
+            for (id, state) in entities {
+                data.extend_from_slice(&id.to_le_bytes());
+                write_vec3(&mut data, state.position);
+                data.extend_from_slice(&state.yaw.to_le_bytes());
+                data.extend_from_slice(&state.pitch.to_le_bytes());
+            }
+        }
+    }
+
+    data
+}
+
+pub fn decode_message(data: &[u8]) -> Result<Message> {
+    let mut reader = Reader { data };
+    let message = match reader.u8()? {
+        HELLO => {
+            if reader.take(4)? != MAGIC {
+                return Err(anyhow!("not a VoxelTest client"));
+            }
+            let version = reader.u8()?;
+            let len = reader.u16()? as usize;
+            let name = String::from_utf8(reader.take(len)?.to_vec())?;
+            Message::Hello { version, name }
+        }
+        WELCOME => Message::Welcome {
+            seed: WorldSeed::new(reader.u64()?),
+            spawn: reader.vec3()?,
+            view_radius: reader.u32()? as i32,
+            entity: reader.u32()?,
+        },
+        REJECTED => {
+            let len = reader.u16()? as usize;
+            Message::Rejected(String::from_utf8(reader.take(len)?.to_vec())?)
+        }
+        POSITION => Message::Position(reader.ivec3()?),
+        SET_BLOCK => Message::SetBlock {
+            position: reader.ivec3()?,
+            id: reader.optional(Reader::u16)?,
+        },
+        CHUNK => Message::Chunk(reader.take(reader.data.len())?.to_vec()),
+        UNLOAD_CHUNK => Message::UnloadChunk(reader.ivec3()?),
+        BLOCK_CHANGES => {
+            let count = reader.u32()?;
+            let changes = (0..count)
+                .map(|_| Ok((reader.ivec3()?, reader.optional(Reader::u32)?)))
+                .collect::<Result<Vec<_>>>()?;
+            Message::BlockChanges(changes)
+        }
+        INPUTS => {
+            let count = reader.u32()?;
+            let inputs = (0..count)
+                .map(|_| {
+                    Ok(PlayerInput {
+                        sequence: reader.u32()?,
+                        movement: reader.vec3()?,
+                        yaw: reader.f32()?,
+                        pitch: reader.f32()?,
+                        dt: reader.f32()?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Message::Inputs(inputs)
+        }
+        SNAPSHOT => {
+            let tick = reader.u64()?;
+            let acknowledged = reader.u32()?;
+            let count = reader.u32()?;
+            let entities = (0..count)
+                .map(|_| {
+                    Ok((
+                        reader.u32()?,
+                        EntityState {
+                            position: reader.vec3()?,
+                            yaw: reader.f32()?,
+                            pitch: reader.f32()?,
+                        },
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Message::Snapshot {
+                tick,
+                acknowledged,
+                entities,
+            }
+        }
+        kind => return Err(anyhow!("unknown message kind {kind}")),
+    };
+    if !reader.data.is_empty() {
+        return Err(anyhow!("message has {} bytes too many", reader.data.len()));
+    }
+
+    Ok(message)
+}
+
+// Messages over TCP, each as its length and then the message. Never blocks
+// once connected, sends wait in a queue until the socket takes them.
+pub struct Connection {
+    stream: TcpStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Result<Self> {
+        stream.set_nonblocking(true)?;
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            incoming: vec![],
+            outgoing: vec![],
+            closed: false,
+        })
+    }
+
+    pub fn send(&mut self, message: &Message) {
+        let data = encode_message(message);
+        self.outgoing
+            .extend_from_slice(&(data.len() as u32).to_le_bytes());
+        self.outgoing.extend_from_slice(&data);
+    }
+
+    // Writes as much of the queue as the socket takes.
+    pub fn flush(&mut self) -> Result<()> {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return Err(anyhow!("connection closed")),
+                Ok(written) => {
+                    self.outgoing.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Messages that arrived in full since the last call. Errors once the other
+    // side closed the connection and everything it sent got taken.
+    pub fn receive(&mut self) -> Result<Vec<Message>> {
+        let mut buffer = [0; 16 * 1024];
+        while !self.closed {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.closed = true,
+                Ok(read) => self.incoming.extend_from_slice(&buffer[..read]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut messages = vec![];
+        while self.incoming.len() >= 4 {
+            let len = u32::from_le_bytes(self.incoming[..4].try_into().unwrap()) as usize;
+            if len > MAX_MESSAGE_LEN {
+                return Err(anyhow!("message of {len} bytes is too long"));
+            }
+            if self.incoming.len() < 4 + len {
+                break;
+            }
+            messages.push(decode_message(&self.incoming[4..4 + len])?);
+            self.incoming.drain(..4 + len);
+        }
+        if self.closed && messages.is_empty() {
+            return Err(anyhow!("connection closed"));
+        }
+
+        Ok(messages)
+    }
+}
+
+struct RemoteClient {
+    connection: Connection,
+    // None until its hello came in.
+    name: Option<String>,
+    accepted: Instant,
+    center: Option<IVec3>,
+    sent: HashSet<IVec3>,
+    entity: u32,
+    player: EntityState,
+    // Sequence of the last input applied to the player.
+    acknowledged: u32,
+    movement_bank: f32,
+}
+
+// Hosts the authoritative world for clients to mirror, without rendering
+// anything. Edits clients ask for get made here and sent back to everyone
+// along with what the simulation changed. Players move by the inputs their
+// clients send, every client gets where all of them are after each tick.
+pub struct Server {
+    listener: TcpListener,
+    world: World,
+    view_radius: i32,
+    spawn: Vec3,
+    clients: Vec<RemoteClient>,
+    tick: u64,
+    next_entity: u32,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(address: A, world: World, view_radius: i32) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        tracing::info!(target: logging::NET, "Listening on {}", listener.local_addr()?);
+        Ok(Self {
+            listener,
+            world,
+            view_radius,
+            spawn: Vec3::new(0.0, 5.0, 10.0),
+            clients: vec![],
+            tick: 0,
+            next_entity: 1,
+        })
+    }
+
+    pub fn with_spawn(mut self, spawn: Vec3) -> Self {
+        self.spawn = spawn;
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    // Clients past the handshake.
+    pub fn clients(&self) -> usize {
+        self.clients
+            .iter()
+            .filter(|client| client.name.is_some())
+            .count()
+    }
+
+    // Takes in new clients and what they sent, runs a fixed tick of the world
+    // and sends out what changed.
+    pub fn tick(&mut self) {
+        self.accept();
+        let (seed, spawn, view_radius) =
+            (self.world.generator().seed(), self.spawn, self.view_radius);
+        let world = &mut self.world;
+        self.clients.retain_mut(|client| {
+            let welcome = Message::Welcome {
+                seed,
+                spawn,
+                view_radius,
+                entity: client.entity,
+            };
+            client.movement_bank = (client.movement_bank + FIXED_TIMESTEP).min(MAX_MOVEMENT_BANK);
+            let result = client
+                .connection
+                .receive()
+                .and_then(|messages| handle_messages(world, client, messages, &welcome))
+                .and_then(|_| {
+                    if client.name.is_none() && client.accepted.elapsed() > HANDSHAKE_TIMEOUT {
+                        return Err(anyhow!("no hello in time"));
+                    }
+                    Ok(())
+                });
+            drop_on_error(client, result)
+        });
+
+        let mut changed = self.world.tick();
+        changed.sort_unstable_by_key(|position| (position.x, position.y, position.z));
+        changed.dedup();
+        self.tick += 1;
+        let entities = self
+            .clients
+            .iter()
+            .filter(|client| client.name.is_some())
+            .map(|client| (client.entity, client.player))
+            .collect::<Vec<_>>();
+        let (world, view_radius, tick) = (&mut self.world, self.view_radius, self.tick);
+        self.clients.retain_mut(|client| {
+            send_changes(world, client, &changed);
+            stream_chunks(world, client, view_radius);
+            if client.name.is_some() {
+                client.connection.send(&Message::Snapshot {
+                    tick,
+                    acknowledged: client.acknowledged,
+                    entities: entities.clone(),
+                });
+            }
+            let result = client.connection.flush();
+            drop_on_error(client, result)
+        });
+        self.unload_chunks();
+    }
+
+    // Chunks no client has any more go away, so the world only keeps and
+    // ticks what's around the players.
+    fn unload_chunks(&mut self) {
+        let kept = self
+            .clients
+            .iter()
+            .flat_map(|client| client.sent.iter().copied())
+            .collect::<HashSet<_>>();
+        let unused = self
+            .world
+            .chunks()
+            .keys()
+            .filter(|position| !kept.contains(position))
+            .copied()
+            .collect::<Vec<_>>();
+        for position in unused {
+            self.world.remove_chunk(position);
+        }
+    }
+
+    // The headless server, ticking until the process gets killed.
+    pub fn run(mut self) -> Result<()> {
+        let timestep = Duration::from_secs_f32(FIXED_TIMESTEP);
+        loop {
+            let start = Instant::now();
+            self.tick();
+            thread::sleep(timestep.saturating_sub(start.elapsed()));
+        }
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => match Connection::new(stream) {
+                    Ok(connection) => {
+                        tracing::debug!(target: logging::NET, "Connection from {address}");
+                        self.clients.push(RemoteClient {
+                            connection,
+                            name: None,
+                            accepted: Instant::now(),
+                            center: None,
+                            sent: HashSet::new(),
+                            entity: self.next_entity,
+                            player: EntityState::new(self.spawn),
+                            acknowledged: 0,
+                            movement_bank: 0.0,
+                        });
+                        self.next_entity += 1;
+                    }
+                    Err(e) => {
+                        tracing::warn!(target: logging::NET, "Couldn't set up {address}: {e}")
+                    }
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    tracing::warn!(target: logging::NET, "Couldn't accept a connection: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// False when the client should go.
+fn drop_on_error(client: &mut RemoteClient, result: Result<()>) -> bool {
+    let Err(e) = result else {
+        return true;
+    };
+
+    let name = client.name.as_deref().unwrap_or("unknown client");
+    tracing::info!(target: logging::NET, "Dropping {name}: {e}");
+    client.connection.send(&Message::Rejected(e.to_string()));
+    let _ = client.connection.flush();
+    false
+}
+
+fn handle_messages(
+    world: &mut World,
+    client: &mut RemoteClient,
+    messages: Vec<Message>,
+    welcome: &Message,
+) -> Result<()> {
+    for message in messages {
+        match (&client.name, message) {
+            (None, Message::Hello { version, name }) => {
+                if version != PROTOCOL_VERSION {
+                    return Err(anyhow!(
+                        "protocol version {version} isn't supported, the server speaks {PROTOCOL_VERSION}"
+                    ));
+                }
+                tracing::info!(target: logging::NET, "{name} joined");
+                client.name = Some(name);
+                client.connection.send(welcome);
+            }
+            (None, _) => return Err(anyhow!("expected a hello")),
+            (Some(_), Message::Position(center)) => client.center = Some(center),
+            // Ids that aren't in the registry fail and get the client dropped.
+            (Some(_), Message::SetBlock { position, id }) => {
+                if client.sent.contains(&split_position(position).0) {
+                    world.set_block(position, id)?;
+                }
+            }
+            (Some(_), Message::Inputs(inputs)) => {
+                for input in inputs {
+                    move_player(world, client, input)?;
+                }
+            }
+            (Some(_), _) => return Err(anyhow!("unexpected message from a client")),
+        }
+    }
+
+    Ok(())
+}
+
+// Inputs that came in before, or twice, get skipped.
+fn move_player(world: &World, client: &mut RemoteClient, input: PlayerInput) -> Result<()> {
+    let values = [input.yaw, input.pitch, input.dt];
+    if !input.movement.is_finite() || values.iter().any(|value| !value.is_finite()) {
+        return Err(anyhow!("input {} isn't a number", input.sequence));
+    }
+    if input.sequence <= client.acknowledged {
+        return Ok(());
+    }
+
+    let dt = input.dt.clamp(0.0, client.movement_bank);
+    client.movement_bank -= dt;
+    step_player(&mut client.player, &PlayerInput { dt, ..input }, world);
+    client.acknowledged = input.sequence;
+    Ok(())
+}
+
+fn send_changes(world: &World, client: &mut RemoteClient, changed: &[IVec3]) {
+    let changes = changed
+        .iter()
+        .filter(|&&position| client.sent.contains(&split_position(position).0))
+        .map(|&position| {
+            let (chunk_position, local) = split_position(position);
+            let state = world
+                .chunk(chunk_position)
+                .and_then(|chunk| chunk.block_state(local));
+            (position, state)
+        })
+        .collect::<Vec<_>>();
+    if !changes.is_empty() {
+        client.connection.send(&Message::BlockChanges(changes));
+    }
+}
+
+// Chunks only span a single layer, like the ones apps stream in. They get
+// streamed around the player rather than anywhere the client asks for.
+fn stream_chunks(world: &mut World, client: &mut RemoteClient, view_radius: i32) {
+    let Some(center) = client.center else {
+        return;
+    };
+    let player = (client.player.position / CHUNK_SIZE as f32)
+        .floor()
+        .as_ivec3();
+    let offset = IVec3::splat(MAX_CENTER_OFFSET);
+    let center = player + (center - player).clamp(-offset, offset);
+    let center = IVec3::new(center.x, 0, center.z);
+    let in_range = |position: &IVec3| {
+        let offset = (*position - center).abs();
+        offset.x <= view_radius && offset.z <= view_radius
+    };
+
+    let unload = client
+        .sent
+        .iter()
+        .copied()
+        .filter(|position| !in_range(position))
+        .collect::<Vec<_>>();
+    for chunk_position in unload {
+        client.sent.remove(&chunk_position);
+        client
+            .connection
+            .send(&Message::UnloadChunk(chunk_position));
+    }
+
+    let mut missing = vec![];
+    for x in -view_radius..=view_radius {
+        for z in -view_radius..=view_radius {
+            let position = center + IVec3::new(x, 0, z);
+            if !client.sent.contains(&position) {
+                missing.push(position);
+            }
+        }
+    }
+    missing.sort_by_key(|position| (*position - center).length_squared());
+    for chunk_position in missing.into_iter().take(CHUNKS_PER_TICK) {
+        world.generate_region(chunk_position, chunk_position);
+        let chunk = world.chunk(chunk_position).unwrap();
+        client.connection.send(&Message::Chunk(encode_chunk(chunk)));
+        client.sent.insert(chunk_position);
+    }
+}
+
+// A connection to a server, mirroring its world into the current dimension of
+// an App, which should be a remote one.
+pub struct Client {
+    connection: Connection,
+    seed: WorldSeed,
+    spawn: Vec3,
+    view_radius: i32,
+    center: Option<IVec3>,
+    entity: u32,
+    prediction: Arc<Mutex<Prediction>>,
+    interpolation: Interpolation,
+}
+
+impl Client {
+    // Connects and goes through the handshake, blocking until the server
+    // answers it.
+    pub fn connect<A: ToSocketAddrs>(address: A, name: &str) -> Result<Self> {
+        let mut connection = Connection::new(TcpStream::connect(address)?)?;
+        connection.send(&Message::Hello {
+            version: PROTOCOL_VERSION,
+            name: name.to_string(),
+        });
+
+        let start = Instant::now();
+        loop {
+            connection.flush()?;
+            if let Some(message) = connection.receive()?.into_iter().next() {
+                return match message {
+                    Message::Welcome {
+                        seed,
+                        spawn,
+                        view_radius,
+                        entity,
+                    } => Ok(Self {
+                        connection,
+                        seed,
+                        spawn,
+                        view_radius,
+                        center: None,
+                        entity,
+                        prediction: Arc::new(Mutex::new(Prediction::new(EntityState::new(spawn)))),
+                        interpolation: Interpolation::new(),
+                    }),
+                    Message::Rejected(reason) => Err(anyhow!("server rejected us: {reason}")),
+                    _ => Err(anyhow!("server didn't answer the handshake with a welcome")),
+                };
+            }
+            if start.elapsed() > HANDSHAKE_TIMEOUT {
+                return Err(anyhow!("server didn't answer the handshake"));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Of the server's world, for a dimension generating the same biome tints.
+    pub fn seed(&self) -> WorldSeed {
+        self.seed
+    }
+
+    pub fn spawn(&self) -> Vec3 {
+        self.spawn
+    }
+
+    pub fn view_radius(&self) -> i32 {
+        self.view_radius
+    }
+
+    pub fn entity(&self) -> u32 {
+        self.entity
+    }
+
+    // The local player, for a replication::PlayerController to move. Inputs
+    // it makes get sent on the next update.
+    pub fn prediction(&self) -> Arc<Mutex<Prediction>> {
+        self.prediction.clone()
+    }
+
+    // The other players, where they were INTERPOLATION_DELAY before the
+    // newest snapshot.
+    pub fn remote_entities(&self) -> Vec<(u32, EntityState)> {
+        self.interpolation.entities()
+    }
+
+    // Asks the server to make an edit, it shows up once the server sends it
+    // back.
+    pub fn set_block(&mut self, position: IVec3, id: Option<u16>) {
+        self.connection.send(&Message::SetBlock { position, id });
+    }
+
+    // Tells the server where the camera is and how the player moved and puts
+    // what it sent into the app's current dimension. Errors once the
+    // connection is gone.
+    pub fn update(&mut self, app: &mut App, dt: Duration) -> Result<()> {
+        let position = app.camera().read().unwrap().position();
+        let center = (position / CHUNK_SIZE as f32).floor().as_ivec3();
+        if self.center != Some(center) {
+            self.connection.send(&Message::Position(center));
+            self.center = Some(center);
+        }
+        let inputs = self.prediction.lock().unwrap().take_outgoing();
+        if !inputs.is_empty() {
+            self.connection.send(&Message::Inputs(inputs));
+        }
+        self.connection.flush()?;
+        self.interpolation.advance(dt);
+
+        for message in self.connection.receive()? {
+            match message {
+                Message::Chunk(data) => app.insert_chunk(decode_chunk(&data)?)?,
+                Message::UnloadChunk(chunk_position) => app.remove_chunk(chunk_position),
+                Message::BlockChanges(changes) => {
+                    for (position, state) in changes {
+                        app.set_block_state(position, state);
+                    }
+                }
+                Message::Snapshot {
+                    tick,
+                    acknowledged,
+                    mut entities,
+                } => {
+                    if let Some(idx) = entities.iter().position(|(id, _)| *id == self.entity) {
+                        let (_, state) = entities.swap_remove(idx);
+                        self.prediction.lock().unwrap().confirm(acknowledged, state);
+                    }
+                    let time = tick as f64 * FIXED_TIMESTEP as f64;
+                    self.interpolation.push_snapshot(time, &entities);
+                }
+                Message::Rejected(reason) => {
+                    return Err(anyhow!("server closed the connection: {reason}"))
+                }
+                _ => return Err(anyhow!("unexpected message from the server")),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages() -> Vec<Message> {
+        vec![
+            Message::Hello {
+                version: PROTOCOL_VERSION,
+                name: "player".to_string(),
+            },
+            Message::Welcome {
+                seed: WorldSeed::new(42),
+                spawn: Vec3::new(1.0, 2.5, -3.0),
+                view_radius: 8,
+                entity: 7,
+            },
+            Message::Rejected("full".to_string()),
+            Message::Position(IVec3::new(-1, 0, 3)),
+            Message::SetBlock {
+                position: IVec3::new(5, -6, 7),
+                id: Some(3),
+            },
+            Message::SetBlock {
+                position: IVec3::ZERO,
+                id: None,
+            },
+            Message::Chunk(vec![1, 2, 3, 4]),
+            Message::UnloadChunk(IVec3::new(2, 0, -2)),
+            Message::BlockChanges(vec![
+                (IVec3::ONE, Some(0x1234_5678)),
+                (IVec3::NEG_ONE, None),
+            ]),
+            Message::Inputs(vec![PlayerInput {
+                sequence: 9,
+                movement: Vec3::new(0.0, 1.0, -1.0),
+                yaw: 0.5,
+                pitch: -0.25,
+                dt: 0.05,
+            }]),
+            Message::Snapshot {
+                tick: 1 << 40,
+                acknowledged: 9,
+                entities: vec![(7, EntityState::new(Vec3::new(0.5, 6.0, 0.5)))],
+            },
+        ]
+    }
+
+    // Both ends of a loopback connection.
+    fn connections() -> (Connection, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (
+            Connection::new(client).unwrap(),
+            Connection::new(server).unwrap(),
+        )
+    }
+
+    // Flushes what's left to send while reading, big messages don't fit in
+    // the socket in one go.
+    fn exchange(from: &mut Connection, to: &mut Connection, count: usize) -> Result<Vec<Message>> {
+        let start = Instant::now();
+        let mut messages = vec![];
+        while messages.len() < count && start.elapsed() < HANDSHAKE_TIMEOUT {
+            from.flush()?;
+            messages.extend(to.receive()?);
+            thread::sleep(Duration::from_millis(1));
+        }
+        Ok(messages)
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        for message in messages() {
+            assert_eq!(decode_message(&encode_message(&message)).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn truncated_and_padded_messages_fail() {
+        // Chunks take whatever is left, any length of it.
+        for message in messages()
+            .into_iter()
+            .filter(|message| !matches!(message, Message::Chunk(_)))
+        {
+            let data = encode_message(&message);
+            for len in 0..data.len() {
+                assert!(
+                    decode_message(&data[..len]).is_err(),
+                    "{message:?} at {len}"
+                );
+            }
+            let mut padded = data.clone();
+            padded.push(0);
+            assert!(decode_message(&padded).is_err(), "{message:?} padded");
+        }
+        assert!(decode_message(&[0xff]).is_err());
+    }
+
+    #[test]
+    fn long_strings_get_cut_on_a_char_boundary() {
+        let name = "é".repeat(40_000);
+        let (mut client, mut server) = connections();
+        client.send(&Message::Hello {
+            version: PROTOCOL_VERSION,
+            name: name.clone(),
+        });
+        client.send(&Message::Position(IVec3::ONE));
+
+        // The stream stays in sync past the cut string.
+        let messages = exchange(&mut client, &mut server, 2).unwrap();
+        let Message::Hello { name: received, .. } = &messages[0] else {
+            panic!("expected a hello, got {:?}", messages[0]);
+        };
+        assert_eq!(received.len(), u16::MAX as usize - 1);
+        assert!(name.starts_with(received.as_str()));
+        assert_eq!(messages[1], Message::Position(IVec3::ONE));
+    }
+
+    #[test]
+    fn whole_messages_come_through_the_connection() {
+        let (mut client, mut server) = connections();
+        for message in messages() {
+            client.send(&message);
+        }
+        assert_eq!(
+            exchange(&mut client, &mut server, messages().len()).unwrap(),
+            messages()
+        );
+    }
+
+    // A server with a connection to it, past the handshake unless `name` is None.
+    fn server_with_client(name: Option<&str>) -> (Server, Connection) {
+        let server = Server::bind("127.0.0.1:0", World::new(WorldSeed::new(1)), 1)
+            .unwrap()
+            .with_spawn(Vec3::new(8.0, 40.0, 8.0));
+        let mut client =
+            Connection::new(TcpStream::connect(server.local_addr().unwrap()).unwrap()).unwrap();
+        if let Some(name) = name {
+            client.send(&Message::Hello {
+                version: PROTOCOL_VERSION,
+                name: name.to_string(),
+            });
+        }
+        client.flush().unwrap();
+        (server, client)
+    }
+
+    // Ticks until `done` or the handshake timeout.
+    fn tick_until<F: Fn(&Server) -> bool>(server: &mut Server, done: F) {
+        let start = Instant::now();
+        while !done(server) && start.elapsed() < HANDSHAKE_TIMEOUT {
+            server.tick();
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn connections_without_a_hello_get_dropped() {
+        let (mut server, _client) = server_with_client(None);
+        tick_until(&mut server, |server| !server.clients.is_empty());
+        server.tick();
+        assert_eq!(server.clients.len(), 1);
+
+        server.clients[0].accepted -= HANDSHAKE_TIMEOUT;
+        server.tick();
+        assert!(server.clients.is_empty());
+    }
+
+    #[test]
+    fn unknown_block_ids_get_rejected() {
+        let (mut server, mut client) = server_with_client(Some("player"));
+        let position = IVec3::new(8, 8, 8);
+        client.send(&Message::Position(IVec3::ZERO));
+        client.flush().unwrap();
+        tick_until(&mut server, |server| {
+            server.world().chunk(split_position(position).0).is_some()
+        });
+        client.send(&Message::SetBlock {
+            position,
+            id: Some(0xfff),
+        });
+        client.flush().unwrap();
+        tick_until(&mut server, |server| server.clients() == 0);
+
+        assert_eq!(server.clients(), 0);
+        let mut messages = vec![];
+        while let Ok(received) = client.receive() {
+            messages.extend(received);
+        }
+        assert_eq!(
+            messages.last(),
+            Some(&Message::Rejected("unknown block id 4095".to_string()))
+        );
+    }
+
+    #[test]
+    fn chunks_get_streamed_around_the_player() {
+        let (mut server, mut client) = server_with_client(Some("player"));
+        tick_until(&mut server, |server| server.clients() == 1);
+        client.send(&Message::Position(IVec3::new(1000, 0, -1000)));
+        client.flush().unwrap();
+        for _ in 0..20 {
+            server.tick();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let reach = 1 + MAX_CENTER_OFFSET;
+        let chunks = server.world().chunks().keys().copied().collect::<Vec<_>>();
+        assert!(!chunks.is_empty());
+        assert!(chunks
+            .iter()
+            .all(|position| position.x.abs() <= reach && position.z.abs() <= reach));
+
+        drop(client);
+        tick_until(&mut server, |server| server.world().chunks().is_empty());
+        assert!(server.world().chunks().is_empty());
+    }
+
+    #[test]
+    fn oversize_messages_get_rejected() {
+        let (mut client, mut server) = connections();
+        client
+            .outgoing
+            .extend_from_slice(&(MAX_MESSAGE_LEN as u32 + 1).to_le_bytes());
+        assert!(exchange(&mut client, &mut server, 1).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+// Cells in a 16³ chunk.
+pub const CELLS: usize = 4096;
+
+fn bits_for(entries: usize) -> u32 {
+    if entries <= 1 {
+        0
+    } else {
+        usize::BITS - (entries - 1).leading_zeros()
+    }
+}
+
+// Values of every cell of a chunk, stored as indices into a palette of the
+// distinct values in it. Indices take as few bits as the palette needs and are
+// packed in u64 words without crossing into the next one, a chunk made of a
+// single value stores nothing besides its palette.
+#[derive(Clone, Debug)]
+pub struct PalettedContainer {
+    palette: Vec<u32>,
+    // How many cells use each palette entry, unused entries get reused.
+    counts: Vec<u16>,
+    bits: u32,
+    words: Vec<u64>,
+}
+
+impl PalettedContainer {
+    pub fn new(value: u32) -> Self {
+        Self {
+            palette: vec![value],
+            counts: vec![CELLS as u16],
+            bits: 0,
+            words: vec![],
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> u32 {
+        self.palette[self.entry_at(idx)]
+    }
+
+    // Returns the value the cell had.
+    pub fn set(&mut self, idx: usize, value: u32) -> u32 {
+        let old_entry = self.entry_at(idx);
+        let old = self.palette[old_entry];
+        if old == value {
+            return old;
+        }
+
+        let entry = self.entry(value);
+        self.counts[old_entry] -= 1;
+        self.counts[entry] += 1;
+        if self.counts[entry] as usize == CELLS {
+            *self = Self::new(value);
+        } else {
+            self.set_entry(idx, entry);
+        }
+
+        old
+    }
+
+    // Cells holding a value that matches, without looking at the cells themselves.
+    pub fn count<F: Fn(u32) -> bool>(&self, matches: F) -> usize {
+        self.palette
+            .iter()
+            .zip(self.counts.iter())
+            .filter(|(&value, _)| matches(value))
+            .map(|(_, &count)| count as usize)
+            .sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..CELLS).map(|idx| self.get(idx))
+    }
+
+    fn entry_at(&self, idx: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+
+        let per_word = 64 / self.bits as usize;
+        let shift = (idx % per_word) as u32 * self.bits;
+        ((self.words[idx / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_entry(&mut self, idx: usize, entry: usize) {
+        let per_word = 64 / self.bits as usize;
+        let shift = (idx % per_word) as u32 * self.bits;
+        let mask = ((1 << self.bits) - 1) << shift;
+        let word = &mut self.words[idx / per_word];
+        *word = (*word & !mask) | (entry as u64) << shift;
+    }
+
+    // Palettes stay small, a linear search beats hashing them.
+    fn entry(&mut self, value: u32) -> usize {
+        if let Some(entry) = self.palette.iter().position(|&v| v == value) {
+            return entry;
+        }
+        if let Some(entry) = self.counts.iter().position(|&count| count == 0) {
+            self.palette[entry] = value;
+            return entry;
+        }
+
+        self.palette.push(value);
+        self.counts.push(0);
+        let bits = bits_for(self.palette.len());
+        if bits > self.bits {
+            self.repack(bits);
+        }
+        self.palette.len() - 1
+    }
+
+    fn repack(&mut self, bits: u32) {
+        let entries = (0..CELLS).map(|idx| self.entry_at(idx)).collect::<Vec<_>>();
+        self.bits = bits;
+        self.words = vec![0; CELLS.div_ceil(64 / bits as usize)];
+        for (idx, entry) in entries.into_iter().enumerate() {
+            self.set_entry(idx, entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_survive_growing_bit_widths() {
+        let mut container = PalettedContainer::new(0);
+        // 300 distinct values take the indices from 0 up to 9 bits.
+        for idx in 0..CELLS {
+            assert_eq!(container.set(idx, (idx % 300) as u32), 0);
+        }
+        assert_eq!(container.bits, 9);
+        for idx in 0..CELLS {
+            assert_eq!(container.get(idx), (idx % 300) as u32);
+        }
+        assert_eq!(container.count(|value| value == 7), CELLS / 300 + 1);
+    }
+
+    #[test]
+    fn unused_entries_get_reused() {
+        let mut container = PalettedContainer::new(0);
+        container.set(0, 1);
+        container.set(1, 2);
+        assert_eq!(container.palette.len(), 3);
+
+        // The only cell with 1 changes, its entry is free for the next value.
+        assert_eq!(container.set(0, 0), 1);
+        assert_eq!(container.count(|value| value == 1), 0);
+        container.set(2, 3);
+        assert_eq!(container.palette.len(), 3);
+        assert_eq!((container.get(1), container.get(2)), (2, 3));
+        assert_eq!(container.count(|value| value == 0), CELLS - 2);
+    }
+
+    #[test]
+    fn full_chunk_of_one_value_collapses() {
+        let mut container = PalettedContainer::new(0);
+        for idx in 0..CELLS {
+            container.set(idx, (idx % 5) as u32);
+        }
+        for idx in 0..CELLS {
+            container.set(idx, 9);
+        }
+
+        // Every cell counted in a single entry, which fits in a u16.
+        assert_eq!(container.count(|value| value == 9), CELLS);
+        assert_eq!(container.palette, vec![9]);
+        assert_eq!(container.counts, vec![CELLS as u16]);
+        assert_eq!(container.bits, 0);
+        assert!(container.words.is_empty());
+        assert!(container.iter().all(|value| value == 9));
+    }
+}
@@ -0,0 +1,363 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::{IVec3, Vec3, Vec3A};
+use std::mem;
+use wgpu::{
+    BindGroup, BindGroupLayout, Buffer, BufferAddress, BufferDescriptor, BufferUsages, Device,
+    PipelineLayoutDescriptor, PolygonMode, Queue, RenderPass, RenderPipeline,
+    ShaderModuleDescriptor, ShaderSource, VertexAttribute, VertexBufferLayout, VertexStepMode,
+};
+
+use crate::command_buffer::RenderLayer;
+use crate::post_process::HDR_FORMAT;
+use crate::texture::Texture;
+use crate::{create_render_pipeline, PipelineDesc};
+
+// Particles alive at once, emitters stop spawning while it's reached.
+pub const MAX_PARTICLES: usize = 16384;
+const GRAVITY: f32 = -16.0;
+// Speed kept by a particle bouncing off a block.
+const BOUNCE: f32 = 0.3;
+const DEBRIS_COUNT: u32 = 24;
+
+// A burst of particles and, for ambient effects, more of them at a steady rate
+// for a while. Velocities get spread out in random directions by up to `speed`.
+#[derive(Copy, Clone, Debug)]
+pub struct ParticleEmitter {
+    pub position: Vec3A,
+    // Half size of the box particles spawn in.
+    pub extent: Vec3A,
+    pub velocity: Vec3A,
+    pub speed: f32,
+    pub color: [f32; 4],
+    pub size: f32,
+    pub lifetime: f32,
+    // How much gravity pulls on the particles, 0.0 for floating ones.
+    pub gravity: f32,
+    pub collide: bool,
+    pub burst: u32,
+    // Particles per second and for how many seconds.
+    pub rate: f32,
+    pub duration: f32,
+}
+
+impl ParticleEmitter {
+    pub fn burst(position: Vec3A, count: u32, color: [f32; 4]) -> Self {
+        Self {
+            position,
+            extent: Vec3A::ZERO,
+            velocity: Vec3A::ZERO,
+            speed: 2.0,
+            color,
+            size: 0.1,
+            lifetime: 1.0,
+            gravity: 1.0,
+            collide: true,
+            burst: count,
+            rate: 0.0,
+            duration: 0.0,
+        }
+    }
+
+    pub fn continuous(position: Vec3A, rate: f32, duration: f32, color: [f32; 4]) -> Self {
+        Self {
+            rate,
+            duration,
+            ..Self::burst(position, 0, color)
+        }
+    }
+
+    // Bits of a block flying off it when it's broken or placed.
+    pub fn debris(block: IVec3, color: [f32; 4]) -> Self {
+        Self {
+            extent: Vec3A::splat(0.4),
+            velocity: Vec3A::new(0.0, 2.0, 0.0),
+            speed: 2.5,
+            size: 0.08,
+            lifetime: 0.8,
+            ..Self::burst(block.as_vec3a() + 0.5, DEBRIS_COUNT, color)
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Particle {
+    position: Vec3,
+    velocity: Vec3,
+    color: [f32; 4],
+    size: f32,
+    gravity: f32,
+    collide: bool,
+    age: f32,
+    lifetime: f32,
+}
+
+struct ActiveEmitter {
+    emitter: ParticleEmitter,
+    elapsed: f32,
+    // Fractional particles carried over to the next tick.
+    pending: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ParticleInstance {
+    // The w is the size.
+    position: [f32; 4],
+    velocity: [f32; 4],
+    color: [f32; 4],
+}
+
+impl ParticleInstance {
+    const ATTRIBUTES: [VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4];
+
+    fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: mem::size_of::<ParticleInstance>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// xorshift, particles only need to look random.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / u32::MAX as f32
+    }
+
+    fn signed(&mut self) -> f32 {
+        self.next() * 2.0 - 1.0
+    }
+
+    fn in_box(&mut self, extent: Vec3A) -> Vec3A {
+        Vec3A::new(self.signed(), self.signed(), self.signed()) * extent
+    }
+
+    // Points in a box until one lands in the sphere, or a burst spreads out as a cube.
+    fn in_sphere(&mut self, radius: f32) -> Vec3A {
+        loop {
+            let point = self.in_box(Vec3A::ONE);
+            if point.length_squared() <= 1.0 {
+                return point * radius;
+            }
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &Device,
+    camera_layout: &BindGroupLayout,
+    sample_count: u32,
+    reverse_z: bool,
+) -> RenderPipeline {
+    let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("particle_pipeline_layout"),
+        bind_group_layouts: &[camera_layout],
+        push_constant_ranges: &[],
+    });
+
+    create_render_pipeline(
+        device,
+        PipelineDesc {
+            layout: &layout,
+            color_format: HDR_FORMAT,
+            depth_format: Some(Texture::DEPTH_FORMAT),
+            vertex_layouts: &[ParticleInstance::desc()],
+            shader: ShaderModuleDescriptor {
+                label: Some("particle_shader"),
+                source: ShaderSource::Wgsl(include_str!("../shaders/particle.wgsl").into()),
+            },
+            layer: RenderLayer::Transparent,
+            polygon_mode: PolygonMode::Fill,
+            sample_count,
+            reverse_z,
+        },
+    )
+}
+
+// Particles simulated on the CPU every tick and drawn as camera facing quads,
+// one instance each.
+pub struct Particles {
+    particles: Vec<Particle>,
+    emitters: Vec<ActiveEmitter>,
+    rng: Rng,
+    pipeline: RenderPipeline,
+    buffer: Buffer,
+    capacity: usize,
+    instances: u32,
+}
+
+impl Particles {
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        sample_count: u32,
+        reverse_z: bool,
+    ) -> Self {
+        let capacity = 256;
+        Self {
+            particles: vec![],
+            emitters: vec![],
+            rng: Rng(0x9e3779b9),
+            pipeline: create_pipeline(device, camera_layout, sample_count, reverse_z),
+            buffer: Self::create_buffer(device, capacity),
+            capacity,
+            instances: 0,
+        }
+    }
+
+    fn create_buffer(device: &Device, capacity: usize) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Particle Buffer"),
+            size: (capacity * mem::size_of::<ParticleInstance>()) as BufferAddress,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // For when the sample count or depth direction changed.
+    pub fn rebuild_pipeline(
+        &mut self,
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        sample_count: u32,
+        reverse_z: bool,
+    ) {
+        self.pipeline = create_pipeline(device, camera_layout, sample_count, reverse_z);
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    pub fn emitters(&self) -> usize {
+        self.emitters.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.emitters.clear();
+    }
+
+    pub fn spawn(&mut self, emitter: ParticleEmitter) {
+        for _ in 0..emitter.burst {
+            self.emit(&emitter);
+        }
+        if emitter.rate > 0.0 && emitter.duration > 0.0 {
+            self.emitters.push(ActiveEmitter {
+                emitter,
+                elapsed: 0.0,
+                pending: 0.0,
+            });
+        }
+    }
+
+    fn emit(&mut self, emitter: &ParticleEmitter) {
+        if self.particles.len() >= MAX_PARTICLES {
+            return;
+        }
+
+        let position = emitter.position + self.rng.in_box(emitter.extent);
+        let velocity = emitter.velocity + self.rng.in_sphere(emitter.speed);
+        // Not all at once, so a burst doesn't vanish in a single tick.
+        let lifetime = emitter.lifetime * (0.75 + self.rng.next() * 0.5);
+        self.particles.push(Particle {
+            position: position.into(),
+            velocity: velocity.into(),
+            color: emitter.color,
+            size: emitter.size,
+            gravity: emitter.gravity,
+            collide: emitter.collide,
+            age: 0.0,
+            lifetime,
+        });
+    }
+
+    // Moves everything along by a tick, `solid` tells if a block stops particles.
+    pub fn tick<F: Fn(IVec3) -> bool>(&mut self, dt: f32, solid: F) {
+        let mut emitters = mem::take(&mut self.emitters);
+        for active in emitters.iter_mut() {
+            let dt = dt.min(active.emitter.duration - active.elapsed);
+            active.elapsed += dt;
+            active.pending += active.emitter.rate * dt;
+            while active.pending >= 1.0 {
+                active.pending -= 1.0;
+                self.emit(&active.emitter);
+            }
+        }
+        emitters.retain(|active| active.elapsed < active.emitter.duration);
+        self.emitters = emitters;
+
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            particle.velocity.y += GRAVITY * particle.gravity * dt;
+            let next = particle.position + particle.velocity * dt;
+            if particle.collide && solid(next.floor().as_ivec3()) {
+                // Bounce off whichever axis entered the block, sliding along the rest.
+                for axis in 0..3 {
+                    let mut moved = particle.position;
+                    moved[axis] = next[axis];
+                    if solid(moved.floor().as_ivec3()) {
+                        particle.velocity[axis] *= -BOUNCE;
+                    }
+                }
+                particle.velocity *= 1.0 - BOUNCE;
+            } else {
+                particle.position = next;
+            }
+            particle.age < particle.lifetime
+        });
+    }
+
+    pub fn upload(&mut self, device: &Device, queue: &Queue) {
+        self.instances = self.particles.len() as u32;
+        if self.particles.is_empty() {
+            return;
+        }
+        if self.particles.len() > self.capacity {
+            self.capacity = self.particles.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+
+        let instances = self
+            .particles
+            .iter()
+            .map(|particle| {
+                // Shrinks away over the last part of its life.
+                let fade =
+                    ((particle.lifetime - particle.age) / (particle.lifetime * 0.25)).min(1.0);
+                ParticleInstance {
+                    position: particle.position.extend(particle.size * fade).to_array(),
+                    velocity: particle.velocity.extend(0.0).to_array(),
+                    color: particle.color,
+                }
+            })
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.buffer, 0, cast_slice(&instances));
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        if self.instances == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.buffer.slice(..));
+        render_pass.draw(0..6, 0..self.instances);
+    }
+}
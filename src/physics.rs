@@ -0,0 +1,185 @@
+use glam::{IVec3, Vec3};
+use std::collections::HashMap;
+
+use crate::chunks::Chunk;
+pub use crate::frustum::Aabb;
+use crate::registry::block_info;
+use crate::world::World;
+use crate::world_edit::block_at;
+
+// Boxes closer than this to a block face count as touching it, so resting on
+// the ground doesn't read as overlapping it after float rounding.
+const SKIN: f32 = 1e-4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CollisionResult {
+    // How far the box actually moved and where it ended up.
+    pub offset: Vec3,
+    pub aabb: Aabb,
+    // Faces that stopped the movement, pointing away from the blocks hit.
+    pub normal: IVec3,
+}
+
+impl CollisionResult {
+    pub fn collided(&self) -> bool {
+        self.normal != IVec3::ZERO
+    }
+
+    pub fn on_ground(&self) -> bool {
+        self.normal.y > 0
+    }
+}
+
+// Range of block positions whose cells overlap `min..max` on one axis. Blocks
+// are centered on their positions, so a cell spans half a block either way.
+fn cell_range(min: f32, max: f32) -> (i32, i32) {
+    (
+        (min + 0.5 + SKIN).floor() as i32,
+        (max + 0.5 - SKIN).ceil() as i32 - 1,
+    )
+}
+
+// Walks the grid cell by cell along the ray until `hit` returns something, with
+// the cell, the face the ray went through and the distance to it. Blocks are
+// centered on their positions, so the grid is shifted by half a block.
+pub fn raycast_grid<T, F: FnMut(IVec3) -> Option<T>>(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    mut hit: F,
+) -> Option<(T, IVec3, IVec3, f32)> {
+    let direction = direction.try_normalize()?;
+    let start = origin + 0.5;
+    let mut cell = start.floor().as_ivec3();
+    let step = direction.signum().as_ivec3();
+    let delta = direction.recip().abs();
+    let mut next = Vec3::select(
+        direction.cmpgt(Vec3::ZERO),
+        cell.as_vec3() + 1.0 - start,
+        start - cell.as_vec3(),
+    ) * delta;
+    let mut normal = IVec3::ZERO;
+    let mut distance = 0.0;
+
+    while distance <= max_distance {
+        if let Some(found) = hit(cell) {
+            return Some((found, cell, normal, distance));
+        }
+
+        let axis = if next.x < next.y && next.x < next.z {
+            0
+        } else if next.y < next.z {
+            1
+        } else {
+            2
+        };
+        distance = next[axis];
+        next[axis] += delta[axis];
+        cell[axis] += step[axis];
+        normal = IVec3::ZERO;
+        normal[axis] = -step[axis];
+    }
+
+    None
+}
+
+// Collision queries against block data. Anything that can tell whether a
+// position is solid gets them, so actors don't need their own voxel lookups.
+pub trait BlockQuery {
+    fn is_solid(&self, position: IVec3) -> bool;
+
+    fn overlaps_solid(&self, aabb: &Aabb) -> bool {
+        let (min, max) = (aabb.min(), aabb.max());
+        let (x0, x1) = cell_range(min.x, max.x);
+        let (y0, y1) = cell_range(min.y, max.y);
+        let (z0, z1) = cell_range(min.z, max.z);
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                for z in z0..=z1 {
+                    if self.is_solid(IVec3::new(x, y, z)) {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    // Distance to the first solid block along the ray.
+    fn raycast_solid(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<f32> {
+        raycast_grid(origin, direction, max_distance, |cell| {
+            self.is_solid(cell).then_some(())
+        })
+        .map(|(_, _, _, distance)| distance)
+    }
+
+    // Moves the box by `velocity`, this step's displacement, one axis at a
+    // time, Y first so landing happens before sliding along the ground.
+    fn sweep_aabb(&self, aabb: &Aabb, velocity: Vec3) -> CollisionResult {
+        let (mut min, mut max) = (aabb.min(), aabb.max());
+        let mut offset = Vec3::ZERO;
+        let mut normal = IVec3::ZERO;
+
+        for axis in [1, 0, 2] {
+            let delta = velocity[axis];
+            if delta == 0.0 {
+                continue;
+            }
+
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            let (u0, u1) = cell_range(min[u], max[u]);
+            let (v0, v1) = cell_range(min[v], max[v]);
+            let blocked = |layer: i32| {
+                (u0..=u1).any(|a| {
+                    (v0..=v1).any(|b| {
+                        let mut position = IVec3::ZERO;
+                        position[axis] = layer;
+                        position[u] = a;
+                        position[v] = b;
+                        self.is_solid(position)
+                    })
+                })
+            };
+
+            let mut moved = delta;
+            if delta > 0.0 {
+                let first = (max[axis] + 0.5 - SKIN).ceil() as i32;
+                let last = (max[axis] + delta + 0.5).ceil() as i32 - 1;
+                if let Some(layer) = (first..=last).find(|layer| blocked(*layer)) {
+                    moved = (layer as f32 - 0.5 - max[axis]).max(0.0);
+                    normal[axis] = -1;
+                }
+            } else {
+                let first = (min[axis] - 0.5 + SKIN).floor() as i32;
+                let last = (min[axis] + delta - 0.5).floor() as i32 + 1;
+                if let Some(layer) = (last..=first).rev().find(|layer| blocked(*layer)) {
+                    moved = (layer as f32 + 0.5 - min[axis]).min(0.0);
+                    normal[axis] = 1;
+                }
+            }
+
+            min[axis] += moved;
+            max[axis] += moved;
+            offset[axis] = moved;
+        }
+
+        CollisionResult {
+            offset,
+            aabb: Aabb::from_params(min, max),
+            normal,
+        }
+    }
+}
+
+impl BlockQuery for HashMap<IVec3, Chunk> {
+    fn is_solid(&self, position: IVec3) -> bool {
+        block_at(self, position).is_some_and(|id| block_info(id).is_solid())
+    }
+}
+
+impl BlockQuery for World {
+    fn is_solid(&self, position: IVec3) -> bool {
+        self.chunks().is_solid(position)
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use wgpu::{
+    BindGroupLayout, Device, Id, PipelineLayout, PipelineLayoutDescriptor, PolygonMode,
+    PushConstantRange, RenderPipeline, VertexBufferLayout,
+};
+
+use crate::command_buffer::RenderLayer;
+
+// Everything a model pipeline is created from. Shaders are compared by source,
+// layouts by identity, the ones from the bind group cache are already shared.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub shader: &'static str,
+    pub bind_group_layouts: Vec<Id<BindGroupLayout>>,
+    pub vertex_layouts: Vec<VertexBufferLayout<'static>>,
+    pub push_constant_ranges: Vec<PushConstantRange>,
+    pub layer: RenderLayer,
+    pub polygon_mode: PolygonMode,
+    pub sample_count: u32,
+    pub reverse_z: bool,
+}
+
+type LayoutKey = (Vec<Id<BindGroupLayout>>, Vec<PushConstantRange>);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PipelineCacheStats {
+    pub pipelines: usize,
+    pub layouts: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+// Pipelines and their layouts by what they were created from, so models asking
+// for the same pipeline share it. Pipelines no model holds anymore go away on
+// `prune`, along with the layouts only they used.
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: HashMap<PipelineKey, Arc<RenderPipeline>>,
+    layouts: HashMap<LayoutKey, PipelineLayout>,
+    hits: u64,
+    misses: u64,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `create` only runs on a miss, with the layout for the key's bind group layouts.
+    pub fn pipeline<F>(
+        &mut self,
+        device: &Device,
+        key: PipelineKey,
+        bind_group_layouts: &[&BindGroupLayout],
+        create: F,
+    ) -> Arc<RenderPipeline>
+    where
+        F: FnOnce(&PipelineLayout) -> RenderPipeline,
+    {
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            self.hits += 1;
+            return pipeline.clone();
+        }
+
+        self.misses += 1;
+        let layout = self
+            .layouts
+            .entry((
+                key.bind_group_layouts.clone(),
+                key.push_constant_ranges.clone(),
+            ))
+            .or_insert_with(|| {
+                device.create_pipeline_layout(&PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts,
+                    push_constant_ranges: &key.push_constant_ranges,
+                })
+            });
+        let pipeline = Arc::new(create(layout));
+        self.pipelines.insert(key, pipeline.clone());
+
+        pipeline
+    }
+
+    pub fn prune(&mut self) {
+        self.pipelines
+            .retain(|_, pipeline| Arc::strong_count(pipeline) > 1);
+        let pipelines = &self.pipelines;
+        self.layouts.retain(|(layouts, ranges), _| {
+            pipelines.keys().any(|key| {
+                &key.bind_group_layouts == layouts && &key.push_constant_ranges == ranges
+            })
+        });
+    }
+
+    pub fn stats(&self) -> PipelineCacheStats {
+        PipelineCacheStats {
+            pipelines: self.pipelines.len(),
+            layouts: self.layouts.len(),
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    AddressMode, BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingResource, BindingType, Buffer,
+    BufferBindingType, BufferUsages, Color, CommandEncoder, Device, Extent3d, FilterMode, LoadOp,
+    Operations, PipelineLayoutDescriptor, PolygonMode, Queue, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, Sampler, SamplerBindingType, SamplerDescriptor,
+    ShaderModuleDescriptor, ShaderSource, ShaderStages, StoreOp, SurfaceConfiguration,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureView, TextureViewDescriptor, TextureViewDimension,
+};
+
+use crate::command_buffer::RenderLayer;
+use crate::{create_render_pipeline, PipelineDesc};
+
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Tonemapping {
+    None,
+    Reinhard,
+    Aces,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PostProcessSettings {
+    pub tonemapping: Tonemapping,
+    pub exposure: f32,
+    pub fxaa: bool,
+    // 0.0 disables the vignette, 1.0 turns the corners black.
+    pub vignette: f32,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            tonemapping: Tonemapping::Aces,
+            exposure: 1.0,
+            fxaa: true,
+            vignette: 0.25,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct PostProcessUniform {
+    exposure: f32,
+    tonemapping: u32,
+    fxaa: u32,
+    vignette: f32,
+    texel_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+impl PostProcessUniform {
+    fn new(settings: &PostProcessSettings, width: u32, height: u32) -> Self {
+        Self {
+            exposure: settings.exposure,
+            tonemapping: settings.tonemapping as u32,
+            fxaa: settings.fxaa as u32,
+            vignette: settings.vignette,
+            texel_size: [1.0 / width as f32, 1.0 / height as f32],
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// The scene is drawn into an HDR texture, this pass resolves it into the output
+// format applying tonemapping first and then the optional FXAA and vignette.
+pub struct PostProcess {
+    settings: PostProcessSettings,
+    bypass: bool,
+    width: u32,
+    height: u32,
+    hdr_view: TextureView,
+    sampler: Sampler,
+    buffer: Buffer,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+}
+
+impl PostProcess {
+    pub fn new(device: &Device, config: &SurfaceConfiguration) -> Self {
+        let settings = PostProcessSettings::default();
+        let hdr_view = Self::create_hdr_view(device, config);
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("post_process_sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Post Process Buffer"),
+            contents: cast_slice(&[PostProcessUniform::new(
+                &settings,
+                config.width,
+                config.height,
+            )]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("post_process_bind_group_layout"),
+        });
+
+        let bind_group = Self::create_bind_group(device, &layout, &hdr_view, &sampler, &buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("post_process_pipeline_layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_render_pipeline(
+            device,
+            PipelineDesc {
+                layout: &pipeline_layout,
+                color_format: config.format,
+                depth_format: None,
+                vertex_layouts: &[],
+                shader: ShaderModuleDescriptor {
+                    label: Some("post_process_shader"),
+                    source: ShaderSource::Wgsl(include_str!("../shaders/post_process.wgsl").into()),
+                },
+                layer: RenderLayer::Opaque,
+                polygon_mode: PolygonMode::Fill,
+                sample_count: 1,
+                reverse_z: false,
+            },
+        );
+
+        Self {
+            settings,
+            bypass: false,
+            width: config.width,
+            height: config.height,
+            hdr_view,
+            sampler,
+            buffer,
+            layout,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    fn create_hdr_view(device: &Device, config: &SurfaceConfiguration) -> TextureView {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("hdr_target"),
+            size: Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        texture.create_view(&TextureViewDescriptor::default())
+    }
+
+    fn create_bind_group(
+        device: &Device,
+        layout: &BindGroupLayout,
+        hdr_view: &TextureView,
+        sampler: &Sampler,
+        buffer: &Buffer,
+    ) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(hdr_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("post_process_bind_group"),
+        })
+    }
+
+    pub fn resize(&mut self, device: &Device, queue: &Queue, config: &SurfaceConfiguration) {
+        self.width = config.width;
+        self.height = config.height;
+        self.hdr_view = Self::create_hdr_view(device, config);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            &self.hdr_view,
+            &self.sampler,
+            &self.buffer,
+        );
+        self.write_uniform(queue);
+    }
+
+    pub fn hdr_view(&self) -> &TextureView {
+        &self.hdr_view
+    }
+
+    pub fn settings(&self) -> &PostProcessSettings {
+        &self.settings
+    }
+
+    pub fn set_settings(&mut self, queue: &Queue, settings: PostProcessSettings) {
+        self.settings = settings;
+        self.write_uniform(queue);
+    }
+
+    // Debug views need their exact colors on screen, so every effect gets skipped.
+    pub fn set_bypass(&mut self, queue: &Queue, bypass: bool) {
+        if self.bypass != bypass {
+            self.bypass = bypass;
+            self.write_uniform(queue);
+        }
+    }
+
+    fn write_uniform(&self, queue: &Queue) {
+        let settings = if self.bypass {
+            PostProcessSettings {
+                tonemapping: Tonemapping::None,
+                exposure: 1.0,
+                fxaa: false,
+                vignette: 0.0,
+            }
+        } else {
+            self.settings
+        };
+
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            cast_slice(&[PostProcessUniform::new(&settings, self.width, self.height)]),
+        );
+    }
+
+    pub fn render(&self, encoder: &mut CommandEncoder, output: &TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Post Process Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, MapMode, QuerySet,
+    QuerySetDescriptor, QueryType, Queue, RenderPassTimestampWrites,
+    QUERY_RESOLVE_BUFFER_ALIGNMENT,
+};
+
+// Frames kept for the min/avg/max and the graph.
+const PROFILER_HISTORY: usize = 120;
+const GRAPH_WIDTH: usize = 60;
+const GRAPH_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// The graph tops out at this many milliseconds unless a frame took longer.
+const GRAPH_SCALE: f32 = 1000.0 / 30.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FrameStage {
+    Update,
+    Cull,
+    Record,
+    Submit,
+}
+
+impl FrameStage {
+    pub const ALL: [FrameStage; 4] = [
+        FrameStage::Update,
+        FrameStage::Cull,
+        FrameStage::Record,
+        FrameStage::Submit,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameStage::Update => "update",
+            FrameStage::Cull => "cull",
+            FrameStage::Record => "record",
+            FrameStage::Submit => "submit",
+        }
+    }
+}
+
+// Milliseconds spent on a frame, in total and in each stage on the CPU, and on
+// the GPU when timestamp queries are there.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameTimings {
+    pub frame: f32,
+    pub stages: [f32; 4],
+    pub gpu: Option<f32>,
+}
+
+impl FrameTimings {
+    pub fn stage(&self, stage: FrameStage) -> f32 {
+        self.stages[stage as usize]
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameSummary {
+    pub min: f32,
+    pub avg: f32,
+    pub max: f32,
+}
+
+// What the last frame drew, for seeing what meshing and culling changes do.
+// With GPU culling the CPU doesn't know which models got culled, they all
+// count as drawn, and so do their triangles. Models other viewports draw count
+// again.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub models: usize,
+    pub models_drawn: usize,
+    pub models_culled: usize,
+    pub draw_calls: u32,
+    pub triangles: u64,
+    pub buffer_uploads: usize,
+    pub upload_bytes: u64,
+    pub timings: FrameTimings,
+}
+
+impl RenderStats {
+    pub fn add_draw(&mut self, vertices: u32, instances: u32) {
+        self.draw_calls += 1;
+        self.triangles += (vertices / 3) as u64 * instances as u64;
+    }
+
+    pub fn overlay_text(&self) -> String {
+        format!(
+            "models {} drawn / {} culled of {}\n{} draws, {} triangles\n{} uploads, {:.1} KiB",
+            self.models_drawn,
+            self.models_culled,
+            self.models,
+            self.draw_calls,
+            self.triangles,
+            self.buffer_uploads,
+            self.upload_bytes as f32 / 1024.0
+        )
+    }
+}
+
+pub struct Profiler {
+    current: FrameTimings,
+    history: VecDeque<FrameTimings>,
+    gpu: Option<f32>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            current: FrameTimings::default(),
+            history: VecDeque::with_capacity(PROFILER_HISTORY),
+            gpu: None,
+        }
+    }
+
+    pub fn record(&mut self, stage: FrameStage, time: Duration) {
+        self.current.stages[stage as usize] += time.as_secs_f32() * 1000.0;
+    }
+
+    pub fn set_frame_time(&mut self, time: Duration) {
+        self.current.frame = time.as_secs_f32() * 1000.0;
+    }
+
+    // GPU times arrive a few frames late, the latest one sticks to every frame
+    // until a newer one comes.
+    pub fn set_gpu_time(&mut self, ms: f32) {
+        self.gpu = Some(ms);
+    }
+
+    pub fn end_frame(&mut self) {
+        self.current.gpu = self.gpu;
+        if self.history.len() == PROFILER_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.current);
+        self.current = FrameTimings::default();
+    }
+
+    pub fn history(&self) -> &VecDeque<FrameTimings> {
+        &self.history
+    }
+
+    pub fn latest(&self) -> Option<&FrameTimings> {
+        self.history.back()
+    }
+
+    pub fn summary(&self) -> FrameSummary {
+        if self.history.is_empty() {
+            return FrameSummary::default();
+        }
+
+        let frames = self.history.iter().map(|timings| timings.frame);
+        FrameSummary {
+            min: frames.clone().fold(f32::INFINITY, f32::min),
+            avg: frames.clone().sum::<f32>() / self.history.len() as f32,
+            max: frames.fold(0.0, f32::max),
+        }
+    }
+
+    // Frame times of the last frames as a row of bars, oldest first.
+    pub fn graph(&self) -> String {
+        let frames = self
+            .history
+            .iter()
+            .rev()
+            .take(GRAPH_WIDTH)
+            .map(|timings| timings.frame)
+            .collect::<Vec<_>>();
+        let scale = frames.iter().copied().fold(GRAPH_SCALE, f32::max);
+        frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let level = (frame / scale * GRAPH_BARS.len() as f32) as usize;
+                GRAPH_BARS[level.min(GRAPH_BARS.len() - 1)]
+            })
+            .collect()
+    }
+
+    pub fn overlay_text(&self) -> String {
+        let summary = self.summary();
+        let mut text = format!(
+            "frame {:.2} min / {:.2} avg / {:.2} max ms\n",
+            summary.min, summary.avg, summary.max
+        );
+        if let Some(latest) = self.latest() {
+            let stages = FrameStage::ALL
+                .iter()
+                .map(|stage| format!("{} {:.2}", stage.name(), latest.stage(*stage)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            text.push_str(&format!("cpu {stages} ms\n"));
+            match latest.gpu {
+                Some(gpu) => text.push_str(&format!("gpu {gpu:.2} ms\n")),
+                None => text.push_str("gpu n/a\n"),
+            }
+        }
+        text.push_str(&self.graph());
+        text
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Two timestamps around the frame's passes, resolved into a buffer that gets
+// read back without waiting on it. While a read back is in flight no new
+// timestamps get written, so some frames go unmeasured.
+pub struct GpuTimer {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readback_buffer: Buffer,
+    // Nanoseconds per timestamp tick.
+    period: f32,
+    written: bool,
+    pending: bool,
+    mapped: Arc<AtomicBool>,
+}
+
+impl GpuTimer {
+    pub fn new(device: &Device, queue: &Queue) -> Self {
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("gpu_timer_queries"),
+            ty: QueryType::Timestamp,
+            count: 2,
+        });
+        let resolve_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("gpu_timer_resolve"),
+            size: QUERY_RESOLVE_BUFFER_ALIGNMENT,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("gpu_timer_readback"),
+            size: QUERY_RESOLVE_BUFFER_ALIGNMENT,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            written: false,
+            pending: false,
+            mapped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // For the first pass of the frame, none while a read back is in flight.
+    pub fn begin_writes(&self) -> Option<RenderPassTimestampWrites<'_>> {
+        (!self.pending).then_some(RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: None,
+        })
+    }
+
+    // For the last pass of the frame.
+    pub fn end_writes(&self) -> Option<RenderPassTimestampWrites<'_>> {
+        (!self.pending).then_some(RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: None,
+            end_of_pass_write_index: Some(1),
+        })
+    }
+
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        if self.pending {
+            return;
+        }
+
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            QUERY_RESOLVE_BUFFER_ALIGNMENT,
+        );
+        self.written = true;
+    }
+
+    // After the encoder with the resolve got submitted.
+    pub fn after_submit(&mut self) {
+        if !self.written {
+            return;
+        }
+
+        self.written = false;
+        self.pending = true;
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                mapped.store(result.is_ok(), Ordering::Release);
+            });
+    }
+
+    // Milliseconds between the two timestamps, once the read back finished.
+    pub fn poll(&mut self, device: &Device) -> Option<f32> {
+        if !self.pending {
+            return None;
+        }
+
+        device.poll(wgpu::Maintain::Poll);
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return None;
+        }
+
+        let timestamps = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data[..16]);
+            (timestamps[0], timestamps[1])
+        };
+        self.readback_buffer.unmap();
+        self.pending = false;
+
+        let ticks = timestamps.1.saturating_sub(timestamps.0);
+        Some(ticks as f32 * self.period / 1_000_000.0)
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Result};
+use glam::IVec3;
+use std::collections::HashMap;
+
+use crate::block_ticks::{GravityBehavior, TickContext};
+use crate::chunks::{GRAVEL_ID, LAMP_ID, LEAVES_ID, LOG_ID, ORE_ID, SAND_ID, STONE_ID, WATER_ID};
+use crate::fluid::FluidBehavior;
+
+#[derive(Copy, Clone, Debug)]
+pub struct BlockInfo {
+    pub id: u16,
+    pub name: &'static str,
+    // Fluids spread with fluid::FluidBehavior and never collide with anything.
+    pub fluid: bool,
+    // Gravity blocks turn into falling blocks as soon as nothing holds them up.
+    pub gravity: bool,
+    // Block light it gives off, up to MAX_LIGHT.
+    pub light: u8,
+    // Light it takes off on top of the usual step, for blocks light goes through.
+    pub filter: u8,
+    // What its debris particles look like.
+    pub color: [f32; 4],
+}
+
+impl BlockInfo {
+    const fn new(id: u16, name: &'static str, color: [f32; 4]) -> Self {
+        Self {
+            id,
+            name,
+            fluid: false,
+            gravity: false,
+            light: 0,
+            filter: 0,
+            color,
+        }
+    }
+
+    const fn fluid(mut self) -> Self {
+        self.fluid = true;
+        self
+    }
+
+    const fn gravity(mut self) -> Self {
+        self.gravity = true;
+        self
+    }
+
+    const fn emits(mut self, light: u8) -> Self {
+        self.light = light;
+        self
+    }
+
+    const fn filters(mut self, filter: u8) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn is_solid(&self) -> bool {
+        !self.fluid
+    }
+}
+
+pub const BLOCKS: [BlockInfo; 8] = [
+    BlockInfo::new(STONE_ID, "stone", [0.5, 0.5, 0.5, 1.0]),
+    BlockInfo::new(WATER_ID, "water", [0.2, 0.4, 0.8, 0.6])
+        .fluid()
+        .filters(2),
+    BlockInfo::new(ORE_ID, "ore", [0.6, 0.45, 0.35, 1.0]),
+    BlockInfo::new(SAND_ID, "sand", [0.85, 0.78, 0.55, 1.0]).gravity(),
+    BlockInfo::new(GRAVEL_ID, "gravel", [0.45, 0.42, 0.4, 1.0]).gravity(),
+    BlockInfo::new(LAMP_ID, "lamp", [1.0, 0.85, 0.55, 1.0]).emits(14),
+    BlockInfo::new(LOG_ID, "log", [0.45, 0.3, 0.18, 1.0]),
+    BlockInfo::new(LEAVES_ID, "leaves", [0.25, 0.5, 0.2, 1.0]),
+];
+
+// Unknown ids behave like plain solid blocks.
+const UNKNOWN: BlockInfo = BlockInfo::new(u16::MAX, "unknown", [1.0, 0.0, 1.0, 1.0]);
+
+pub fn block_info(id: u16) -> &'static BlockInfo {
+    BLOCKS.iter().find(|info| info.id == id).unwrap_or(&UNKNOWN)
+}
+
+pub fn is_registered(id: u16) -> bool {
+    BLOCKS.iter().any(|info| info.id == id)
+}
+
+// Custom logic of a block, run on the block ticks of the cells it's in.
+pub trait BlockBehavior: Send + Sync {
+    // Fixed ticks between a cell getting scheduled and its tick.
+    fn delay(&self) -> u32 {
+        1
+    }
+
+    fn tick(&self, ctx: &mut TickContext<'_>, position: IVec3);
+}
+
+// Behaviors of the blocks that have one, by id. Starts out with the built-in
+// ones.
+pub struct BehaviorRegistry {
+    behaviors: HashMap<u16, Box<dyn BlockBehavior>>,
+}
+
+impl BehaviorRegistry {
+    pub fn new() -> Self {
+        let mut behaviors = HashMap::new();
+        for info in BLOCKS.iter().filter(|info| info.gravity) {
+            behaviors.insert(info.id, Box::new(GravityBehavior) as Box<dyn BlockBehavior>);
+        }
+        for info in BLOCKS.iter().filter(|info| info.fluid) {
+            behaviors.insert(info.id, Box::new(FluidBehavior::new(info.id)));
+        }
+
+        Self { behaviors }
+    }
+
+    pub fn register(&mut self, id: u16, behavior: Box<dyn BlockBehavior>) -> Result<()> {
+        if self.behaviors.contains_key(&id) {
+            return Err(anyhow!("block {id} already has a behavior"));
+        }
+
+        self.behaviors.insert(id, behavior);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u16) -> Option<&dyn BlockBehavior> {
+        self.behaviors.get(&id).map(Box::as_ref)
+    }
+}
+
+impl Default for BehaviorRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,342 @@
+use glam::{Vec3, Vec3A};
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::FRAC_PI_2;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::action_map::{MOVE_BACKWARD, MOVE_DOWN, MOVE_FORWARD, MOVE_LEFT, MOVE_RIGHT, MOVE_UP};
+use crate::app::Actor;
+use crate::command_buffer::{CommandBuffer, NCommandUpdate};
+use crate::input::InputState;
+use crate::physics::{Aabb, BlockQuery};
+use crate::world_view::WorldView;
+
+pub const PLAYER_SPEED: f32 = 4.0;
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const EYE_HEIGHT: f32 = 1.6;
+// Remote entities are shown this many seconds in the past, so there's usually
+// a snapshot on either side of what's shown to interpolate between.
+pub const INTERPOLATION_DELAY: f64 = 0.1;
+// Snapshots kept per remote entity.
+const SNAPSHOT_HISTORY: usize = 32;
+// Inputs the server hasn't acknowledged past this many get dropped, oldest first.
+const MAX_PENDING_INPUTS: usize = 128;
+// Longer steps get cut down to this, so claiming them doesn't move anyone faster.
+const MAX_INPUT_DT: f32 = 0.25;
+// How far the shown time may drift from the snapshots before it jumps back.
+const MAX_CLOCK_DRIFT: f64 = 0.5;
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.0001;
+
+// Where an entity is and where it looks. Players stand on `position`, the
+// middle of the bottom of their box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EntityState {
+    pub position: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl EntityState {
+    pub fn new(position: Vec3) -> Self {
+        Self {
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let half = Vec3::new(PLAYER_WIDTH, 0.0, PLAYER_WIDTH) / 2.0;
+        Aabb::from_params(
+            self.position - half,
+            self.position + half + Vec3::Y * PLAYER_HEIGHT,
+        )
+    }
+
+    pub fn eye(&self) -> Vec3 {
+        self.position + Vec3::Y * EYE_HEIGHT
+    }
+
+    pub fn lerp(&self, other: &EntityState, t: f32) -> Self {
+        Self {
+            position: self.position.lerp(other.position, t),
+            yaw: self.yaw + (other.yaw - self.yaw) * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+        }
+    }
+}
+
+// One step of a player's movement. `movement` goes right, up and forward from
+// where it looks, each from -1 to 1.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PlayerInput {
+    pub sequence: u32,
+    pub movement: Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub dt: f32,
+}
+
+// Flying movement stopped by blocks. The server and the client predicting the
+// player both run it, so the same inputs end up in the same place on both.
+pub fn step_player<Q: BlockQuery + ?Sized>(
+    state: &mut EntityState,
+    input: &PlayerInput,
+    world: &Q,
+) {
+    state.yaw = input.yaw;
+    state.pitch = input.pitch.clamp(-MAX_PITCH, MAX_PITCH);
+
+    let movement = input.movement.clamp(Vec3::NEG_ONE, Vec3::ONE);
+    let (yaw_sin, yaw_cos) = state.yaw.sin_cos();
+    let forward = Vec3::new(yaw_cos, 0.0, yaw_sin);
+    let right = Vec3::new(-yaw_sin, 0.0, yaw_cos);
+    let velocity =
+        (right * movement.x + Vec3::Y * movement.y + forward * movement.z) * PLAYER_SPEED;
+    let dt = input.dt.clamp(0.0, MAX_INPUT_DT);
+    state.position += world.sweep_aabb(&state.aabb(), velocity * dt).offset;
+}
+
+// Snapshots of a remote entity by server time in seconds.
+#[derive(Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<(f64, EntityState)>,
+}
+
+impl SnapshotBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Snapshots older than the newest one came in out of order and get dropped.
+    pub fn push(&mut self, time: f64, state: EntityState) {
+        if self.latest_time().is_some_and(|latest| time <= latest) {
+            return;
+        }
+
+        self.snapshots.push_back((time, state));
+        if self.snapshots.len() > SNAPSHOT_HISTORY {
+            self.snapshots.pop_front();
+        }
+    }
+
+    pub fn latest_time(&self) -> Option<f64> {
+        self.snapshots.back().map(|(time, _)| *time)
+    }
+
+    // Interpolated between the snapshots around `time`. Before the first or
+    // after the last it stays at that one instead of guessing.
+    pub fn sample(&self, time: f64) -> Option<EntityState> {
+        let after = self
+            .snapshots
+            .iter()
+            .position(|(snapshot_time, _)| *snapshot_time >= time);
+        match after {
+            Some(0) => self.snapshots.front().map(|(_, state)| *state),
+            Some(idx) => {
+                let (from_time, from) = self.snapshots[idx - 1];
+                let (to_time, to) = self.snapshots[idx];
+                let t = (time - from_time) / (to_time - from_time);
+                Some(from.lerp(&to, t as f32))
+            }
+            None => self.snapshots.back().map(|(_, state)| *state),
+        }
+    }
+}
+
+// Remote entities shown INTERPOLATION_DELAY behind the newest snapshots, on a
+// clock running with the frames in between them.
+#[derive(Default)]
+pub struct Interpolation {
+    entities: HashMap<u32, SnapshotBuffer>,
+    time: Option<f64>,
+}
+
+impl Interpolation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Every entity the server knows about at `time`, entities missing from it
+    // are gone.
+    pub fn push_snapshot(&mut self, time: f64, entities: &[(u32, EntityState)]) {
+        self.entities
+            .retain(|id, _| entities.iter().any(|(entity, _)| entity == id));
+        for (id, state) in entities {
+            self.entities.entry(*id).or_default().push(time, *state);
+        }
+
+        let target = time - INTERPOLATION_DELAY;
+        if self
+            .time
+            .is_none_or(|shown| (shown - target).abs() > MAX_CLOCK_DRIFT)
+        {
+            self.time = Some(target);
+        }
+    }
+
+    pub fn advance(&mut self, dt: Duration) {
+        if let Some(time) = &mut self.time {
+            *time += dt.as_secs_f64();
+        }
+    }
+
+    pub fn entities(&self) -> Vec<(u32, EntityState)> {
+        let Some(time) = self.time else {
+            return vec![];
+        };
+
+        self.entities
+            .iter()
+            .filter_map(|(id, buffer)| Some((*id, buffer.sample(time)?)))
+            .collect()
+    }
+}
+
+// The local player, moved by its own inputs right away instead of waiting for
+// the server. Inputs stay around until the server acknowledges them, when its
+// state for the player comes in the rest get replayed on top of it.
+pub struct Prediction {
+    state: EntityState,
+    next_sequence: u32,
+    pending: VecDeque<PlayerInput>,
+    outgoing: Vec<PlayerInput>,
+    // The server's state with the last input it applied to get there.
+    confirmed: Option<(u32, EntityState)>,
+}
+
+impl Prediction {
+    pub fn new(state: EntityState) -> Self {
+        Self {
+            state,
+            next_sequence: 1,
+            pending: VecDeque::new(),
+            outgoing: vec![],
+            confirmed: None,
+        }
+    }
+
+    pub fn state(&self) -> EntityState {
+        self.state
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Moves the player by a new input and queues it for the server.
+    pub fn apply<Q: BlockQuery + ?Sized>(
+        &mut self,
+        movement: Vec3,
+        yaw: f32,
+        pitch: f32,
+        dt: f32,
+        world: &Q,
+    ) -> PlayerInput {
+        self.reconcile(world);
+
+        let input = PlayerInput {
+            sequence: self.next_sequence,
+            movement,
+            yaw,
+            pitch,
+            dt,
+        };
+        self.next_sequence += 1;
+        step_player(&mut self.state, &input, world);
+        self.pending.push_back(input);
+        if self.pending.len() > MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+        self.outgoing.push(input);
+        input
+    }
+
+    pub fn take_outgoing(&mut self) -> Vec<PlayerInput> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    // The state the server sent, after the input with `sequence`. Applied on
+    // the next input, which has the world to replay the rest in.
+    pub fn confirm(&mut self, sequence: u32, state: EntityState) {
+        self.confirmed = Some((sequence, state));
+    }
+
+    fn reconcile<Q: BlockQuery + ?Sized>(&mut self, world: &Q) {
+        let Some((sequence, state)) = self.confirmed.take() else {
+            return;
+        };
+
+        self.pending.retain(|input| input.sequence > sequence);
+        self.state = state;
+        for input in self.pending.iter() {
+            step_player(&mut self.state, input, world);
+        }
+    }
+}
+
+// Moves the camera with a predicted player, see net::Client::prediction. The
+// camera gets moved to the player's eyes rather than teleported there, so it
+// stays interpolated between fixed ticks, corrections from the server included.
+pub struct PlayerController {
+    id: Uuid,
+    prediction: Arc<Mutex<Prediction>>,
+    sensitivity: f32,
+}
+
+impl PlayerController {
+    pub fn new(prediction: Arc<Mutex<Prediction>>, sensitivity: f32) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            prediction,
+            sensitivity,
+        }
+    }
+}
+
+impl Actor for PlayerController {
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    fn update(
+        &mut self,
+        dt: &Duration,
+        inputs: &InputState,
+        world: &WorldView,
+    ) -> CommandBuffer<NCommandUpdate> {
+        let mut buffer = CommandBuffer::new();
+        let dt = dt.as_secs_f32();
+        let amount = |positive, negative| {
+            inputs.is_action_pressed(positive) as u8 as f32
+                - inputs.is_action_pressed(negative) as u8 as f32
+        };
+        let movement = Vec3::new(
+            amount(MOVE_RIGHT, MOVE_LEFT),
+            amount(MOVE_UP, MOVE_DOWN),
+            amount(MOVE_FORWARD, MOVE_BACKWARD),
+        );
+        let delta = inputs.mouse_delta();
+        let (turn, tilt) = (
+            delta.0 * self.sensitivity * dt,
+            -delta.1 * self.sensitivity * dt,
+        );
+
+        let camera = world.camera();
+        let mut prediction = self.prediction.lock().unwrap();
+        prediction.apply(movement, camera.yaw + turn, camera.pitch + tilt, dt, world);
+        let state = prediction.state();
+        drop(prediction);
+
+        buffer.push(NCommandUpdate::MoveCamera(
+            Vec3A::from(state.eye()) - camera.position,
+        ));
+        buffer.push(NCommandUpdate::RotateCamera(
+            state.yaw - camera.yaw,
+            state.pitch - camera.pitch,
+        ));
+        buffer
+    }
+}
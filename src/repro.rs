@@ -0,0 +1,125 @@
+use anyhow::Result;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use web_time::Instant;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+// Log lines kept for reproduction bundles.
+const LOG_TAIL_LINES: usize = 2000;
+// The tail keeps info and up even when RUST_LOG is quieter than that.
+const LOG_TAIL_LEVEL: LevelFilter = LevelFilter::Info;
+
+static LOG_TAIL: OnceLock<Mutex<VecDeque<String>>> = OnceLock::new();
+
+// env_logger's output, with the latest lines also kept in memory.
+struct TailLogger {
+    inner: env_logger::Logger,
+    started: Instant,
+}
+
+impl Log for TailLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata) || metadata.level() <= LOG_TAIL_LEVEL
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.matches(record) {
+            self.inner.log(record);
+        }
+        if record.level() > LOG_TAIL_LEVEL {
+            return;
+        }
+
+        let line = format!(
+            "{:.3} {} {}: {}",
+            self.started.elapsed().as_secs_f32(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if let Some(tail) = LOG_TAIL.get() {
+            let mut tail = tail.lock().unwrap();
+            if tail.len() == LOG_TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// Sets up env_logger as usual, call instead of env_logger::init.
+pub fn init_logging() {
+    install_logger(env_logger::Builder::from_default_env());
+}
+
+// Like init_logging, with RUST_LOG style filters on top of RUST_LOG's, like
+// `render=debug,chunks=warn` to pick what each subsystem logs.
+pub fn init_logging_with(filters: &str) {
+    let mut builder = env_logger::Builder::from_default_env();
+    builder.parse_filters(filters);
+    install_logger(builder);
+}
+
+fn install_logger(mut builder: env_logger::Builder) {
+    let inner = builder.build();
+    let max_level = inner.filter().max(LOG_TAIL_LEVEL);
+    LOG_TAIL.get_or_init(|| Mutex::new(VecDeque::with_capacity(LOG_TAIL_LINES)));
+    let logger = TailLogger {
+        inner,
+        started: Instant::now(),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+// Empty unless init_logging ran.
+pub fn log_tail() -> Vec<String> {
+    LOG_TAIL
+        .get()
+        .map(|tail| tail.lock().unwrap().iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+// Named text files that end up together in one zip.
+#[derive(Default)]
+pub struct ReproBundle {
+    files: Vec<(String, String)>,
+}
+
+impl ReproBundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<N: Into<String>, C: Into<String>>(&mut self, name: N, contents: C) {
+        self.files.push((name.into(), contents.into()));
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.files
+            .iter()
+            .map(|(name, contents)| (name.as_str(), contents.as_str()))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut zip = ZipWriter::new(File::create(path)?);
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        for (name, contents) in self.files.iter() {
+            zip.start_file(name.as_str(), options)?;
+            zip.write_all(contents.as_bytes())?;
+        }
+        zip.finish()?;
+
+        Ok(())
+    }
+}
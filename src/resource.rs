@@ -1,58 +1,209 @@
-use crate::assets::Res;
-use crate::model::{Material, Mesh, ObjModel, ModelVertex};
+use crate::asset_cache::AssetCache;
+use crate::logging;
+use crate::model::{Material, Mesh, ModelVertex, ObjModel};
 use crate::texture::Texture;
-use anyhow::Result;
+use crate::texture_streaming::TextureStreamer;
+use crate::vfs::Vfs;
+use anyhow::{anyhow, Result};
+use glam::{Vec2, Vec3};
+use image::GenericImageView;
 use std::io::{BufReader, Cursor};
 use std::path::Path;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::{BindGroupLayout, BufferUsages, Device, Queue};
 
-pub fn load_string<P: AsRef<Path>>(file_name: P) -> Result<String> {
-    let res = Res::get(&format!("res/{}", file_name.as_ref().to_string_lossy())).unwrap();
-    let txt = String::from_utf8(res.data.into())?;
+pub fn load_string<P: AsRef<Path>>(vfs: &Vfs, file_name: P) -> Result<String> {
+    vfs.read_string(&file_name.as_ref().to_string_lossy())
+}
 
-    Ok(txt)
+pub fn load_binary(vfs: &Vfs, file_name: &str) -> Result<Vec<u8>> {
+    vfs.read(file_name)
 }
 
-pub fn load_binary(file_name: &str) -> Result<Vec<u8>> {
-    let res = Res::get(&format!("res/{file_name}")).unwrap();
-    let data = res.data.into();
+// Normal map texel pointing straight out of the surface.
+const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
+// Bump when the decoded layout changes so old cache entries stop matching.
+const DECODED_TEXTURE_VERSION: u32 = 1;
+
+// Width and height followed by the RGBA8 pixels.
+fn decode_texture(data: &[u8]) -> Result<Vec<u8>> {
+    let img = image::load_from_memory(data)?;
+    let (width, height) = img.dimensions();
+    let mut decoded = Vec::with_capacity(8 + (width * height * 4) as usize);
+    decoded.extend_from_slice(&width.to_le_bytes());
+    decoded.extend_from_slice(&height.to_le_bytes());
+    decoded.extend_from_slice(&img.to_rgba8());
+
+    Ok(decoded)
+}
+
+// Decoded RGBA8 pixels of a texture, through the cache when there is one.
+pub fn load_texture_rgba(
+    vfs: &Vfs,
+    file_name: &str,
+    cache: Option<&AssetCache>,
+) -> Result<((u32, u32), Vec<u8>)> {
+    let data = load_binary(vfs, file_name)?;
+    let Some(cache) = cache else {
+        let img = image::load_from_memory(&data)?;
+        return Ok((img.dimensions(), img.to_rgba8().into_raw()));
+    };
+
+    let mut decoded =
+        cache.get_or_insert_with("texture", DECODED_TEXTURE_VERSION, &data, || {
+            decode_texture(&data)
+        })?;
+    if decoded.len() < 8 {
+        return Err(anyhow!("cached texture {file_name} is corrupted"));
+    }
+    let width = u32::from_le_bytes(decoded[0..4].try_into().unwrap());
+    let height = u32::from_le_bytes(decoded[4..8].try_into().unwrap());
+    if decoded.len() != 8 + (width * height * 4) as usize {
+        return Err(anyhow!("cached texture {file_name} is corrupted"));
+    }
+    decoded.drain(..8);
 
-    Ok(data)
+    Ok(((width, height), decoded))
+}
+
+// KTX2 textures are already in their GPU format, so they skip decoding, the cache
+// and streaming.
+fn is_ktx2(file_name: &str) -> bool {
+    Path::new(file_name)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2"))
 }
 
 pub fn load_texture(
+    vfs: &Vfs,
     file_name: &str,
     device: &Device,
     queue: &Queue,
     is_normal_map: bool,
+    cache: Option<&AssetCache>,
 ) -> Result<Texture> {
-    let data = load_binary(file_name)?;
-    Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
+    if is_ktx2(file_name) {
+        let data = load_binary(vfs, file_name)?;
+        return Texture::from_ktx2(device, queue, &data, Some(file_name));
+    }
+
+    let (dimensions, rgba) = load_texture_rgba(vfs, file_name, cache)?;
+    Texture::from_rgba(
+        device,
+        queue,
+        dimensions,
+        &rgba,
+        Some(file_name),
+        is_normal_map,
+    )
+}
+
+// Obj files without normals are flat shaded, so every triangle gets its own
+// vertices with the face normal.
+fn flat_shaded(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let mut flat = Vec::with_capacity(indices.len());
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(vertices[triangle[i] as usize].position));
+        let normal = (b - a).cross(c - a).normalize_or_zero().to_array();
+        flat.extend(triangle.iter().map(|&i| ModelVertex {
+            normal,
+            ..vertices[i as usize]
+        }));
+    }
+
+    let indices = (0..flat.len() as u32).collect();
+    (flat, indices)
+}
+
+fn compute_tangents(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut tangents = vec![Vec3::ZERO; vertices.len()];
+    let mut bitangents = vec![Vec3::ZERO; vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| &vertices[triangle[i] as usize]);
+        let edge1 = Vec3::from(b.position) - Vec3::from(a.position);
+        let edge2 = Vec3::from(c.position) - Vec3::from(a.position);
+        let uv1 = Vec2::from(b.tex_coords) - Vec2::from(a.tex_coords);
+        let uv2 = Vec2::from(c.tex_coords) - Vec2::from(a.tex_coords);
+        let det = uv1.x * uv2.y - uv2.x * uv1.y;
+        if det.abs() < f32::EPSILON {
+            continue;
+        }
+
+        let tangent = (edge1 * uv2.y - edge2 * uv1.y) / det;
+        // Flipped, texture v goes down the image while normal maps point green up.
+        let bitangent = (edge1 * uv2.x - edge2 * uv1.x) / det;
+        for &i in triangle {
+            tangents[i as usize] += tangent;
+            bitangents[i as usize] += bitangent;
+        }
+    }
+
+    for ((vertex, tangent), bitangent) in vertices.iter_mut().zip(tangents).zip(bitangents) {
+        let normal = Vec3::from(vertex.normal);
+        // Kept perpendicular to the normal, smoothed normals bend away from the faces.
+        let tangent = (tangent - normal * normal.dot(tangent))
+            .try_normalize()
+            .unwrap_or_else(|| normal.any_orthonormal_vector());
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        vertex.tangent = tangent.extend(handedness).to_array();
+    }
 }
 
 pub fn load_model(
+    vfs: &Vfs,
     file_name: &str,
     device: &Device,
     queue: &Queue,
     layout: &BindGroupLayout,
+    cache: Option<&AssetCache>,
+    mut streamer: Option<&mut TextureStreamer>,
 ) -> Result<ObjModel> {
-    let obj_text = load_string(file_name)?;
+    let obj_text = load_string(vfs, file_name)?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
     let (models, obj_materials) =
         tobj::load_obj_buf(&mut obj_reader, &tobj::GPU_LOAD_OPTIONS, |p| {
-            let mat_text = load_string(p).unwrap();
+            let mat_text = load_string(vfs, p).map_err(|e| {
+                tracing::warn!(target: logging::ASSETS, "{e}");
+                tobj::LoadError::OpenFileFailed
+            })?;
             tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
         })?;
 
     let mut materials = vec![];
     for m in obj_materials? {
-        let diffuse_texture =
-            load_texture(&m.diffuse_texture.unwrap(), device, queue, false)?;
+        let normal_texture = match &m.normal_texture {
+            Some(file_name) => load_texture(vfs, file_name, device, queue, true, cache)?,
+            None => Texture::from_rgba(
+                device,
+                queue,
+                (1, 1),
+                &FLAT_NORMAL,
+                Some("flat normal"),
+                true,
+            )?,
+        };
+        let file_name = m.diffuse_texture.unwrap();
+        let material = match streamer.as_deref_mut() {
+            Some(streamer) if !is_ktx2(&file_name) => {
+                let (dimensions, rgba) = load_texture_rgba(vfs, &file_name, cache)?;
+                let (id, diffuse_texture) =
+                    streamer.add(device, queue, &file_name, dimensions, rgba, false)?;
+                Material::new(device, &m.name, diffuse_texture, normal_texture, layout).streamed(id)
+            }
+            _ => {
+                let diffuse_texture = load_texture(vfs, &file_name, device, queue, false, cache)?;
+                Material::new(device, &m.name, diffuse_texture, normal_texture, layout)
+            }
+        };
 
-        materials.push(Material::new(device, &m.name, diffuse_texture, layout));
+        materials.push(material);
     }
 
     let meshes = models
@@ -66,8 +217,20 @@ pub fn load_model(
                         m.mesh.positions[i * 3 + 2],
                     ],
                     tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
+                    normal: m
+                        .mesh
+                        .normals
+                        .get(i * 3..i * 3 + 3)
+                        .map_or([0.0; 3], |normal| normal.try_into().unwrap()),
+                    tangent: [0.0; 4],
                 })
                 .collect::<Vec<ModelVertex>>();
+            let (mut vertices, indices) = if m.mesh.normals.is_empty() {
+                flat_shaded(&vertices, &m.mesh.indices)
+            } else {
+                (vertices, m.mesh.indices)
+            };
+            compute_tangents(&mut vertices, &indices);
 
             let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
@@ -76,7 +239,7 @@ pub fn load_model(
             });
             let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
+                contents: bytemuck::cast_slice(&indices),
                 usage: BufferUsages::INDEX,
             });
 
@@ -84,7 +247,7 @@ pub fn load_model(
                 name: file_name.to_string(),
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
+                num_elements: indices.len() as u32,
                 material: m.mesh.material_id.unwrap_or(0),
             }
         })
@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use glam::{UVec3, Vec3A};
+use std::iter;
+use uuid::Uuid;
+
+use crate::app::Model;
+use crate::chunks::{Block, Chunk, FallingBlock};
+use crate::foliage::{Decoration, DecorationKind};
+use crate::logging;
+
+const MAGIC: &[u8; 4] = b"VXCK";
+pub const SAVE_VERSION: u8 = 3;
+
+const FALLING_BLOCK: u8 = 1;
+const DECORATION: u8 = 2;
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(anyhow!("chunk save is truncated"));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+// Entities are stored as kind, length and then their components, so kinds this
+// version doesn't know about can be skipped without losing the rest of the chunk.
+fn write_entity(data: &mut Vec<u8>, kind: u8, components: &[u8]) {
+    data.push(kind);
+    data.extend_from_slice(&(components.len() as u16).to_le_bytes());
+    data.extend_from_slice(components);
+}
+
+pub fn encode_chunk(chunk: &Chunk) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(MAGIC);
+    data.push(SAVE_VERSION);
+    for axis in chunk.chunk_position().to_array() {
+        data.extend_from_slice(&(axis as i32).to_le_bytes());
+    }
+
+    data.extend_from_slice(&(chunk.block_count() as u32).to_le_bytes());
+    for block in chunk.blocks() {
+        data.extend_from_slice(&block.data().to_le_bytes());
+    }
+
+    // Metadata and light as runs of the same value, most of a chunk is one long run.
+    let mut runs: Vec<(u16, u16)> = vec![];
+    for cell in chunk.cell_data() {
+        match runs.last_mut() {
+            Some((len, value)) if *value as u32 == cell => *len += 1,
+            _ => runs.push((1, cell as u16)),
+        }
+    }
+    data.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    for (len, value) in runs {
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.push(chunk.decorated() as u8);
+
+    let entity_count = chunk.falling_blocks().len() + chunk.decorations().len();
+    data.extend_from_slice(&(entity_count as u32).to_le_bytes());
+    for falling in chunk.falling_blocks() {
+        let mut components = vec![];
+        components.extend_from_slice(&falling.id.to_le_bytes());
+        for axis in falling.position.to_array() {
+            components.extend_from_slice(&axis.to_le_bytes());
+        }
+        components.extend_from_slice(&falling.velocity.to_le_bytes());
+        write_entity(&mut data, FALLING_BLOCK, &components);
+    }
+    for decoration in chunk.decorations() {
+        let mut components = vec![decoration.kind.id()];
+        components.extend(decoration.position.to_array().map(|axis| axis as u8));
+        write_entity(&mut data, DECORATION, &components);
+    }
+
+    data
+}
+
+pub fn decode_chunk(data: &[u8]) -> Result<Chunk> {
+    let mut reader = Reader { data };
+    if reader.take(4)? != MAGIC {
+        return Err(anyhow!("not a chunk save"));
+    }
+    let version = reader.u8()?;
+    if version == 0 || version > SAVE_VERSION {
+        return Err(anyhow!("unsupported chunk save version {version}"));
+    }
+
+    let mut position = [0.0; 3];
+    for axis in position.iter_mut() {
+        *axis = reader.u32()? as i32 as f32;
+    }
+
+    let block_count = reader.u32()?;
+    let blocks = (0..block_count)
+        .map(|_| reader.u32().map(Block::new))
+        .collect::<Result<Vec<_>>>()?;
+    let mut chunk = Chunk::restore(Uuid::new_v4(), Vec3A::from_array(position), blocks);
+
+    // Version 1 saves have no metadata or light, everything starts at 0.
+    if version >= 2 {
+        let run_count = reader.u32()?;
+        let mut cells = vec![];
+        for _ in 0..run_count {
+            let len = reader.u16()? as usize;
+            let value = reader.u16()? as u32;
+            cells.extend(iter::repeat_n(value, len));
+        }
+        chunk.restore_cell_data(cells);
+    }
+    // Chunks from before structures existed keep looking like they did.
+    if version < 3 || reader.u8()? != 0 {
+        chunk.set_decorated();
+    }
+
+    let entity_count = reader.u32()?;
+    for _ in 0..entity_count {
+        let kind = reader.u8()?;
+        let len = reader.u16()? as usize;
+        let mut components = Reader {
+            data: reader.take(len)?,
+        };
+        match kind {
+            FALLING_BLOCK => chunk.add_falling_block(FallingBlock {
+                id: components.u16()?,
+                position: Vec3A::new(components.f32()?, components.f32()?, components.f32()?),
+                velocity: components.f32()?,
+            }),
+            DECORATION => {
+                let id = components.u8()?;
+                let position = UVec3::new(
+                    components.u8()? as u32,
+                    components.u8()? as u32,
+                    components.u8()? as u32,
+                );
+                match DecorationKind::from_id(id) {
+                    Some(kind) => chunk.add_decoration(Decoration::new(kind, position)),
+                    None => {
+                        tracing::warn!(
+                            target: logging::CHUNKS,
+                            "Skipping unknown decoration kind {id} in chunk save"
+                        )
+                    }
+                }
+            }
+            _ => {
+                tracing::warn!(
+                    target: logging::CHUNKS,
+                    "Skipping unknown entity kind {kind} in chunk save"
+                )
+            }
+        }
+    }
+
+    Ok(chunk)
+}
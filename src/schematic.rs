@@ -0,0 +1,159 @@
+use anyhow::{anyhow, Result};
+use glam::{IVec3, UVec3};
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use crate::chunks::Chunk;
+use crate::mesher::CHUNK_SIZE;
+use crate::world_edit::{block_at, EditSet};
+
+const MAGIC: &[u8; 4] = b"VXSC";
+const VERSION: u8 = 1;
+const AIR: u16 = u16::MAX;
+
+// A box of blocks copied out of the world. Cells are stored x major like the
+// chunks, empty cells are air and clear whatever they get pasted over.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schematic {
+    size: UVec3,
+    blocks: Vec<Option<u16>>,
+}
+
+impl Schematic {
+    pub fn new(size: UVec3) -> Self {
+        Self {
+            size,
+            blocks: vec![None; (size.x * size.y * size.z) as usize],
+        }
+    }
+
+    // Copies the region between two corners, both included, out of the chunks
+    // keyed by their chunk position. Missing chunks read as air.
+    pub fn capture(chunks: &HashMap<IVec3, Chunk>, corner_a: IVec3, corner_b: IVec3) -> Self {
+        let min = corner_a.min(corner_b);
+        let max = corner_a.max(corner_b);
+        let mut schematic = Self::new((max - min + 1).as_uvec3());
+
+        for (chunk_position, chunk) in chunks.iter() {
+            let origin = *chunk_position * CHUNK_SIZE;
+            for block in chunk.blocks() {
+                let position = origin + block.position().as_ivec3();
+                if position.cmpge(min).all() && position.cmple(max).all() {
+                    schematic.set((position - min).as_uvec3(), Some(block.id()));
+                }
+            }
+        }
+
+        schematic
+    }
+
+    pub fn size(&self) -> UVec3 {
+        self.size
+    }
+
+    fn index(&self, position: UVec3) -> usize {
+        (position.x * self.size.y * self.size.z + position.y * self.size.z + position.z) as usize
+    }
+
+    pub fn get(&self, position: UVec3) -> Option<u16> {
+        self.blocks[self.index(position)]
+    }
+
+    pub fn set(&mut self, position: UVec3, id: Option<u16>) {
+        let idx = self.index(position);
+        self.blocks[idx] = id;
+    }
+
+    // Rotates clockwise around the Y axis, seen from above, by the given quarter turns.
+    pub fn rotated(&self, quarter_turns: u32) -> Self {
+        let mut rotated = self.clone();
+        for _ in 0..quarter_turns % 4 {
+            let source = rotated;
+            rotated = Self::new(UVec3::new(source.size.z, source.size.y, source.size.x));
+            for x in 0..source.size.x {
+                for y in 0..source.size.y {
+                    for z in 0..source.size.z {
+                        let id = source.get(UVec3::new(x, y, z));
+                        rotated.set(UVec3::new(source.size.z - 1 - z, y, x), id);
+                    }
+                }
+            }
+        }
+
+        rotated
+    }
+
+    // Writes the whole region with its minimum corner at `origin`. Every chunk it
+    // touches has to be loaded, otherwise nothing gets changed at all. The applied
+    // changes are returned so the paste can be undone.
+    pub fn paste(&self, chunks: &mut HashMap<IVec3, Chunk>, origin: IVec3) -> Result<EditSet> {
+        let mut set = EditSet::new();
+        for x in 0..self.size.x {
+            for y in 0..self.size.y {
+                for z in 0..self.size.z {
+                    let cell = UVec3::new(x, y, z);
+                    let position = origin + cell.as_ivec3();
+                    let before = block_at(chunks, position);
+                    let after = self.get(cell);
+                    if before != after {
+                        set.record(position, before, after);
+                    }
+                }
+            }
+        }
+
+        set.apply(chunks)?;
+        Ok(set)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.encode())?;
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(17 + self.blocks.len() * 2);
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        for axis in self.size.to_array() {
+            data.extend_from_slice(&axis.to_le_bytes());
+        }
+        for id in self.blocks.iter() {
+            data.extend_from_slice(&id.unwrap_or(AIR).to_le_bytes());
+        }
+
+        data
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::decode(&fs::read(path)?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < 17 || &data[..4] != MAGIC {
+            return Err(anyhow!("not a schematic file"));
+        }
+        if data[4] != VERSION {
+            return Err(anyhow!("unsupported schematic version {}", data[4]));
+        }
+
+        let axis = |i: usize| u32::from_le_bytes(data[5 + i * 4..9 + i * 4].try_into().unwrap());
+        let mut schematic = Self::new(UVec3::new(axis(0), axis(1), axis(2)));
+        let cells = &data[17..];
+        if cells.len() != schematic.blocks.len() * 2 {
+            return Err(anyhow!("schematic file is truncated"));
+        }
+
+        for (block, cell) in schematic.blocks.iter_mut().zip(cells.chunks_exact(2)) {
+            let id = u16::from_le_bytes([cell[0], cell[1]]);
+            *block = (id != AIR).then_some(id);
+        }
+
+        Ok(schematic)
+    }
+}
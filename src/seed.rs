@@ -0,0 +1,105 @@
+use glam::IVec3;
+use std::fmt;
+
+// The seed a world gets generated from. Everything random in it takes a stream
+// of its own from here, mixed with what it's for and where it is, so the same
+// seed makes the same world whatever order chunks come in and on every
+// platform. Only fixed integer math goes into the mixing, no std hashers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WorldSeed(u64);
+
+impl WorldSeed {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    // Seeds typed in as text, numbers are taken as they are.
+    pub fn from_text(text: &str) -> Self {
+        match text.trim().parse() {
+            Ok(seed) => Self(seed),
+            Err(_) => Self(fnv1a(0xcbf29ce484222325, text.as_bytes())),
+        }
+    }
+
+    pub fn value(self) -> u64 {
+        self.0
+    }
+
+    // A seed of its own for a feature, like the biome map or a decorator, so
+    // features don't line up with each other.
+    pub fn feature(self, name: &str) -> WorldSeed {
+        Self(mix(fnv1a(self.0, name.as_bytes())))
+    }
+
+    pub fn hash2(self, x: i32, z: i32) -> u64 {
+        mix(self.0
+            ^ (x as u64).wrapping_mul(0x9e3779b97f4a7c15)
+            ^ (z as u64).wrapping_mul(0xbf58476d1ce4e5b9))
+    }
+
+    pub fn hash3(self, position: IVec3) -> u64 {
+        mix(self.0
+            ^ (position.x as u64).wrapping_mul(0x9e3779b97f4a7c15)
+            ^ (position.y as u64).wrapping_mul(0xbf58476d1ce4e5b9)
+            ^ (position.z as u64).wrapping_mul(0x94d049bb133111eb))
+    }
+
+    // A stream of numbers for one place, usually a chunk.
+    pub fn rng(self, position: IVec3) -> SeededRng {
+        SeededRng {
+            state: self.hash3(position),
+        }
+    }
+}
+
+impl From<u64> for WorldSeed {
+    fn from(seed: u64) -> Self {
+        Self(seed)
+    }
+}
+
+impl fmt::Display for WorldSeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Splitmix64's finalizer.
+fn mix(mut h: u64) -> u64 {
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+fn fnv1a(mut h: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        h = (h ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+// Splitmix64.
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        mix(self.state)
+    }
+
+    // From 0 up to, not including, 1.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // From `min` up to, not including, `max`.
+    pub fn range(&mut self, min: i32, max: i32) -> i32 {
+        min + (self.next_u64() % (max - min).max(1) as u64) as i32
+    }
+
+    pub fn chance(&mut self, probability: f32) -> bool {
+        self.next_f32() < probability
+    }
+}
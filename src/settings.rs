@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use std::fmt::Display;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::mesher::CHUNK_SIZE;
+
+fn parse_value<T: FromStr>(line: usize, value: &str) -> Result<T>
+where
+    T::Err: Display,
+{
+    value.parse().map_err(|e| anyhow!("line {line}: {e}"))
+}
+
+// What an options menu usually lets players change. `App::apply_settings` only
+// redoes what changed, MSAA being the one that rebuilds pipelines.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GraphicsSettings {
+    // In chunks, nothing further away gets drawn.
+    pub render_distance: u32,
+    pub vsync: bool,
+    pub msaa: u32,
+    // Vertical, in degrees.
+    pub fov: f32,
+    pub fog: bool,
+    pub max_fps: Option<u32>,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            render_distance: 16,
+            vsync: false,
+            msaa: 4,
+            // What the camera always had, about 44.7 degrees.
+            fov: 0.78_f32.to_degrees(),
+            fog: true,
+            max_fps: None,
+        }
+    }
+}
+
+impl GraphicsSettings {
+    pub fn view_distance(&self) -> f32 {
+        (self.render_distance.max(1) * CHUNK_SIZE as u32) as f32
+    }
+
+    // A flat TOML file with one `key = value` line per setting, the ones left
+    // out keep their defaults. No `max_fps`, or 0, leaves the frame rate alone.
+    pub fn parse(data: &str) -> Result<Self> {
+        let mut settings = Self::default();
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected `setting = value`", i + 1))?;
+            let (line, value) = (i + 1, value.trim());
+            match key.trim() {
+                "render_distance" => settings.render_distance = parse_value(line, value)?,
+                "vsync" => settings.vsync = parse_value(line, value)?,
+                "msaa" => settings.msaa = parse_value(line, value)?,
+                "fov" => settings.fov = parse_value(line, value)?,
+                "fog" => settings.fog = parse_value(line, value)?,
+                "max_fps" => {
+                    let max_fps: u32 = parse_value(line, value)?;
+                    settings.max_fps = (max_fps > 0).then_some(max_fps);
+                }
+                key => return Err(anyhow!("line {line}: unknown setting `{key}`")),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_config(&self) -> String {
+        let mut config = format!(
+            "render_distance = {}\nvsync = {}\nmsaa = {}\nfov = {:.1}\nfog = {}\n",
+            self.render_distance, self.vsync, self.msaa, self.fov, self.fog
+        );
+        if let Some(max_fps) = self.max_fps {
+            config.push_str(&format!("max_fps = {max_fps}\n"));
+        }
+        config
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_config())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::{Mat4, Vec3};
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType, BufferUsages,
+    Color, Device, PipelineLayoutDescriptor, PolygonMode, Queue, RenderPass, RenderPipeline,
+    SamplerBindingType, ShaderModuleDescriptor, ShaderSource, ShaderStages, TextureFormat,
+    TextureSampleType, TextureViewDimension,
+};
+
+use crate::command_buffer::RenderLayer;
+use crate::environment::smoothstep;
+use crate::texture::Texture;
+use crate::{create_render_pipeline, PipelineDesc};
+
+const SUN_SIZE: f32 = 0.08;
+const MOON_SIZE: f32 = 0.06;
+const CELESTIAL_TEXTURE_SIZE: u32 = 64;
+
+// Sun and moon side by side. There's no art for them yet, so they're drawn here:
+// a bright disc with a glow around it and a grey one with a few darker maria.
+fn celestial_atlas() -> Vec<u8> {
+    let size = CELESTIAL_TEXTURE_SIZE;
+    let mut rgba = vec![0; (size * 2 * size * 4) as usize];
+    let maria = [(-0.3, -0.2, 0.3), (0.25, 0.1, 0.25), (-0.05, 0.4, 0.2)];
+    for y in 0..size {
+        for x in 0..size * 2 {
+            let local = x % size;
+            let u = (local as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+            let distance = (u * u + v * v).sqrt();
+
+            let pixel = if x < size {
+                let core = 1.0 - smoothstep(0.4, 0.45, distance);
+                let glow = (1.0 - distance).max(0.0).powi(3) * 0.6;
+                [255, 245, 220, ((core + glow).min(1.0) * 255.0) as u8]
+            } else {
+                let disc = 1.0 - smoothstep(0.75, 0.8, distance);
+                let shade = maria
+                    .iter()
+                    .filter(|(mx, my, r)| ((u - mx).powi(2) + (v - my).powi(2)).sqrt() < *r)
+                    .count() as f32;
+                let grey = (220.0 - shade * 35.0) as u8;
+                [
+                    grey,
+                    grey,
+                    (grey as f32 * 1.05).min(255.0) as u8,
+                    (disc * 255.0) as u8,
+                ]
+            };
+
+            let i = ((y * size * 2 + x) * 4) as usize;
+            rgba[i..i + 4].copy_from_slice(&pixel);
+        }
+    }
+
+    rgba
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct SkyUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    top_color: [f32; 4],
+    horizon_color: [f32; 4],
+    ground_color: [f32; 4],
+    // w is the angular radius of the quad.
+    sun_direction: [f32; 4],
+    moon_direction: [f32; 4],
+    // x is how visible the stars are, y how far the night sky turned.
+    stars: [f32; 4],
+    // Coverage, wind offset and altitude of the cloud layer.
+    clouds: [f32; 4],
+    // x is the depth the sky is drawn at, right in front of the far plane.
+    depth: [f32; 4],
+}
+
+impl SkyUniform {
+    pub fn new() -> Self {
+        Self {
+            inv_view_proj: Mat4::IDENTITY.to_cols_array_2d(),
+            top_color: [0.1, 0.3, 0.7, 1.0],
+            horizon_color: [0.6, 0.75, 0.9, 1.0],
+            ground_color: [0.25, 0.25, 0.3, 1.0],
+            sun_direction: [0.0, 1.0, 0.0, SUN_SIZE],
+            moon_direction: [0.0, -1.0, 0.0, MOON_SIZE],
+            stars: [0.0; 4],
+            clouds: [0.0; 4],
+            depth: [0.9999, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+impl Default for SkyUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The sky as another camera sees it, its uniform only differs in the matrix.
+pub struct SkyView {
+    buffer: Buffer,
+    bind_group: BindGroup,
+}
+
+pub struct Sky {
+    uniform: SkyUniform,
+    buffer: Buffer,
+    layout: BindGroupLayout,
+    bind_group: BindGroup,
+    pipeline: RenderPipeline,
+    celestial_bind_group: BindGroup,
+    celestial_pipeline: RenderPipeline,
+}
+
+impl Sky {
+    pub fn new(
+        device: &Device,
+        camera_layout: &BindGroupLayout,
+        queue: &Queue,
+        format: TextureFormat,
+        sample_count: u32,
+        reverse_z: bool,
+    ) -> Self {
+        let mut uniform = SkyUniform::new();
+        if reverse_z {
+            uniform.depth[0] = 0.0001;
+        }
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Sky Buffer"),
+            contents: cast_slice(&[uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("sky_bind_group_layout"),
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("sky_bind_group"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("sky_pipeline_layout"),
+            bind_group_layouts: &[camera_layout, &layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = create_render_pipeline(
+            device,
+            PipelineDesc {
+                layout: &pipeline_layout,
+                color_format: format,
+                depth_format: Some(Texture::DEPTH_FORMAT),
+                vertex_layouts: &[],
+                shader: ShaderModuleDescriptor {
+                    label: Some("sky_shader"),
+                    source: ShaderSource::Wgsl(include_str!("../shaders/sky.wgsl").into()),
+                },
+                layer: RenderLayer::Transparent,
+                polygon_mode: PolygonMode::Fill,
+                sample_count,
+                reverse_z,
+            },
+        );
+
+        let atlas = Texture::from_rgba(
+            device,
+            queue,
+            (CELESTIAL_TEXTURE_SIZE * 2, CELESTIAL_TEXTURE_SIZE),
+            &celestial_atlas(),
+            Some("celestial_texture"),
+            false,
+        )
+        .unwrap();
+        let celestial_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("celestial_bind_group_layout"),
+        });
+        let celestial_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &celestial_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(&atlas.view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&atlas.sampler),
+                },
+            ],
+            label: Some("celestial_bind_group"),
+        });
+
+        let celestial_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("celestial_pipeline_layout"),
+            bind_group_layouts: &[camera_layout, &layout, &celestial_layout],
+            push_constant_ranges: &[],
+        });
+        let celestial_pipeline = create_render_pipeline(
+            device,
+            PipelineDesc {
+                layout: &celestial_pipeline_layout,
+                color_format: format,
+                depth_format: Some(Texture::DEPTH_FORMAT),
+                vertex_layouts: &[],
+                shader: ShaderModuleDescriptor {
+                    label: Some("celestial_shader"),
+                    source: ShaderSource::Wgsl(include_str!("../shaders/celestial.wgsl").into()),
+                },
+                layer: RenderLayer::Transparent,
+                polygon_mode: PolygonMode::Fill,
+                sample_count,
+                reverse_z,
+            },
+        );
+
+        Self {
+            uniform,
+            buffer,
+            layout,
+            bind_group,
+            pipeline,
+            celestial_bind_group,
+            celestial_pipeline,
+        }
+    }
+
+    pub fn set_colors(&mut self, top: Vec3, horizon: Vec3, ground: Vec3) {
+        self.uniform.top_color = top.extend(1.0).to_array();
+        self.uniform.horizon_color = horizon.extend(1.0).to_array();
+        self.uniform.ground_color = ground.extend(1.0).to_array();
+    }
+
+    pub fn set_celestial(
+        &mut self,
+        sun_direction: Vec3,
+        moon_direction: Vec3,
+        star_visibility: f32,
+        star_rotation: f32,
+    ) {
+        self.uniform.sun_direction = sun_direction.extend(SUN_SIZE).to_array();
+        self.uniform.moon_direction = moon_direction.extend(MOON_SIZE).to_array();
+        self.uniform.stars = [star_visibility, star_rotation, 0.0, 0.0];
+    }
+
+    pub fn set_clouds(&mut self, clouds: [f32; 4]) {
+        self.uniform.clouds = clouds;
+    }
+
+    pub fn horizon_color(&self) -> Vec3 {
+        Vec3::from_slice(&self.uniform.horizon_color)
+    }
+
+    pub fn clear_color(&self) -> Color {
+        let [r, g, b, a] = self.uniform.horizon_color;
+        Color {
+            r: r as f64,
+            g: g as f64,
+            b: b as f64,
+            a: a as f64,
+        }
+    }
+
+    pub fn update(&mut self, queue: &Queue, view_proj: Mat4) {
+        self.uniform.inv_view_proj = view_proj.inverse().to_cols_array_2d();
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+
+    pub fn create_view(&self, device: &Device) -> SkyView {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Sky View Buffer"),
+            contents: cast_slice(&[self.uniform]),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            layout: &self.layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("sky_view_bind_group"),
+        });
+
+        SkyView { buffer, bind_group }
+    }
+
+    pub fn update_view(&self, queue: &Queue, view: &SkyView, view_proj: Mat4) {
+        let mut uniform = self.uniform;
+        uniform.inv_view_proj = view_proj.inverse().to_cols_array_2d();
+        queue.write_buffer(&view.buffer, 0, cast_slice(&[uniform]));
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+    ) {
+        self.draw(render_pass, camera_bind_group, &self.bind_group);
+    }
+
+    pub fn render_view<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+        view: &'a SkyView,
+    ) {
+        self.draw(render_pass, camera_bind_group, &view.bind_group);
+    }
+
+    fn draw<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        camera_bind_group: &'a BindGroup,
+        sky_bind_group: &'a BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, sky_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        render_pass.set_pipeline(&self.celestial_pipeline);
+        render_pass.set_bind_group(2, &self.celestial_bind_group, &[]);
+        render_pass.draw(0..6, 0..2);
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::Result;
+use glam::Vec3A;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::app::{Actor, ResourceCounts};
+use crate::command_buffer::{CommandBuffer, NCommandUpdate};
+use crate::input::InputState;
+use crate::world_view::WorldView;
+
+// One bucket per millisecond, slower frames all land in the last one.
+const HISTOGRAM_BUCKETS: usize = 250;
+const SNAPSHOT_HEADER: &str = "elapsed_s,frames,mean_ms,p50_ms,p95_ms,p99_ms,max_ms,\
+resident_bytes,models,buffers,bind_groups,pooled_bytes,uniform_slabs,loaded_chunks";
+
+#[derive(Clone, Debug)]
+pub struct FrameHistogram {
+    buckets: Vec<u64>,
+    frames: u64,
+    total: f64,
+    max: f32,
+}
+
+impl FrameHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: vec![0; HISTOGRAM_BUCKETS],
+            frames: 0,
+            total: 0.0,
+            max: 0.0,
+        }
+    }
+
+    pub fn record(&mut self, dt: Duration) {
+        let ms = dt.as_secs_f32() * 1000.0;
+        self.buckets[(ms as usize).min(HISTOGRAM_BUCKETS - 1)] += 1;
+        self.frames += 1;
+        self.total += ms as f64;
+        self.max = self.max.max(ms);
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn mean(&self) -> f32 {
+        if self.frames == 0 {
+            return 0.0;
+        }
+        (self.total / self.frames as f64) as f32
+    }
+
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    // Upper edge of the bucket holding the `p` quantile, in milliseconds.
+    pub fn percentile(&self, p: f32) -> f32 {
+        let target = (self.frames as f32 * p.clamp(0.0, 1.0)).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (ms, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return (ms + 1) as f32;
+            }
+        }
+
+        0.0
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("bucket_ms,frames\n");
+        for (ms, count) in self.buckets.iter().enumerate() {
+            csv.push_str(&format!("{ms},{count}\n"));
+        }
+        csv
+    }
+}
+
+impl Default for FrameHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Resident set size of the process, only known on Linux for now.
+pub fn resident_memory() -> Option<u64> {
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4096)
+}
+
+// Records every frame of a long unattended run and writes a CSV row per
+// interval, with the frame times of that interval next to memory and GPU
+// resource counts, so leaks show up as columns that keep growing. The histogram
+// of the whole run is rewritten with every snapshot.
+pub struct SoakTest {
+    dir: PathBuf,
+    snapshots: BufWriter<File>,
+    interval: Duration,
+    duration: Option<Duration>,
+    elapsed: Duration,
+    since_snapshot: Duration,
+    window: FrameHistogram,
+    total: FrameHistogram,
+}
+
+impl SoakTest {
+    pub fn new<P: Into<PathBuf>>(
+        dir: P,
+        interval: Duration,
+        duration: Option<Duration>,
+    ) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut snapshots = BufWriter::new(File::create(dir.join("snapshots.csv"))?);
+        writeln!(snapshots, "{SNAPSHOT_HEADER}")?;
+        snapshots.flush()?;
+
+        Ok(Self {
+            dir,
+            snapshots,
+            interval,
+            duration,
+            elapsed: Duration::ZERO,
+            since_snapshot: Duration::ZERO,
+            window: FrameHistogram::new(),
+            total: FrameHistogram::new(),
+        })
+    }
+
+    pub fn histogram(&self) -> &FrameHistogram {
+        &self.total
+    }
+
+    pub fn finished(&self) -> bool {
+        self.duration
+            .is_some_and(|duration| self.elapsed >= duration)
+    }
+
+    // Returns false once the run lasted as long as it was asked to.
+    pub fn record_frame(&mut self, dt: Duration, counts: ResourceCounts) -> bool {
+        self.window.record(dt);
+        self.total.record(dt);
+        self.elapsed += dt;
+        self.since_snapshot += dt;
+
+        if self.since_snapshot >= self.interval || self.finished() {
+            self.since_snapshot = Duration::ZERO;
+            if let Err(e) = self.snapshot(counts) {
+                tracing::warn!("Couldn't write the soak test snapshot: {e}");
+            }
+            self.window.clear();
+        }
+
+        !self.finished()
+    }
+
+    fn snapshot(&mut self, counts: ResourceCounts) -> Result<()> {
+        writeln!(
+            self.snapshots,
+            "{:.1},{},{:.3},{},{},{},{:.3},{},{},{},{},{},{},{}",
+            self.elapsed.as_secs_f32(),
+            self.window.frames(),
+            self.window.mean(),
+            self.window.percentile(0.5),
+            self.window.percentile(0.95),
+            self.window.percentile(0.99),
+            self.window.max(),
+            resident_memory().map_or(String::new(), |bytes| bytes.to_string()),
+            counts.models,
+            counts.buffers,
+            counts.bind_groups,
+            counts.pooled_bytes,
+            counts.uniform_slabs,
+            counts.loaded_chunks,
+        )?;
+        self.snapshots.flush()?;
+        fs::write(self.dir.join("histogram.csv"), self.total.to_csv())?;
+
+        Ok(())
+    }
+}
+
+// Flies the camera in a wide circle so chunks keep streaming in and out.
+pub struct SoakPilot {
+    id: Uuid,
+    speed: f32,
+    turn_rate: f32,
+    heading: f32,
+}
+
+impl SoakPilot {
+    pub fn new(speed: f32, radius: f32) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            speed,
+            turn_rate: speed / radius,
+            heading: 0.0,
+        }
+    }
+}
+
+impl Actor for SoakPilot {
+    fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    fn update(
+        &mut self,
+        dt: &Duration,
+        _: &InputState,
+        _: &WorldView,
+    ) -> CommandBuffer<NCommandUpdate> {
+        let mut buffer = CommandBuffer::new();
+        let dt = dt.as_secs_f32();
+        self.heading += self.turn_rate * dt;
+        let (heading_sin, heading_cos) = self.heading.sin_cos();
+
+        buffer.push(NCommandUpdate::MoveCamera(
+            Vec3A::new(heading_cos, 0.0, heading_sin) * self.speed * dt,
+        ));
+        buffer.push(NCommandUpdate::RotateCamera(self.turn_rate * dt, 0.0));
+
+        buffer
+    }
+}
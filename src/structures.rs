@@ -0,0 +1,265 @@
+use glam::IVec3;
+use std::collections::{HashMap, HashSet};
+
+use crate::app::Model;
+use crate::biome::{Biome, BiomeRegistry};
+use crate::chunks::{Chunk, LEAVES_ID, LOG_ID, ORE_ID, STONE_ID};
+use crate::mesher::CHUNK_SIZE;
+use crate::seed::{SeededRng, WorldSeed};
+use crate::world_edit::{block_at, split_position};
+
+pub const TREES: &str = "trees";
+pub const ORE_VEINS: &str = "ore_veins";
+
+// Chunks on the same layer around a chunk, which have to exist before it gets
+// decorated since structures reach into them.
+const AROUND: [IVec3; 8] = [
+    IVec3::new(-1, 0, -1),
+    IVec3::new(0, 0, -1),
+    IVec3::new(1, 0, -1),
+    IVec3::new(-1, 0, 0),
+    IVec3::new(1, 0, 0),
+    IVec3::new(-1, 0, 1),
+    IVec3::new(0, 0, 1),
+    IVec3::new(1, 0, 1),
+];
+
+// Blocks of the loaded chunks, wherever they're kept. Positions are in world
+// space, chunk positions in chunks.
+pub trait BlockAccess {
+    fn block(&self, position: IVec3) -> Option<u16>;
+
+    // Does nothing where no chunk is loaded.
+    fn set_block(&mut self, position: IVec3, id: Option<u16>);
+
+    // Everything stored for the block, like fluid levels, see Model::block_state.
+    fn block_state(&self, position: IVec3) -> Option<u32>;
+
+    // Puts a block in a state from block_state without waking anything up
+    // around it. Does nothing where no chunk is loaded.
+    fn set_block_state(&mut self, position: IVec3, state: Option<u32>);
+
+    fn chunk_loaded(&self, chunk_position: IVec3) -> bool;
+
+    fn decorated(&self, chunk_position: IVec3) -> bool;
+
+    fn set_decorated(&mut self, chunk_position: IVec3);
+}
+
+// What a decorator gets to build with: the blocks of its chunk and of the
+// chunks around it.
+pub struct DecorationContext<'a> {
+    access: &'a mut dyn BlockAccess,
+    chunk_position: IVec3,
+    seed: WorldSeed,
+    biomes: &'a BiomeRegistry,
+    changed: &'a mut Vec<IVec3>,
+}
+
+impl DecorationContext<'_> {
+    pub fn chunk_position(&self) -> IVec3 {
+        self.chunk_position
+    }
+
+    pub fn origin(&self) -> IVec3 {
+        self.chunk_position * CHUNK_SIZE
+    }
+
+    pub fn biome(&self, x: i32, z: i32) -> &Biome {
+        self.biomes.biome_at(self.seed, x, z)
+    }
+
+    pub fn block(&self, position: IVec3) -> Option<u16> {
+        self.access.block(position)
+    }
+
+    // Writes past the chunks around this one are dropped.
+    pub fn set_block(&mut self, position: IVec3, id: Option<u16>) {
+        let offset = split_position(position).0 - self.chunk_position;
+        if offset.abs().cmpgt(IVec3::ONE).any() || self.access.block(position) == id {
+            return;
+        }
+
+        self.access.set_block(position, id);
+        self.changed.push(position);
+    }
+
+    // World height of the first empty cell above the highest block in a
+    // column of this chunk's layer, None when the column is empty.
+    pub fn surface(&self, x: i32, z: i32) -> Option<i32> {
+        let bottom = self.origin().y;
+        (bottom..bottom + CHUNK_SIZE)
+            .rev()
+            .find(|&y| self.block(IVec3::new(x, y, z)).is_some())
+            .map(|y| y + 1)
+    }
+}
+
+// A pass over generated chunks placing structures that can span chunk borders,
+// like trees and ore veins. Runs once per chunk, with numbers from the
+// decorator's own stream for that chunk so it always builds the same things.
+pub trait Decorator: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn decorate(&self, ctx: &mut DecorationContext<'_>, rng: &mut SeededRng);
+}
+
+// Decorates the chunks around the given ones that can be now, returns the
+// positions of the blocks that changed.
+pub fn decorate_ready<A: BlockAccess>(
+    access: &mut A,
+    decorators: &[Box<dyn Decorator>],
+    seed: WorldSeed,
+    biomes: &BiomeRegistry,
+    chunk_positions: &[IVec3],
+) -> Vec<IVec3> {
+    let candidates = chunk_positions
+        .iter()
+        .flat_map(|&position| {
+            AROUND
+                .iter()
+                .map(move |&offset| position + offset)
+                .chain([position])
+        })
+        .collect::<HashSet<_>>();
+
+    let mut changed = vec![];
+    for chunk_position in candidates {
+        let ready = access.chunk_loaded(chunk_position)
+            && !access.decorated(chunk_position)
+            && AROUND
+                .iter()
+                .all(|&offset| access.chunk_loaded(chunk_position + offset));
+        if !ready {
+            continue;
+        }
+
+        access.set_decorated(chunk_position);
+        for decorator in decorators {
+            let mut rng = seed.feature(decorator.name()).rng(chunk_position);
+            let mut ctx = DecorationContext {
+                access: &mut *access,
+                chunk_position,
+                seed,
+                biomes,
+                changed: &mut changed,
+            };
+            decorator.decorate(&mut ctx, &mut rng);
+        }
+    }
+
+    changed
+}
+
+// A few trees on the surface of biomes with vegetation.
+pub struct TreeDecorator;
+
+impl Decorator for TreeDecorator {
+    fn name(&self) -> &'static str {
+        TREES
+    }
+
+    fn decorate(&self, ctx: &mut DecorationContext<'_>, rng: &mut SeededRng) {
+        let origin = ctx.origin();
+        for _ in 0..rng.range(1, 5) {
+            let (x, z) = (
+                origin.x + rng.range(0, CHUNK_SIZE),
+                origin.z + rng.range(0, CHUNK_SIZE),
+            );
+            let biome = *ctx.biome(x, z);
+            let Some(ground) = ctx.surface(x, z) else {
+                continue;
+            };
+            let top = IVec3::new(x, ground - 1, z);
+            if !biome.vegetation || ctx.block(top) != Some(biome.surface) {
+                continue;
+            }
+
+            let trunk = rng.range(4, 6);
+            let crown = top + IVec3::Y * trunk;
+            for dx in -2..=2 {
+                for dy in -1..=2 {
+                    for dz in -2..=2 {
+                        let offset = IVec3::new(dx, dy, dz);
+                        let position = crown + offset;
+                        if offset.length_squared() <= 5 && ctx.block(position).is_none() {
+                            ctx.set_block(position, Some(LEAVES_ID));
+                        }
+                    }
+                }
+            }
+            for y in 1..=trunk {
+                ctx.set_block(top + IVec3::Y * y, Some(LOG_ID));
+            }
+        }
+    }
+}
+
+// Winding veins of ore through the stone, on top of the single ores the ores
+// stage scatters.
+pub struct OreVeinDecorator;
+
+impl Decorator for OreVeinDecorator {
+    fn name(&self) -> &'static str {
+        ORE_VEINS
+    }
+
+    fn decorate(&self, ctx: &mut DecorationContext<'_>, rng: &mut SeededRng) {
+        let origin = ctx.origin();
+        for _ in 0..rng.range(0, 2) {
+            let mut position = origin
+                + IVec3::new(
+                    rng.range(0, CHUNK_SIZE),
+                    rng.range(0, CHUNK_SIZE),
+                    rng.range(0, CHUNK_SIZE),
+                );
+            for _ in 0..rng.range(4, 10) {
+                if ctx.block(position) == Some(STONE_ID) {
+                    ctx.set_block(position, Some(ORE_ID));
+                }
+                let axis = rng.range(0, 3) as usize;
+                position[axis] += if rng.chance(0.5) { 1 } else { -1 };
+            }
+        }
+    }
+}
+
+impl BlockAccess for HashMap<IVec3, Chunk> {
+    fn block(&self, position: IVec3) -> Option<u16> {
+        block_at(self, position)
+    }
+
+    fn set_block(&mut self, position: IVec3, id: Option<u16>) {
+        let (chunk_position, local) = split_position(position);
+        if let Some(chunk) = self.get_mut(&chunk_position) {
+            chunk.set_block(local, id);
+        }
+    }
+
+    fn block_state(&self, position: IVec3) -> Option<u32> {
+        let (chunk_position, local) = split_position(position);
+        self.get(&chunk_position)?.block_state(local)
+    }
+
+    fn set_block_state(&mut self, position: IVec3, state: Option<u32>) {
+        let (chunk_position, local) = split_position(position);
+        if let Some(chunk) = self.get_mut(&chunk_position) {
+            chunk.set_block_state(local, state);
+        }
+    }
+
+    fn chunk_loaded(&self, chunk_position: IVec3) -> bool {
+        self.contains_key(&chunk_position)
+    }
+
+    fn decorated(&self, chunk_position: IVec3) -> bool {
+        self.get(&chunk_position)
+            .is_some_and(|chunk| chunk.decorated())
+    }
+
+    fn set_decorated(&mut self, chunk_position: IVec3) {
+        if let Some(chunk) = self.get_mut(&chunk_position) {
+            chunk.set_decorated();
+        }
+    }
+}
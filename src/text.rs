@@ -0,0 +1,308 @@
+use glyphon::{
+    Color, FontSystem, Metrics, Resolution, Shaping, SwashCache, TextArea, TextAtlas, TextBounds,
+    TextRenderer,
+};
+use wgpu::{Device, MultisampleState, Queue, RenderPass, TextureFormat};
+
+use crate::fonts::{FontChain, FontSettings};
+use crate::ui::Anchor;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LabelId(usize);
+
+pub struct Label {
+    buffer: glyphon::Buffer,
+    text: String,
+    // Offset from the anchor, placed again whenever the text or the screen changes.
+    anchor: Anchor,
+    position: (f32, f32),
+    bounds: Option<TextBounds>,
+    color: Color,
+    visible: bool,
+}
+
+// Labels are only re-shaped when their text changes and the renderer only runs
+// `prepare` when something was damaged since the last frame, otherwise the
+// vertices uploaded by the previous prepare are drawn again as they are.
+pub struct TextLayer {
+    font_settings: FontSettings,
+    font_system: FontSystem,
+    font_chain: FontChain,
+    cache: SwashCache,
+    atlas: TextAtlas,
+    renderer: TextRenderer,
+    // Removed labels leave a hole that the next added label fills.
+    labels: Vec<Option<Label>>,
+    resolution: Resolution,
+    format: TextureFormat,
+    visible: bool,
+    dirty: bool,
+}
+
+// Width of the widest line and height of all lines, as last shaped.
+fn buffer_size(buffer: &glyphon::Buffer) -> (f32, f32) {
+    let line_height = buffer.metrics().line_height;
+    buffer
+        .layout_runs()
+        .fold((0.0, 0.0), |(width, height), run| {
+            (width.max(run.line_w), height + line_height)
+        })
+}
+
+fn shape_label(
+    buffer: &mut glyphon::Buffer,
+    font_system: &mut FontSystem,
+    font_chain: &mut FontChain,
+    text: &str,
+) {
+    let spans = font_chain.spans(font_system, text);
+    buffer.set_rich_text(
+        font_system,
+        spans
+            .into_iter()
+            .map(|(span, family)| (span, font_chain.attrs(family))),
+        Shaping::Advanced,
+    );
+}
+
+impl TextLayer {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let mut atlas = TextAtlas::new(device, queue, format);
+        let renderer = TextRenderer::new(&mut atlas, device, MultisampleState::default(), None);
+        let font_settings = FontSettings::default();
+        let (font_system, font_chain) = FontChain::load(&font_settings);
+
+        Self {
+            font_settings,
+            font_system,
+            font_chain,
+            cache: SwashCache::new(),
+            atlas,
+            renderer,
+            labels: vec![],
+            resolution: Resolution { width, height },
+            format,
+            visible: true,
+            dirty: true,
+        }
+    }
+
+    pub fn font_settings(&self) -> &FontSettings {
+        &self.font_settings
+    }
+
+    // Font ids change with the new database, so glyphs cached in the atlas can't be
+    // reused and every label is shaped again.
+    pub fn set_font_settings(&mut self, device: &Device, queue: &Queue, settings: FontSettings) {
+        let (font_system, font_chain) = FontChain::load(&settings);
+        self.font_settings = settings;
+        self.font_system = font_system;
+        self.font_chain = font_chain;
+        self.cache = SwashCache::new();
+        self.atlas = TextAtlas::new(device, queue, self.format);
+        self.renderer =
+            TextRenderer::new(&mut self.atlas, device, MultisampleState::default(), None);
+
+        for label in self.labels.iter_mut().flatten() {
+            let metrics = label.buffer.metrics();
+            label.buffer = glyphon::Buffer::new(&mut self.font_system, metrics);
+            label.buffer.set_size(
+                &mut self.font_system,
+                self.resolution.width as f32,
+                self.resolution.height as f32,
+            );
+            shape_label(
+                &mut label.buffer,
+                &mut self.font_system,
+                &mut self.font_chain,
+                &label.text,
+            );
+        }
+        self.dirty = true;
+    }
+
+    pub fn add_label(
+        &mut self,
+        metrics: Metrics,
+        position: (f32, f32),
+        bounds: Option<TextBounds>,
+        color: Color,
+    ) -> LabelId {
+        self.add_anchored_label(metrics, Anchor::TopLeft, position, bounds, color)
+    }
+
+    pub fn add_anchored_label(
+        &mut self,
+        metrics: Metrics,
+        anchor: Anchor,
+        offset: (f32, f32),
+        bounds: Option<TextBounds>,
+        color: Color,
+    ) -> LabelId {
+        let mut buffer = glyphon::Buffer::new(&mut self.font_system, metrics);
+        buffer.set_size(
+            &mut self.font_system,
+            self.resolution.width as f32,
+            self.resolution.height as f32,
+        );
+
+        let label = Label {
+            buffer,
+            text: String::new(),
+            anchor,
+            position: offset,
+            bounds,
+            color,
+            visible: true,
+        };
+        self.dirty = true;
+
+        match self.labels.iter().position(Option::is_none) {
+            Some(idx) => {
+                self.labels[idx] = Some(label);
+                LabelId(idx)
+            }
+            None => {
+                self.labels.push(Some(label));
+                LabelId(self.labels.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove_label(&mut self, id: LabelId) {
+        if self.labels[id.0].take().is_some() {
+            self.dirty = true;
+        }
+    }
+
+    fn label_mut(&mut self, id: LabelId) -> &mut Label {
+        self.labels[id.0].as_mut().expect("label was removed")
+    }
+
+    pub fn set_text(&mut self, id: LabelId, text: &str) {
+        let label = self.labels[id.0].as_mut().expect("label was removed");
+        if label.text == text {
+            return;
+        }
+
+        label.text.clear();
+        label.text.push_str(text);
+        shape_label(
+            &mut label.buffer,
+            &mut self.font_system,
+            &mut self.font_chain,
+            text,
+        );
+        self.dirty = true;
+    }
+
+    pub fn set_label_visible(&mut self, id: LabelId, visible: bool) {
+        let label = self.label_mut(id);
+        if label.visible != visible {
+            label.visible = visible;
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_label_position(&mut self, id: LabelId, position: (f32, f32)) {
+        let label = self.label_mut(id);
+        if label.position != position {
+            label.position = position;
+            self.dirty = true;
+        }
+    }
+
+    pub fn set_label_anchor(&mut self, id: LabelId, anchor: Anchor, offset: (f32, f32)) {
+        let label = self.label_mut(id);
+        if label.anchor != anchor || label.position != offset {
+            label.anchor = anchor;
+            label.position = offset;
+            self.dirty = true;
+        }
+    }
+
+    pub fn label_size(&self, id: LabelId) -> (f32, f32) {
+        buffer_size(
+            &self.labels[id.0]
+                .as_ref()
+                .expect("label was removed")
+                .buffer,
+        )
+    }
+
+    pub fn set_visible(&mut self, visible: bool) {
+        if self.visible != visible {
+            self.visible = visible;
+            self.dirty = true;
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.resolution = Resolution { width, height };
+        for label in self.labels.iter_mut().flatten() {
+            label
+                .buffer
+                .set_size(&mut self.font_system, width as f32, height as f32);
+        }
+        self.dirty = true;
+    }
+
+    pub fn prepare(&mut self, device: &Device, queue: &Queue) {
+        if !self.dirty {
+            return;
+        }
+
+        let resolution = self.resolution;
+        let text_areas = self
+            .labels
+            .iter()
+            .flatten()
+            .filter(|label| self.visible && label.visible && !label.text.is_empty())
+            .map(|label| {
+                let (left, top) = label.anchor.place(
+                    (resolution.width as f32, resolution.height as f32),
+                    buffer_size(&label.buffer),
+                    label.position,
+                );
+                TextArea {
+                    buffer: &label.buffer,
+                    left,
+                    top,
+                    scale: 1.0,
+                    bounds: label.bounds.unwrap_or(TextBounds {
+                        left: 0,
+                        top: 0,
+                        right: resolution.width as i32,
+                        bottom: resolution.height as i32,
+                    }),
+                    default_color: label.color,
+                }
+            });
+
+        self.renderer
+            .prepare(
+                device,
+                queue,
+                &mut self.font_system,
+                &mut self.atlas,
+                resolution,
+                text_areas,
+                &mut self.cache,
+            )
+            .unwrap();
+        // Trimming only after a prepare keeps the glyphs referenced by the cached
+        // vertices alive while nothing changes.
+        self.atlas.trim();
+        self.dirty = false;
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        self.renderer.render(&self.atlas, render_pass).unwrap();
+    }
+}
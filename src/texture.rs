@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use image::{DynamicImage, GenericImageView};
 use wgpu::{
     AddressMode, CompareFunction, Device, Extent3d, FilterMode, Queue, Sampler, SamplerDescriptor,
@@ -6,6 +6,10 @@ use wgpu::{
     TextureView, TextureViewDescriptor,
 };
 
+use crate::ktx2::Ktx2;
+use crate::logging;
+use crate::texture_streaming::Mip;
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: TextureView,
@@ -35,7 +39,17 @@ impl Texture {
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
+        Self::from_rgba(device, queue, dimensions, &rgba, label, is_normal_map)
+    }
 
+    pub fn from_rgba(
+        device: &Device,
+        queue: &Queue,
+        dimensions: (u32, u32),
+        rgba: &[u8],
+        label: Option<&str>,
+        is_normal_map: bool,
+    ) -> Result<Self> {
         let size = Extent3d {
             width: dimensions.0,
             height: dimensions.1,
@@ -66,7 +80,7 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            rgba,
             wgpu::ImageDataLayout {
                 offset: 0,
                 bytes_per_row: Some(4 * dimensions.0),
@@ -93,9 +107,189 @@ impl Texture {
         })
     }
 
+    // Texture made of the given mips, the first one being the full size.
+    pub fn from_mips(
+        device: &Device,
+        queue: &Queue,
+        mips: &[Mip],
+        label: Option<&str>,
+        is_normal_map: bool,
+    ) -> Result<Self> {
+        let Some(top) = mips.first() else {
+            return Err(anyhow!("texture needs at least one mip"));
+        };
+        let size = Extent3d {
+            width: top.width,
+            height: top.height,
+            depth_or_array_layers: 1,
+        };
+
+        let format = if is_normal_map {
+            TextureFormat::Rgba8Unorm
+        } else {
+            TextureFormat::Rgba8UnormSrgb
+        };
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label,
+            size,
+            mip_level_count: mips.len() as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (level, mip) in mips.iter().enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                &mip.rgba,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * mip.width),
+                    rows_per_image: Some(mip.height),
+                },
+                Extent3d {
+                    width: mip.width,
+                    height: mip.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    // Compressed levels go to the GPU as they are when the device can sample the
+    // format, otherwise the ones that can be get decoded to RGBA8 first.
+    pub fn from_ktx2(
+        device: &Device,
+        queue: &Queue,
+        data: &[u8],
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let ktx2 = Ktx2::parse(data)?;
+        if !device.features().contains(ktx2.required_features()) {
+            if !ktx2.can_decode() {
+                return Err(anyhow!(
+                    "{:?} isn't supported by the device and can't be decoded",
+                    ktx2.format
+                ));
+            }
+            tracing::warn!(
+                target: logging::ASSETS,
+                "{:?} isn't supported by the device, decoding {} on the CPU",
+                ktx2.format,
+                label.unwrap_or("texture")
+            );
+            let mips = (0..ktx2.levels.len())
+                .map(|level| {
+                    Ok(Mip {
+                        width: ktx2.levels[level].width,
+                        height: ktx2.levels[level].height,
+                        rgba: ktx2.decode_rgba(level)?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            return Self::from_mips(device, queue, &mips, label, !ktx2.format.is_srgb());
+        }
+
+        let format = ktx2.format;
+        let (block_width, block_height) = format.block_dimensions();
+        let block_size = format.block_copy_size(None).unwrap();
+        let top = &ktx2.levels[0];
+        let size = Extent3d {
+            width: top.width,
+            height: top.height,
+            depth_or_array_layers: 1,
+        };
+        if top.width % block_width != 0 || top.height % block_height != 0 {
+            return Err(anyhow!(
+                "{}x{} isn't a multiple of the {block_width}x{block_height} blocks of {format:?}",
+                top.width,
+                top.height
+            ));
+        }
+
+        let texture = device.create_texture(&TextureDescriptor {
+            label,
+            size,
+            mip_level_count: ktx2.levels.len() as u32,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (level, mip) in ktx2.levels.iter().enumerate() {
+            // Mips smaller than a block still take a whole one.
+            let extent = Extent3d {
+                width: mip.width,
+                height: mip.height,
+                depth_or_array_layers: 1,
+            }
+            .physical_size(format);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                &mip.data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(extent.width / block_width * block_size),
+                    rows_per_image: Some(extent.height / block_height),
+                },
+                extent,
+            );
+        }
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
     pub fn create_depth_texture(
         device: &Device,
         config: &SurfaceConfiguration,
+        sample_count: u32,
         label: &str,
     ) -> Self {
         let size = Extent3d {
@@ -104,14 +298,22 @@ impl Texture {
             depth_or_array_layers: 1,
         };
 
+        // Multisampled depth can't be sampled as a regular texture, so it's only bindable
+        // when MSAA is off.
+        let usage = if sample_count > 1 {
+            TextureUsages::RENDER_ATTACHMENT
+        } else {
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING
+        };
+
         let desc = TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            usage,
             view_formats: &[],
         };
 
@@ -0,0 +1,261 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use wgpu::{Device, Queue};
+
+use crate::logging;
+use crate::texture::Texture;
+
+pub const DEFAULT_TEXTURE_BUDGET: u64 = 64 << 20;
+// Mips this size or smaller are loaded up front and never evicted.
+const RESIDENT_MIP_SIZE: u32 = 32;
+// Textures used closer than this get their full resolution, every time the
+// distance doubles they need one mip less.
+const FULL_DETAIL_DISTANCE: f32 = 32.0;
+const STREAM_UPLOADS_PER_FRAME: usize = 2;
+
+pub type StreamedTextureId = usize;
+
+pub struct Mip {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Mip {
+    fn bytes(&self) -> u64 {
+        self.rgba.len() as u64
+    }
+}
+
+// Full chain down to 1x1, each level a 2x2 box filter of the one above.
+pub fn mip_chain(width: u32, height: u32, rgba: Vec<u8>) -> Vec<Mip> {
+    let mut mips = vec![Mip {
+        width,
+        height,
+        rgba,
+    }];
+    loop {
+        let last = mips.last().unwrap();
+        if last.width == 1 && last.height == 1 {
+            break;
+        }
+
+        let (width, height) = ((last.width / 2).max(1), (last.height / 2).max(1));
+        let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0u32; 4];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let sx = (x * 2 + dx).min(last.width - 1);
+                    let sy = (y * 2 + dy).min(last.height - 1);
+                    let i = ((sy * last.width + sx) * 4) as usize;
+                    for (channel, total) in sum.iter_mut().enumerate() {
+                        *total += last.rgba[i + channel] as u32;
+                    }
+                }
+                rgba.extend(sum.map(|total| (total / 4) as u8));
+            }
+        }
+        mips.push(Mip {
+            width,
+            height,
+            rgba,
+        });
+    }
+
+    mips
+}
+
+struct StreamedTexture {
+    label: String,
+    is_normal_map: bool,
+    mips: Vec<Mip>,
+    // Highest detail level on the GPU, everything below it is resident too.
+    resident: usize,
+    // Level the small mips start from, what stays loaded whatever happens.
+    base: usize,
+    target: usize,
+}
+
+impl StreamedTexture {
+    fn cost(&self, level: usize) -> u64 {
+        self.mips[level..].iter().map(Mip::bytes).sum()
+    }
+
+    fn upload(&self, device: &Device, queue: &Queue, level: usize) -> Result<Texture> {
+        Texture::from_mips(
+            device,
+            queue,
+            &self.mips[level..],
+            Some(&self.label),
+            self.is_normal_map,
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextureStreamStats {
+    pub textures: usize,
+    pub resident_bytes: u64,
+    pub budget: u64,
+    // Textures still missing mips they want.
+    pub pending: usize,
+    pub uploads: u64,
+}
+
+// Keeps the mips of every streamed texture in memory and decides which of them
+// live on the GPU. Textures start with only their small mips, the ones used by
+// visible models get higher mips the closer they are, nearest first, as long as
+// the resident total fits the budget. Changes are uploaded a few per frame by
+// creating the texture again with the new mip range.
+pub struct TextureStreamer {
+    textures: Vec<StreamedTexture>,
+    requests: HashMap<StreamedTextureId, f32>,
+    budget: u64,
+    uploads: u64,
+}
+
+impl TextureStreamer {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            textures: vec![],
+            requests: HashMap::new(),
+            budget,
+            uploads: 0,
+        }
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    pub fn set_budget(&mut self, budget: u64) {
+        self.budget = budget;
+    }
+
+    // Returns the texture with only its small mips resident.
+    pub fn add(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        label: &str,
+        dimensions: (u32, u32),
+        rgba: Vec<u8>,
+        is_normal_map: bool,
+    ) -> Result<(StreamedTextureId, Texture)> {
+        let mips = mip_chain(dimensions.0, dimensions.1, rgba);
+        let base = mips
+            .iter()
+            .position(|mip| mip.width.max(mip.height) <= RESIDENT_MIP_SIZE)
+            .unwrap();
+        let texture = StreamedTexture {
+            label: label.to_string(),
+            is_normal_map,
+            mips,
+            resident: base,
+            base,
+            target: base,
+        };
+        let gpu_texture = texture.upload(device, queue, base)?;
+        self.textures.push(texture);
+
+        Ok((self.textures.len() - 1, gpu_texture))
+    }
+
+    // Marks the texture as used this frame by something `distance` away.
+    pub fn request(&mut self, id: StreamedTextureId, distance: f32) {
+        let nearest = self.requests.entry(id).or_insert(f32::INFINITY);
+        *nearest = nearest.min(distance);
+    }
+
+    fn wanted_level(&self, id: StreamedTextureId) -> usize {
+        let texture = &self.textures[id];
+        match self.requests.get(&id) {
+            Some(distance) => {
+                let drop = (distance.max(FULL_DETAIL_DISTANCE) / FULL_DETAIL_DISTANCE).log2();
+                (drop as usize).min(texture.base)
+            }
+            None => texture.base,
+        }
+    }
+
+    // Plans the levels for this frame's requests and uploads the first changes,
+    // evictions before anything new. Returns the textures that got recreated.
+    pub fn update(&mut self, device: &Device, queue: &Queue) -> Vec<(StreamedTextureId, Texture)> {
+        let mut order = (0..self.textures.len()).collect::<Vec<_>>();
+        order.sort_by(|a, b| {
+            let distance = |id| self.requests.get(id).copied().unwrap_or(f32::INFINITY);
+            distance(a).total_cmp(&distance(b))
+        });
+
+        let mut used = self
+            .textures
+            .iter()
+            .map(|texture| texture.cost(texture.base))
+            .sum::<u64>();
+        for id in order.iter().copied() {
+            let mut level = self.wanted_level(id);
+            let texture = &self.textures[id];
+            while level < texture.base
+                && used + texture.cost(level) - texture.cost(texture.base) > self.budget
+            {
+                level += 1;
+            }
+            used += texture.cost(level) - texture.cost(texture.base);
+            self.textures[id].target = level;
+        }
+        self.requests.clear();
+
+        let evictions = order
+            .iter()
+            .rev()
+            .filter(|id| self.textures[**id].target > self.textures[**id].resident);
+        let loads = order
+            .iter()
+            .filter(|id| self.textures[**id].target < self.textures[**id].resident);
+        let changes = evictions
+            .chain(loads)
+            .copied()
+            .take(STREAM_UPLOADS_PER_FRAME)
+            .collect::<Vec<_>>();
+
+        let mut uploaded = vec![];
+        for id in changes {
+            let texture = &mut self.textures[id];
+            match texture.upload(device, queue, texture.target) {
+                Ok(gpu_texture) => {
+                    texture.resident = texture.target;
+                    self.uploads += 1;
+                    uploaded.push((id, gpu_texture));
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        target: logging::ASSETS,
+                        "Couldn't stream {}: {e}",
+                        texture.label
+                    )
+                }
+            }
+        }
+
+        uploaded
+    }
+
+    pub fn stats(&self) -> TextureStreamStats {
+        TextureStreamStats {
+            textures: self.textures.len(),
+            resident_bytes: self
+                .textures
+                .iter()
+                .map(|texture| texture.cost(texture.resident))
+                .sum(),
+            budget: self.budget,
+            pending: self
+                .textures
+                .iter()
+                .filter(|texture| texture.target < texture.resident)
+                .count(),
+            uploads: self.uploads,
+        }
+    }
+}
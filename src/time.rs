@@ -0,0 +1,37 @@
+use bytemuck::{Pod, Zeroable};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+pub struct TimeUniform {
+    elapsed: f32,
+    delta: f32,
+    // App::tick_alpha and the tick length, for interpolating things that move
+    // on ticks.
+    tick_alpha: f32,
+    tick_length: f32,
+}
+
+impl TimeUniform {
+    pub fn new() -> Self {
+        Self {
+            elapsed: 0.0,
+            delta: 0.0,
+            tick_alpha: 1.0,
+            tick_length: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.elapsed += dt;
+        self.delta = dt;
+    }
+
+    pub fn set_tick(&mut self, alpha: f32, length: f32) {
+        self.tick_alpha = alpha;
+        self.tick_length = length;
+    }
+
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+}
@@ -0,0 +1,122 @@
+use anyhow::{anyhow, Result};
+use glam::{Mat4, Quat, Vec3};
+use std::collections::HashMap;
+use std::mem;
+use uuid::Uuid;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn from_position(position: Vec3) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.position)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl From<Transform> for Mat4 {
+    fn from(transform: Transform) -> Self {
+        transform.matrix()
+    }
+}
+
+// Local transforms of the models and what they're attached to. A model's world
+// transform is its parent's world transform times its own, parents don't need
+// to have a transform themselves.
+#[derive(Default)]
+pub struct TransformHierarchy {
+    local: HashMap<Uuid, Mat4>,
+    parents: HashMap<Uuid, Uuid>,
+    dirty: bool,
+}
+
+impl TransformHierarchy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn local(&self, id: &Uuid) -> Option<Mat4> {
+        self.local.get(id).copied()
+    }
+
+    pub fn set_local(&mut self, id: Uuid, matrix: Mat4) {
+        self.local.insert(id, matrix);
+        self.dirty = true;
+    }
+
+    // Keeps the current transform when the model already had one, so rebuilding
+    // it doesn't snap it back to where it started.
+    pub fn insert(&mut self, id: Uuid, matrix: Mat4) {
+        if !self.local.contains_key(&id) {
+            self.set_local(id, matrix);
+        }
+    }
+
+    pub fn parent(&self, id: &Uuid) -> Option<&Uuid> {
+        self.parents.get(id)
+    }
+
+    pub fn set_parent(&mut self, id: Uuid, parent: Option<Uuid>) -> Result<()> {
+        match parent {
+            Some(parent) => {
+                let mut ancestor = Some(&parent);
+                while let Some(current) = ancestor {
+                    if *current == id {
+                        return Err(anyhow!("can't attach {id} to its own descendant {parent}"));
+                    }
+                    ancestor = self.parents.get(current);
+                }
+                self.parents.insert(id, parent);
+            }
+            None => {
+                self.parents.remove(&id);
+            }
+        }
+
+        self.dirty = true;
+        Ok(())
+    }
+
+    // Children of a removed model stay where the hierarchy leaves them, detached.
+    pub fn remove(&mut self, id: &Uuid) {
+        self.local.remove(id);
+        self.parents.remove(id);
+        self.parents.retain(|_, parent| parent != id);
+        self.dirty = true;
+    }
+
+    pub fn world(&self, id: &Uuid) -> Mat4 {
+        let mut matrix = self.local(id).unwrap_or(Mat4::IDENTITY);
+        let mut ancestor = self.parents.get(id);
+        while let Some(parent) = ancestor {
+            matrix = self.local(parent).unwrap_or(Mat4::IDENTITY) * matrix;
+            ancestor = self.parents.get(parent);
+        }
+
+        matrix
+    }
+
+    pub fn take_dirty(&mut self) -> bool {
+        mem::take(&mut self.dirty)
+    }
+}
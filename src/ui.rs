@@ -1,130 +1,823 @@
-// use glyphon::{Font, FontSystem};
-// use std::rc::Rc;
-//
-// use wgpu::{util::StagingBelt, CommandEncoder, Device, TextureFormat, TextureView};
-//
-// pub trait Component {
-//     fn render(&self, glyph_brush: &mut GlyphBrush<()>);
-// }
-//
-// pub struct Label {
-//     position: (f32, f32),
-//     bounds: (f32, f32),
-//     text: String,
-//     color: [f32; 4],
-// }
-//
-// impl Label {
-//     #[inline]
-//     pub fn new(position: (f32, f32), bounds: (f32, f32), text: String, color: [f32; 4]) -> Self {
-//         Self {
-//             position,
-//             bounds,
-//             text,
-//             color,
-//         }
-//     }
-//
-//     #[inline]
-//     pub fn with_position(mut self, position: (f32, f32)) -> Self {
-//         self.position = position;
-//         self
-//     }
-//
-//     #[inline]
-//     pub fn with_bounds(mut self, bounds: (f32, f32)) -> Self {
-//         self.bounds = bounds;
-//         self
-//     }
-//
-//     #[inline]
-//     pub fn with_text<S: Into<String>>(mut self, str: S) -> Self {
-//         self.text = str.into();
-//         self
-//     }
-//
-//     #[inline]
-//     pub fn with_color(mut self, color: [f32; 4]) -> Self {
-//         self.color = color;
-//         self
-//     }
-//
-//     #[inline]
-//     pub fn set_text<S: Into<String>>(&mut self, str: S) {
-//         self.text = str.into();
-//     }
-// }
-//
-// impl Default for Label {
-//     #[inline]
-//     fn default() -> Self {
-//         Self::new(
-//             (0.0, 0.0),
-//             (1920.0, 1080.0),
-//             String::new(),
-//             [1.0, 1.0, 1.0, 1.0],
-//         )
-//     }
-// }
-//
-// impl Component for Label {
-//     #[inline]
-//     fn render(&self, glyph_brush: &mut GlyphBrush<()>) {
-//         glyph_brush.queue(Section {
-//             screen_position: self.position,
-//             bounds: self.bounds,
-//             text: vec![Text::new(&self.text).with_color(self.color)],
-//             ..Default::default()
-//         });
-//     }
-// }
-//
-// pub struct UI {
-//     device: Rc<Device>,
-// }
-//
-// impl UI {
-//     pub fn new(device: Rc<Device>, format: TextureFormat) -> Self {
-//         let font = FontSystem::new();
-//
-//         let glyph_brush = GlyphBrushBuilder::using_font(font).build(&device, format);
-//         let staging_belt = StagingBelt::new(1024);
-//
-//         Self {
-//             glyph_brush,
-//             staging_belt,
-//             device,
-//         }
-//     }
-//
-//     #[inline]
-//     pub fn render<C: Component>(&mut self, component: &C) {
-//         component.render(&mut self.glyph_brush);
-//     }
-//
-//     #[inline]
-//     pub fn draw(
-//         &mut self,
-//         encoder: &mut CommandEncoder,
-//         view: &TextureView,
-//         width: u32,
-//         height: u32,
-//     ) -> Result<(), String> {
-//         self.glyph_brush.draw_queued(
-//             &self.device,
-//             &mut self.staging_belt,
-//             encoder,
-//             view,
-//             width,
-//             height,
-//         )?;
-//         self.staging_belt.finish();
-//
-//         Ok(())
-//     }
-//
-//     #[inline]
-//     pub fn recall(&mut self) {
-//         self.staging_belt.recall();
-//     }
-// }
+use bytemuck::{cast_slice, Pod, Zeroable};
+use glam::Vec2;
+use glyphon::{Color, Metrics};
+use std::mem;
+use std::mem::size_of;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    Buffer, BufferAddress, BufferUsages, Device, PipelineLayoutDescriptor, PolygonMode, Queue,
+    RenderPass, RenderPipeline, ShaderModuleDescriptor, ShaderSource, TextureFormat,
+    VertexAttribute, VertexBufferLayout, VertexFormat, VertexStepMode,
+};
+use winit::event::MouseButton;
+
+use crate::command_buffer::{NCommandUpdate, RenderLayer};
+use crate::input::InputState;
+use crate::model::Vertex;
+use crate::text::{LabelId, TextLayer};
+use crate::{create_render_pipeline, PipelineDesc};
+
+// Space between a button's border and its text when it sizes itself.
+const BUTTON_PADDING: Vec2 = Vec2::new(12.0, 6.0);
+const DEFAULT_METRICS: Metrics = Metrics::new(18.0, 24.0);
+
+pub type UiCallback = Box<dyn FnMut() -> Vec<NCommandUpdate>>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct UiRootId(usize);
+
+// Where on the screen a root widget sits, the offset moves it away from that
+// corner or edge towards the middle.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    // Top left corner of something `size` big on a `screen` sized target.
+    pub fn place<V: Into<Vec2> + From<Vec2>>(&self, screen: V, size: V, offset: V) -> V {
+        let (screen, size, offset) = (screen.into(), size.into(), offset.into());
+        let (x, y) = match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        };
+        let factor = Vec2::new(x, y);
+        let direction = Vec2::ONE - factor * 2.0;
+        V::from((screen - size) * factor + offset * direction)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Vertical,
+    Horizontal,
+}
+
+// Children one after the other, inset by the padding.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stack {
+    pub direction: Direction,
+    pub spacing: f32,
+    pub padding: f32,
+}
+
+impl Stack {
+    pub fn vertical(spacing: f32, padding: f32) -> Self {
+        Self {
+            direction: Direction::Vertical,
+            spacing,
+            padding,
+        }
+    }
+
+    pub fn horizontal(spacing: f32, padding: f32) -> Self {
+        Self {
+            direction: Direction::Horizontal,
+            spacing,
+            padding,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ButtonStyle {
+    pub normal: [f32; 4],
+    pub hovered: [f32; 4],
+    pub pressed: [f32; 4],
+    pub text: Color,
+}
+
+impl Default for ButtonStyle {
+    fn default() -> Self {
+        Self {
+            normal: [0.2, 0.2, 0.25, 0.9],
+            hovered: [0.3, 0.3, 0.38, 0.9],
+            pressed: [0.12, 0.12, 0.15, 0.9],
+            text: Color::rgb(255, 255, 255),
+        }
+    }
+}
+
+// Four arms around the middle of the screen, `gap` away from it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Crosshair {
+    pub size: f32,
+    pub thickness: f32,
+    pub gap: f32,
+    pub color: [f32; 4],
+}
+
+impl Crosshair {
+    fn push_quads(&self, screen: Vec2, srgb: bool, vertices: &mut Vec<UiVertex>) {
+        let center = (screen / 2.0).round();
+        let half = self.thickness / 2.0;
+        let horizontal = Vec2::new(self.size, self.thickness);
+        let vertical = Vec2::new(self.thickness, self.size);
+        for (min, size) in [
+            (Vec2::new(-self.gap - self.size, -half), horizontal),
+            (Vec2::new(self.gap, -half), horizontal),
+            (Vec2::new(-half, -self.gap - self.size), vertical),
+            (Vec2::new(-half, self.gap), vertical),
+        ] {
+            let rect = Rect {
+                min: center + min,
+                size,
+            };
+            push_quad(rect, self.color, screen, srgb, vertices);
+        }
+    }
+}
+
+impl Default for Crosshair {
+    fn default() -> Self {
+        Self {
+            size: 8.0,
+            thickness: 2.0,
+            gap: 3.0,
+            color: [1.0, 1.0, 1.0, 0.8],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+enum ButtonState {
+    #[default]
+    Normal,
+    Hovered,
+    Pressed,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct Rect {
+    min: Vec2,
+    size: Vec2,
+}
+
+impl Rect {
+    fn contains(&self, point: Vec2) -> bool {
+        point.cmpge(self.min).all() && point.cmplt(self.min + self.size).all()
+    }
+}
+
+enum WidgetKind {
+    Panel {
+        color: [f32; 4],
+        stack: Stack,
+        children: Vec<Widget>,
+    },
+    Button {
+        text: String,
+        style: ButtonStyle,
+        on_click: Option<UiCallback>,
+        state: ButtonState,
+    },
+    Label {
+        text: String,
+        color: Color,
+    },
+}
+
+pub struct Widget {
+    kind: WidgetKind,
+    name: Option<String>,
+    // Fixed size, otherwise it fits the text or the children.
+    size: Option<Vec2>,
+    metrics: Metrics,
+    label: Option<LabelId>,
+    rect: Rect,
+}
+
+impl Widget {
+    fn new(kind: WidgetKind) -> Self {
+        Self {
+            kind,
+            name: None,
+            size: None,
+            metrics: DEFAULT_METRICS,
+            label: None,
+            rect: Rect::default(),
+        }
+    }
+
+    // Colors are in sRGB, like the text's.
+    pub fn panel(color: [f32; 4], stack: Stack) -> Self {
+        Self::new(WidgetKind::Panel {
+            color,
+            stack,
+            children: vec![],
+        })
+    }
+
+    pub fn button<S: Into<String>>(text: S) -> Self {
+        Self::new(WidgetKind::Button {
+            text: text.into(),
+            style: ButtonStyle::default(),
+            on_click: None,
+            state: ButtonState::Normal,
+        })
+    }
+
+    pub fn label<S: Into<String>>(text: S, color: Color) -> Self {
+        Self::new(WidgetKind::Label {
+            text: text.into(),
+            color,
+        })
+    }
+
+    pub fn with_child(mut self, child: Widget) -> Self {
+        if let WidgetKind::Panel { children, .. } = &mut self.kind {
+            children.push(child);
+        }
+        self
+    }
+
+    // Lets the widget's text be changed later through its root.
+    pub fn with_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_size(mut self, size: Vec2) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_style(mut self, button_style: ButtonStyle) -> Self {
+        if let WidgetKind::Button { style, .. } = &mut self.kind {
+            *style = button_style;
+        }
+        self
+    }
+
+    // Runs when the button is released over itself after being pressed on it,
+    // the commands get run by the app like an actor's.
+    pub fn on_click<F: FnMut() -> Vec<NCommandUpdate> + 'static>(mut self, callback: F) -> Self {
+        if let WidgetKind::Button { on_click, .. } = &mut self.kind {
+            *on_click = Some(Box::new(callback));
+        }
+        self
+    }
+
+    fn add_labels(&mut self, text_layer: &mut TextLayer) {
+        let (text, color) = match &mut self.kind {
+            WidgetKind::Panel { children, .. } => {
+                for child in children.iter_mut() {
+                    child.add_labels(text_layer);
+                }
+                return;
+            }
+            WidgetKind::Button { text, style, .. } => (text.as_str(), style.text),
+            WidgetKind::Label { text, color } => (text.as_str(), *color),
+        };
+        let label = text_layer.add_label(self.metrics, (0.0, 0.0), None, color);
+        text_layer.set_text(label, text);
+        self.label = Some(label);
+    }
+
+    fn remove_labels(&self, text_layer: &mut TextLayer) {
+        if let Some(label) = self.label {
+            text_layer.remove_label(label);
+        }
+        if let WidgetKind::Panel { children, .. } = &self.kind {
+            for child in children.iter() {
+                child.remove_labels(text_layer);
+            }
+        }
+    }
+
+    fn find_mut(&mut self, name: &str) -> Option<&mut Widget> {
+        if self.name.as_deref() == Some(name) {
+            return Some(self);
+        }
+        match &mut self.kind {
+            WidgetKind::Panel { children, .. } => {
+                children.iter_mut().find_map(|child| child.find_mut(name))
+            }
+            _ => None,
+        }
+    }
+
+    fn measure(&self, text_layer: &TextLayer) -> Vec2 {
+        if let Some(size) = self.size {
+            return size;
+        }
+
+        match &self.kind {
+            WidgetKind::Panel {
+                stack, children, ..
+            } => {
+                let sizes = children
+                    .iter()
+                    .map(|child| child.measure(text_layer))
+                    .collect::<Vec<_>>();
+                let spacing = stack.spacing * sizes.len().saturating_sub(1) as f32;
+                let content = match stack.direction {
+                    Direction::Vertical => Vec2::new(
+                        sizes.iter().map(|size| size.x).fold(0.0, f32::max),
+                        sizes.iter().map(|size| size.y).sum::<f32>() + spacing,
+                    ),
+                    Direction::Horizontal => Vec2::new(
+                        sizes.iter().map(|size| size.x).sum::<f32>() + spacing,
+                        sizes.iter().map(|size| size.y).fold(0.0, f32::max),
+                    ),
+                };
+                content + Vec2::splat(stack.padding * 2.0)
+            }
+            WidgetKind::Button { .. } => self.text_size(text_layer) + BUTTON_PADDING * 2.0,
+            WidgetKind::Label { .. } => self.text_size(text_layer),
+        }
+    }
+
+    fn text_size(&self, text_layer: &TextLayer) -> Vec2 {
+        self.label
+            .map_or(Vec2::ZERO, |label| text_layer.label_size(label).into())
+    }
+
+    fn layout(&mut self, text_layer: &mut TextLayer, min: Vec2) {
+        let size = self.measure(text_layer);
+        self.rect = Rect { min, size };
+
+        if let Some(label) = self.label {
+            // Buttons center their text, labels start at their corner.
+            let offset = match self.kind {
+                WidgetKind::Button { .. } => (size - self.text_size(text_layer)) / 2.0,
+                _ => Vec2::ZERO,
+            };
+            text_layer.set_label_position(label, (min + offset).into());
+        }
+
+        if let WidgetKind::Panel {
+            stack, children, ..
+        } = &mut self.kind
+        {
+            let mut cursor = min + Vec2::splat(stack.padding);
+            for child in children.iter_mut() {
+                child.layout(text_layer, cursor);
+                match stack.direction {
+                    Direction::Vertical => cursor.y += child.rect.size.y + stack.spacing,
+                    Direction::Horizontal => cursor.x += child.rect.size.x + stack.spacing,
+                }
+            }
+        }
+    }
+
+    // Sets every button's state from the cursor and collects the clicked ones'
+    // commands, true when some button changed its look.
+    fn update_buttons(
+        &mut self,
+        cursor: Vec2,
+        pressed: bool,
+        released: bool,
+        commands: &mut Vec<NCommandUpdate>,
+    ) -> bool {
+        let inside = self.rect.contains(cursor);
+        match &mut self.kind {
+            WidgetKind::Panel { children, .. } => {
+                children.iter_mut().fold(false, |dirty, child| {
+                    child.update_buttons(cursor, pressed, released, commands) | dirty
+                })
+            }
+            WidgetKind::Button {
+                on_click, state, ..
+            } => {
+                let next = match *state {
+                    ButtonState::Pressed if released && inside => {
+                        if let Some(on_click) = on_click {
+                            commands.extend(on_click());
+                        }
+                        ButtonState::Hovered
+                    }
+                    ButtonState::Pressed if !released => ButtonState::Pressed,
+                    _ if inside && pressed => ButtonState::Pressed,
+                    _ if inside => ButtonState::Hovered,
+                    _ => ButtonState::Normal,
+                };
+                let changed = *state != next;
+                *state = next;
+                changed
+            }
+            WidgetKind::Label { .. } => false,
+        }
+    }
+
+    fn push_quads(&self, screen: Vec2, srgb: bool, vertices: &mut Vec<UiVertex>) {
+        let color = match &self.kind {
+            WidgetKind::Panel { color, .. } => *color,
+            WidgetKind::Button { style, state, .. } => match state {
+                ButtonState::Normal => style.normal,
+                ButtonState::Hovered => style.hovered,
+                ButtonState::Pressed => style.pressed,
+            },
+            WidgetKind::Label { .. } => return,
+        };
+        push_quad(self.rect, color, screen, srgb, vertices);
+
+        if let WidgetKind::Panel { children, .. } = &self.kind {
+            for child in children.iter() {
+                child.push_quads(screen, srgb, vertices);
+            }
+        }
+    }
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn push_quad(rect: Rect, color: [f32; 4], screen: Vec2, srgb: bool, vertices: &mut Vec<UiVertex>) {
+    // sRGB targets expect linear colors and encode them on write.
+    let color = if srgb {
+        [
+            srgb_to_linear(color[0]),
+            srgb_to_linear(color[1]),
+            srgb_to_linear(color[2]),
+            color[3],
+        ]
+    } else {
+        color
+    };
+    let to_clip = |point: Vec2| {
+        let clip = point / screen * 2.0 - Vec2::ONE;
+        [clip.x, -clip.y]
+    };
+    let [left, top] = to_clip(rect.min);
+    let [right, bottom] = to_clip(rect.min + rect.size);
+
+    for position in [
+        [left, bottom],
+        [right, bottom],
+        [right, top],
+        [left, bottom],
+        [right, top],
+        [left, top],
+    ] {
+        vertices.push(UiVertex { position, color });
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct UiVertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+impl Vertex for UiVertex {
+    fn desc() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: size_of::<UiVertex>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: &[
+                VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: VertexFormat::Float32x2,
+                },
+                VertexAttribute {
+                    offset: size_of::<[f32; 2]>() as BufferAddress,
+                    shader_location: 1,
+                    format: VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+// Text drawn by a user component, gathered again every frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComponentText {
+    pub text: String,
+    pub metrics: Metrics,
+    pub anchor: Anchor,
+    pub offset: (f32, f32),
+    pub color: Color,
+}
+
+impl ComponentText {
+    pub fn new<S: Into<String>>(text: S, anchor: Anchor, offset: (f32, f32)) -> Self {
+        Self {
+            text: text.into(),
+            metrics: DEFAULT_METRICS,
+            anchor,
+            offset,
+            color: Color::rgb(255, 255, 255),
+        }
+    }
+
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+}
+
+// Anything that puts text on the screen each frame, through the same text
+// layer as the widgets and the app's own labels.
+pub trait Component {
+    fn text_areas(&mut self) -> Vec<ComponentText>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ComponentId(usize);
+
+struct ComponentSlot {
+    component: Box<dyn Component>,
+    labels: Vec<(LabelId, ComponentText)>,
+}
+
+struct UiRoot {
+    anchor: Anchor,
+    offset: Vec2,
+    widget: Widget,
+}
+
+// Everything drawn over the 3D view: widget trees and the crosshair as quads,
+// with all the text on top of them from one text layer.
+pub struct Ui {
+    text: TextLayer,
+    roots: Vec<Option<UiRoot>>,
+    components: Vec<Option<ComponentSlot>>,
+    crosshair: Option<Crosshair>,
+    pipeline: RenderPipeline,
+    vertex_buffer: Option<(Buffer, u32)>,
+    srgb: bool,
+    screen: Vec2,
+    // The layout needs redoing, or only the quads when just the looks changed.
+    layout_dirty: bool,
+    quads_dirty: bool,
+}
+
+impl Ui {
+    pub fn new(
+        device: &Device,
+        queue: &Queue,
+        format: TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("ui_pipeline_layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_render_pipeline(
+            device,
+            PipelineDesc {
+                layout: &layout,
+                color_format: format,
+                depth_format: None,
+                vertex_layouts: &[UiVertex::desc()],
+                shader: ShaderModuleDescriptor {
+                    label: Some("ui_shader"),
+                    source: ShaderSource::Wgsl(include_str!("../shaders/ui.wgsl").into()),
+                },
+                layer: RenderLayer::Transparent,
+                polygon_mode: PolygonMode::Fill,
+                sample_count: 1,
+                reverse_z: false,
+            },
+        );
+
+        Self {
+            text: TextLayer::new(device, queue, format, width, height),
+            roots: vec![],
+            components: vec![],
+            crosshair: None,
+            pipeline,
+            vertex_buffer: None,
+            srgb: format.is_srgb(),
+            screen: Vec2::new(width as f32, height as f32),
+            layout_dirty: false,
+            quads_dirty: false,
+        }
+    }
+
+    pub fn text(&self) -> &TextLayer {
+        &self.text
+    }
+
+    pub fn text_mut(&mut self) -> &mut TextLayer {
+        &mut self.text
+    }
+
+    pub fn add(&mut self, anchor: Anchor, offset: Vec2, mut widget: Widget) -> UiRootId {
+        widget.add_labels(&mut self.text);
+        let root = Some(UiRoot {
+            anchor,
+            offset,
+            widget,
+        });
+        self.layout_dirty = true;
+
+        match self.roots.iter().position(Option::is_none) {
+            Some(idx) => {
+                self.roots[idx] = root;
+                UiRootId(idx)
+            }
+            None => {
+                self.roots.push(root);
+                UiRootId(self.roots.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: UiRootId) {
+        if let Some(root) = self.roots.get_mut(id.0).and_then(Option::take) {
+            root.widget.remove_labels(&mut self.text);
+            self.layout_dirty = true;
+        }
+    }
+
+    // Changes the text of a button or label named in the tree of `id`.
+    pub fn set_text(&mut self, id: UiRootId, name: &str, text: &str) -> bool {
+        let Some(widget) = self
+            .roots
+            .get_mut(id.0)
+            .and_then(Option::as_mut)
+            .and_then(|root| root.widget.find_mut(name))
+        else {
+            return false;
+        };
+        match &mut widget.kind {
+            WidgetKind::Button { text: old, .. } | WidgetKind::Label { text: old, .. } => {
+                old.clear();
+                old.push_str(text);
+            }
+            WidgetKind::Panel { .. } => return false,
+        }
+        if let Some(label) = widget.label {
+            self.text.set_text(label, text);
+        }
+        self.layout_dirty = true;
+        true
+    }
+
+    pub fn add_component(&mut self, component: Box<dyn Component>) -> ComponentId {
+        let slot = Some(ComponentSlot {
+            component,
+            labels: vec![],
+        });
+        match self.components.iter().position(Option::is_none) {
+            Some(idx) => {
+                self.components[idx] = slot;
+                ComponentId(idx)
+            }
+            None => {
+                self.components.push(slot);
+                ComponentId(self.components.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove_component(&mut self, id: ComponentId) {
+        if let Some(slot) = self.components.get_mut(id.0).and_then(Option::take) {
+            for (label, _) in slot.labels {
+                self.text.remove_label(label);
+            }
+        }
+    }
+
+    // Matches every component's labels to the text it wants now, labels whose
+    // text stayed the same aren't shaped again.
+    fn update_components(&mut self) {
+        for slot in self.components.iter_mut().flatten() {
+            let areas = slot.component.text_areas();
+            for (i, area) in areas.iter().enumerate() {
+                let reusable = slot.labels.get(i).is_some_and(|(_, last)| {
+                    last.metrics == area.metrics && last.color == area.color
+                });
+                if !reusable {
+                    let label = self.text.add_anchored_label(
+                        area.metrics,
+                        area.anchor,
+                        area.offset,
+                        None,
+                        area.color,
+                    );
+                    match slot.labels.get_mut(i) {
+                        Some((old, _)) => self.text.remove_label(mem::replace(old, label)),
+                        None => slot.labels.push((label, area.clone())),
+                    }
+                }
+
+                let (label, last) = &mut slot.labels[i];
+                self.text.set_label_anchor(*label, area.anchor, area.offset);
+                self.text.set_text(*label, &area.text);
+                *last = area.clone();
+            }
+            for (label, _) in slot.labels.drain(areas.len()..) {
+                self.text.remove_label(label);
+            }
+        }
+    }
+
+    pub fn crosshair(&self) -> Option<Crosshair> {
+        self.crosshair
+    }
+
+    pub fn set_crosshair(&mut self, crosshair: Option<Crosshair>) {
+        if self.crosshair != crosshair {
+            self.crosshair = crosshair;
+            self.quads_dirty = true;
+        }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.text.resize(width, height);
+        self.screen = Vec2::new(width as f32, height as f32);
+        self.layout_dirty = true;
+    }
+
+    // Lays out what changed and gathers the components' text, without input
+    // to look at the buttons stay as they are.
+    pub fn update(&mut self) {
+        self.update_components();
+        if self.layout_dirty {
+            for root in self.roots.iter_mut().flatten() {
+                let size = root.widget.measure(&self.text);
+                let min = root.anchor.place(self.screen, size, root.offset);
+                root.widget.layout(&mut self.text, min);
+            }
+            self.layout_dirty = false;
+            self.quads_dirty = true;
+        }
+    }
+
+    // Runs the callbacks of the clicked buttons.
+    pub fn handle_input(&mut self, input_state: &InputState) -> Vec<NCommandUpdate> {
+        let cursor = Vec2::from(input_state.cursor_position());
+        let pressed = input_state.is_mouse_button_just_pressed(MouseButton::Left);
+        let released = input_state.is_mouse_button_just_released(MouseButton::Left);
+        let mut commands = vec![];
+        for root in self.roots.iter_mut().flatten() {
+            self.quads_dirty |=
+                root.widget
+                    .update_buttons(cursor, pressed, released, &mut commands);
+        }
+
+        commands
+    }
+
+    pub fn prepare(&mut self, device: &Device, queue: &Queue) {
+        self.text.prepare(device, queue);
+        if !self.quads_dirty {
+            return;
+        }
+
+        // Under the widgets, so menus cover it.
+        let mut vertices = vec![];
+        if let Some(crosshair) = self.crosshair {
+            crosshair.push_quads(self.screen, self.srgb, &mut vertices);
+        }
+        for root in self.roots.iter().flatten() {
+            root.widget
+                .push_quads(self.screen, self.srgb, &mut vertices);
+        }
+        self.vertex_buffer = (!vertices.is_empty()).then(|| {
+            let buffer = device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("ui_vertex_buffer"),
+                contents: cast_slice(&vertices),
+                usage: BufferUsages::VERTEX,
+            });
+            (buffer, vertices.len() as u32)
+        });
+        self.quads_dirty = false;
+    }
+
+    pub fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if let Some((buffer, count)) = self.vertex_buffer.as_ref() {
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_vertex_buffer(0, buffer.slice(..));
+            render_pass.draw(0..*count, 0..1);
+        }
+        self.text.render(render_pass);
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use crate::assets::Res;
+
+// Somewhere assets can be read from, by their path relative to the asset root
+// with `/` separators, like `cube.obj` or `textures/stone.png`.
+pub trait AssetSource: Send + Sync {
+    // None when the source doesn't have the file.
+    fn read(&self, path: &str) -> Option<Result<Vec<u8>>>;
+    fn contains(&self, path: &str) -> bool;
+    fn describe(&self) -> String;
+}
+
+// The `res` folder built into the binary.
+pub struct EmbeddedSource;
+
+impl AssetSource for EmbeddedSource {
+    fn read(&self, path: &str) -> Option<Result<Vec<u8>>> {
+        Res::get(&format!("res/{path}")).map(|file| Ok(file.data.into_owned()))
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        Res::get(&format!("res/{path}")).is_some()
+    }
+
+    fn describe(&self) -> String {
+        "embedded".to_string()
+    }
+}
+
+pub struct DirectorySource {
+    root: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    // Asset paths stay inside the directory, `..` and absolute paths never match.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let relative = Path::new(path);
+        relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+            .then(|| self.root.join(relative))
+    }
+}
+
+impl AssetSource for DirectorySource {
+    fn read(&self, path: &str) -> Option<Result<Vec<u8>>> {
+        let path = self.resolve(path)?;
+        match fs::read(&path) {
+            Ok(data) => Some(Ok(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => Some(Err(anyhow!("{}: {e}", path.display()))),
+        }
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.resolve(path).is_some_and(|path| path.is_file())
+    }
+
+    fn describe(&self) -> String {
+        self.root.display().to_string()
+    }
+}
+
+// Files handed over already loaded, like the ones a web page fetched before
+// starting the engine, where there's no filesystem to read them from.
+pub struct MemorySource {
+    name: String,
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl MemorySource {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, path: &str, data: Vec<u8>) {
+        self.files
+            .insert(path.trim_start_matches('/').to_string(), data);
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl AssetSource for MemorySource {
+    fn read(&self, path: &str) -> Option<Result<Vec<u8>>> {
+        self.files.get(path).map(|data| Ok(data.clone()))
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn describe(&self) -> String {
+        self.name.clone()
+    }
+}
+
+struct Mount {
+    priority: i32,
+    source: Box<dyn AssetSource>,
+}
+
+// Mounted sources looked up from the highest priority down, so a resource pack
+// mounted above the embedded assets overrides just the files it has. Among
+// mounts with the same priority the latest one wins.
+#[derive(Default)]
+pub struct Vfs {
+    mounts: Vec<Mount>,
+}
+
+impl Vfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Just the embedded assets, at priority 0.
+    pub fn with_embedded() -> Self {
+        let mut vfs = Self::new();
+        vfs.mount(EmbeddedSource, 0);
+        vfs
+    }
+
+    pub fn mount<S: AssetSource + 'static>(&mut self, source: S, priority: i32) {
+        self.mount_boxed(Box::new(source), priority);
+    }
+
+    pub fn mount_boxed(&mut self, source: Box<dyn AssetSource>, priority: i32) {
+        let idx = self
+            .mounts
+            .iter()
+            .position(|mount| mount.priority <= priority)
+            .unwrap_or(self.mounts.len());
+        self.mounts.insert(idx, Mount { priority, source });
+    }
+
+    pub fn mount_dir<P: Into<PathBuf>>(&mut self, root: P, priority: i32) -> Result<()> {
+        let root = root.into();
+        if !root.is_dir() {
+            return Err(anyhow!("{} isn't a directory", root.display()));
+        }
+
+        self.mount(DirectorySource::new(root), priority);
+        Ok(())
+    }
+
+    // Removes the mounts described as `description`, true if there was one.
+    pub fn unmount(&mut self, description: &str) -> bool {
+        let len = self.mounts.len();
+        self.mounts
+            .retain(|mount| mount.source.describe() != description);
+        self.mounts.len() != len
+    }
+
+    // Descriptions and priorities, in lookup order.
+    pub fn mounts(&self) -> Vec<(String, i32)> {
+        self.mounts
+            .iter()
+            .map(|mount| (mount.source.describe(), mount.priority))
+            .collect()
+    }
+
+    pub fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let path = path.trim_start_matches('/');
+        self.mounts
+            .iter()
+            .find_map(|mount| mount.source.read(path))
+            .unwrap_or_else(|| Err(anyhow!("no mounted source has {path}")))
+    }
+
+    pub fn read_string(&self, path: &str) -> Result<String> {
+        Ok(String::from_utf8(self.read(path)?)?)
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.source_of(path).is_some()
+    }
+
+    // Which mount the file would be read from.
+    pub fn source_of(&self, path: &str) -> Option<String> {
+        let path = path.trim_start_matches('/');
+        self.mounts
+            .iter()
+            .find(|mount| mount.source.contains(path))
+            .map(|mount| mount.source.describe())
+    }
+}
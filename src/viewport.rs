@@ -0,0 +1,157 @@
+use bytemuck::cast_slice;
+use glam::Mat4;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use wgpu::{BindGroup, Buffer, Queue};
+
+use crate::camera::{Camera, CameraUniform, Projection};
+use crate::handle::Handle;
+use crate::sky::SkyView;
+
+// Part of the surface in fractions of its size, so it keeps its place on resizes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub const FULL: Self = Self {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        let x = x.clamp(0.0, 1.0);
+        let y = y.clamp(0.0, 1.0);
+        Self {
+            x,
+            y,
+            width: width.clamp(0.0, 1.0 - x),
+            height: height.clamp(0.0, 1.0 - y),
+        }
+    }
+
+    // Side by side, `index` out of `count` from the left.
+    pub fn columns(index: u32, count: u32) -> Self {
+        let width = 1.0 / count.max(1) as f32;
+        Self::new(index as f32 * width, 0.0, width, 1.0)
+    }
+
+    // Stacked, `index` out of `count` from the top.
+    pub fn rows(index: u32, count: u32) -> Self {
+        let height = 1.0 / count.max(1) as f32;
+        Self::new(0.0, index as f32 * height, 1.0, height)
+    }
+
+    pub fn is_full(&self) -> bool {
+        *self == Self::FULL
+    }
+
+    // Origin and size in pixels, at least one pixel big so the pass stays valid.
+    pub fn to_pixels(&self, width: u32, height: u32) -> (u32, u32, u32, u32) {
+        let x = ((self.x * width as f32) as u32).min(width - 1);
+        let y = ((self.y * height as f32) as u32).min(height - 1);
+        let w = ((self.width * width as f32).round() as u32).clamp(1, width - x);
+        let h = ((self.height * height as f32).round() as u32).clamp(1, height - y);
+        (x, y, w, h)
+    }
+}
+
+pub type ViewportHandle = Handle<Viewport>;
+
+// Another camera drawing the scene into its own rectangle of the surface, after
+// the main one. Its projection follows the rectangle's aspect ratio.
+pub struct Viewport {
+    camera: Arc<RwLock<Camera>>,
+    projection: Projection,
+    rect: ViewportRect,
+    uniform: CameraUniform,
+    buffer: Buffer,
+    bind_group: Rc<BindGroup>,
+    sky: SkyView,
+}
+
+impl Viewport {
+    pub fn new(
+        camera: Arc<RwLock<Camera>>,
+        projection: Projection,
+        rect: ViewportRect,
+        buffer: Buffer,
+        bind_group: BindGroup,
+        sky: SkyView,
+    ) -> Self {
+        Self {
+            camera,
+            projection,
+            rect,
+            uniform: CameraUniform::new(),
+            buffer,
+            bind_group: Rc::new(bind_group),
+            sky,
+        }
+    }
+
+    pub fn camera(&self) -> Arc<RwLock<Camera>> {
+        self.camera.clone()
+    }
+
+    pub fn projection(&self) -> &Projection {
+        &self.projection
+    }
+
+    pub fn projection_mut(&mut self) -> &mut Projection {
+        &mut self.projection
+    }
+
+    pub fn rect(&self) -> ViewportRect {
+        self.rect
+    }
+
+    pub fn set_rect(&mut self, rect: ViewportRect, width: u32, height: u32) {
+        self.rect = rect;
+        self.resize(width, height);
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let (_, _, width, height) = self.rect.to_pixels(width, height);
+        self.projection.resize(width, height);
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn bind_group(&self) -> &Rc<BindGroup> {
+        &self.bind_group
+    }
+
+    pub fn set_bind_group(&mut self, bind_group: BindGroup) {
+        self.bind_group = Rc::new(bind_group);
+    }
+
+    pub fn sky(&self) -> &SkyView {
+        &self.sky
+    }
+
+    pub fn set_sky(&mut self, sky: SkyView) {
+        self.sky = sky;
+    }
+
+    pub fn view_proj(&self) -> Mat4 {
+        Mat4::from_cols_array_2d(&self.uniform.view_proj)
+    }
+
+    // Other cameras are moved directly, so there's nothing to interpolate between ticks.
+    pub fn update(&mut self, queue: &Queue, dt: f32) {
+        self.camera.write().unwrap().update_view(dt, 1.0);
+        self.projection.update(dt);
+        self.uniform
+            .update_view_proj(&self.camera.read().unwrap(), &self.projection);
+        queue.write_buffer(&self.buffer, 0, cast_slice(&[self.uniform]));
+    }
+}
@@ -0,0 +1,97 @@
+use glam::Vec2;
+
+// Altitude of the cloud layer, in blocks.
+pub const CLOUD_HEIGHT: f32 = 96.0;
+// Blocks per cloud noise cell and cells before the noise repeats, they have to
+// match the shaders.
+const CLOUD_SCALE: f32 = 48.0;
+const CLOUD_PERIOD: f32 = 64.0;
+// How much coverage changes per second, and wind speed in blocks per second.
+const COVERAGE_CHANGE_RATE: f32 = 0.02;
+const WIND_CHANGE_RATE: f32 = 0.5;
+pub const DEFAULT_WIND: Vec2 = Vec2::new(2.0, 0.5);
+
+fn move_towards(current: f32, target: f32, max_delta: f32) -> f32 {
+    current + (target - current).clamp(-max_delta, max_delta)
+}
+
+// Cloud coverage and wind. Changes asked for through `change_to` happen slowly,
+// so the sky clears up or clouds over instead of switching at once.
+pub struct Weather {
+    coverage: f32,
+    wind: Vec2,
+    target_coverage: f32,
+    target_wind: Vec2,
+    cloud_offset: Vec2,
+}
+
+impl Weather {
+    pub fn new(coverage: f32, wind: Vec2) -> Self {
+        let coverage = coverage.clamp(0.0, 1.0);
+        Self {
+            coverage,
+            wind,
+            target_coverage: coverage,
+            target_wind: wind,
+            cloud_offset: Vec2::ZERO,
+        }
+    }
+
+    // From 0.0, clear skies, to 1.0, overcast.
+    pub fn coverage(&self) -> f32 {
+        self.coverage
+    }
+
+    pub fn set_coverage(&mut self, coverage: f32) {
+        self.coverage = coverage.clamp(0.0, 1.0);
+        self.target_coverage = self.coverage;
+    }
+
+    pub fn wind(&self) -> Vec2 {
+        self.wind
+    }
+
+    pub fn set_wind(&mut self, wind: Vec2) {
+        self.wind = wind;
+        self.target_wind = wind;
+    }
+
+    pub fn change_to(&mut self, coverage: f32, wind: Vec2) {
+        self.target_coverage = coverage.clamp(0.0, 1.0);
+        self.target_wind = wind;
+    }
+
+    // How far the wind pushed the clouds, wrapped where the noise repeats.
+    pub fn cloud_offset(&self) -> Vec2 {
+        self.cloud_offset
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.coverage = move_towards(
+            self.coverage,
+            self.target_coverage,
+            COVERAGE_CHANGE_RATE * dt,
+        );
+        let wind_delta = self.target_wind - self.wind;
+        self.wind += wind_delta.clamp_length_max(WIND_CHANGE_RATE * dt);
+
+        let period = CLOUD_SCALE * CLOUD_PERIOD;
+        self.cloud_offset = (self.cloud_offset + self.wind * dt).rem_euclid(Vec2::splat(period));
+    }
+
+    // Coverage, cloud offset and altitude, as the shaders read them.
+    pub fn cloud_params(&self) -> [f32; 4] {
+        [
+            self.coverage,
+            self.cloud_offset.x,
+            self.cloud_offset.y,
+            CLOUD_HEIGHT,
+        ]
+    }
+}
+
+impl Default for Weather {
+    fn default() -> Self {
+        Self::new(0.0, DEFAULT_WIND)
+    }
+}
@@ -0,0 +1,352 @@
+use anyhow::{anyhow, Result};
+use rayon::prelude::*;
+use std::path::Path;
+use std::thread;
+
+use crate::chunks::Chunk;
+use crate::mesher::mesh_chunk;
+
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    crate::save::{decode_chunk, encode_chunk},
+    crate::seed::WorldSeed,
+    crate::worldgen::WorldGenerator,
+    glam::IVec3,
+    rayon::{ThreadPool, ThreadPoolBuilder},
+    std::fs,
+    std::time::Duration,
+    web_time::Instant,
+};
+
+// Jobs per pool size tried while calibrating, enough to keep every thread busy
+// a few times over on most machines without making startup noticeably slower.
+#[cfg(not(target_arch = "wasm32"))]
+const CALIBRATION_JOBS: usize = 64;
+// The smallest pool within this much of the best throughput wins, more threads
+// than that mostly take cores away from the other pools and the render thread.
+#[cfg(not(target_arch = "wasm32"))]
+const CALIBRATION_TOLERANCE: f32 = 0.9;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum WorkerKind {
+    Meshing,
+    Generation,
+    Io,
+}
+
+impl WorkerKind {
+    pub const ALL: [WorkerKind; 3] = [WorkerKind::Meshing, WorkerKind::Generation, WorkerKind::Io];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkerKind::Meshing => "meshing",
+            WorkerKind::Generation => "generation",
+            WorkerKind::Io => "io",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+fn available_threads() -> usize {
+    thread::available_parallelism().map_or(1, |threads| threads.get())
+}
+
+// Threads per pool, indexed by WorkerKind.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WorkerCounts([usize; 3]);
+
+impl WorkerCounts {
+    pub fn new(meshing: usize, generation: usize, io: usize) -> Self {
+        Self([meshing.max(1), generation.max(1), io.max(1)])
+    }
+
+    pub fn get(&self, kind: WorkerKind) -> usize {
+        self.0[kind as usize]
+    }
+
+    pub fn set(&mut self, kind: WorkerKind, threads: usize) {
+        self.0[kind as usize] = threads.max(1);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_config())?;
+        Ok(())
+    }
+
+    // One `kind = threads` line per pool, the same format the config uses.
+    pub fn to_config(&self) -> String {
+        WorkerKind::ALL
+            .iter()
+            .map(|kind| format!("{} = {}\n", kind.name(), self.get(*kind)))
+            .collect()
+    }
+
+    // Fails unless the file has every pool in it.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config = WorkerConfig::load(path)?;
+        let mut counts = Self::default();
+        for kind in WorkerKind::ALL {
+            let threads = config
+                .get(kind)
+                .ok_or_else(|| anyhow!("missing thread count for {}", kind.name()))?;
+            counts.set(kind, threads);
+        }
+
+        Ok(counts)
+    }
+}
+
+// Before calibrating, the cores get split between generation and meshing with
+// a couple of threads for IO, which mostly waits anyway.
+impl Default for WorkerCounts {
+    fn default() -> Self {
+        let threads = available_threads();
+        Self::new(threads / 2, threads / 2, 2)
+    }
+}
+
+// Thread counts set by hand, pools left out are calibrated.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkerConfig([Option<usize>; 3]);
+
+impl WorkerConfig {
+    pub fn get(&self, kind: WorkerKind) -> Option<usize> {
+        self.0[kind as usize]
+    }
+
+    pub fn set(&mut self, kind: WorkerKind, threads: Option<usize>) {
+        self.0[kind as usize] = threads.map(|threads| threads.max(1));
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.0.iter().all(Option::is_some)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(data: &str) -> Result<Self> {
+        let mut config = Self::default();
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, threads) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected `pool = threads`", i + 1))?;
+            let kind = WorkerKind::from_name(name.trim())
+                .ok_or_else(|| anyhow!("line {}: unknown pool `{}`", i + 1, name.trim()))?;
+            let threads = threads
+                .trim()
+                .parse::<usize>()
+                .map_err(|e| anyhow!("line {}: {e}", i + 1))?;
+            config.set(kind, Some(threads));
+        }
+
+        Ok(config)
+    }
+
+    // Overrides first, then the calibration saved at `calibration_path`, and
+    // only if that's missing or broken the benchmark runs and gets saved there.
+    pub fn resolve<P: AsRef<Path>>(&self, calibration_path: P) -> WorkerCounts {
+        let mut counts = if self.is_complete() {
+            WorkerCounts::default()
+        } else {
+            calibration(calibration_path.as_ref())
+        };
+        for kind in WorkerKind::ALL {
+            if let Some(threads) = self.get(kind) {
+                counts.set(kind, threads);
+            }
+        }
+
+        counts
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn calibration(path: &Path) -> WorkerCounts {
+    WorkerCounts::load(path).unwrap_or_else(|_| {
+        let counts = calibrate();
+        if let Err(e) = counts.save(path) {
+            tracing::warn!("Couldn't save {}: {e}", path.display());
+        }
+        counts
+    })
+}
+
+// Plain wasm32 has a single thread and no files, there's nothing to measure.
+#[cfg(target_arch = "wasm32")]
+fn calibration(_path: &Path) -> WorkerCounts {
+    WorkerCounts::new(1, 1, 1)
+}
+
+// Pool sizes to try, powers of two up to the core count and the core count.
+#[cfg(not(target_arch = "wasm32"))]
+fn candidate_counts() -> Vec<usize> {
+    let threads = available_threads();
+    let mut counts = (0..)
+        .map(|exp| 1 << exp)
+        .take_while(|count| *count < threads)
+        .collect::<Vec<_>>();
+    counts.push(threads);
+    counts
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn time_jobs<F: Fn(usize) + Sync>(threads: usize, job: &F) -> Option<Duration> {
+    let pool = ThreadPoolBuilder::new().num_threads(threads).build().ok()?;
+    let start = Instant::now();
+    pool.install(|| (0..CALIBRATION_JOBS).into_par_iter().for_each(job));
+    Some(start.elapsed())
+}
+
+// Smallest pool whose throughput on `job` gets close enough to the best one.
+#[cfg(not(target_arch = "wasm32"))]
+fn calibrate_pool<F: Fn(usize) + Sync>(job: F) -> usize {
+    let timings = candidate_counts()
+        .into_iter()
+        .filter_map(|threads| time_jobs(threads, &job).map(|time| (threads, time)))
+        .collect::<Vec<_>>();
+    let best = timings
+        .iter()
+        .map(|(_, time)| time.as_secs_f32())
+        .fold(f32::INFINITY, f32::min);
+
+    timings
+        .iter()
+        .find(|(_, time)| best / time.as_secs_f32() >= CALIBRATION_TOLERANCE)
+        .map_or(1, |(threads, _)| *threads)
+}
+
+// Measures how generation, meshing and chunk reads and writes scale with more
+// threads on this machine, on chunks from the default generator.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn calibrate() -> WorkerCounts {
+    let start = Instant::now();
+    let generator = WorldGenerator::with_default_stages(WorldSeed::new(0));
+    let sample = generator.generate(IVec3::ZERO);
+    let occupancy = sample.occupancy();
+    let light = sample.light_samples();
+    let grass = sample.grass_tints();
+    let origin = sample.mesh_origin();
+    let data = encode_chunk(&sample);
+    let io_dir = std::env::temp_dir().join(format!("voxeltest-io-{}", std::process::id()));
+
+    let generation = calibrate_pool(|i| {
+        generator.generate(IVec3::new(i as i32, 0, 0));
+    });
+    let meshing = calibrate_pool(|_| {
+        mesh_chunk(&occupancy, &light, &grass, origin);
+    });
+    let io = if fs::create_dir_all(&io_dir).is_ok() {
+        let io = calibrate_pool(|i| {
+            let path = io_dir.join(format!("{i}.chunk"));
+            let read = fs::write(&path, &data).and_then(|_| fs::read(&path));
+            if let Ok(read) = read {
+                let _ = decode_chunk(&read);
+            }
+        });
+        let _ = fs::remove_dir_all(&io_dir);
+        io
+    } else {
+        WorkerCounts::default().get(WorkerKind::Io)
+    };
+
+    let counts = WorkerCounts::new(meshing, generation, io);
+    tracing::info!(
+        "Calibrated worker pools in {:.0} ms: {counts:?}",
+        start.elapsed().as_secs_f32() * 1000.0
+    );
+    counts
+}
+
+pub struct WorkerPools {
+    counts: WorkerCounts,
+    // Plain wasm32 can't start threads, work runs on the calling thread there
+    // through rayon's global pool, which falls back to it.
+    #[cfg(not(target_arch = "wasm32"))]
+    pools: [ThreadPool; 3],
+}
+
+impl WorkerPools {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(counts: WorkerCounts) -> Result<Self> {
+        let build = |kind: WorkerKind| {
+            ThreadPoolBuilder::new()
+                .num_threads(counts.get(kind))
+                .thread_name(move |i| format!("{}-{i}", kind.name()))
+                .build()
+        };
+
+        Ok(Self {
+            counts,
+            pools: [
+                build(WorkerKind::Meshing)?,
+                build(WorkerKind::Generation)?,
+                build(WorkerKind::Io)?,
+            ],
+        })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(counts: WorkerCounts) -> Result<Self> {
+        Ok(Self { counts })
+    }
+
+    pub fn counts(&self) -> WorkerCounts {
+        self.counts
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn pool(&self, kind: WorkerKind) -> &ThreadPool {
+        &self.pools[kind as usize]
+    }
+
+    // Runs `op` in the pool of `kind`, parallel iterators inside it use that
+    // pool's threads.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn install<R: Send, F: FnOnce() -> R + Send>(&self, kind: WorkerKind, op: F) -> R {
+        self.pool(kind).install(op)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn install<R: Send, F: FnOnce() -> R + Send>(&self, _kind: WorkerKind, op: F) -> R {
+        op()
+    }
+
+    // Meshes ahead of setup, so adding the chunks only uploads them.
+    pub fn mesh_chunks(&self, chunks: &[Chunk]) {
+        let inputs = chunks
+            .iter()
+            .map(|chunk| {
+                (
+                    chunk.occupancy(),
+                    chunk.light_samples(),
+                    chunk.grass_tints(),
+                    chunk.mesh_origin(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let meshes = self.install(WorkerKind::Meshing, || {
+            inputs
+                .par_iter()
+                .map(|(occupancy, light, grass, origin)| {
+                    mesh_chunk(occupancy, light, grass, *origin)
+                })
+                .collect::<Vec<_>>()
+        });
+        for (chunk, mesh) in chunks.iter().zip(meshes) {
+            chunk.set_mesh(mesh);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use anyhow::{anyhow, Result};
+use glam::{IVec3, Vec3};
+use std::collections::{HashMap, HashSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::mem;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use crate::app::{Model, FIXED_TIMESTEP};
+use crate::block_ticks::BlockTicks;
+use crate::chunks::{Block, Chunk};
+use crate::lighting::{self, LightMap};
+use crate::mesher::CHUNK_SIZE;
+use crate::physics::raycast_grid;
+use crate::registry::{is_registered, BehaviorRegistry};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::save::{decode_chunk, encode_chunk};
+use crate::seed::WorldSeed;
+use crate::world_edit::{block_at, split_position, EditSet};
+use crate::worldgen::WorldGenerator;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RaycastHit {
+    pub position: IVec3,
+    // Face of the block the ray went through, zero when it started inside it.
+    pub normal: IVec3,
+    pub distance: f32,
+    pub id: u16,
+}
+
+// Chunks and their simulation without anything tied to rendering, for tools and
+// tests that build or inspect worlds. Chunks are keyed by their chunk position.
+// The app keeps its chunks in the scene instead and runs them through the same
+// edits and block ticks, see place_block and tick_blocks.
+pub struct World {
+    generator: WorldGenerator,
+    chunks: HashMap<IVec3, Chunk>,
+    behaviors: BehaviorRegistry,
+    ticks: BlockTicks,
+}
+
+impl World {
+    pub fn new(seed: WorldSeed) -> Self {
+        Self::with_generator(WorldGenerator::with_default_stages(seed))
+    }
+
+    pub fn with_generator(generator: WorldGenerator) -> Self {
+        Self {
+            generator,
+            chunks: HashMap::new(),
+            behaviors: BehaviorRegistry::new(),
+            ticks: BlockTicks::new(),
+        }
+    }
+
+    pub fn generator(&self) -> &WorldGenerator {
+        &self.generator
+    }
+
+    pub fn behaviors(&self) -> &BehaviorRegistry {
+        &self.behaviors
+    }
+
+    // Custom block behaviors go in here.
+    pub fn behaviors_mut(&mut self) -> &mut BehaviorRegistry {
+        &mut self.behaviors
+    }
+
+    pub fn block_ticks(&self) -> &BlockTicks {
+        &self.ticks
+    }
+
+    // Generates every chunk between the two chunk positions, both included, that
+    // isn't there yet.
+    pub fn generate_region(&mut self, min: IVec3, max: IVec3) {
+        let (min, max) = (min.min(max), min.max(max));
+        let mut generated = vec![];
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let position = IVec3::new(x, y, z);
+                    if !self.chunks.contains_key(&position) {
+                        let chunk = self.generator.generate(position);
+                        self.chunks.insert(position, chunk);
+                        generated.push(position);
+                    }
+                }
+            }
+        }
+
+        let built = self.generator.decorate(&mut self.chunks, &generated);
+        for &position in &generated {
+            lighting::light_chunk(&mut self.chunks, position);
+        }
+        // Structures reaching into chunks that were already lit.
+        for position in built {
+            if !generated.contains(&split_position(position).0) {
+                lighting::update_block(&mut self.chunks, position);
+            }
+        }
+    }
+
+    pub fn chunk(&self, position: IVec3) -> Option<&Chunk> {
+        self.chunks.get(&position)
+    }
+
+    // Block ticks scheduled in it go away when they come due.
+    pub fn remove_chunk(&mut self, position: IVec3) -> Option<Chunk> {
+        self.chunks.remove(&position)
+    }
+
+    pub fn chunks(&self) -> &HashMap<IVec3, Chunk> {
+        &self.chunks
+    }
+
+    // For the edit APIs working on chunk maps, like schematics.
+    pub fn chunks_mut(&mut self) -> &mut HashMap<IVec3, Chunk> {
+        &mut self.chunks
+    }
+
+    pub fn into_chunks(self) -> HashMap<IVec3, Chunk> {
+        self.chunks
+    }
+
+    pub fn block(&self, position: IVec3) -> Option<u16> {
+        block_at(&self.chunks, position)
+    }
+
+    // None places air, the chunk gets generated first if it isn't there. The
+    // returned set undoes the edit when applied inverted. Fails for ids that
+    // aren't in the registry.
+    pub fn set_block(&mut self, position: IVec3, id: Option<u16>) -> Result<EditSet> {
+        if let Some(id) = id.filter(|&id| !is_registered(id)) {
+            return Err(anyhow!("unknown block id {id}"));
+        }
+
+        let (chunk_position, _) = split_position(position);
+        self.generate_region(chunk_position, chunk_position);
+        let mut set = EditSet::new();
+        set.record(position, self.block(position), id);
+        Self::place_block(&mut self.chunks, position, id);
+        Ok(set)
+    }
+
+    // Changes a block wherever the chunks are kept and updates the light
+    // around it. None when its chunk isn't loaded or the block already was
+    // `id`, otherwise the chunks the light changed in.
+    pub fn place_block<M: LightMap>(
+        chunks: &mut M,
+        position: IVec3,
+        id: Option<u16>,
+    ) -> Option<HashSet<IVec3>> {
+        if !chunks.chunk_loaded(split_position(position).0) || chunks.block(position) == id {
+            return None;
+        }
+
+        chunks.set_block(position, id);
+        Some(lighting::update_block(chunks, position))
+    }
+
+    // Like place_block with a block state, see Model::block_state. The block
+    // gets placed as usual, so what's around it wakes up, then put in the state.
+    pub fn place_block_state<M: LightMap>(
+        chunks: &mut M,
+        position: IVec3,
+        state: Option<u32>,
+    ) -> Option<HashSet<IVec3>> {
+        let (chunk_position, local) = split_position(position);
+        let state = state.map(|state| Block::new(state).with_position(local).data());
+        if !chunks.chunk_loaded(chunk_position) || chunks.block_state(position) == state {
+            return None;
+        }
+
+        chunks.set_block(position, state.map(|state| Block::new(state).id()));
+        chunks.set_block_state(position, state);
+        Some(lighting::update_block(chunks, position))
+    }
+
+    // Runs a fixed tick of every chunk, then the block ticks due. Returns the
+    // cells that changed since the last tick, edits made by this one's block
+    // ticks come with the next.
+    pub fn tick(&mut self) -> Vec<IVec3> {
+        for chunk in self.chunks.values_mut() {
+            let _ = chunk.tick(FIXED_TIMESTEP);
+        }
+
+        let changed = self
+            .chunks
+            .iter_mut()
+            .flat_map(|(&chunk_position, chunk)| {
+                let origin = chunk_position * CHUNK_SIZE;
+                chunk
+                    .take_changed()
+                    .into_iter()
+                    .map(move |local| origin + local.as_ivec3())
+            })
+            .collect::<Vec<_>>();
+        let mut chunks = mem::take(&mut self.chunks);
+        self.tick_blocks(&mut chunks, &changed);
+        self.chunks = chunks;
+
+        changed
+    }
+
+    // Schedules the cells that changed in `chunks` and runs the block ticks
+    // due, placing their edits. Returns the cells the edits changed with the
+    // chunks the light changed in around each.
+    pub fn tick_blocks<M: LightMap>(
+        &mut self,
+        chunks: &mut M,
+        changed: &[IVec3],
+    ) -> Vec<(IVec3, HashSet<IVec3>)> {
+        self.ticks.register(changed, chunks, &self.behaviors);
+        self.ticks
+            .tick(chunks, &self.behaviors)
+            .into_iter()
+            .filter_map(|(position, state)| {
+                Some((position, Self::place_block_state(chunks, position, state)?))
+            })
+            .collect()
+    }
+
+    // Drops the scheduled block ticks, for when every chunk goes away at once.
+    pub fn clear_block_ticks(&mut self) {
+        self.ticks.clear();
+    }
+
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        raycast_grid(origin, direction, max_distance, |cell| self.block(cell)).map(
+            |(id, position, normal, distance)| RaycastHit {
+                position,
+                normal,
+                distance,
+                id,
+            },
+        )
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for (position, chunk) in self.chunks.iter() {
+            fs::write(
+                dir.join(format!(
+                    "{}.{}.{}.chunk",
+                    position.x, position.y, position.z
+                )),
+                encode_chunk(chunk),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    // Loads every chunk saved in `dir`, anything missing gets generated as usual.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load<P: AsRef<Path>>(dir: P, generator: WorldGenerator) -> Result<Self> {
+        let mut world = Self::with_generator(generator);
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "chunk")
+            {
+                let mut chunk = decode_chunk(&fs::read(&path)?)?;
+                world.generator.tint_chunk(&mut chunk);
+                world
+                    .chunks
+                    .insert(chunk.chunk_position().as_ivec3(), chunk);
+            }
+        }
+        // Saves keep the light inside chunks but not what they show of their
+        // neighbours'.
+        let positions = world.chunks.keys().copied().collect::<Vec<_>>();
+        for position in positions {
+            lighting::light_chunk(&mut world.chunks, position);
+        }
+
+        Ok(world)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::STONE_ID;
+
+    #[test]
+    fn set_block_rejects_unknown_ids() {
+        let mut world = World::with_generator(WorldGenerator::new(WorldSeed::new(1)));
+        let position = IVec3::new(8, 40, 8);
+        assert!(world.set_block(position, Some(0xfff)).is_err());
+        assert!(world.set_block(position, Some(u16::MAX)).is_err());
+        assert_eq!(world.block(position), None);
+
+        world.set_block(position, Some(STONE_ID)).unwrap();
+        assert_eq!(world.block(position), Some(STONE_ID));
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::{anyhow, Result};
+use glam::{IVec3, UVec3};
+use std::collections::{HashMap, VecDeque};
+
+use crate::chunks::Chunk;
+use crate::mesher::CHUNK_SIZE;
+
+// Past either limit the oldest undo steps get dropped.
+pub const MAX_UNDO_STEPS: usize = 128;
+pub const MAX_HISTORY_EDITS: usize = 1 << 20;
+
+pub fn split_position(position: IVec3) -> (IVec3, UVec3) {
+    (
+        position.div_euclid(IVec3::splat(CHUNK_SIZE)),
+        position.rem_euclid(IVec3::splat(CHUNK_SIZE)).as_uvec3(),
+    )
+}
+
+// Block id at a world position, None for air or chunks that aren't loaded.
+pub fn block_at(chunks: &HashMap<IVec3, Chunk>, position: IVec3) -> Option<u16> {
+    let (chunk_position, local) = split_position(position);
+    chunks.get(&chunk_position)?.block_id(local)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BlockEdit {
+    pub position: IVec3,
+    pub before: Option<u16>,
+    pub after: Option<u16>,
+}
+
+// Block changes applied together. Editing the same position twice keeps the
+// first `before`, so the set always undoes back to where it started.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EditSet {
+    edits: Vec<BlockEdit>,
+    indices: HashMap<IVec3, usize>,
+}
+
+impl EditSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, position: IVec3, before: Option<u16>, after: Option<u16>) {
+        match self.indices.get(&position) {
+            Some(&idx) => self.edits[idx].after = after,
+            None => {
+                self.indices.insert(position, self.edits.len());
+                self.edits.push(BlockEdit {
+                    position,
+                    before,
+                    after,
+                });
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: EditSet) {
+        for edit in other.edits {
+            self.record(edit.position, edit.before, edit.after);
+        }
+    }
+
+    pub fn edits(&self) -> &[BlockEdit] {
+        &self.edits
+    }
+
+    pub fn len(&self) -> usize {
+        self.edits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub fn inverse(&self) -> Self {
+        let mut inverse = Self::new();
+        for edit in self.edits.iter().rev() {
+            inverse.record(edit.position, edit.after, edit.before);
+        }
+
+        inverse
+    }
+
+    // Writes every `after` in one go, batched per chunk. Nothing is touched
+    // unless all the chunks the set covers are loaded.
+    pub fn apply(&self, chunks: &mut HashMap<IVec3, Chunk>) -> Result<()> {
+        let mut batches: HashMap<IVec3, Vec<(UVec3, Option<u16>)>> = HashMap::new();
+        for edit in self.edits.iter() {
+            let (chunk_position, local) = split_position(edit.position);
+            batches
+                .entry(chunk_position)
+                .or_default()
+                .push((local, edit.after));
+        }
+
+        if let Some(missing) = batches
+            .keys()
+            .find(|position| !chunks.contains_key(position))
+        {
+            return Err(anyhow!(
+                "can't edit the world, chunk {missing} is not loaded"
+            ));
+        }
+
+        for (chunk_position, batch) in batches {
+            let chunk = chunks.get_mut(&chunk_position).unwrap();
+            for (local, id) in batch {
+                match (chunk.block_id(local), id) {
+                    (Some(current), Some(id)) if current != id => chunk.set_block_id(local, id),
+                    (None, Some(id)) => chunk.add_block_data(local, id),
+                    (Some(_), None) => chunk.remove_block(local),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Undo and redo stacks of applied edit sets.
+#[derive(Default)]
+pub struct EditHistory {
+    undo: VecDeque<EditSet>,
+    redo: Vec<EditSet>,
+    edits: usize,
+}
+
+impl EditHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records an edit set that was just applied, a new edit drops the redo stack.
+    pub fn record(&mut self, set: EditSet) {
+        if set.is_empty() {
+            return;
+        }
+
+        self.redo.clear();
+        self.edits += set.len();
+        self.undo.push_back(set);
+        self.trim();
+    }
+
+    // Folds the set into the last undo step, for edits made in one go like
+    // dragging out blocks or repeated brush strokes.
+    pub fn record_coalesced(&mut self, set: EditSet) {
+        match self.undo.back_mut() {
+            Some(last) if self.redo.is_empty() => {
+                self.edits -= last.len();
+                last.merge(set);
+                self.edits += last.len();
+                self.trim();
+            }
+            _ => self.record(set),
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.undo.len() > MAX_UNDO_STEPS
+            || (self.edits > MAX_HISTORY_EDITS && self.undo.len() > 1)
+        {
+            let dropped = self.undo.pop_front().unwrap();
+            self.edits -= dropped.len();
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    // Returns false when there was nothing to undo.
+    pub fn undo(&mut self, chunks: &mut HashMap<IVec3, Chunk>) -> Result<bool> {
+        let Some(set) = self.undo.pop_back() else {
+            return Ok(false);
+        };
+        if let Err(e) = set.inverse().apply(chunks) {
+            self.undo.push_back(set);
+            return Err(e);
+        }
+
+        self.edits -= set.len();
+        self.redo.push(set);
+        Ok(true)
+    }
+
+    pub fn redo(&mut self, chunks: &mut HashMap<IVec3, Chunk>) -> Result<bool> {
+        let Some(set) = self.redo.pop() else {
+            return Ok(false);
+        };
+        if let Err(e) = set.apply(chunks) {
+            self.redo.push(set);
+            return Err(e);
+        }
+
+        self.edits += set.len();
+        self.undo.push_back(set);
+        self.trim();
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::Model;
+    use crate::chunks::{LAMP_ID, STONE_ID};
+    use crate::seed::WorldSeed;
+    use crate::worldgen::WorldGenerator;
+
+    fn chunks() -> HashMap<IVec3, Chunk> {
+        let generator = WorldGenerator::with_default_stages(WorldSeed::new(0));
+        [IVec3::ZERO, IVec3::X]
+            .into_iter()
+            .map(|position| (position, generator.generate(position)))
+            .collect()
+    }
+
+    // Raw state of every cell, ids along with the rest of their data.
+    fn states(chunks: &HashMap<IVec3, Chunk>) -> Vec<(IVec3, Option<u32>)> {
+        let mut states = chunks
+            .iter()
+            .flat_map(|(&chunk_position, chunk)| {
+                (0..CHUNK_SIZE.pow(3) as u32).map(move |idx| {
+                    let local = UVec3::new(idx / 256, idx / 16 % 16, idx % 16);
+                    (
+                        chunk_position * CHUNK_SIZE + local.as_ivec3(),
+                        chunk.block_state(local),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        states.sort_by_key(|(position, _)| (position.x, position.y, position.z));
+        states
+    }
+
+    // Fills a box across the border between the chunks, the way the editing
+    // tools do.
+    fn fill(chunks: &mut HashMap<IVec3, Chunk>, id: Option<u16>, y: i32) -> EditSet {
+        let mut set = EditSet::new();
+        for x in 12..20 {
+            for z in 4..8 {
+                let position = IVec3::new(x, y, z);
+                set.record(position, block_at(chunks, position), id);
+            }
+        }
+        set.apply(chunks).unwrap();
+        set
+    }
+
+    #[test]
+    fn undo_and_redo_restore_exact_states() {
+        let mut chunks = chunks();
+        let mut history = EditHistory::new();
+        let start = states(&chunks);
+
+        let set = fill(&mut chunks, Some(LAMP_ID), 6);
+        history.record(set);
+        let set = fill(&mut chunks, None, 5);
+        history.record(set);
+        let edited = states(&chunks);
+        assert_ne!(edited, start);
+
+        assert!(history.undo(&mut chunks).unwrap());
+        assert!(history.undo(&mut chunks).unwrap());
+        assert!(!history.undo(&mut chunks).unwrap());
+        assert_eq!(states(&chunks), start);
+
+        assert!(history.redo(&mut chunks).unwrap());
+        assert!(history.redo(&mut chunks).unwrap());
+        assert!(!history.can_redo());
+        assert_eq!(states(&chunks), edited);
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut chunks = chunks();
+        let mut history = EditHistory::new();
+        let set = fill(&mut chunks, Some(LAMP_ID), 6);
+        history.record(set);
+        history.undo(&mut chunks).unwrap();
+        assert!(history.can_redo());
+
+        let set = fill(&mut chunks, Some(STONE_ID), 7);
+        history.record(set);
+        assert!(!history.can_redo());
+        assert!(!history.redo(&mut chunks).unwrap());
+        assert!(history.undo(&mut chunks).unwrap());
+        assert!(!history.undo(&mut chunks).unwrap());
+        assert_ne!(block_at(&chunks, IVec3::new(12, 6, 4)), Some(LAMP_ID));
+    }
+}
@@ -0,0 +1,70 @@
+use glam::{IVec3, Vec3};
+use uuid::Uuid;
+
+use crate::app::{Model, ModelState};
+use crate::camera::CameraPose;
+use crate::dimension::Dimension;
+use crate::physics::{raycast_grid, BlockQuery};
+use crate::registry::block_info;
+use crate::world::RaycastHit;
+use crate::world_edit::split_position;
+
+// What actors get to read during their update. Everything is as it was when the
+// frame's actors started, changes they ask for show up the next frame.
+pub struct WorldView<'a> {
+    camera: CameraPose,
+    models: &'a ModelState,
+    dimension: Option<&'a Dimension>,
+}
+
+impl<'a> WorldView<'a> {
+    pub fn new(
+        camera: CameraPose,
+        models: &'a ModelState,
+        dimension: Option<&'a Dimension>,
+    ) -> Self {
+        Self {
+            camera,
+            models,
+            dimension,
+        }
+    }
+
+    pub fn camera(&self) -> CameraPose {
+        self.camera
+    }
+
+    pub fn model(&self, id: &Uuid) -> Option<&'a (dyn Model + Send + Sync)> {
+        self.models.get_model(id).map(|model| model.model())
+    }
+
+    pub fn models(&self) -> impl Iterator<Item = &'a (dyn Model + Send + Sync)> {
+        self.models.iter_models().map(|model| model.model())
+    }
+
+    // Block in the loaded chunks of the current dimension, None for air or
+    // unloaded chunks.
+    pub fn block(&self, position: IVec3) -> Option<u16> {
+        let (chunk_position, local) = split_position(position);
+        let id = self.dimension?.chunk_id(chunk_position)?;
+        self.model(id)?.block(local)
+    }
+
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RaycastHit> {
+        raycast_grid(origin, direction, max_distance, |cell| self.block(cell)).map(
+            |(id, position, normal, distance)| RaycastHit {
+                position,
+                normal,
+                distance,
+                id,
+            },
+        )
+    }
+}
+
+impl BlockQuery for WorldView<'_> {
+    fn is_solid(&self, position: IVec3) -> bool {
+        self.block(position)
+            .is_some_and(|id| block_info(id).is_solid())
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::{anyhow, Result};
+use glam::{IVec3, UVec3, Vec3A};
+use uuid::Uuid;
+
+use crate::biome::{Biome, BiomeRegistry};
+use crate::chunks::{Chunk, ORE_ID, STONE_ID, WATER_ID};
+use crate::foliage::{Decoration, DecorationKind};
+use crate::mesher::CHUNK_SIZE;
+use crate::seed::WorldSeed;
+use crate::structures::{decorate_ready, BlockAccess, Decorator, OreVeinDecorator, TreeDecorator};
+
+pub const BASE_TERRAIN: &str = "base_terrain";
+pub const CAVES: &str = "caves";
+pub const ORES: &str = "ores";
+pub const STRUCTURES: &str = "structures";
+pub const DECORATIONS: &str = "decorations";
+
+const SEA_LEVEL: i32 = 3;
+
+pub struct GenContext<'a> {
+    pub chunk_position: IVec3,
+    pub seed: WorldSeed,
+    pub biomes: &'a BiomeRegistry,
+}
+
+impl GenContext<'_> {
+    pub fn world_position(&self, local: UVec3) -> IVec3 {
+        self.chunk_position * CHUNK_SIZE + local.as_ivec3()
+    }
+
+    // Biome of the column a local position is in.
+    pub fn biome(&self, local: UVec3) -> &Biome {
+        let world = self.world_position(local);
+        self.biomes.biome_at(self.seed, world.x, world.z)
+    }
+
+    pub fn hash(&self, position: IVec3) -> u64 {
+        self.seed.hash3(position)
+    }
+}
+
+pub trait GenerationStage: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn dependencies(&self) -> &[&'static str] {
+        &[]
+    }
+
+    fn generate(&self, chunk: &mut Chunk, ctx: &GenContext);
+}
+
+pub struct BaseTerrainStage;
+
+impl BaseTerrainStage {
+    fn height(ctx: &GenContext, x: i32, z: i32) -> i32 {
+        let terrain = ctx.biomes.terrain_at(ctx.seed, x, z);
+        let wave = (x as f32 * 0.15).sin() + (z as f32 * 0.12).cos();
+        (terrain.base_height + wave * terrain.height_variation)
+            .round()
+            .clamp(1.0, (CHUNK_SIZE - 1) as f32) as i32
+    }
+}
+
+impl GenerationStage for BaseTerrainStage {
+    fn name(&self) -> &'static str {
+        BASE_TERRAIN
+    }
+
+    fn generate(&self, chunk: &mut Chunk, ctx: &GenContext) {
+        if ctx.chunk_position.y != 0 {
+            return;
+        }
+
+        for x in 0..CHUNK_SIZE as u32 {
+            for z in 0..CHUNK_SIZE as u32 {
+                let world = ctx.world_position(UVec3::new(x, 0, z));
+                let height = Self::height(ctx, world.x, world.z);
+                let biome = ctx.biome(UVec3::new(x, 0, z));
+                for y in 0..height as u32 {
+                    let id = if y + biome.surface_depth >= height as u32 {
+                        biome.surface
+                    } else {
+                        STONE_ID
+                    };
+                    chunk.add_block_data(UVec3::new(x, y, z), id);
+                }
+                for y in height..=SEA_LEVEL {
+                    chunk.add_block_data(UVec3::new(x, y as u32, z), WATER_ID);
+                }
+            }
+        }
+    }
+}
+
+pub struct CavesStage;
+
+impl GenerationStage for CavesStage {
+    fn name(&self) -> &'static str {
+        CAVES
+    }
+
+    fn dependencies(&self) -> &[&'static str] {
+        &[BASE_TERRAIN]
+    }
+
+    fn generate(&self, chunk: &mut Chunk, ctx: &GenContext) {
+        let carved = chunk
+            .blocks()
+            .filter(|block| block.id() == STONE_ID && block.y() > 0)
+            .map(|block| block.position())
+            .filter(|&position| {
+                let world = ctx.world_position(position).as_vec3a();
+                let density = (world * Vec3A::new(0.3, 0.5, 0.3)).to_array();
+                density[0].sin() * density[1].sin() * density[2].sin() > 0.6
+            })
+            .collect::<Vec<UVec3>>();
+
+        for position in carved {
+            chunk.remove_block(position);
+        }
+    }
+}
+
+pub struct OresStage;
+
+impl GenerationStage for OresStage {
+    fn name(&self) -> &'static str {
+        ORES
+    }
+
+    fn dependencies(&self) -> &[&'static str] {
+        &[CAVES]
+    }
+
+    fn generate(&self, chunk: &mut Chunk, ctx: &GenContext) {
+        let ores = chunk
+            .blocks()
+            .filter(|block| block.id() == STONE_ID)
+            .map(|block| block.position())
+            .filter(|&position| ctx.hash(ctx.world_position(position)).is_multiple_of(16))
+            .collect::<Vec<UVec3>>();
+
+        for position in ores {
+            chunk.set_block_id(position, ORE_ID);
+        }
+    }
+}
+
+// Grass and the odd flower on the surface of biomes with vegetation, where
+// nothing is above it, so none grow under water.
+pub struct DecorationsStage;
+
+impl GenerationStage for DecorationsStage {
+    fn name(&self) -> &'static str {
+        DECORATIONS
+    }
+
+    fn dependencies(&self) -> &[&'static str] {
+        &[STRUCTURES]
+    }
+
+    fn generate(&self, chunk: &mut Chunk, ctx: &GenContext) {
+        let tops = chunk
+            .blocks()
+            .filter(|block| {
+                let biome = ctx.biome(block.position());
+                biome.vegetation && block.id() == biome.surface && block.y() + 1 < CHUNK_SIZE as u32
+            })
+            .map(|block| block.position() + UVec3::Y)
+            .filter(|&above| !chunk.exists_block(above))
+            .collect::<Vec<UVec3>>();
+
+        for position in tops {
+            let kind = match ctx.hash(ctx.world_position(position)) % 16 {
+                0..=4 => DecorationKind::Grass,
+                5 => DecorationKind::Flower,
+                _ => continue,
+            };
+            chunk.add_decoration(Decoration::new(kind, position));
+        }
+    }
+}
+
+// Anchor stages keep the well known stage names available so plugins can
+// depend on them even before the built-in implementations exist.
+pub struct EmptyStage {
+    name: &'static str,
+    dependencies: Vec<&'static str>,
+}
+
+impl EmptyStage {
+    pub fn new(name: &'static str, dependencies: Vec<&'static str>) -> Self {
+        Self { name, dependencies }
+    }
+}
+
+impl GenerationStage for EmptyStage {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn dependencies(&self) -> &[&'static str] {
+        &self.dependencies
+    }
+
+    fn generate(&self, _chunk: &mut Chunk, _ctx: &GenContext) {}
+}
+
+pub struct WorldGenerator {
+    seed: WorldSeed,
+    stages: Vec<Box<dyn GenerationStage>>,
+    order: Vec<usize>,
+    biomes: BiomeRegistry,
+    decorators: Vec<Box<dyn Decorator>>,
+}
+
+impl WorldGenerator {
+    pub fn new(seed: WorldSeed) -> Self {
+        Self {
+            seed,
+            stages: vec![],
+            order: vec![],
+            biomes: BiomeRegistry::new(),
+            decorators: vec![],
+        }
+    }
+
+    pub fn seed(&self) -> WorldSeed {
+        self.seed
+    }
+
+    pub fn biomes(&self) -> &BiomeRegistry {
+        &self.biomes
+    }
+
+    // Custom biomes go in here, before any chunk gets generated.
+    pub fn biomes_mut(&mut self) -> &mut BiomeRegistry {
+        &mut self.biomes
+    }
+
+    pub fn with_default_stages(seed: WorldSeed) -> Self {
+        let mut generator = Self::new(seed);
+        generator.stages.push(Box::new(BaseTerrainStage));
+        generator.stages.push(Box::new(CavesStage));
+        generator.stages.push(Box::new(OresStage));
+        generator
+            .stages
+            .push(Box::new(EmptyStage::new(STRUCTURES, vec![CAVES, ORES])));
+        generator.stages.push(Box::new(DecorationsStage));
+        generator.order = generator.resolve_order().unwrap();
+        generator.decorators.push(Box::new(TreeDecorator));
+        generator.decorators.push(Box::new(OreVeinDecorator));
+
+        generator
+    }
+
+    pub fn add_stage(&mut self, stage: Box<dyn GenerationStage>) -> Result<()> {
+        if self.stages.iter().any(|s| s.name() == stage.name()) {
+            return Err(anyhow!("stage `{}` is already registered", stage.name()));
+        }
+
+        self.stages.push(stage);
+        match self.resolve_order() {
+            Ok(order) => {
+                self.order = order;
+                Ok(())
+            }
+            Err(err) => {
+                self.stages.pop();
+                Err(err)
+            }
+        }
+    }
+
+    pub fn replace_stage(&mut self, stage: Box<dyn GenerationStage>) -> Result<()> {
+        let idx = self
+            .stages
+            .iter()
+            .position(|s| s.name() == stage.name())
+            .ok_or_else(|| anyhow!("stage `{}` is not registered", stage.name()))?;
+
+        let previous = std::mem::replace(&mut self.stages[idx], stage);
+        match self.resolve_order() {
+            Ok(order) => {
+                self.order = order;
+                Ok(())
+            }
+            Err(err) => {
+                self.stages[idx] = previous;
+                Err(err)
+            }
+        }
+    }
+
+    // Decorators run in the order they're added.
+    pub fn add_decorator(&mut self, decorator: Box<dyn Decorator>) -> Result<()> {
+        if self.decorators.iter().any(|d| d.name() == decorator.name()) {
+            return Err(anyhow!(
+                "decorator `{}` is already registered",
+                decorator.name()
+            ));
+        }
+
+        self.decorators.push(decorator);
+        Ok(())
+    }
+
+    pub fn decorator_names(&self) -> Vec<&'static str> {
+        self.decorators.iter().map(|d| d.name()).collect()
+    }
+
+    // Runs the decorators on the chunks around the given ones once every chunk
+    // next to them exists. Returns the positions of the blocks they placed.
+    pub fn decorate<A: BlockAccess>(
+        &self,
+        access: &mut A,
+        chunk_positions: &[IVec3],
+    ) -> Vec<IVec3> {
+        decorate_ready(
+            access,
+            &self.decorators,
+            self.seed,
+            &self.biomes,
+            chunk_positions,
+        )
+    }
+
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.order
+            .iter()
+            .map(|&idx| self.stages[idx].name())
+            .collect()
+    }
+
+    pub fn generate(&self, chunk_position: IVec3) -> Chunk {
+        let mut chunk = Chunk::new(Uuid::new_v4(), chunk_position.as_vec3a());
+        let ctx = GenContext {
+            chunk_position,
+            seed: self.seed,
+            biomes: &self.biomes,
+        };
+
+        for &idx in &self.order {
+            self.stages[idx].generate(&mut chunk, &ctx);
+        }
+        chunk.settle_fluids();
+        self.tint_chunk(&mut chunk);
+
+        chunk
+    }
+
+    // Tints aren't saved, chunks read back from disk get them from the biome
+    // map again.
+    pub fn tint_chunk(&self, chunk: &mut Chunk) {
+        let origin = chunk.chunk_position().as_ivec3() * CHUNK_SIZE;
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let biome = self.biomes.biome_at(self.seed, origin.x + x, origin.z + z);
+                chunk.set_tint(x as u32, z as u32, biome.tint);
+            }
+        }
+    }
+
+    // Kahn's algorithm, picking ready stages in registration order so the
+    // resulting pipeline is stable across runs.
+    fn resolve_order(&self) -> Result<Vec<usize>> {
+        for stage in &self.stages {
+            for dependency in stage.dependencies() {
+                if !self.stages.iter().any(|s| s.name() == *dependency) {
+                    return Err(anyhow!(
+                        "stage `{}` depends on unknown stage `{}`",
+                        stage.name(),
+                        dependency
+                    ));
+                }
+            }
+        }
+
+        let mut order = vec![];
+        let mut done = vec![false; self.stages.len()];
+        while order.len() < self.stages.len() {
+            let next = self.stages.iter().enumerate().position(|(idx, stage)| {
+                !done[idx]
+                    && stage.dependencies().iter().all(|dependency| {
+                        self.stages
+                            .iter()
+                            .enumerate()
+                            .any(|(i, s)| done[i] && s.name() == *dependency)
+                    })
+            });
+
+            match next {
+                Some(idx) => {
+                    done[idx] = true;
+                    order.push(idx);
+                }
+                None => return Err(anyhow!("generation stages contain a dependency cycle")),
+            }
+        }
+
+        Ok(order)
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use glam::IVec3;
+use image::RgbaImage;
+use VoxelTest::app::{App, NModel};
+use VoxelTest::debug::DebugView;
+use VoxelTest::lighting;
+use VoxelTest::seed::WorldSeed;
+use VoxelTest::worldgen::WorldGenerator;
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+
+// A pixel counts as different when its luminance moves by more than this (0..255),
+// and a fixture fails when more than MAX_DIFF_RATIO of its pixels differ.
+const PIXEL_THRESHOLD: f32 = 8.0;
+const MAX_DIFF_RATIO: f32 = 0.01;
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{name}.png"))
+}
+
+fn luminance(pixel: &image::Rgba<u8>) -> f32 {
+    let [r, g, b, _] = pixel.0;
+    0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32
+}
+
+fn diff_ratio(a: &RgbaImage, b: &RgbaImage) -> f32 {
+    if a.dimensions() != b.dimensions() {
+        return 1.0;
+    }
+
+    let different = a
+        .pixels()
+        .zip(b.pixels())
+        .filter(|(a, b)| (luminance(a) - luminance(b)).abs() > PIXEL_THRESHOLD)
+        .count();
+
+    different as f32 / (a.width() * a.height()) as f32
+}
+
+fn check_golden(name: &str, frame: &RgbaImage) {
+    let path = golden_path(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        frame.save(&path).unwrap();
+        return;
+    }
+    assert!(
+        path.exists(),
+        "{name}: no golden at {}, run with UPDATE_GOLDEN=1 to write it",
+        path.display()
+    );
+
+    let golden = image::open(&path).unwrap().to_rgba8();
+    let ratio = diff_ratio(&golden, frame);
+    if ratio > MAX_DIFF_RATIO {
+        let actual = path.with_extension("actual.png");
+        frame.save(&actual).unwrap();
+        panic!(
+            "{name}: {:.2}% of pixels differ from {}, actual frame written to {}",
+            ratio * 100.0,
+            path.display(),
+            actual.display()
+        );
+    }
+}
+
+struct Fixture {
+    view: DebugView,
+    time_of_day: f32,
+    position: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+}
+
+fn render_fixture(fixture: &Fixture) -> Option<RgbaImage> {
+    let mut app = match pollster::block_on(App::new_headless(WIDTH, HEIGHT, 1)) {
+        Ok(app) => app,
+        Err(err) => {
+            eprintln!("skipping golden test: {err}");
+            return None;
+        }
+    };
+
+    app.set_overlay_visible(false);
+    app.register_model("cube.obj").unwrap();
+    let generator = WorldGenerator::with_default_stages(WorldSeed::new(0));
+    let mut chunks = HashMap::from([(IVec3::ZERO, generator.generate(IVec3::ZERO))]);
+    lighting::light_chunk(&mut chunks, IVec3::ZERO);
+    let chunk = chunks.remove(&IVec3::ZERO).unwrap();
+    app.add_model(NModel::new(Box::new(chunk)));
+
+    {
+        let camera = app.camera();
+        let mut camera = camera.write().unwrap();
+        camera.set_position(fixture.position);
+        camera.set_rotation(fixture.yaw, fixture.pitch);
+    }
+    app.time_of_day_mut().set_cycle_length(0.0);
+    app.time_of_day_mut().set_time(fixture.time_of_day);
+    app.debug_keys_mut().set_view(fixture.view);
+
+    app.update(Duration::ZERO);
+    app.render().unwrap();
+    Some(app.capture_frame().unwrap())
+}
+
+fn chunk_fixture(view: DebugView) -> Fixture {
+    Fixture {
+        view,
+        time_of_day: 0.5,
+        position: [8.0, 14.0, 30.0],
+        yaw: -1.57,
+        pitch: -0.45,
+    }
+}
+
+#[test]
+fn golden_debug_views() {
+    for view in [
+        DebugView::LightLevels,
+        DebugView::AmbientOcclusion,
+        DebugView::Normals,
+        DebugView::Overdraw,
+    ] {
+        let Some(frame) = render_fixture(&chunk_fixture(view)) else {
+            return;
+        };
+        let name = view.name().to_lowercase().replace(' ', "_");
+        check_golden(&format!("chunk_{name}"), &frame);
+    }
+}
+
+#[test]
+fn golden_lit_scene() {
+    for (name, time_of_day) in [("noon", 0.5), ("sunset", 0.74)] {
+        let fixture = Fixture {
+            time_of_day,
+            ..chunk_fixture(DebugView::None)
+        };
+        let Some(frame) = render_fixture(&fixture) else {
+            return;
+        };
+        check_golden(&format!("lit_{name}"), &frame);
+    }
+}
+
+#[test]
+fn golden_transparent_pass() {
+    // Looks down at the water surface filling the chunk up to sea level.
+    let fixture = Fixture {
+        position: [3.0, 10.0, 22.0],
+        yaw: -1.9,
+        pitch: -0.7,
+        ..chunk_fixture(DebugView::None)
+    };
+    let Some(frame) = render_fixture(&fixture) else {
+        return;
+    };
+    check_golden("transparent_water", &frame);
+}